@@ -0,0 +1,136 @@
+//! Compares the DashMap ledger against the sharded single-writer ledger under concurrent
+//! transfers, both through the `LedgerInterface` directly, which isolates the execution
+//! engines, and through the `TransactionProcessor`, which adds the event log on top.
+
+use {
+    chrono::Utc,
+    criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main},
+    dashmap::{DashMap, DashSet},
+    quasar::{
+        ledger::{Ledger, interface::LedgerInterface, sharded::ShardedLedger},
+        models::{
+            AccountType, Instruction, Posting, Transaction, TransactionStatus, TransferInstruction,
+        },
+        transaction_processor::{TransactionProcessor, interface::TransactionProcessorInterface},
+    },
+    rand::{Rng, SeedableRng, rngs::StdRng},
+    std::{hint::black_box, sync::Arc, thread},
+    uuid::Uuid,
+};
+
+const ACCOUNTS: usize = 1_000;
+const WORKERS: usize = 8;
+const TRANSFERS_PER_WORKER: usize = 1_000;
+
+type SharedLedger = Arc<dyn LedgerInterface + Send + Sync>;
+
+fn ledgers() -> Vec<(BenchmarkId, SharedLedger)> {
+    let mut ledgers: Vec<(BenchmarkId, SharedLedger)> = vec![(
+        BenchmarkId::from_parameter("dashmap"),
+        Arc::new(Ledger::default()),
+    )];
+    for shards in [2, 4, 8] {
+        let ledger = ShardedLedger::new(DashMap::new(), DashSet::new(), shards, 1024);
+        ledgers.push((BenchmarkId::new("sharded", shards), Arc::new(ledger)));
+    }
+    ledgers
+}
+
+fn funded_accounts(ledger: &SharedLedger) -> Arc<Vec<Uuid>> {
+    let accounts = (0..ACCOUNTS)
+        .map(|_| {
+            let id = ledger
+                .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+                .unwrap();
+            ledger
+                .commit_postings(Uuid::new_v4(), &[Posting::credit(id, u32::MAX as u64)])
+                .unwrap();
+            id
+        })
+        .collect();
+    Arc::new(accounts)
+}
+
+/// Runs `transfer` from `WORKERS` threads, each moving money between random accounts.
+fn run_transfers<F>(accounts: &Arc<Vec<Uuid>>, transfer: F)
+where
+    F: Fn(Uuid, Uuid) + Clone + Send + 'static,
+{
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|worker| {
+            let accounts = accounts.clone();
+            let transfer = transfer.clone();
+            thread::spawn(move || {
+                let mut rng = StdRng::seed_from_u64(worker as u64);
+                for _ in 0..TRANSFERS_PER_WORKER {
+                    let source = accounts[rng.random_range(0..accounts.len())];
+                    let destination = accounts[rng.random_range(0..accounts.len())];
+                    transfer(source, destination);
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+fn bench_ledgers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ledger_transfers");
+    group.throughput(Throughput::Elements(
+        (WORKERS * TRANSFERS_PER_WORKER) as u64,
+    ));
+    group.sample_size(10);
+
+    for (id, ledger) in ledgers() {
+        let accounts = funded_accounts(&ledger);
+        group.bench_function(id, |b| {
+            b.iter(|| {
+                let ledger = ledger.clone();
+                run_transfers(&accounts, move |source, destination| {
+                    let postings = [Posting::debit(source, 1), Posting::credit(destination, 1)];
+                    let _ = black_box(ledger.commit_postings(Uuid::new_v4(), &postings));
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_processor(c: &mut Criterion) {
+    let mut group = c.benchmark_group("processor_transfers");
+    group.throughput(Throughput::Elements(
+        (WORKERS * TRANSFERS_PER_WORKER) as u64,
+    ));
+    group.sample_size(10);
+
+    for (id, ledger) in ledgers() {
+        let accounts = funded_accounts(&ledger);
+        let processor = Arc::new(TransactionProcessor::new(ledger, DashMap::new()));
+        group.bench_function(id, |b| {
+            b.iter(|| {
+                let processor = processor.clone();
+                run_transfers(&accounts, move |source, destination| {
+                    let transaction = Transaction {
+                        id: Uuid::new_v4(),
+                        instruction: Instruction::Transfer(TransferInstruction {
+                            source_account_id: source,
+                            destination_account_id: destination,
+                            amount: 1,
+                        }),
+                        status: TransactionStatus::Pending,
+                        timestamp: Utc::now(),
+                    };
+                    let _ = black_box(processor.process_transaction(transaction));
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_ledgers, bench_processor);
+criterion_main!(benches);
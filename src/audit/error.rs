@@ -0,0 +1,13 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AuditError {
+    #[error("Audit store error: {0}")]
+    Store(#[from] rusqlite::Error),
+    #[error("Failed to encode audit entry: {0}")]
+    Encoding(#[from] serde_json::Error),
+    #[error("The commit log does not hold the transaction audited at sequence {0}")]
+    LogMismatch(u64),
+    #[error("Invalid audit checkpoint time: {0}")]
+    InvalidTimestamp(i64),
+}
@@ -0,0 +1,570 @@
+//! Tamper-evident audit log of committed transactions.
+//! Every committed transaction is appended to a chain whose entries hold the hash of the entry
+//! before them, so altering, removing or reordering an entry breaks every hash after it. Every
+//! `checkpoint_interval` entries, the hash of the chain is signed with a secret key: rewriting
+//! the chain from some entry on is then detected at the next checkpoint, even when every hash
+//! was recomputed.
+
+pub mod error;
+pub mod store;
+
+use {
+    crate::{
+        audit::{error::AuditError, store::AuditStore},
+        config::AuditConfig,
+        events::{CommittedTransaction, EventLog},
+        metrics::{AUDIT_CHAIN_SEQUENCE, AUDIT_CHECKPOINTS_TOTAL},
+    },
+    chrono::{DateTime, Utc},
+    hmac::{Hmac, Mac},
+    sha2::{Digest, Sha256},
+    std::{
+        collections::{BTreeMap, HashMap},
+        fmt,
+        sync::Arc,
+    },
+    tokio::sync::broadcast,
+    tracing::{error, info},
+    uuid::Uuid,
+};
+
+/// Previous hash of the first entry of the chain.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A committed transaction, chained to the entry before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub sequence: u64,
+    pub transaction_id: Uuid,
+    /// The committed transaction, serialized as JSON. The hash covers these exact bytes.
+    pub event: String,
+    pub previous_hash: String,
+    /// Hex encoded SHA-256 of the previous hash, the sequence number and the event.
+    pub hash: String,
+}
+
+impl AuditEntry {
+    pub fn new(previous_hash: &str, event: &CommittedTransaction) -> Result<Self, AuditError> {
+        let payload = serde_json::to_string(event)?;
+        Ok(AuditEntry {
+            sequence: event.sequence,
+            transaction_id: event.transaction_id,
+            hash: chain_hash(previous_hash, event.sequence, &payload),
+            event: payload,
+            previous_hash: previous_hash.to_string(),
+        })
+    }
+
+    /// Whether the entry's hash matches its contents.
+    pub fn is_sealed(&self) -> bool {
+        self.hash == chain_hash(&self.previous_hash, self.sequence, &self.event)
+    }
+
+    pub fn decode_event(&self) -> Result<CommittedTransaction, serde_json::Error> {
+        serde_json::from_str(&self.event)
+    }
+}
+
+fn chain_hash(previous_hash: &str, sequence: u64, event: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(previous_hash.as_bytes());
+    hasher.update(sequence.to_be_bytes());
+    hasher.update(event.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Hash of the chain at some entry, signed with the checkpoint key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditCheckpoint {
+    pub sequence: u64,
+    pub hash: String,
+    pub signed_at: DateTime<Utc>,
+    /// Hex encoded HMAC-SHA256 of the sequence number, hash and signing time.
+    pub signature: String,
+}
+
+impl AuditCheckpoint {
+    pub fn sign(key: &str, sequence: u64, hash: &str) -> Self {
+        // Stored with millisecond precision, so only sign what is stored.
+        let now = Utc::now();
+        let signed_at = DateTime::from_timestamp_millis(now.timestamp_millis()).unwrap_or(now);
+        AuditCheckpoint {
+            sequence,
+            hash: hash.to_string(),
+            signed_at,
+            signature: hex::encode(
+                checkpoint_mac(key, sequence, hash, signed_at)
+                    .finalize()
+                    .into_bytes(),
+            ),
+        }
+    }
+
+    /// Checks the signature in constant time.
+    pub fn verify(&self, key: &str) -> bool {
+        let Ok(signature) = hex::decode(&self.signature) else {
+            return false;
+        };
+        checkpoint_mac(key, self.sequence, &self.hash, self.signed_at)
+            .verify_slice(&signature)
+            .is_ok()
+    }
+}
+
+fn checkpoint_mac(key: &str, sequence: u64, hash: &str, signed_at: DateTime<Utc>) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{sequence}:{hash}:{}", signed_at.timestamp_millis()).as_bytes());
+    mac
+}
+
+/// Appends committed transactions to the audit chain.
+pub struct AuditLog {
+    store: Arc<AuditStore>,
+    checkpoint_interval: u64,
+    checkpoint_key: String,
+}
+
+impl AuditLog {
+    pub fn new(config: &AuditConfig, store: Arc<AuditStore>) -> Self {
+        AuditLog {
+            store,
+            checkpoint_interval: config.checkpoint_interval.max(1),
+            checkpoint_key: config.checkpoint_key.clone(),
+        }
+    }
+
+    /// Chains every committed transaction after the last entry, and returns how many were
+    /// appended. Fails without appending anything if the commit log no longer holds the last
+    /// chained transaction, rather than chaining a different history onto it.
+    pub fn append_new_events(&self, events: &EventLog) -> Result<usize, AuditError> {
+        let (last_sequence, mut previous_hash) = match self.store.last()? {
+            None => (0, GENESIS_HASH.to_string()),
+            Some((sequence, transaction_id, hash)) => {
+                if events
+                    .get(sequence)
+                    .is_none_or(|event| event.transaction_id != transaction_id)
+                {
+                    return Err(AuditError::LogMismatch(sequence));
+                }
+                (sequence, hash)
+            }
+        };
+
+        let mut entries = Vec::new();
+        let mut checkpoints = Vec::new();
+        for event in events.since(last_sequence + 1) {
+            let entry = AuditEntry::new(&previous_hash, &event)?;
+            if entry.sequence % self.checkpoint_interval == 0 {
+                checkpoints.push(AuditCheckpoint::sign(
+                    &self.checkpoint_key,
+                    entry.sequence,
+                    &entry.hash,
+                ));
+            }
+            previous_hash = entry.hash.clone();
+            entries.push(entry);
+        }
+        if entries.is_empty() {
+            return Ok(0);
+        }
+
+        self.store.append(&entries, &checkpoints)?;
+        AUDIT_CHAIN_SEQUENCE.set(entries.last().map_or(0, |e| e.sequence) as f64);
+        AUDIT_CHECKPOINTS_TOTAL.inc_by(checkpoints.len() as f64);
+        Ok(entries.len())
+    }
+}
+
+/// Keeps the audit chain up to date with the commit log until shutdown.
+pub async fn start_audit_log(
+    audit: Arc<AuditLog>,
+    events: Arc<EventLog>,
+    mut shutdown_receiver: broadcast::Receiver<()>,
+) {
+    // The receiver is only used as a wake-up signal: entries are always read from the log
+    // starting after the last chained one, so a lagging receiver loses nothing.
+    let mut receiver = events.subscribe(None).receiver;
+
+    if let Err(e) = audit.append_new_events(&events) {
+        error!("Failed to append to the audit log: {}", e);
+    }
+    info!("Audit log initialized");
+
+    loop {
+        tokio::select! {
+            result = receiver.recv() => {
+                if let Err(broadcast::error::RecvError::Closed) = result {
+                    break;
+                }
+                if let Err(e) = audit.append_new_events(&events) {
+                    error!("Failed to append to the audit log: {}", e);
+                }
+            }
+            _ = shutdown_receiver.recv() => {
+                info!("Shutting down audit log...");
+                break;
+            }
+        }
+    }
+
+    if let Err(e) = audit.append_new_events(&events) {
+        error!("Failed to append to the audit log: {}", e);
+    }
+}
+
+/// First sign of tampering found in the audit chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tampering {
+    /// The chain skips this sequence number.
+    MissingEntry(u64),
+    /// The entry's contents do not match its hash.
+    AlteredEntry(u64),
+    /// The entry does not link to the hash of the entry before it.
+    BrokenLink(u64),
+    /// The entry differs from the transaction stored in the commit log.
+    EventMismatch(u64),
+    /// The checkpoint's signature is invalid.
+    ForgedCheckpoint(u64),
+    /// The chain was rewritten between two checkpoints, hashes included.
+    CheckpointMismatch { sequence: u64, after: u64 },
+    /// The chain stops before a signed checkpoint.
+    Truncated { sequence: u64, checkpoint: u64 },
+}
+
+impl Tampering {
+    /// Sequence number of the first entry that may have been tampered with.
+    pub fn sequence(&self) -> u64 {
+        match self {
+            Tampering::MissingEntry(sequence)
+            | Tampering::AlteredEntry(sequence)
+            | Tampering::EventMismatch(sequence)
+            | Tampering::ForgedCheckpoint(sequence)
+            | Tampering::Truncated { sequence, .. } => *sequence,
+            Tampering::BrokenLink(sequence) => sequence.saturating_sub(1),
+            Tampering::CheckpointMismatch { after, .. } => after + 1,
+        }
+    }
+}
+
+impl fmt::Display for Tampering {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tampering::MissingEntry(sequence) => write!(f, "Entry {sequence} is missing"),
+            Tampering::AlteredEntry(sequence) => {
+                write!(f, "Entry {sequence} does not match its hash")
+            }
+            Tampering::BrokenLink(sequence) => write!(
+                f,
+                "Entry {sequence} does not link to entry {}, which was altered",
+                sequence - 1
+            ),
+            Tampering::EventMismatch(sequence) => write!(
+                f,
+                "Entry {sequence} differs from the committed transaction in the commit log"
+            ),
+            Tampering::ForgedCheckpoint(sequence) => {
+                write!(f, "Checkpoint {sequence} has an invalid signature")
+            }
+            Tampering::CheckpointMismatch { sequence, after } => write!(
+                f,
+                "The chain no longer matches checkpoint {sequence}: entries {} to {sequence} were rewritten",
+                after + 1
+            ),
+            Tampering::Truncated {
+                sequence,
+                checkpoint,
+            } => write!(
+                f,
+                "Entries from {sequence} on were removed, although checkpoint {checkpoint} covers them"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuditReport {
+    pub entries_checked: usize,
+    pub checkpoints_checked: usize,
+    pub tampering: Option<Tampering>,
+}
+
+impl AuditReport {
+    pub fn is_ok(&self) -> bool {
+        self.tampering.is_none()
+    }
+}
+
+/// Walks the chain from its first entry and stops at the first sign of tampering. Entries are
+/// also compared with the commit log where it holds them. Checkpoint signatures are only
+/// checked when the key is given.
+pub fn verify_chain(
+    entries: &[AuditEntry],
+    checkpoints: &[AuditCheckpoint],
+    events: &[CommittedTransaction],
+    checkpoint_key: Option<&str>,
+) -> AuditReport {
+    let logged: HashMap<u64, &CommittedTransaction> =
+        events.iter().map(|event| (event.sequence, event)).collect();
+    let checkpoints: BTreeMap<u64, &AuditCheckpoint> = checkpoints
+        .iter()
+        .map(|checkpoint| (checkpoint.sequence, checkpoint))
+        .collect();
+
+    let mut report = AuditReport::default();
+    let mut previous_hash = GENESIS_HASH;
+    let mut last_checkpoint = 0;
+
+    for (expected, entry) in (1..).zip(entries) {
+        let tampering = if entry.sequence != expected {
+            Some(Tampering::MissingEntry(expected))
+        } else if entry.previous_hash != previous_hash {
+            Some(Tampering::BrokenLink(expected))
+        } else if !entry.is_sealed() {
+            Some(Tampering::AlteredEntry(expected))
+        } else {
+            match entry.decode_event() {
+                Ok(event)
+                    if event.sequence == expected
+                        && event.transaction_id == entry.transaction_id
+                        && logged.get(&expected).is_none_or(|logged| **logged == event) =>
+                {
+                    None
+                }
+                _ => Some(Tampering::EventMismatch(expected)),
+            }
+        };
+        if tampering.is_some() {
+            report.tampering = tampering;
+            return report;
+        }
+        report.entries_checked += 1;
+
+        if let Some(checkpoint) = checkpoints.get(&expected) {
+            if checkpoint_key.is_some_and(|key| !checkpoint.verify(key)) {
+                report.tampering = Some(Tampering::ForgedCheckpoint(expected));
+                return report;
+            }
+            if checkpoint.hash != entry.hash {
+                report.tampering = Some(Tampering::CheckpointMismatch {
+                    sequence: expected,
+                    after: last_checkpoint,
+                });
+                return report;
+            }
+            last_checkpoint = expected;
+            report.checkpoints_checked += 1;
+        }
+        previous_hash = &entry.hash;
+    }
+
+    let next = entries.len() as u64 + 1;
+    if let Some(&checkpoint) = checkpoints
+        .range(next..)
+        .next()
+        .map(|(sequence, _)| sequence)
+    {
+        report.tampering = Some(Tampering::Truncated {
+            sequence: next,
+            checkpoint,
+        });
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            models::{DepositInstruction, Instruction, Posting},
+            persistence::{SharedBackend, sqlite::SqliteBackend},
+        },
+        std::sync::Mutex,
+    };
+
+    const KEY: &str = "checkpoint-key";
+
+    fn temp_db() -> String {
+        std::env::temp_dir()
+            .join(format!("quasar-audit-{}.db", Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn deposit(log: &EventLog, amount: u64) {
+        let account_id = Uuid::new_v4();
+        log.append(
+            Uuid::new_v4(),
+            Instruction::Deposit(DepositInstruction {
+                destination_account_id: account_id,
+                amount,
+            }),
+            vec![Posting::credit(account_id, amount)],
+            None,
+        )
+        .unwrap();
+    }
+
+    fn verify(store: &AuditStore, events: &EventLog) -> AuditReport {
+        let events: Vec<CommittedTransaction> =
+            events.entries().iter().map(|e| (**e).clone()).collect();
+        verify_chain(
+            &store.entries().unwrap(),
+            &store.checkpoints().unwrap(),
+            &events,
+            Some(KEY),
+        )
+    }
+
+    #[test]
+    fn test_chain_pinpoints_tampering() {
+        let path = temp_db();
+        let store = Arc::new(AuditStore::open(&path).unwrap());
+        let audit = AuditLog::new(
+            &AuditConfig {
+                enabled: true,
+                checkpoint_interval: 4,
+                checkpoint_key: KEY.to_string(),
+            },
+            store.clone(),
+        );
+        let events = EventLog::default();
+        for amount in 1..=6 {
+            deposit(&events, amount);
+        }
+        assert_eq!(audit.append_new_events(&events).unwrap(), 6);
+        deposit(&events, 7);
+        assert_eq!(audit.append_new_events(&events).unwrap(), 1);
+        assert_eq!(audit.append_new_events(&events).unwrap(), 0);
+
+        let report = verify(&store, &events);
+        assert!(report.is_ok(), "{:?}", report.tampering);
+        assert_eq!(report.entries_checked, 7);
+        assert_eq!(report.checkpoints_checked, 1);
+
+        // Wrong checkpoint key.
+        let entries = store.entries().unwrap();
+        let checkpoints = store.checkpoints().unwrap();
+        assert_eq!(
+            verify_chain(&entries, &checkpoints, &[], Some("other")).tampering,
+            Some(Tampering::ForgedCheckpoint(4))
+        );
+
+        let tamper = |change: &dyn Fn(&mut Vec<AuditEntry>)| {
+            let mut tampered = entries.clone();
+            change(&mut tampered);
+            verify_chain(&tampered, &checkpoints, &[], Some(KEY))
+                .tampering
+                .unwrap()
+        };
+        assert_eq!(
+            tamper(&|entries| {
+                entries[4].event = entries[4].event.replace("\"amount\":5", "\"amount\":500")
+            }),
+            Tampering::AlteredEntry(5)
+        );
+        assert_eq!(
+            tamper(&|entries| entries[4].previous_hash = GENESIS_HASH.to_string()),
+            Tampering::BrokenLink(5)
+        );
+        assert_eq!(
+            tamper(&|entries| {
+                entries.remove(2);
+            }),
+            Tampering::MissingEntry(3)
+        );
+        assert_eq!(
+            tamper(&|entries| entries.truncate(2)),
+            Tampering::Truncated {
+                sequence: 3,
+                checkpoint: 4
+            }
+        );
+
+        // Rewriting entries 2 onwards with consistent hashes is caught by the commit log, or
+        // without it by the checkpoint.
+        let mut rewritten = entries.clone();
+        let mut event = rewritten[1].decode_event().unwrap();
+        event.postings[0].amount = 200;
+        let mut previous_hash = rewritten[0].hash.clone();
+        for entry in rewritten.iter_mut().skip(1) {
+            let event = if entry.sequence == 2 {
+                event.clone()
+            } else {
+                entry.decode_event().unwrap()
+            };
+            *entry = AuditEntry::new(&previous_hash, &event).unwrap();
+            previous_hash = entry.hash.clone();
+        }
+        let logged: Vec<CommittedTransaction> =
+            events.entries().iter().map(|e| (**e).clone()).collect();
+        assert_eq!(
+            verify_chain(&rewritten, &checkpoints, &logged, Some(KEY)).tampering,
+            Some(Tampering::EventMismatch(2))
+        );
+        let tampering = verify_chain(&rewritten, &checkpoints, &[], Some(KEY))
+            .tampering
+            .unwrap();
+        assert_eq!(
+            tampering,
+            Tampering::CheckpointMismatch {
+                sequence: 4,
+                after: 0
+            }
+        );
+        assert_eq!(tampering.sequence(), 1);
+
+        drop(store);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_chain_resumes_after_a_restart() {
+        let path = temp_db();
+        let config = AuditConfig {
+            enabled: true,
+            checkpoint_interval: 2,
+            checkpoint_key: KEY.to_string(),
+        };
+        let open = || -> (EventLog, AuditLog) {
+            let journal: SharedBackend =
+                Arc::new(Mutex::new(Box::new(SqliteBackend::new(&path).unwrap())));
+            let stored = journal.lock().unwrap().load_events().unwrap();
+            (
+                EventLog::new(stored, 16).with_journal(journal),
+                AuditLog::new(&config, Arc::new(AuditStore::open(&path).unwrap())),
+            )
+        };
+
+        let (events, audit) = open();
+        for amount in 1..=3 {
+            deposit(&events, amount);
+        }
+        assert_eq!(audit.append_new_events(&events).unwrap(), 3);
+        // Stopped without a shutdown checkpoint.
+        drop((events, audit));
+
+        let (events, audit) = open();
+        deposit(&events, 4);
+        deposit(&events, 5);
+        assert_eq!(events.last_sequence(), 5);
+        assert_eq!(audit.append_new_events(&events).unwrap(), 2);
+        let store = AuditStore::open(&path).unwrap();
+        let report = verify(&store, &events);
+        assert!(report.is_ok(), "{:?}", report.tampering);
+        assert_eq!(report.entries_checked, 5);
+
+        // A log that was not kept is refused rather than chained onto the audited one.
+        let lost = EventLog::default();
+        deposit(&lost, 6);
+        assert!(matches!(
+            audit.append_new_events(&lost),
+            Err(AuditError::LogMismatch(5))
+        ));
+        assert_eq!(store.entries().unwrap().len(), 5);
+
+        drop((events, audit, store));
+        std::fs::remove_file(path).unwrap();
+    }
+}
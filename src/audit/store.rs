@@ -0,0 +1,159 @@
+use {
+    crate::audit::{AuditCheckpoint, AuditEntry, error::AuditError},
+    chrono::DateTime,
+    rusqlite::{Connection, OptionalExtension, Row, params},
+    std::sync::{Mutex, MutexGuard},
+    uuid::Uuid,
+};
+
+/// SQLite-backed audit chain and checkpoints. Uses its own connection so entries are durable
+/// as soon as they are appended, independently of the ledger state checkpoints.
+pub struct AuditStore {
+    conn: Mutex<Connection>,
+}
+
+impl AuditStore {
+    pub fn open(db_path: &str) -> Result<Self, AuditError> {
+        let conn = Connection::open(db_path)?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        let store = AuditStore {
+            conn: Mutex::new(conn),
+        };
+        store.init_db()?;
+        Ok(store)
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn init_db(&self) -> Result<(), AuditError> {
+        let conn = self.conn();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS audit_log (
+                sequence INTEGER PRIMARY KEY,
+                transaction_id TEXT NOT NULL,
+                event TEXT NOT NULL,
+                previous_hash TEXT NOT NULL,
+                hash TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS audit_checkpoints (
+                sequence INTEGER PRIMARY KEY,
+                hash TEXT NOT NULL,
+                signed_at INTEGER NOT NULL,
+                signature TEXT NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Sequence number, transaction ID and hash of the last entry of the chain.
+    pub fn last(&self) -> Result<Option<(u64, Uuid, String)>, AuditError> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT sequence, transaction_id, hash FROM audit_log ORDER BY sequence DESC LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, parse_uuid(row, 1)?, row.get(2)?)),
+            )
+            .optional()?)
+    }
+
+    /// Appends entries and checkpoints in a single transaction.
+    pub fn append(
+        &self,
+        entries: &[AuditEntry],
+        checkpoints: &[AuditCheckpoint],
+    ) -> Result<(), AuditError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        for entry in entries {
+            tx.execute(
+                "INSERT INTO audit_log (sequence, transaction_id, event, previous_hash, hash)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    entry.sequence,
+                    entry.transaction_id.to_string(),
+                    entry.event,
+                    entry.previous_hash,
+                    entry.hash,
+                ],
+            )?;
+        }
+        for checkpoint in checkpoints {
+            tx.execute(
+                "INSERT INTO audit_checkpoints (sequence, hash, signed_at, signature)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    checkpoint.sequence,
+                    checkpoint.hash,
+                    checkpoint.signed_at.timestamp_millis(),
+                    checkpoint.signature,
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The whole chain, in sequence order.
+    pub fn entries(&self) -> Result<Vec<AuditEntry>, AuditError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT sequence, transaction_id, event, previous_hash, hash FROM audit_log
+             ORDER BY sequence",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(AuditEntry {
+                sequence: row.get(0)?,
+                transaction_id: parse_uuid(row, 1)?,
+                event: row.get(2)?,
+                previous_hash: row.get(3)?,
+                hash: row.get(4)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Every checkpoint, in sequence order.
+    pub fn checkpoints(&self) -> Result<Vec<AuditCheckpoint>, AuditError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT sequence, hash, signed_at, signature FROM audit_checkpoints ORDER BY sequence",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        let mut checkpoints = Vec::new();
+        for row in rows {
+            let (sequence, hash, signed_at, signature) = row?;
+            checkpoints.push(AuditCheckpoint {
+                sequence,
+                hash,
+                signed_at: DateTime::from_timestamp_millis(signed_at)
+                    .ok_or(AuditError::InvalidTimestamp(signed_at))?,
+                signature,
+            });
+        }
+        Ok(checkpoints)
+    }
+}
+
+fn parse_uuid(row: &Row, index: usize) -> rusqlite::Result<Uuid> {
+    let value: String = row.get(index)?;
+    Uuid::parse_str(&value).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
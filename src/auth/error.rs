@@ -0,0 +1,44 @@
+use {crate::auth::Scope, thiserror::Error, tonic::Status, uuid::Uuid};
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("Missing credentials: send an x-api-key, a bearer token or a client certificate")]
+    MissingCredentials,
+    #[error("Unknown API key")]
+    UnknownApiKey,
+    #[error("Invalid token: {0}")]
+    InvalidToken(#[from] jsonwebtoken::errors::Error),
+    #[error("Bearer tokens are not accepted")]
+    TokensDisabled,
+    #[error("Unknown client {0}")]
+    UnknownClient(String),
+    #[error("No client is identified by the certificate of {0}")]
+    UnknownCertificate(String),
+    #[error("Client {client} lacks the {scope} scope")]
+    MissingScope { client: String, scope: Scope },
+    #[error("Client {client} does not own account {account_id}")]
+    NotOwner { client: String, account_id: Uuid },
+    #[error("Invalid auth configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Ownership store error: {0}")]
+    Store(#[from] rusqlite::Error),
+}
+
+impl From<AuthError> for Status {
+    fn from(error: AuthError) -> Self {
+        match error {
+            AuthError::MissingCredentials
+            | AuthError::UnknownApiKey
+            | AuthError::InvalidToken(_)
+            | AuthError::TokensDisabled
+            | AuthError::UnknownClient(_)
+            | AuthError::UnknownCertificate(_) => Status::unauthenticated(error.to_string()),
+            AuthError::MissingScope { .. } | AuthError::NotOwner { .. } => {
+                Status::permission_denied(error.to_string())
+            }
+            AuthError::InvalidConfig(_) | AuthError::Store(_) => {
+                Status::internal(error.to_string())
+            }
+        }
+    }
+}
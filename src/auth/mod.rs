@@ -0,0 +1,707 @@
+//! Authentication and authorization of gRPC clients.
+//! Clients are configured with the scopes they are granted and authenticate with an API key,
+//! with a JWT signed with the server's HMAC secret whose subject names the client, or with a
+//! TLS client certificate when the server verifies them. An
+//! interceptor attaches the authenticated client to each request, and the service checks its
+//! scopes before doing anything. Transfers also require owning the debited account: one listed
+//! for the client in the configuration or created by it, unless the client may debit any
+//! account. Reading an account's balance, history or statement and managing its webhooks
+//! require owning it too, unless the client may debit any account or is an administrator.
+//! Account creations carry the client creating them, recorded as owner wherever the commit
+//! log is applied or replicated.
+
+pub mod error;
+pub mod store;
+
+use {
+    crate::{
+        auth::{error::AuthError, store::OwnershipStore},
+        config::AuthConfig,
+        metrics::AUTH_REJECTIONS_TOTAL,
+        models::{Instruction, Transaction},
+        tls::peer_common_name,
+    },
+    chrono::Utc,
+    jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation},
+    serde::{Deserialize, Serialize},
+    sha2::{Digest, Sha256},
+    std::{
+        collections::{HashMap, HashSet},
+        fmt,
+        sync::Arc,
+    },
+    tonic::{
+        Request, Status,
+        metadata::{AsciiMetadataValue, MetadataMap},
+        service::Interceptor,
+    },
+    uuid::Uuid,
+};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    // Balances, history, statements, events and webhooks of owned accounts, and transaction
+    // statuses.
+    Read,
+    // Creating accounts, transfers from owned accounts, submissions and webhook registrations
+    // for owned accounts.
+    Write,
+    // Deposits, which credit accounts with money from outside the ledger.
+    Deposit,
+    // Transfers from any account, owned or not, and access to any account.
+    DebitAny,
+    // Backups, invariant checks, promotion of followers and access to any account, including
+    // the events of every account.
+    Admin,
+    // Streaming the whole commit log, as replication followers do.
+    Replicate,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Deposit => "deposit",
+            Scope::DebitAny => "debit_any",
+            Scope::Admin => "admin",
+            Scope::Replicate => "replicate",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An authenticated client, attached to the extensions of its requests.
+#[derive(Debug)]
+pub struct ClientIdentity {
+    pub id: String,
+    pub scopes: HashSet<Scope>,
+    // Accounts the client may debit besides the ones it created.
+    pub accounts: HashSet<Uuid>,
+}
+
+impl ClientIdentity {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    exp: i64,
+}
+
+/// Issues a token for a client, valid for the given number of seconds.
+pub fn issue_token(secret: &str, client_id: &str, ttl_seconds: i64) -> Result<String, AuthError> {
+    let claims = Claims {
+        sub: client_id.to_string(),
+        exp: Utc::now().timestamp() + ttl_seconds,
+    };
+    Ok(jsonwebtoken::encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )?)
+}
+
+fn hash_api_key(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+pub struct Authenticator {
+    clients: HashMap<String, Arc<ClientIdentity>>,
+    // Keyed by the SHA-256 digest of the key, so looking a key up never compares secrets.
+    api_keys: HashMap<[u8; 32], Arc<ClientIdentity>>,
+    // Keyed by the common name of the client certificate.
+    certificate_subjects: HashMap<String, Arc<ClientIdentity>>,
+    jwt_key: Option<DecodingKey>,
+    validation: Validation,
+    owners: Arc<OwnershipStore>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig, owners: Arc<OwnershipStore>) -> Result<Self, AuthError> {
+        if config.clients.is_empty() {
+            return Err(AuthError::InvalidConfig(
+                "at least one client is required".to_string(),
+            ));
+        }
+
+        let mut clients = HashMap::new();
+        let mut api_keys = HashMap::new();
+        let mut certificate_subjects = HashMap::new();
+        for client in &config.clients {
+            let identity = Arc::new(ClientIdentity {
+                id: client.id.clone(),
+                scopes: client.scopes.iter().copied().collect(),
+                accounts: client.accounts.iter().copied().collect(),
+            });
+            if clients
+                .insert(client.id.clone(), identity.clone())
+                .is_some()
+            {
+                return Err(AuthError::InvalidConfig(format!(
+                    "client {} is configured twice",
+                    client.id
+                )));
+            }
+            for key in &client.api_keys {
+                if key.is_empty() || AsciiMetadataValue::try_from(key.as_str()).is_err() {
+                    return Err(AuthError::InvalidConfig(format!(
+                        "client {} has an empty or non-ASCII API key",
+                        client.id
+                    )));
+                }
+                if api_keys
+                    .insert(hash_api_key(key), identity.clone())
+                    .is_some()
+                {
+                    return Err(AuthError::InvalidConfig(format!(
+                        "an API key of client {} is used more than once",
+                        client.id
+                    )));
+                }
+            }
+            for subject in &client.certificate_subjects {
+                if certificate_subjects
+                    .insert(subject.clone(), identity.clone())
+                    .is_some()
+                {
+                    return Err(AuthError::InvalidConfig(format!(
+                        "certificate subject {subject} is mapped to more than one client"
+                    )));
+                }
+            }
+        }
+
+        let jwt_key = (!config.jwt_secret.is_empty())
+            .then(|| DecodingKey::from_secret(config.jwt_secret.as_bytes()));
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_required_spec_claims(&["exp", "sub"]);
+
+        Ok(Authenticator {
+            clients,
+            api_keys,
+            certificate_subjects,
+            jwt_key,
+            validation,
+            owners,
+        })
+    }
+
+    /// Identifies the client from the x-api-key header, or else from an
+    /// `authorization: Bearer <token>` header, or else from the common name of its verified TLS
+    /// certificate.
+    pub fn authenticate(
+        &self,
+        metadata: &MetadataMap,
+        certificate_subject: Option<&str>,
+    ) -> Result<Arc<ClientIdentity>, AuthError> {
+        self.identify(metadata, certificate_subject)
+            .inspect_err(|_| AUTH_REJECTIONS_TOTAL.inc())
+    }
+
+    /// The configured client with this ID, e.g. the one a cluster member forwarded a write of.
+    pub fn client(&self, id: &str) -> Result<Arc<ClientIdentity>, AuthError> {
+        self.clients
+            .get(id)
+            .cloned()
+            .ok_or_else(|| AuthError::UnknownClient(id.to_string()))
+    }
+
+    fn identify(
+        &self,
+        metadata: &MetadataMap,
+        certificate_subject: Option<&str>,
+    ) -> Result<Arc<ClientIdentity>, AuthError> {
+        if let Some(key) = metadata.get(API_KEY_HEADER) {
+            let key = key.to_str().map_err(|_| AuthError::UnknownApiKey)?;
+            return self
+                .api_keys
+                .get(&hash_api_key(key))
+                .cloned()
+                .ok_or(AuthError::UnknownApiKey);
+        }
+
+        let Some(authorization) = metadata.get("authorization") else {
+            let subject = certificate_subject.ok_or(AuthError::MissingCredentials)?;
+            return self
+                .certificate_subjects
+                .get(subject)
+                .cloned()
+                .ok_or_else(|| AuthError::UnknownCertificate(subject.to_string()));
+        };
+        let token = authorization
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(AuthError::MissingCredentials)?;
+        let key = self.jwt_key.as_ref().ok_or(AuthError::TokensDisabled)?;
+        let claims = jsonwebtoken::decode::<Claims>(token.trim(), key, &self.validation)?.claims;
+        self.clients
+            .get(&claims.sub)
+            .cloned()
+            .ok_or(AuthError::UnknownClient(claims.sub))
+    }
+
+    pub fn authorize(&self, client: &ClientIdentity, scope: Scope) -> Result<(), AuthError> {
+        if client.has_scope(scope) {
+            return Ok(());
+        }
+        AUTH_REJECTIONS_TOTAL.inc();
+        Err(AuthError::MissingScope {
+            client: client.id.clone(),
+            scope,
+        })
+    }
+
+    /// Checks that the client may execute the transaction. Account creations are stamped with
+    /// the client, recorded as their owner once committed.
+    pub fn authorize_transaction(
+        &self,
+        client: &ClientIdentity,
+        transaction: &mut Transaction,
+    ) -> Result<(), AuthError> {
+        match &mut transaction.instruction {
+            Instruction::CreateAccount(create) => {
+                self.authorize(client, Scope::Write)?;
+                create.owner = Some(client.id.clone());
+                Ok(())
+            }
+            Instruction::Transfer(transfer) => {
+                self.authorize(client, Scope::Write)?;
+                if client.has_scope(Scope::DebitAny)
+                    || self.owns(client, transfer.source_account_id)?
+                {
+                    return Ok(());
+                }
+                AUTH_REJECTIONS_TOTAL.inc();
+                Err(AuthError::NotOwner {
+                    client: client.id.clone(),
+                    account_id: transfer.source_account_id,
+                })
+            }
+            Instruction::Deposit(_) => self.authorize(client, Scope::Deposit),
+            Instruction::GetBalance(get_balance) => {
+                self.authorize_account(client, Scope::Read, get_balance.account_id)
+            }
+            Instruction::Review(_)
+            | Instruction::OpenDispute(_)
+            | Instruction::UpdateDispute(_) => self.authorize(client, Scope::Admin),
+        }
+    }
+
+    /// Checks that the client has the scope on the account: that it owns the account, unless
+    /// it may debit any account or is an administrator.
+    pub fn authorize_account(
+        &self,
+        client: &ClientIdentity,
+        scope: Scope,
+        account_id: Uuid,
+    ) -> Result<(), AuthError> {
+        self.authorize(client, scope)?;
+        if client.has_scope(Scope::DebitAny)
+            || client.has_scope(Scope::Admin)
+            || self.owns(client, account_id)?
+        {
+            return Ok(());
+        }
+        AUTH_REJECTIONS_TOTAL.inc();
+        Err(AuthError::NotOwner {
+            client: client.id.clone(),
+            account_id,
+        })
+    }
+
+    fn owns(&self, client: &ClientIdentity, account_id: Uuid) -> Result<bool, AuthError> {
+        Ok(client.accounts.contains(&account_id)
+            || self.owners.owner(account_id)?.as_deref() == Some(client.id.as_str()))
+    }
+}
+
+/// Attaches the authenticated client to every request, rejecting unauthenticated ones. Lets
+/// everything through when authentication is disabled.
+#[derive(Clone)]
+pub struct AuthInterceptor(pub Option<Arc<Authenticator>>);
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(authenticator) = &self.0 {
+            let subject = peer_common_name(&request);
+            let client = authenticator.authenticate(request.metadata(), subject.as_deref())?;
+            request.extensions_mut().insert(client);
+        }
+        Ok(request)
+    }
+}
+
+/// Sends an API key with every request of a gRPC client. Sends nothing for an empty key.
+#[derive(Clone, Default)]
+pub struct ApiKey(Option<AsciiMetadataValue>);
+
+impl ApiKey {
+    pub fn new(key: &str) -> Result<Self, AuthError> {
+        if key.is_empty() {
+            return Ok(ApiKey(None));
+        }
+        AsciiMetadataValue::try_from(key)
+            .map(|value| ApiKey(Some(value)))
+            .map_err(|_| AuthError::InvalidConfig("API keys must be ASCII".to_string()))
+    }
+}
+
+impl Interceptor for ApiKey {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(key) = &self.0 {
+            request.metadata_mut().insert(API_KEY_HEADER, key.clone());
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            config::AuthClient,
+            grpc_server::{
+                QuasarGrpcServer,
+                server::{
+                    CreateAccountRequest, CreateBackupRequest, ListTransactionsRequest,
+                    ReplicateRequest, SubscribeEventsRequest, TransferRequest,
+                    grpc_service_client::GrpcServiceClient, grpc_service_server::GrpcServiceServer,
+                },
+            },
+            ledger::Ledger,
+            models::{
+                Account, AccountType, CreateAccountInstruction, DepositInstruction,
+                GetBalanceInstruction, TransactionStatus, TransferInstruction,
+            },
+            submission::SubmissionQueue,
+            transaction_processor::TransactionProcessor,
+            transaction_processor::interface::{TransactionProcessorInterface, TransactionResult},
+        },
+        dashmap::DashMap,
+        tokio_stream::wrappers::TcpListenerStream,
+        tonic::{
+            Code,
+            transport::{Endpoint, Server},
+        },
+    };
+
+    fn transaction(instruction: Instruction) -> Transaction {
+        Transaction {
+            id: Uuid::new_v4(),
+            instruction,
+            status: TransactionStatus::Pending,
+            timestamp: Utc::now(),
+        }
+    }
+
+    fn transfer_from(source_account_id: Uuid) -> Transaction {
+        transaction(Instruction::Transfer(TransferInstruction {
+            source_account_id,
+            destination_account_id: Uuid::new_v4(),
+            amount: 10,
+        }))
+    }
+
+    fn authenticator(treasury: Uuid) -> Authenticator {
+        let config = AuthConfig {
+            enabled: true,
+            jwt_secret: "token-secret".to_string(),
+            clients: vec![
+                AuthClient {
+                    id: "shop".to_string(),
+                    scopes: vec![Scope::Read, Scope::Write],
+                    accounts: vec![treasury],
+                    api_keys: vec!["shop-key".to_string()],
+                    certificate_subjects: vec![],
+                },
+                AuthClient {
+                    id: "auditor".to_string(),
+                    scopes: vec![Scope::Read],
+                    accounts: vec![],
+                    api_keys: vec![],
+                    certificate_subjects: vec!["auditor.example".to_string()],
+                },
+                AuthClient {
+                    id: "operator".to_string(),
+                    scopes: vec![Scope::Read, Scope::Admin],
+                    accounts: vec![],
+                    api_keys: vec![],
+                    certificate_subjects: vec![],
+                },
+                AuthClient {
+                    id: "follower".to_string(),
+                    scopes: vec![Scope::Replicate],
+                    accounts: vec![],
+                    api_keys: vec!["follower-key".to_string()],
+                    certificate_subjects: vec![],
+                },
+            ],
+        };
+        let owners = Arc::new(OwnershipStore::open(":memory:").unwrap());
+        Authenticator::new(&config, owners).unwrap()
+    }
+
+    #[test]
+    fn test_authentication_and_scopes() {
+        let treasury = Uuid::new_v4();
+        let authenticator = authenticator(treasury);
+
+        let mut metadata = MetadataMap::new();
+        let status = Status::from(authenticator.authenticate(&metadata, None).unwrap_err());
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        metadata.insert(API_KEY_HEADER, "wrong-key".parse().unwrap());
+        assert!(matches!(
+            authenticator.authenticate(&metadata, None),
+            Err(AuthError::UnknownApiKey)
+        ));
+        metadata.insert(API_KEY_HEADER, "shop-key".parse().unwrap());
+        let shop = authenticator.authenticate(&metadata, None).unwrap();
+        assert_eq!(shop.id, "shop");
+
+        let mut metadata = MetadataMap::new();
+        let token = issue_token("token-secret", "auditor", 60).unwrap();
+        metadata.insert("authorization", format!("Bearer {token}").parse().unwrap());
+        let auditor = authenticator.authenticate(&metadata, None).unwrap();
+        assert_eq!(auditor.id, "auditor");
+
+        for token in [
+            issue_token("other-secret", "auditor", 60).unwrap(),
+            issue_token("token-secret", "auditor", -120).unwrap(),
+        ] {
+            metadata.insert("authorization", format!("Bearer {token}").parse().unwrap());
+            assert!(matches!(
+                authenticator.authenticate(&metadata, None),
+                Err(AuthError::InvalidToken(_))
+            ));
+        }
+        let token = issue_token("token-secret", "stranger", 60).unwrap();
+        metadata.insert("authorization", format!("Bearer {token}").parse().unwrap());
+        assert!(matches!(
+            authenticator.authenticate(&metadata, None),
+            Err(AuthError::UnknownClient(_))
+        ));
+
+        // Headers take precedence over the client certificate.
+        let metadata = MetadataMap::new();
+        let by_certificate = authenticator
+            .authenticate(&metadata, Some("auditor.example"))
+            .unwrap();
+        assert_eq!(by_certificate.id, "auditor");
+        assert!(matches!(
+            authenticator.authenticate(&metadata, Some("intruder.example")),
+            Err(AuthError::UnknownCertificate(_))
+        ));
+
+        // Scopes gate instructions, and transfers need the debited account to be owned.
+        let mut deposit = transaction(Instruction::Deposit(DepositInstruction {
+            destination_account_id: treasury,
+            amount: 10,
+        }));
+        let status = Status::from(
+            authenticator
+                .authorize_transaction(&shop, &mut deposit)
+                .unwrap_err(),
+        );
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert!(authenticator.authorize(&auditor, Scope::Write).is_err());
+        authenticator
+            .authorize_transaction(&shop, &mut transfer_from(treasury))
+            .unwrap();
+        assert!(matches!(
+            authenticator.authorize_transaction(&shop, &mut transfer_from(Uuid::new_v4())),
+            Err(AuthError::NotOwner { .. })
+        ));
+
+        // Creations are stamped with the client, which owns the account once it is committed.
+        let mut creation = transaction(Instruction::CreateAccount(CreateAccountInstruction {
+            keys: vec![],
+            account_type: AccountType::Personal,
+            owner: Some("auditor".to_string()),
+        }));
+        authenticator
+            .authorize_transaction(&shop, &mut creation)
+            .unwrap();
+        let account_id = Account::id_for(creation.id);
+        let mut spend = transfer_from(account_id);
+        assert!(
+            authenticator
+                .authorize_transaction(&shop, &mut spend)
+                .is_err()
+        );
+        TransactionProcessor::new(Arc::new(Ledger::default()), DashMap::new())
+            .with_owners(authenticator.owners.clone())
+            .process_transaction(creation)
+            .unwrap();
+        assert_eq!(
+            authenticator.owners.owner(account_id).unwrap().as_deref(),
+            Some("shop")
+        );
+        authenticator
+            .authorize_transaction(&shop, &mut spend)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_account_access_requires_ownership() {
+        let treasury = Uuid::new_v4();
+        let authenticator = authenticator(treasury);
+        let client = |id: &str| authenticator.clients[id].clone();
+        let (shop, auditor, operator) = (client("shop"), client("auditor"), client("operator"));
+
+        authenticator
+            .authorize_account(&shop, Scope::Write, treasury)
+            .unwrap();
+        assert!(matches!(
+            authenticator.authorize_account(&shop, Scope::Read, Uuid::new_v4()),
+            Err(AuthError::NotOwner { .. })
+        ));
+        assert!(matches!(
+            authenticator.authorize_account(&auditor, Scope::Read, treasury),
+            Err(AuthError::NotOwner { .. })
+        ));
+        let mut balance = transaction(Instruction::GetBalance(GetBalanceInstruction {
+            account_id: treasury,
+        }));
+        assert!(
+            authenticator
+                .authorize_transaction(&auditor, &mut balance)
+                .is_err()
+        );
+
+        // Administrators access every account, with the scopes they have.
+        authenticator
+            .authorize_account(&operator, Scope::Read, treasury)
+            .unwrap();
+        assert!(matches!(
+            authenticator.authorize_account(&operator, Scope::Write, treasury),
+            Err(AuthError::MissingScope { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_grpc_service_requires_credentials() {
+        let authenticator = Arc::new(authenticator(Uuid::new_v4()));
+        let processor = Arc::new(
+            TransactionProcessor::new(Arc::new(Ledger::default()), DashMap::new())
+                .with_owners(authenticator.owners.clone()),
+        );
+        let unowned = processor
+            .process_transaction(transaction(Instruction::CreateAccount(
+                CreateAccountInstruction::new(vec![]),
+            )))
+            .unwrap();
+        let TransactionResult::AccountCreated(unowned) = unowned else {
+            panic!("expected an account");
+        };
+        let service = QuasarGrpcServer::new(processor, Arc::new(SubmissionQueue::new(8)))
+            .with_auth(authenticator.clone());
+        let interceptor = AuthInterceptor(Some(authenticator));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(GrpcServiceServer::with_interceptor(service, interceptor))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let channel = Endpoint::from_shared(format!("http://{address}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let create = || CreateAccountRequest {
+            transaction_id: Uuid::new_v4().to_string(),
+            ..Default::default()
+        };
+
+        let mut anonymous = GrpcServiceClient::new(channel.clone());
+        let status = anonymous.create_account(create()).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        let mut shop =
+            GrpcServiceClient::with_interceptor(channel.clone(), ApiKey::new("shop-key").unwrap());
+        let created = shop.create_account(create()).await.unwrap().into_inner();
+        assert!(created.success);
+
+        // The shop owns the account it created, but no other.
+        let transfer = |source_account_id: String| TransferRequest {
+            transaction_id: Uuid::new_v4().to_string(),
+            source_account_id,
+            destination_account_id: Uuid::new_v4().to_string(),
+            amount: 1,
+        };
+        let response = shop
+            .process_transfer(transfer(created.created_account_id.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.success, "an empty account cannot pay");
+        let status = shop
+            .process_transfer(transfer(Uuid::new_v4().to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        let status = shop
+            .create_backup(CreateBackupRequest {})
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        // Histories are only listed to the owner.
+        let history = |account_id: String| ListTransactionsRequest {
+            account_id,
+            ..Default::default()
+        };
+        let listed = shop
+            .list_transactions(history(created.created_account_id.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(listed.transactions.len(), 1);
+        let status = shop
+            .list_transactions(history(unowned.to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        // So are events, and those of every account only to administrators.
+        let events = |account_id: String| SubscribeEventsRequest {
+            account_id,
+            ..Default::default()
+        };
+        assert!(
+            shop.subscribe_events(events(created.created_account_id))
+                .await
+                .is_ok()
+        );
+        for account_id in [unowned.to_string(), String::new()] {
+            let status = shop.subscribe_events(events(account_id)).await.unwrap_err();
+            assert_eq!(status.code(), Code::PermissionDenied);
+        }
+
+        // The whole log is only streamed to replication followers.
+        let status = shop
+            .replicate(ReplicateRequest::default())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        let mut follower =
+            GrpcServiceClient::with_interceptor(channel, ApiKey::new("follower-key").unwrap());
+        assert!(
+            follower
+                .replicate(ReplicateRequest::default())
+                .await
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use {
+    crate::{auth::error::AuthError, events::CommittedTransaction, models::Instruction},
+    rusqlite::{Connection, OptionalExtension, params},
+    std::sync::{Mutex, MutexGuard},
+    uuid::Uuid,
+};
+
+/// SQLite-backed record of which client created each account. Uses its own connection, like
+/// the webhook store, so ownership survives restarts whatever the persistence backend. Owners
+/// are recorded from committed account creations, so that every node applying or replicating
+/// the commit log records the same ones.
+pub struct OwnershipStore {
+    conn: Mutex<Connection>,
+}
+
+impl OwnershipStore {
+    pub fn open(db_path: &str) -> Result<Self, AuthError> {
+        let conn = Connection::open(db_path)?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS account_owners (
+                account_id TEXT PRIMARY KEY,
+                client_id TEXT NOT NULL
+            )",
+            [],
+        )?;
+        Ok(OwnershipStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records the client as owner of the account, unless it already has one: the first claim
+    /// wins, so replaying another client's creation does not take its account over.
+    pub fn claim(&self, account_id: Uuid, client_id: &str) -> Result<(), AuthError> {
+        self.conn().execute(
+            "INSERT OR IGNORE INTO account_owners (account_id, client_id) VALUES (?1, ?2)",
+            params![account_id.to_string(), client_id],
+        )?;
+        Ok(())
+    }
+
+    /// Records the owner stamped on an account creation, if any.
+    pub fn record(&self, event: &CommittedTransaction) -> Result<(), AuthError> {
+        if let (Instruction::CreateAccount(create), Some(account_id)) =
+            (&event.instruction, event.created_account_id)
+            && let Some(owner) = &create.owner
+        {
+            return self.claim(account_id, owner);
+        }
+        Ok(())
+    }
+
+    pub fn owner(&self, account_id: Uuid) -> Result<Option<String>, AuthError> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT client_id FROM account_owners WHERE account_id = ?1",
+                params![account_id.to_string()],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+}
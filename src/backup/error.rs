@@ -0,0 +1,36 @@
+use {
+    crate::{
+        invariants::Violation, ledger::error::LedgerError, persistence::error::PersistenceError,
+    },
+    thiserror::Error,
+};
+
+#[derive(Debug, Error)]
+pub enum BackupError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("Persistence error: {0}")]
+    Persistence(#[from] PersistenceError),
+    #[error("Ledger error: {0}")]
+    Ledger(#[from] LedgerError),
+    #[error("Invalid manifest: {0}")]
+    Manifest(#[from] serde_json::Error),
+    #[error("{0} already holds a backup")]
+    AlreadyExists(String),
+    #[error("Unsupported manifest format version {0}")]
+    UnsupportedFormat(u32),
+    #[error("Checksum mismatch: the manifest lists {expected} but the database hashes to {found}")]
+    ChecksumMismatch { expected: String, found: String },
+    #[error("The backup holds {found} {table} but the manifest lists {expected}")]
+    ContentMismatch {
+        table: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    #[error("The backup violates {} ledger invariants", .0.len())]
+    InvariantsViolated(Vec<Violation>),
+    #[error("No consistent snapshot of the ledger could be taken: {} invariants violated", .0.len())]
+    InconsistentSnapshot(Vec<Violation>),
+}
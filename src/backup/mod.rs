@@ -0,0 +1,376 @@
+//! Online backups of the ledger database.
+//! A backup is a directory holding a SQLite database, in the format written by [`SqliteBackend`],
+//! next to a manifest recording its checksum and contents. Backups are taken either from a live
+//! transaction processor or from a database file through SQLite's backup API. Before a backup is
+//! restored, its checksum, contents and ledger invariants are verified.
+
+pub mod error;
+
+use {
+    crate::{
+        backup::error::BackupError,
+        events::CommittedTransaction,
+        invariants::check_state,
+        models::{Account, Transaction},
+        persistence::{
+            PersistenceBackend,
+            migrations::{self, current_version},
+            sqlite::SqliteBackend,
+        },
+        transaction_processor::TransactionProcessor,
+    },
+    chrono::{DateTime, Utc},
+    dashmap::{DashMap, DashSet},
+    rusqlite::{Connection, OpenFlags, backup::Backup},
+    serde::{Deserialize, Serialize},
+    sha2::{Digest, Sha256},
+    std::{
+        fs::{self, File},
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::Duration,
+    },
+    uuid::Uuid,
+};
+
+pub const DATABASE_FILE: &str = "quasar.db";
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+const MANIFEST_FORMAT_VERSION: u32 = 1;
+
+// Pages copied by each step of the SQLite backup API, and the pause between steps that lets
+// writers to the source database through.
+const BACKUP_PAGES_PER_STEP: i32 = 256;
+const BACKUP_STEP_PAUSE: Duration = Duration::from_millis(10);
+
+// A live ledger can look inconsistent while transactions are in flight. Snapshots are retried
+// until they pass the invariant checks.
+const SNAPSHOT_ATTEMPTS: usize = 5;
+const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format_version: u32,
+    pub created_at: DateTime<Utc>,
+    pub schema_version: u32,
+    /// Size in bytes of the database file.
+    pub size: u64,
+    /// Hex encoded SHA-256 of the database file.
+    pub sha256: String,
+    pub accounts: usize,
+    pub transactions: usize,
+    pub events: usize,
+}
+
+/// State of a live transaction processor, passing every invariant check.
+struct Snapshot {
+    accounts: DashMap<Uuid, Account>,
+    transactions: DashMap<Uuid, Transaction>,
+    processed: DashSet<Uuid>,
+    events: Vec<Arc<CommittedTransaction>>,
+}
+
+async fn snapshot(processor: &TransactionProcessor) -> Result<Snapshot, BackupError> {
+    let mut violations = Vec::new();
+    for attempt in 0..SNAPSHOT_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(SNAPSHOT_RETRY_DELAY).await;
+        }
+
+        let events = processor.events.entries();
+        let (accounts, processed) = processor.ledger.snapshot()?;
+        let transactions = processor.transactions.clone();
+        let report = check_state(
+            &accounts,
+            &processed,
+            &transactions,
+            events.iter().map(|event| event.as_ref()),
+        );
+        if report.is_ok() {
+            return Ok(Snapshot {
+                accounts,
+                transactions,
+                processed,
+                events,
+            });
+        }
+        violations = report.violations;
+    }
+
+    Err(BackupError::InconsistentSnapshot(violations))
+}
+
+/// Writes a consistent snapshot of a live transaction processor to `directory`.
+pub async fn backup_processor(
+    processor: &TransactionProcessor,
+    directory: PathBuf,
+) -> Result<BackupManifest, BackupError> {
+    let snapshot = snapshot(processor).await?;
+
+    tokio::task::spawn_blocking(move || {
+        let database = prepare(&directory)?;
+        let mut backend = SqliteBackend::new(&database.to_string_lossy())?;
+        backend.append_events(&snapshot.events)?;
+        backend.checkpoint(
+            &snapshot.accounts,
+            &snapshot.transactions,
+            &snapshot.processed,
+        )?;
+        drop(backend);
+        write_manifest(&directory)
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+/// Copies the database at `db_path` to `directory` with SQLite's backup API. The copy is
+/// consistent even if the database is written to meanwhile.
+pub fn backup_database(db_path: &str, directory: &Path) -> Result<BackupManifest, BackupError> {
+    let database = prepare(directory)?;
+    let source = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut destination = Connection::open(&database)?;
+    Backup::new(&source, &mut destination)?.run_to_completion(
+        BACKUP_PAGES_PER_STEP,
+        BACKUP_STEP_PAUSE,
+        None,
+    )?;
+    // Databases written by older releases are brought to the current schema, so that the
+    // manifest describes what a restore will install.
+    migrations::migrate(&mut destination)?;
+    drop(destination);
+
+    write_manifest(directory)
+}
+
+/// Checks the manifest, checksum, contents and ledger invariants of a backup.
+pub fn verify_backup(directory: &Path) -> Result<BackupManifest, BackupError> {
+    let copy = std::env::temp_dir().join(format!("quasar-verify-{}.db", Uuid::new_v4()));
+    let result = validate_copy(directory, &copy);
+    fs::remove_file(&copy).ok();
+    result
+}
+
+/// Replaces the database at `db_path` with a verified backup. The replaced database is kept
+/// as `<db_path>.pre-restore`. The server must be stopped, since it rewrites the database
+/// from memory on shutdown.
+pub fn restore_backup(directory: &Path, db_path: &str) -> Result<BackupManifest, BackupError> {
+    // Staged next to the database, so that the final rename does not cross file systems.
+    let staged = PathBuf::from(format!("{db_path}.restoring"));
+    let manifest = match validate_copy(directory, &staged) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            fs::remove_file(&staged).ok();
+            return Err(e);
+        }
+    };
+
+    if Path::new(db_path).exists() {
+        fs::rename(db_path, format!("{db_path}.pre-restore"))?;
+    }
+    fs::rename(&staged, db_path)?;
+    Ok(manifest)
+}
+
+/// Creates the backup directory, refusing to overwrite an existing backup, and returns the path
+/// of the database to write.
+fn prepare(directory: &Path) -> Result<PathBuf, BackupError> {
+    let database = directory.join(DATABASE_FILE);
+    if database.exists() || directory.join(MANIFEST_FILE).exists() {
+        return Err(BackupError::AlreadyExists(directory.display().to_string()));
+    }
+    fs::create_dir_all(directory)?;
+    Ok(database)
+}
+
+fn write_manifest(directory: &Path) -> Result<BackupManifest, BackupError> {
+    let database = directory.join(DATABASE_FILE);
+    let conn = Connection::open_with_flags(&database, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let count = |table: &str| {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+            row.get::<_, usize>(0)
+        })
+    };
+    let mut manifest = BackupManifest {
+        format_version: MANIFEST_FORMAT_VERSION,
+        created_at: Utc::now(),
+        schema_version: current_version(&conn)?,
+        size: 0,
+        sha256: String::new(),
+        accounts: count("accounts")?,
+        transactions: count("transactions")?,
+        events: count("committed_transactions")?,
+    };
+    drop(conn);
+
+    manifest.size = fs::metadata(&database)?.len();
+    manifest.sha256 = file_sha256(&database)?;
+    fs::write(
+        directory.join(MANIFEST_FILE),
+        serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    Ok(manifest)
+}
+
+/// Copies the backup database to `copy` and validates the copy against the manifest. The copy
+/// is what gets hashed, so what is validated is exactly what a restore installs.
+fn validate_copy(directory: &Path, copy: &Path) -> Result<BackupManifest, BackupError> {
+    let manifest: BackupManifest =
+        serde_json::from_slice(&fs::read(directory.join(MANIFEST_FILE))?)?;
+    if manifest.format_version != MANIFEST_FORMAT_VERSION {
+        return Err(BackupError::UnsupportedFormat(manifest.format_version));
+    }
+
+    fs::copy(directory.join(DATABASE_FILE), copy)?;
+    let sha256 = file_sha256(copy)?;
+    if sha256 != manifest.sha256 {
+        return Err(BackupError::ChecksumMismatch {
+            expected: manifest.sha256,
+            found: sha256,
+        });
+    }
+
+    let mut backend = SqliteBackend::new(&copy.to_string_lossy())?;
+    let (accounts, transactions, processed) = backend.load_state()?;
+    let events = backend.load_events()?;
+    for (table, expected, found) in [
+        ("accounts", manifest.accounts, accounts.len()),
+        ("transactions", manifest.transactions, transactions.len()),
+        ("events", manifest.events, events.len()),
+    ] {
+        if expected != found {
+            return Err(BackupError::ContentMismatch {
+                table,
+                expected,
+                found,
+            });
+        }
+    }
+
+    let report = check_state(&accounts, &processed, &transactions, &events);
+    if !report.is_ok() {
+        return Err(BackupError::InvariantsViolated(report.violations));
+    }
+
+    Ok(manifest)
+}
+
+fn file_sha256(path: &Path) -> Result<String, BackupError> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            ledger::Ledger,
+            models::{
+                CreateAccountInstruction, DepositInstruction, Instruction, TransactionStatus,
+            },
+            transaction_processor::interface::{TransactionProcessorInterface, TransactionResult},
+        },
+    };
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("quasar-{name}-{}", Uuid::new_v4()))
+    }
+
+    fn process(processor: &TransactionProcessor, instruction: Instruction) -> TransactionResult {
+        processor
+            .process_transaction(Transaction {
+                id: Uuid::new_v4(),
+                instruction,
+                status: TransactionStatus::Pending,
+                timestamp: Utc::now(),
+            })
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_backup_and_restore_live_ledger() {
+        let processor = TransactionProcessor::new(Arc::new(Ledger::default()), DashMap::new());
+        let TransactionResult::AccountCreated(account_id) = process(
+            &processor,
+            Instruction::CreateAccount(CreateAccountInstruction::new(vec![])),
+        ) else {
+            panic!("expected an account");
+        };
+        process(
+            &processor,
+            Instruction::Deposit(DepositInstruction {
+                destination_account_id: account_id,
+                amount: 40,
+            }),
+        );
+
+        let directory = temp_path("backup");
+        let manifest = backup_processor(&processor, directory.clone())
+            .await
+            .unwrap();
+        assert_eq!((manifest.accounts, manifest.transactions), (1, 2));
+        assert_eq!(verify_backup(&directory).unwrap(), manifest);
+        assert!(matches!(
+            backup_processor(&processor, directory.clone()).await,
+            Err(BackupError::AlreadyExists(_))
+        ));
+
+        // Restoring over an existing database keeps it aside.
+        let db_path = temp_path("restored.db").to_string_lossy().into_owned();
+        fs::write(&db_path, b"previous").unwrap();
+        restore_backup(&directory, &db_path).unwrap();
+        assert_eq!(
+            fs::read(format!("{db_path}.pre-restore")).unwrap(),
+            b"previous"
+        );
+        let (accounts, _, _) = SqliteBackend::new(&db_path).unwrap().load_state().unwrap();
+        assert_eq!(accounts.get(&account_id).unwrap().balance, 40);
+
+        // A backup of the restored file is identical in content.
+        let copy = temp_path("backup");
+        let copied = backup_database(&db_path, &copy).unwrap();
+        assert_eq!(copied.events, manifest.events);
+        verify_backup(&copy).unwrap();
+
+        for path in [db_path.clone(), format!("{db_path}.pre-restore")] {
+            fs::remove_file(path).unwrap();
+        }
+        fs::remove_dir_all(directory).unwrap();
+        fs::remove_dir_all(copy).unwrap();
+    }
+
+    #[test]
+    fn test_restore_rejects_invalid_backups() {
+        // An account holding money that was never deposited.
+        let db_path = temp_path("source.db").to_string_lossy().into_owned();
+        let (account_id, mut account) = Account::new(Uuid::new_v4(), vec![]);
+        account.balance = 10;
+        let accounts = DashMap::new();
+        accounts.insert(account_id, account);
+        SqliteBackend::new(&db_path)
+            .unwrap()
+            .checkpoint(&accounts, &DashMap::new(), &DashSet::new())
+            .unwrap();
+
+        let directory = temp_path("backup");
+        backup_database(&db_path, &directory).unwrap();
+        let target = temp_path("target.db").to_string_lossy().into_owned();
+        assert!(matches!(
+            restore_backup(&directory, &target),
+            Err(BackupError::InvariantsViolated(_))
+        ));
+        assert!(!Path::new(&target).exists());
+
+        let mut content = fs::read(directory.join(DATABASE_FILE)).unwrap();
+        *content.last_mut().unwrap() ^= 1;
+        fs::write(directory.join(DATABASE_FILE), content).unwrap();
+        assert!(matches!(
+            verify_backup(&directory),
+            Err(BackupError::ChecksumMismatch { .. })
+        ));
+
+        fs::remove_file(db_path).unwrap();
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
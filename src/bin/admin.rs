@@ -0,0 +1,235 @@
+use {
+    chrono::{DateTime, Utc},
+    clap::{Parser, Subcommand, ValueEnum},
+    quasar::{
+        audit::{store::AuditStore, verify_chain},
+        auth::issue_token,
+        backup::{BackupManifest, backup_database, restore_backup, verify_backup},
+        events::replay::verify_snapshot,
+        invariants::check_state,
+        persistence::{PersistenceBackend, sqlite::SqliteBackend},
+        receipts::ReceiptSigner,
+        statements::{DEFAULT_STATEMENT_CURRENCY, Statement, StatementFormat},
+    },
+    std::path::PathBuf,
+    uuid::Uuid,
+};
+
+/// Offline administration tools working on the SQLite database written by the server.
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[arg(short, long, default_value = "quasar.db")]
+    db_path: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints the statement of an account over a period.
+    Statement {
+        #[arg(long)]
+        account_id: Uuid,
+        /// Inclusive RFC 3339 start of the period. Defaults to the first transaction.
+        #[arg(long)]
+        from: Option<DateTime<Utc>>,
+        /// Exclusive RFC 3339 end of the period. Defaults to now.
+        #[arg(long)]
+        to: Option<DateTime<Utc>>,
+        #[arg(long, value_enum, default_value_t = Format::Csv)]
+        format: Format,
+        #[arg(long, default_value = DEFAULT_STATEMENT_CURRENCY)]
+        currency: String,
+        /// Writes the statement to this file instead of stdout.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Verifies money conservation and transaction bookkeeping. Exits with an error if any
+    /// invariant is violated.
+    Check,
+    /// Rebuilds the ledger by replaying the commit log and compares it with the checkpointed
+    /// accounts. Exits with an error if they differ.
+    VerifyReplay,
+    /// Walks the hash-chained audit log and reports the first tampered entry. Exits with an
+    /// error if the chain was altered.
+    VerifyAudit {
+        /// Key the checkpoints were signed with. Signatures are not checked without it.
+        #[arg(long)]
+        checkpoint_key: Option<String>,
+    },
+    /// Prints a new Ed25519 key pair for signing receipts. The secret key goes in
+    /// receipts.signing_key, the public key to whoever verifies receipts.
+    GenerateReceiptKey,
+    /// Prints a bearer token for a client configured in the auth section, signed with
+    /// auth.jwt_secret.
+    IssueToken {
+        #[arg(long)]
+        client_id: String,
+        #[arg(long)]
+        jwt_secret: String,
+        /// Seconds before the token expires.
+        #[arg(long, default_value_t = 3600)]
+        ttl_seconds: i64,
+    },
+    /// Copies the database, with a checksum manifest, to a new backup directory. Safe to run
+    /// while the server is up.
+    Backup {
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Verifies the checksum, contents and invariants of a backup.
+    VerifyBackup { backup: PathBuf },
+    /// Replaces the database with a verified backup. The server must be stopped.
+    Restore { backup: PathBuf },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    Ofx,
+}
+
+impl From<Format> for StatementFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Csv => StatementFormat::Csv,
+            Format::Ofx => StatementFormat::Ofx,
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let open = || {
+        SqliteBackend::new(&cli.db_path)
+            .map_err(|e| format!("Failed to open database {}: {}", cli.db_path, e))
+    };
+
+    match cli.command {
+        Command::Statement {
+            account_id,
+            from,
+            to,
+            format,
+            currency,
+            output,
+        } => {
+            // The log holds every account, so only keep the entries touching this one.
+            let events = open()?.load_events()?;
+            let statement = Statement::generate(
+                account_id,
+                from.unwrap_or(DateTime::UNIX_EPOCH),
+                to.unwrap_or_else(Utc::now),
+                events.iter().filter(|e| e.touches_account(account_id)),
+            )?;
+            let content = statement.render(format.into(), &currency);
+
+            match output {
+                Some(path) => std::fs::write(path, content)?,
+                None => print!("{content}"),
+            }
+        }
+        Command::Check => {
+            let mut persistence = open()?;
+            let (accounts, transactions, processed) = persistence.load_state()?;
+            let events = persistence.load_events()?;
+            let report = check_state(&accounts, &processed, &transactions, &events);
+
+            for violation in &report.violations {
+                println!("{violation}");
+            }
+            println!(
+                "Checked {} accounts and {} transactions: {} violations",
+                report.accounts_checked,
+                report.transactions_checked,
+                report.violations.len()
+            );
+            if !report.is_ok() {
+                return Err("Ledger invariants violated".into());
+            }
+        }
+        Command::VerifyReplay => {
+            let mut persistence = open()?;
+            let (accounts, _, processed) = persistence.load_state()?;
+            let events = persistence.load_events()?;
+            let report = verify_snapshot(&accounts, &processed, &events)?;
+
+            for divergence in &report.divergences {
+                println!("{divergence}");
+            }
+            println!(
+                "Replayed {} transactions against {} accounts: {} divergences",
+                report.events_replayed,
+                report.accounts_checked,
+                report.divergences.len()
+            );
+            if !report.is_ok() {
+                return Err("Snapshot differs from the replayed log".into());
+            }
+        }
+        Command::VerifyAudit { checkpoint_key } => {
+            let events = open()?.load_events()?;
+            let store = AuditStore::open(&cli.db_path)?;
+            let report = verify_chain(
+                &store.entries()?,
+                &store.checkpoints()?,
+                &events,
+                checkpoint_key.as_deref(),
+            );
+
+            if checkpoint_key.is_none() {
+                println!("No checkpoint key given, checkpoint signatures were not checked");
+            }
+            println!(
+                "Checked {} entries and {} checkpoints",
+                report.entries_checked, report.checkpoints_checked
+            );
+            if let Some(tampering) = report.tampering {
+                println!("{tampering}");
+                return Err(format!(
+                    "Audit log tampered with from entry {}",
+                    tampering.sequence()
+                )
+                .into());
+            }
+        }
+        Command::GenerateReceiptKey => {
+            let signer = ReceiptSigner::generate();
+            println!("signing_key = \"{}\"", signer.secret_key());
+            println!("public_key = \"{}\"", signer.public_key());
+        }
+        Command::IssueToken {
+            client_id,
+            jwt_secret,
+            ttl_seconds,
+        } => {
+            println!("{}", issue_token(&jwt_secret, &client_id, ttl_seconds)?);
+        }
+        Command::Backup { output } => {
+            let manifest = backup_database(&cli.db_path, &output)?;
+            print_manifest("Wrote backup", &manifest);
+        }
+        Command::VerifyBackup { backup } => {
+            let manifest = verify_backup(&backup)?;
+            print_manifest("Backup is valid", &manifest);
+        }
+        Command::Restore { backup } => {
+            let manifest = restore_backup(&backup, &cli.db_path)?;
+            print_manifest(&format!("Restored {}", cli.db_path), &manifest);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_manifest(message: &str, manifest: &BackupManifest) {
+    println!(
+        "{message}: {} accounts, {} transactions and {} events taken at {} (sha256 {})",
+        manifest.accounts,
+        manifest.transactions,
+        manifest.events,
+        manifest.created_at.to_rfc3339(),
+        manifest.sha256
+    );
+}
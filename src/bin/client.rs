@@ -1,16 +1,21 @@
 use {
-    clap::Parser,
+    clap::{Parser, Subcommand, ValueEnum},
     quasar::{
+        auth::ApiKey,
         config::QuasarClientConfig,
         grpc_server::server::{
-            CreateAccountRequest, DepositRequest, GetBalanceRequest, TransferRequest,
+            CreateAccountRequest, DepositRequest, DisputeAction, DisputeStatus, GetBalanceRequest,
+            ListDisputesRequest, ListHeldTransactionsRequest, OpenDisputeRequest,
+            ReviewTransactionRequest, TransferRequest, UpdateDisputeRequest,
             grpc_service_client::GrpcServiceClient,
         },
+        receipts::SignedReceipt,
+        tls,
     },
     rand::{Rng, SeedableRng, seq::IndexedRandom},
-    std::{sync::Arc, time::Duration},
+    std::{path::PathBuf, sync::Arc, time::Duration},
     tokio::sync::RwLock,
-    tonic::transport::Channel,
+    tonic::{service::interceptor::InterceptedService, transport::Channel},
     tracing::{error, info, warn},
     uuid::Uuid,
 };
@@ -20,26 +25,250 @@ use {
 struct Args {
     #[arg(short, long, default_value = "config.toml")]
     config: String,
+    /// Runs the load generator when omitted.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Verifies a signed receipt, stored as JSON, against the server's public key. Needs no
+    /// connection to the server.
+    VerifyReceipt {
+        receipt: PathBuf,
+        /// Hex encoded public key the server signs receipts with.
+        #[arg(long)]
+        public_key: String,
+    },
+    /// Lists the transfers held for review by a risk rule.
+    HeldTransactions,
+    /// Approves a held transfer, committing it, or rejects it with --reject.
+    Review {
+        held_transaction_id: Uuid,
+        #[arg(long)]
+        reject: bool,
+    },
+    /// Lists the disputes not concluded yet.
+    Disputes,
+    /// Disputes up to `amount` of a committed transfer, blocking it on the payee's balance.
+    OpenDispute {
+        disputed_transaction_id: Uuid,
+        amount: u64,
+    },
+    UpdateDispute {
+        dispute_id: Uuid,
+        #[arg(value_enum)]
+        action: DisputeStep,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum DisputeStep {
+    StartAnalysis,
+    /// Returns the blocked funds to the payer.
+    Return,
+    /// Releases the blocked funds to the payee.
+    Release,
+}
+
+impl From<DisputeStep> for DisputeAction {
+    fn from(step: DisputeStep) -> Self {
+        match step {
+            DisputeStep::StartAnalysis => DisputeAction::StartAnalysis,
+            DisputeStep::Return => DisputeAction::Return,
+            DisputeStep::Release => DisputeAction::Release,
+        }
+    }
+}
+
+type Client = GrpcServiceClient<InterceptedService<Channel, ApiKey>>;
+
+async fn connect(config: &QuasarClientConfig) -> Result<Client, Box<dyn std::error::Error>> {
+    let scheme = if config.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    let channel = tls::endpoint(
+        format!("{scheme}://{}:{}", config.grpc.address, config.grpc.port),
+        config.tls.as_ref(),
+    )?
+    .connect()
+    .await?;
+    Ok(GrpcServiceClient::with_interceptor(
+        channel,
+        ApiKey::new(&config.api_key)?,
+    ))
+}
+
+async fn list_held_transactions(mut client: Client) -> Result<(), Box<dyn std::error::Error>> {
+    let held = client
+        .list_held_transactions(ListHeldTransactionsRequest {})
+        .await?
+        .into_inner()
+        .transactions;
+    for transaction in &held {
+        println!(
+            "{} held at {} by rule {}: {} from {} to {}",
+            transaction.transaction_id,
+            transaction.held_at,
+            transaction.rule,
+            transaction.amount,
+            transaction.source_account_id,
+            transaction.destination_account_id
+        );
+    }
+    println!("{} transfers held for review", held.len());
+    Ok(())
+}
+
+async fn review(
+    mut client: Client,
+    held_transaction_id: Uuid,
+    approve: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response = client
+        .review_transaction(ReviewTransactionRequest {
+            transaction_id: Uuid::new_v4().to_string(),
+            held_transaction_id: held_transaction_id.to_string(),
+            approve,
+        })
+        .await?
+        .into_inner();
+    if !response.success {
+        return Err(response.error_message.into());
+    }
+    let decision = if approve { "Approved" } else { "Rejected" };
+    println!("{decision} transfer {held_transaction_id}");
+    Ok(())
+}
+
+async fn list_disputes(mut client: Client) -> Result<(), Box<dyn std::error::Error>> {
+    let disputes = client
+        .list_disputes(ListDisputesRequest {})
+        .await?
+        .into_inner()
+        .disputes;
+    for dispute in &disputes {
+        println!(
+            "{} {} until {}: {} of {} blocked from {} for {}, disputing {}",
+            dispute.dispute_id,
+            DisputeStatus::try_from(dispute.status)
+                .map_or("unknown", |status| status.as_str_name()),
+            dispute.deadline,
+            dispute.blocked_amount,
+            dispute.amount,
+            dispute.payee_account_id,
+            dispute.payer_account_id,
+            dispute.disputed_transaction_id
+        );
+    }
+    println!("{} disputes in progress", disputes.len());
+    Ok(())
+}
+
+async fn open_dispute(
+    mut client: Client,
+    disputed_transaction_id: Uuid,
+    amount: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dispute_id = Uuid::new_v4();
+    let response = client
+        .open_dispute(OpenDisputeRequest {
+            transaction_id: dispute_id.to_string(),
+            disputed_transaction_id: disputed_transaction_id.to_string(),
+            amount,
+        })
+        .await?
+        .into_inner();
+    if !response.success {
+        return Err(response.error_message.into());
+    }
+    println!("Opened dispute {dispute_id} against transfer {disputed_transaction_id}");
+    Ok(())
+}
+
+async fn update_dispute(
+    mut client: Client,
+    dispute_id: Uuid,
+    step: DisputeStep,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response = client
+        .update_dispute(UpdateDisputeRequest {
+            transaction_id: Uuid::new_v4().to_string(),
+            dispute_id: dispute_id.to_string(),
+            action: DisputeAction::from(step).into(),
+        })
+        .await?
+        .into_inner();
+    if !response.success {
+        return Err(response.error_message.into());
+    }
+    println!("Applied {step:?} to dispute {dispute_id}");
+    Ok(())
+}
+
+fn verify_receipt(path: &PathBuf, public_key: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let receipt: SignedReceipt = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    receipt.verify(public_key)?;
+
+    let receipt = receipt.receipt;
+    println!(
+        "Valid receipt: {} moved {} to {} in transaction {}, committed at {} with sequence {}",
+        receipt
+            .source_account_id
+            .map_or("deposit".to_string(), |id| id.to_string()),
+        receipt.amount,
+        receipt.destination_account_id,
+        receipt.transaction_id,
+        receipt.committed_at.to_rfc3339(),
+        receipt.sequence
+    );
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    if let Some(Command::VerifyReceipt {
+        receipt,
+        public_key,
+    }) = &args.command
+    {
+        return verify_receipt(receipt, public_key);
+    }
 
     let config = QuasarClientConfig::from_file(&args.config)
         .map_err(|e| format!("Failed to load client configuration file: {}", e))?;
 
+    match args.command {
+        Some(Command::HeldTransactions) => {
+            return list_held_transactions(connect(&config).await?).await;
+        }
+        Some(Command::Review {
+            held_transaction_id,
+            reject,
+        }) => return review(connect(&config).await?, held_transaction_id, !reject).await,
+        Some(Command::Disputes) => return list_disputes(connect(&config).await?).await,
+        Some(Command::OpenDispute {
+            disputed_transaction_id,
+            amount,
+        }) => {
+            return open_dispute(connect(&config).await?, disputed_transaction_id, amount).await;
+        }
+        Some(Command::UpdateDispute { dispute_id, action }) => {
+            return update_dispute(connect(&config).await?, dispute_id, action).await;
+        }
+        _ => {}
+    }
+
     let _logging_guard = quasar::logging::init_logging(config.debug);
 
     let account_ids = Arc::new(RwLock::new(Vec::<Uuid>::new()));
 
     let mut join_handles = Vec::new();
     for i in 0..config.tasks {
-        let client = GrpcServiceClient::connect(format!(
-            "http://{}:{}",
-            config.grpc.address, config.grpc.port
-        ))
-        .await?;
+        let client = connect(&config).await?;
         let handle = tokio::spawn(run_worker(
             i.try_into().unwrap(),
             client,
@@ -60,7 +289,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 async fn run_worker(
     worker_id: u32,
-    mut client: GrpcServiceClient<Channel>,
+    mut client: Client,
     account_ids: Arc<RwLock<Vec<Uuid>>>,
     config: QuasarClientConfig,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -72,6 +301,7 @@ async fn run_worker(
         if operation_chance < config.create_chance {
             let create_req = CreateAccountRequest {
                 transaction_id: Uuid::new_v4().to_string(),
+                ..Default::default()
             };
 
             let Ok(creation_response) = client.create_account(create_req.clone()).await else {
@@ -121,6 +351,7 @@ async fn run_worker(
             let get_balance_req = GetBalanceRequest {
                 transaction_id: Uuid::new_v4().to_string(),
                 account_id: source_id.to_string(),
+                ..Default::default()
             };
 
             let Ok(balance_response) = client.get_balance(get_balance_req).await else {
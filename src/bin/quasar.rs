@@ -21,7 +21,13 @@ async fn main() {
         }
     };
 
-    let mut app = Quasar::new(config);
+    let mut app = match Quasar::new(config) {
+        Ok(app) => app,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return;
+        }
+    };
 
     if let Err(e) = app.run().await {
         error!("Quasar failed to run: {}", e);
@@ -1,4 +1,8 @@
-use config::{Config, ConfigError, File, FileFormat};
+use {
+    crate::{auth::Scope, fees::FeeRule},
+    config::{Config, ConfigError, File, FileFormat},
+    uuid::Uuid,
+};
 
 #[derive(Clone, Debug, serde::Deserialize)]
 pub struct QuasarServerConfig {
@@ -7,6 +11,36 @@ pub struct QuasarServerConfig {
     pub metrics: MetricsConfig,
     pub debug: bool,
     pub persistence: PersistenceConfig,
+    #[serde(default)]
+    pub fees: FeesConfig,
+    #[serde(default)]
+    pub execution: ExecutionConfig,
+    #[serde(default)]
+    pub submission: SubmissionConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+    #[serde(default)]
+    pub invariants: InvariantsConfig,
+    #[serde(default)]
+    pub backup: BackupConfig,
+    #[serde(default)]
+    pub replication: ReplicationConfig,
+    #[serde(default)]
+    pub cluster: ClusterConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub receipts: ReceiptsConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub risk: RiskConfig,
+    #[serde(default)]
+    pub disputes: DisputesConfig,
 }
 
 impl QuasarServerConfig {
@@ -28,6 +62,12 @@ pub struct QuasarClientConfig {
     // Chance (0-100) of creating a new account instead of making a transfer
     pub create_chance: u8,
     pub deposit_chance: u8,
+    // Sent with every request when the server requires authentication.
+    #[serde(default)]
+    pub api_key: String,
+    // Connects over TLS when set.
+    #[serde(default)]
+    pub tls: Option<ClientTlsConfig>,
 }
 
 impl QuasarClientConfig {
@@ -44,12 +84,44 @@ impl QuasarClientConfig {
 pub struct GrpcConfig {
     pub address: String,
     pub port: u16,
+    // Serves over TLS when set.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
 pub struct HttpConfig {
     pub address: String,
     pub port: u16,
+    // Serves over TLS when set.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct TlsConfig {
+    // PEM encoded certificate chain and private key of the server.
+    pub cert_path: String,
+    pub key_path: String,
+    // PEM encoded CA certificates. When set, clients must present a certificate issued by one
+    // of them, and may authenticate with it (see auth.clients).
+    #[serde(default)]
+    pub client_ca_path: String,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct ClientTlsConfig {
+    // PEM encoded CA certificates trusted to issue the server's certificate.
+    #[serde(default)]
+    pub ca_path: String,
+    // PEM encoded certificate and key presented to servers requiring client certificates.
+    #[serde(default)]
+    pub cert_path: String,
+    #[serde(default)]
+    pub key_path: String,
+    // Name expected in the server's certificate. Defaults to the host of the URL.
+    #[serde(default)]
+    pub domain_name: String,
 }
 
 #[derive(Clone, Debug, serde::Deserialize)]
@@ -60,5 +132,588 @@ pub struct MetricsConfig {
 
 #[derive(Clone, Debug, serde::Deserialize)]
 pub struct PersistenceConfig {
+    #[serde(default)]
+    pub backend: PersistenceBackendKind,
+    // SQLite database, also holding webhook registrations whatever the backend.
     pub db_path: String,
+    // Journal written by the append-only file backend.
+    #[serde(default = "default_persistence_file_path")]
+    pub file_path: String,
+    #[serde(default)]
+    pub on_corruption: CorruptionPolicy,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PersistenceBackendKind {
+    #[default]
+    Sqlite,
+    // Nothing survives a restart. Meant for tests and throwaway instances.
+    Memory,
+    // Checkpoints and committed transactions appended to a single journal file.
+    File,
+}
+
+/// What to do at startup with stored rows that cannot be decoded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CorruptionPolicy {
+    // Refuse to start, reporting the first corrupt row.
+    #[default]
+    Fail,
+    // Set corrupt rows aside and start without them.
+    Quarantine,
+    // Fill in fields that have a safe default, and quarantine the rows that cannot be repaired.
+    // Account types are recovered from the configuration or the commit log, never guessed.
+    Repair,
+}
+
+fn default_persistence_file_path() -> String {
+    "quasar.journal".to_string()
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct FeesConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Account credited with every fee. Created on startup if it does not exist.
+    #[serde(default)]
+    pub revenue_account_id: Uuid,
+    #[serde(default)]
+    pub rules: Vec<FeeRule>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionMode {
+    // Accounts live in a shared DashMap and transactions run on the calling thread.
+    #[default]
+    Dashmap,
+    // Accounts are partitioned between single-writer shard threads.
+    Sharded,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ExecutionConfig {
+    #[serde(default)]
+    pub mode: ExecutionMode,
+    #[serde(default = "default_shards")]
+    pub shards: usize,
+    // Maximum number of commands queued per shard before callers block.
+    #[serde(default = "default_shard_queue_capacity")]
+    pub queue_capacity: usize,
+    // Middlewares run around every instruction, outermost first.
+    #[serde(default = "default_middleware")]
+    pub middleware: Vec<String>,
+}
+
+impl Default for ExecutionConfig {
+    fn default() -> Self {
+        ExecutionConfig {
+            mode: ExecutionMode::default(),
+            shards: default_shards(),
+            queue_capacity: default_shard_queue_capacity(),
+            middleware: default_middleware(),
+        }
+    }
+}
+
+fn default_shards() -> usize {
+    std::thread::available_parallelism().map_or(4, |n| n.get())
+}
+
+fn default_middleware() -> Vec<String> {
+    vec![]
+}
+
+fn default_shard_queue_capacity() -> usize {
+    1024
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct SubmissionConfig {
+    // Submissions beyond this many queued transactions are rejected.
+    #[serde(default = "default_submission_queue_capacity")]
+    pub queue_capacity: usize,
+    #[serde(default = "default_submission_workers")]
+    pub workers: usize,
+    // Statuses of processed submissions are forgotten after this long, or sooner once more
+    // than max_finished_statuses are kept.
+    #[serde(default = "default_submission_status_ttl_seconds")]
+    pub status_ttl_seconds: u64,
+    #[serde(default = "default_max_finished_statuses")]
+    pub max_finished_statuses: usize,
+}
+
+impl Default for SubmissionConfig {
+    fn default() -> Self {
+        SubmissionConfig {
+            queue_capacity: default_submission_queue_capacity(),
+            workers: default_submission_workers(),
+            status_ttl_seconds: default_submission_status_ttl_seconds(),
+            max_finished_statuses: default_max_finished_statuses(),
+        }
+    }
+}
+
+fn default_submission_queue_capacity() -> usize {
+    10_000
+}
+
+fn default_submission_workers() -> usize {
+    4
+}
+
+fn default_submission_status_ttl_seconds() -> u64 {
+    60 * 60
+}
+
+fn default_max_finished_statuses() -> usize {
+    100_000
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct EventsConfig {
+    // Number of events buffered per live subscriber before it is considered lagging.
+    #[serde(default = "default_events_broadcast_capacity")]
+    pub broadcast_capacity: usize,
+    // Whether the commit log is the source of truth: the ledger is rebuilt on startup by
+    // replaying it, and checkpointed accounts are only kept as a projection.
+    #[serde(default)]
+    pub event_sourced: bool,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            broadcast_capacity: default_events_broadcast_capacity(),
+            event_sourced: false,
+        }
+    }
+}
+
+fn default_events_broadcast_capacity() -> usize {
+    1024
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct WebhooksConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Deliveries are abandoned after this many failed attempts.
+    #[serde(default = "default_webhook_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_webhook_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_webhook_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    #[serde(default = "default_webhook_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    #[serde(default = "default_webhook_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        WebhooksConfig {
+            enabled: false,
+            max_attempts: default_webhook_max_attempts(),
+            initial_backoff_ms: default_webhook_initial_backoff_ms(),
+            max_backoff_ms: default_webhook_max_backoff_ms(),
+            poll_interval_ms: default_webhook_poll_interval_ms(),
+            request_timeout_ms: default_webhook_request_timeout_ms(),
+        }
+    }
+}
+
+fn default_webhook_max_attempts() -> u32 {
+    10
+}
+
+fn default_webhook_initial_backoff_ms() -> u64 {
+    1_000
+}
+
+fn default_webhook_max_backoff_ms() -> u64 {
+    600_000
+}
+
+fn default_webhook_poll_interval_ms() -> u64 {
+    500
+}
+
+fn default_webhook_request_timeout_ms() -> u64 {
+    5_000
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct InvariantsConfig {
+    // Whether invariants are checked periodically in the background. On-demand checks are
+    // always available.
+    #[serde(default = "default_invariants_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_invariants_interval_seconds")]
+    pub interval_seconds: u64,
+}
+
+impl Default for InvariantsConfig {
+    fn default() -> Self {
+        InvariantsConfig {
+            enabled: default_invariants_enabled(),
+            interval_seconds: default_invariants_interval_seconds(),
+        }
+    }
+}
+
+fn default_invariants_enabled() -> bool {
+    true
+}
+
+fn default_invariants_interval_seconds() -> u64 {
+    300
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct BackupConfig {
+    // Directory where backups requested over gRPC are written, one subdirectory each.
+    #[serde(default = "default_backup_directory")]
+    pub directory: String,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            directory: default_backup_directory(),
+        }
+    }
+}
+
+fn default_backup_directory() -> String {
+    "backups".to_string()
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ReplicationConfig {
+    #[serde(default)]
+    pub role: ReplicationRole,
+    // gRPC endpoint of the primary, e.g. "http://127.0.0.1:50051". Required for followers.
+    #[serde(default)]
+    pub primary_url: String,
+    // Delay before reconnecting to the primary after the replication stream was interrupted.
+    #[serde(default = "default_replication_reconnect_interval_ms")]
+    pub reconnect_interval_ms: u64,
+    // Key of a client with the replicate scope, when the primary requires authentication.
+    #[serde(default)]
+    pub api_key: String,
+    // Used to connect to an https primary_url.
+    #[serde(default)]
+    pub tls: Option<ClientTlsConfig>,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        ReplicationConfig {
+            role: ReplicationRole::default(),
+            primary_url: String::new(),
+            reconnect_interval_ms: default_replication_reconnect_interval_ms(),
+            api_key: String::new(),
+            tls: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicationRole {
+    // Accepts writes and streams its commit log to followers.
+    #[default]
+    Primary,
+    // Replicates the commit log of the primary and only serves reads until promoted.
+    Follower,
+}
+
+fn default_replication_reconnect_interval_ms() -> u64 {
+    1_000
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ClusterConfig {
+    // Commits every write through a Raft log replicated to the other members of the cluster.
+    #[serde(default)]
+    pub enabled: bool,
+    // ID of this node, unique within the cluster.
+    #[serde(default = "default_cluster_node_id")]
+    pub node_id: u64,
+    // Every other member of the cluster. A cluster of 2n + 1 nodes survives n failures.
+    #[serde(default)]
+    pub peers: Vec<ClusterPeer>,
+    // Shared by every member and sent with each Raft message. Members reject messages without
+    // it, as they carry log entries and writes already authorized by the sending node.
+    #[serde(default)]
+    pub secret: String,
+    // Interval between the leader's heartbeats to followers.
+    #[serde(default = "default_cluster_heartbeat_interval_ms")]
+    pub heartbeat_interval_ms: u64,
+    // A follower hearing from no leader for a random time in this range starts an election.
+    #[serde(default = "default_cluster_election_timeout_min_ms")]
+    pub election_timeout_min_ms: u64,
+    #[serde(default = "default_cluster_election_timeout_max_ms")]
+    pub election_timeout_max_ms: u64,
+    // Applied log entries kept before the log is compacted into a snapshot of the ledger.
+    #[serde(default = "default_cluster_snapshot_threshold")]
+    pub snapshot_threshold: u64,
+    // Timeout of a single request to another node.
+    #[serde(default = "default_cluster_rpc_timeout_ms")]
+    pub rpc_timeout_ms: u64,
+    // How long a write waits for its log entry to be committed before failing, including
+    // when forwarded to the leader.
+    #[serde(default = "default_cluster_proposal_timeout_ms")]
+    pub proposal_timeout_ms: u64,
+    // Used to connect to https peers.
+    #[serde(default)]
+    pub tls: Option<ClientTlsConfig>,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        ClusterConfig {
+            enabled: false,
+            node_id: default_cluster_node_id(),
+            peers: vec![],
+            secret: String::new(),
+            heartbeat_interval_ms: default_cluster_heartbeat_interval_ms(),
+            election_timeout_min_ms: default_cluster_election_timeout_min_ms(),
+            election_timeout_max_ms: default_cluster_election_timeout_max_ms(),
+            snapshot_threshold: default_cluster_snapshot_threshold(),
+            rpc_timeout_ms: default_cluster_rpc_timeout_ms(),
+            proposal_timeout_ms: default_cluster_proposal_timeout_ms(),
+            tls: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ClusterPeer {
+    pub id: u64,
+    // gRPC endpoint of the peer, e.g. "http://10.0.0.2:50051".
+    pub url: String,
+}
+
+fn default_cluster_node_id() -> u64 {
+    1
+}
+
+fn default_cluster_heartbeat_interval_ms() -> u64 {
+    100
+}
+
+fn default_cluster_election_timeout_min_ms() -> u64 {
+    500
+}
+
+fn default_cluster_election_timeout_max_ms() -> u64 {
+    1_000
+}
+
+fn default_cluster_snapshot_threshold() -> u64 {
+    10_000
+}
+
+fn default_cluster_rpc_timeout_ms() -> u64 {
+    1_000
+}
+
+fn default_cluster_proposal_timeout_ms() -> u64 {
+    5_000
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct AuditConfig {
+    // Whether committed transactions are appended to the hash-chained audit log, stored in the
+    // persistence database.
+    #[serde(default)]
+    pub enabled: bool,
+    // A signed checkpoint of the chain is stored every this many entries.
+    #[serde(default = "default_audit_checkpoint_interval")]
+    pub checkpoint_interval: u64,
+    // HMAC key signing the checkpoints. Required when the audit log is enabled.
+    #[serde(default)]
+    pub checkpoint_key: String,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            enabled: false,
+            checkpoint_interval: default_audit_checkpoint_interval(),
+            checkpoint_key: String::new(),
+        }
+    }
+}
+
+fn default_audit_checkpoint_interval() -> u64 {
+    1000
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct ReceiptsConfig {
+    // Whether committed transfers and deposits are answered with a signed receipt.
+    #[serde(default)]
+    pub enabled: bool,
+    // Hex encoded 32 byte Ed25519 secret key. Generate one with `admin generate-receipt-key`.
+    #[serde(default)]
+    pub signing_key: String,
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+pub struct AuthConfig {
+    // Whether gRPC clients must authenticate. Internal cluster traffic is not affected.
+    #[serde(default)]
+    pub enabled: bool,
+    // HMAC secret of the HS256 tokens accepted as bearer tokens. Tokens are rejected when empty.
+    #[serde(default)]
+    pub jwt_secret: String,
+    #[serde(default)]
+    pub clients: Vec<AuthClient>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct AuthClient {
+    // Subject of the client's tokens.
+    pub id: String,
+    #[serde(default)]
+    pub scopes: Vec<Scope>,
+    // Accounts the client may debit besides the ones it creates.
+    #[serde(default)]
+    pub accounts: Vec<Uuid>,
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+    // Common names of the client certificates identifying the client, when the gRPC or HTTP
+    // server verifies client certificates.
+    #[serde(default)]
+    pub certificate_subjects: Vec<String>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct LimitsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Requests per second allowed per client, with bursts of client_burst. 0 disables the limit.
+    #[serde(default = "default_limits_client_rate")]
+    pub client_rate: f64,
+    #[serde(default = "default_limits_client_burst")]
+    pub client_burst: u32,
+    // Transfers and deposits per second allowed per debited or credited account. 0 disables
+    // the limit.
+    #[serde(default = "default_limits_account_rate")]
+    pub account_rate: f64,
+    #[serde(default = "default_limits_account_burst")]
+    pub account_burst: u32,
+    // Requests allowed in flight. The limit is lowered, down to min_concurrent_requests, while
+    // requests take longer than shed_latency_ms.
+    #[serde(default = "default_limits_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+    #[serde(default = "default_limits_min_concurrent_requests")]
+    pub min_concurrent_requests: usize,
+    #[serde(default = "default_limits_shed_latency_ms")]
+    pub shed_latency_ms: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            enabled: false,
+            client_rate: default_limits_client_rate(),
+            client_burst: default_limits_client_burst(),
+            account_rate: default_limits_account_rate(),
+            account_burst: default_limits_account_burst(),
+            max_concurrent_requests: default_limits_max_concurrent_requests(),
+            min_concurrent_requests: default_limits_min_concurrent_requests(),
+            shed_latency_ms: default_limits_shed_latency_ms(),
+        }
+    }
+}
+
+fn default_limits_client_rate() -> f64 {
+    500.0
+}
+
+fn default_limits_client_burst() -> u32 {
+    1000
+}
+
+fn default_limits_account_rate() -> f64 {
+    20.0
+}
+
+fn default_limits_account_burst() -> u32 {
+    40
+}
+
+fn default_limits_max_concurrent_requests() -> usize {
+    1024
+}
+
+fn default_limits_min_concurrent_requests() -> usize {
+    16
+}
+
+fn default_limits_shed_latency_ms() -> u64 {
+    500
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct RiskConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // TOML file holding the [[rules]] evaluated before every transfer.
+    #[serde(default = "default_risk_rules_path")]
+    pub rules_path: String,
+}
+
+impl Default for RiskConfig {
+    fn default() -> Self {
+        RiskConfig {
+            enabled: false,
+            rules_path: default_risk_rules_path(),
+        }
+    }
+}
+
+fn default_risk_rules_path() -> String {
+    "risk-rules.toml".to_string()
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct DisputesConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // Account holding the funds blocked by open disputes. Created on startup if it does not
+    // exist.
+    #[serde(default)]
+    pub escrow_account_id: Uuid,
+    // Disputes not concluded within this time are released to the payee.
+    #[serde(default = "default_analysis_deadline_hours")]
+    pub analysis_deadline_hours: u64,
+    #[serde(default = "default_dispute_check_interval_seconds")]
+    pub check_interval_seconds: u64,
+}
+
+impl Default for DisputesConfig {
+    fn default() -> Self {
+        DisputesConfig {
+            enabled: false,
+            escrow_account_id: Uuid::nil(),
+            analysis_deadline_hours: default_analysis_deadline_hours(),
+            check_interval_seconds: default_dispute_check_interval_seconds(),
+        }
+    }
+}
+
+fn default_analysis_deadline_hours() -> u64 {
+    7 * 24
+}
+
+fn default_dispute_check_interval_seconds() -> u64 {
+    60
 }
@@ -0,0 +1,28 @@
+use {
+    crate::{disputes::DisputeStatus, models::DisputeAction},
+    thiserror::Error,
+    uuid::Uuid,
+};
+
+#[derive(Debug, Error)]
+pub enum DisputeError {
+    #[error("Disputes are disabled")]
+    Disabled,
+    #[error("Transaction {0} is not a committed transfer")]
+    NotATransfer(Uuid),
+    #[error("Disputed amount {amount} must be between 1 and the transferred {transferred}")]
+    InvalidAmount { amount: u64, transferred: u64 },
+    #[error("Transaction {0} is already disputed")]
+    AlreadyDisputed(Uuid),
+    #[error("Unknown dispute {0}")]
+    UnknownDispute(Uuid),
+    #[error("Cannot {action:?} a dispute that is {status}")]
+    InvalidTransition {
+        status: DisputeStatus,
+        action: DisputeAction,
+    },
+    #[error("Dispute {0} has not reached its deadline")]
+    DeadlineNotReached(Uuid),
+    #[error("Dispute store error: {0}")]
+    Store(#[from] rusqlite::Error),
+}
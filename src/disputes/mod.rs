@@ -0,0 +1,347 @@
+//! Special return mechanism (MED) for disputed transfers.
+//! Opening a dispute against a committed transfer blocks up to the disputed amount of the
+//! payee's balance by moving it to an escrow account, so the funds can no longer be spent. The
+//! dispute may then go under analysis, and is concluded either by returning the blocked funds
+//! to the payer or by releasing them to the payee. Disputes not concluded by their deadline are
+//! released by a background task. Every step is an instruction, so it is committed, logged and
+//! replicated like any other transaction.
+
+pub mod error;
+pub mod store;
+
+use {
+    crate::{
+        disputes::{error::DisputeError, store::DisputeStore},
+        models::{
+            DisputeAction, Instruction, OpenDisputeInstruction, Posting, Transaction,
+            TransactionStatus, TransferInstruction, UpdateDisputeInstruction,
+        },
+        raft::{RaftNode, Role},
+        replication::ReplicationState,
+        transaction_processor::{TransactionProcessor, interface::TransactionProcessorInterface},
+    },
+    chrono::{DateTime, Duration, Utc},
+    serde::{Deserialize, Serialize},
+    std::{fmt, sync::Arc},
+    tokio::sync::broadcast,
+    tracing::{error, info, warn},
+    uuid::Uuid,
+};
+
+// Namespace of the IDs of expiry instructions, derived from dispute IDs so that every node
+// expiring the same dispute submits the same transaction.
+const EXPIRY_ID_NAMESPACE: Uuid = Uuid::from_u128(0x0b9e_4d27_71c5_4f3a_a8e6_5d12_c9f0_3e87);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeStatus {
+    /// Funds are blocked, awaiting analysis.
+    Open,
+    UnderAnalysis,
+    /// The blocked funds were returned to the payer.
+    Returned,
+    /// The blocked funds were released to the payee.
+    Released,
+    /// The deadline passed and the blocked funds were released to the payee.
+    Expired,
+}
+
+impl DisputeStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            DisputeStatus::Open => "open",
+            DisputeStatus::UnderAnalysis => "under_analysis",
+            DisputeStatus::Returned => "returned",
+            DisputeStatus::Released => "released",
+            DisputeStatus::Expired => "expired",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "open" => Some(DisputeStatus::Open),
+            "under_analysis" => Some(DisputeStatus::UnderAnalysis),
+            "returned" => Some(DisputeStatus::Returned),
+            "released" => Some(DisputeStatus::Released),
+            "expired" => Some(DisputeStatus::Expired),
+            _ => None,
+        }
+    }
+
+    pub fn is_concluded(self) -> bool {
+        !matches!(self, DisputeStatus::Open | DisputeStatus::UnderAnalysis)
+    }
+}
+
+impl fmt::Display for DisputeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dispute {
+    /// ID of the transaction that opened the dispute.
+    pub id: Uuid,
+    pub disputed_transaction_id: Uuid,
+    pub payer_account_id: Uuid,
+    pub payee_account_id: Uuid,
+    /// Amount claimed by the payer.
+    pub amount: u64,
+    /// Part of the amount the payee still held when the dispute was opened.
+    pub blocked_amount: u64,
+    pub status: DisputeStatus,
+    pub opened_at: DateTime<Utc>,
+    pub deadline: DateTime<Utc>,
+}
+
+pub struct DisputeManager {
+    pub escrow_account_id: Uuid,
+    /// Time given to conclude a dispute before it expires.
+    pub analysis_deadline: Duration,
+    pub store: Arc<DisputeStore>,
+}
+
+impl DisputeManager {
+    pub fn new(
+        escrow_account_id: Uuid,
+        analysis_deadline: Duration,
+        store: Arc<DisputeStore>,
+    ) -> Self {
+        DisputeManager {
+            escrow_account_id,
+            analysis_deadline,
+            store,
+        }
+    }
+
+    /// The dispute opened by a transaction against `transfer`, and the postings blocking as
+    /// much of the disputed amount as the payee's `payee_balance` covers.
+    pub fn open(
+        &self,
+        dispute_id: Uuid,
+        opened_at: DateTime<Utc>,
+        instruction: &OpenDisputeInstruction,
+        transfer: &TransferInstruction,
+        payee_balance: u64,
+    ) -> Result<(Dispute, Vec<Posting>), DisputeError> {
+        if instruction.amount == 0 || instruction.amount > transfer.amount {
+            return Err(DisputeError::InvalidAmount {
+                amount: instruction.amount,
+                transferred: transfer.amount,
+            });
+        }
+
+        let dispute = Dispute {
+            id: dispute_id,
+            disputed_transaction_id: instruction.disputed_transaction_id,
+            payer_account_id: transfer.source_account_id,
+            payee_account_id: transfer.destination_account_id,
+            amount: instruction.amount,
+            blocked_amount: instruction.amount.min(payee_balance),
+            status: DisputeStatus::Open,
+            opened_at,
+            deadline: opened_at + self.analysis_deadline,
+        };
+        let postings = self.escrow_postings(dispute.payee_account_id, dispute.blocked_amount, true);
+        Ok((dispute, postings))
+    }
+
+    /// The status a dispute moves to on `action` at time `at`, and the postings moving its
+    /// blocked funds out of escrow when the action concludes it.
+    pub fn update(
+        &self,
+        dispute: &Dispute,
+        action: DisputeAction,
+        at: DateTime<Utc>,
+    ) -> Result<(DisputeStatus, Vec<Posting>), DisputeError> {
+        let active = !dispute.status.is_concluded();
+        let status = match action {
+            DisputeAction::StartAnalysis if dispute.status == DisputeStatus::Open => {
+                DisputeStatus::UnderAnalysis
+            }
+            DisputeAction::Return if active => DisputeStatus::Returned,
+            DisputeAction::Release if active => DisputeStatus::Released,
+            DisputeAction::Expire if active => {
+                if at < dispute.deadline {
+                    return Err(DisputeError::DeadlineNotReached(dispute.id));
+                }
+                DisputeStatus::Expired
+            }
+            _ => {
+                return Err(DisputeError::InvalidTransition {
+                    status: dispute.status,
+                    action,
+                });
+            }
+        };
+
+        let postings = match status {
+            DisputeStatus::Returned => {
+                self.escrow_postings(dispute.payer_account_id, dispute.blocked_amount, false)
+            }
+            DisputeStatus::Released | DisputeStatus::Expired => {
+                self.escrow_postings(dispute.payee_account_id, dispute.blocked_amount, false)
+            }
+            DisputeStatus::Open | DisputeStatus::UnderAnalysis => vec![],
+        };
+        Ok((status, postings))
+    }
+
+    /// Moves `amount` from the account into escrow, or out of escrow into the account.
+    fn escrow_postings(&self, account_id: Uuid, amount: u64, into_escrow: bool) -> Vec<Posting> {
+        if amount == 0 {
+            return vec![];
+        }
+        if into_escrow {
+            vec![
+                Posting::debit(account_id, amount),
+                Posting::credit(self.escrow_account_id, amount),
+            ]
+        } else {
+            vec![
+                Posting::debit(self.escrow_account_id, amount),
+                Posting::credit(account_id, amount),
+            ]
+        }
+    }
+}
+
+/// The instruction expiring a dispute, with an ID derived from the dispute's.
+pub fn expiry_transaction(dispute_id: Uuid, now: DateTime<Utc>) -> Transaction {
+    Transaction {
+        id: Uuid::new_v5(&EXPIRY_ID_NAMESPACE, dispute_id.as_bytes()),
+        instruction: Instruction::UpdateDispute(UpdateDisputeInstruction {
+            dispute_id,
+            action: DisputeAction::Expire,
+        }),
+        status: TransactionStatus::Pending,
+        timestamp: now,
+    }
+}
+
+/// Periodically expires the disputes past their deadline. Only the cluster leader does so when
+/// clustered, and replication followers leave it to their primary.
+pub async fn start_deadline_monitor(
+    processor: Arc<TransactionProcessor>,
+    raft: Option<Arc<RaftNode>>,
+    replication: Arc<ReplicationState>,
+    interval_seconds: u64,
+    mut shutdown_receiver: broadcast::Receiver<()>,
+) {
+    let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval_seconds.max(1)));
+    info!("Dispute deadline monitor initialized");
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let leads = raft.as_ref().is_none_or(|raft| raft.status().role == Role::Leader);
+                if leads && !replication.is_follower() {
+                    expire_overdue(&processor, raft.as_ref()).await;
+                }
+            }
+            _ = shutdown_receiver.recv() => {
+                info!("Shutting down dispute deadline monitor...");
+                break;
+            }
+        }
+    }
+}
+
+async fn expire_overdue(processor: &Arc<TransactionProcessor>, raft: Option<&Arc<RaftNode>>) {
+    let Some(disputes) = processor.disputes() else {
+        return;
+    };
+    let now = Utc::now();
+    let overdue = match disputes.store.overdue(now) {
+        Ok(overdue) => overdue,
+        Err(e) => {
+            error!("Failed to list overdue disputes: {}", e);
+            return;
+        }
+    };
+
+    for dispute in overdue {
+        let transaction = expiry_transaction(dispute.id, now);
+        let result = match raft {
+            Some(raft) => raft
+                .propose(transaction, None)
+                .await
+                .map_err(|e| e.to_string()),
+            None => {
+                let processor = processor.clone();
+                tokio::task::spawn_blocking(move || processor.process_transaction(transaction))
+                    .await
+                    .map_err(|e| e.to_string())
+                    .and_then(|result| result.map_err(|e| e.to_string()))
+            }
+        };
+        match result {
+            Ok(_) => warn!(
+                "Dispute {} expired, released {} to {}",
+                dispute.id, dispute.blocked_amount, dispute.payee_account_id
+            ),
+            Err(e) => error!("Failed to expire dispute {}: {}", dispute.id, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overdue_disputes_expire_to_the_payee() {
+        let store = Arc::new(DisputeStore::open(":memory:").unwrap());
+        let escrow_id = Uuid::new_v4();
+        let manager = DisputeManager::new(escrow_id, Duration::hours(24), store.clone());
+        let transfer = TransferInstruction {
+            source_account_id: Uuid::new_v4(),
+            destination_account_id: Uuid::new_v4(),
+            amount: 100,
+        };
+        let opened_at = Utc::now() - Duration::hours(25);
+        let (dispute, postings) = manager
+            .open(
+                Uuid::new_v4(),
+                opened_at,
+                &OpenDisputeInstruction {
+                    disputed_transaction_id: Uuid::new_v4(),
+                    amount: 100,
+                },
+                &transfer,
+                60,
+            )
+            .unwrap();
+        assert_eq!(dispute.blocked_amount, 60);
+        assert_eq!(postings[1], Posting::credit(escrow_id, 60));
+        store.insert(&dispute).unwrap();
+
+        let overdue = store.overdue(Utc::now()).unwrap();
+        assert_eq!(overdue.len(), 1);
+        assert_eq!(overdue[0].id, dispute.id);
+        assert!(store.overdue(opened_at).unwrap().is_empty());
+
+        assert!(matches!(
+            manager.update(&dispute, DisputeAction::Expire, opened_at),
+            Err(DisputeError::DeadlineNotReached(_))
+        ));
+        let (status, postings) = manager
+            .update(&dispute, DisputeAction::Expire, Utc::now())
+            .unwrap();
+        assert_eq!(status, DisputeStatus::Expired);
+        assert_eq!(
+            postings,
+            vec![
+                Posting::debit(escrow_id, 60),
+                Posting::credit(transfer.destination_account_id, 60),
+            ]
+        );
+
+        // Every node derives the same expiry transaction.
+        assert_eq!(
+            expiry_transaction(dispute.id, Utc::now()).id,
+            expiry_transaction(dispute.id, opened_at).id
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use {
+    crate::disputes::{Dispute, DisputeStatus, error::DisputeError},
+    chrono::{DateTime, SecondsFormat, Utc},
+    rusqlite::{Connection, ErrorCode, OptionalExtension, Row, params},
+    std::sync::{Mutex, MutexGuard},
+    uuid::Uuid,
+};
+
+const COLUMNS: &str = "id, disputed_transaction_id, payer_account_id, payee_account_id, amount, blocked_amount, status, opened_at, deadline";
+
+/// SQLite-backed disputes. Uses its own connection, like the webhook store, so disputes
+/// survive restarts whatever the persistence backend.
+pub struct DisputeStore {
+    conn: Mutex<Connection>,
+}
+
+impl DisputeStore {
+    pub fn open(db_path: &str) -> Result<Self, DisputeError> {
+        let conn = Connection::open(db_path)?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        // Timestamps are fixed-width UTC RFC 3339, so they sort chronologically.
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS disputes (
+                id TEXT PRIMARY KEY,
+                disputed_transaction_id TEXT NOT NULL UNIQUE,
+                payer_account_id TEXT NOT NULL,
+                payee_account_id TEXT NOT NULL,
+                amount INTEGER NOT NULL,
+                blocked_amount INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                opened_at TEXT NOT NULL,
+                deadline TEXT NOT NULL
+            )",
+            [],
+        )?;
+        Ok(DisputeStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Stores a new dispute. A transaction can only be disputed once.
+    pub fn insert(&self, dispute: &Dispute) -> Result<(), DisputeError> {
+        let inserted = self.conn().execute(
+            &format!(
+                "INSERT INTO disputes ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"
+            ),
+            params![
+                dispute.id.to_string(),
+                dispute.disputed_transaction_id.to_string(),
+                dispute.payer_account_id.to_string(),
+                dispute.payee_account_id.to_string(),
+                dispute.amount as i64,
+                dispute.blocked_amount as i64,
+                dispute.status.name(),
+                timestamp(dispute.opened_at),
+                timestamp(dispute.deadline),
+            ],
+        );
+        match inserted {
+            Ok(_) => Ok(()),
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == ErrorCode::ConstraintViolation =>
+            {
+                Err(DisputeError::AlreadyDisputed(
+                    dispute.disputed_transaction_id,
+                ))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Removes a dispute whose opening could not be committed.
+    pub fn remove(&self, id: Uuid) -> Result<(), DisputeError> {
+        self.conn().execute(
+            "DELETE FROM disputes WHERE id = ?1",
+            params![id.to_string()],
+        )?;
+        Ok(())
+    }
+
+    pub fn get(&self, id: Uuid) -> Result<Option<Dispute>, DisputeError> {
+        Ok(self
+            .conn()
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM disputes WHERE id = ?1"),
+                params![id.to_string()],
+                dispute,
+            )
+            .optional()?)
+    }
+
+    /// Moves a dispute from `from` to `to`. Returns false if it is no longer in `from`, e.g.
+    /// because a concurrent update concluded it first.
+    pub fn transition(
+        &self,
+        id: Uuid,
+        from: DisputeStatus,
+        to: DisputeStatus,
+    ) -> Result<bool, DisputeError> {
+        let updated = self.conn().execute(
+            "UPDATE disputes SET status = ?2 WHERE id = ?1 AND status = ?3",
+            params![id.to_string(), to.name(), from.name()],
+        )?;
+        Ok(updated == 1)
+    }
+
+    /// Disputes not concluded yet, earliest deadline first.
+    pub fn active(&self) -> Result<Vec<Dispute>, DisputeError> {
+        self.query_active("", &[])
+    }
+
+    /// Disputes not concluded by their deadline.
+    pub fn overdue(&self, now: DateTime<Utc>) -> Result<Vec<Dispute>, DisputeError> {
+        self.query_active("AND deadline <= ?3", &[&timestamp(now)])
+    }
+
+    fn query_active(
+        &self,
+        condition: &str,
+        extra: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<Dispute>, DisputeError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {COLUMNS} FROM disputes WHERE status IN (?1, ?2) {condition} ORDER BY deadline"
+        ))?;
+        let open = DisputeStatus::Open.name();
+        let under_analysis = DisputeStatus::UnderAnalysis.name();
+        let mut values: Vec<&dyn rusqlite::ToSql> = vec![&open, &under_analysis];
+        values.extend_from_slice(extra);
+        Ok(stmt
+            .query_map(values.as_slice(), dispute)?
+            .collect::<Result<_, _>>()?)
+    }
+}
+
+fn timestamp(value: DateTime<Utc>) -> String {
+    value.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn uuid(row: &Row, column: usize) -> rusqlite::Result<Uuid> {
+    let value: String = row.get(column)?;
+    Uuid::parse_str(&value).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, e.into())
+    })
+}
+
+fn datetime(row: &Row, column: usize) -> rusqlite::Result<DateTime<Utc>> {
+    let value: String = row.get(column)?;
+    DateTime::parse_from_rfc3339(&value)
+        .map(|value| value.with_timezone(&Utc))
+        .map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, e.into())
+        })
+}
+
+fn dispute(row: &Row) -> rusqlite::Result<Dispute> {
+    let status: String = row.get(6)?;
+    Ok(Dispute {
+        id: uuid(row, 0)?,
+        disputed_transaction_id: uuid(row, 1)?,
+        payer_account_id: uuid(row, 2)?,
+        payee_account_id: uuid(row, 3)?,
+        amount: row.get::<_, i64>(4)? as u64,
+        blocked_amount: row.get::<_, i64>(5)? as u64,
+        status: DisputeStatus::parse(&status).ok_or_else(|| {
+            rusqlite::Error::FromSqlConversionFailure(
+                6,
+                rusqlite::types::Type::Text,
+                format!("invalid dispute status {status:?}").into(),
+            )
+        })?,
+        opened_at: datetime(row, 7)?,
+        deadline: datetime(row, 8)?,
+    })
+}
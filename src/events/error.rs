@@ -0,0 +1,25 @@
+use {
+    crate::{ledger::error::LedgerError, persistence::error::PersistenceError},
+    thiserror::Error,
+};
+
+#[derive(Debug, Error)]
+pub enum EventLogError {
+    #[error("Failed to persist the transaction at sequence {sequence}: {source}")]
+    Persistence {
+        sequence: u64,
+        source: PersistenceError,
+    },
+    #[error("The event log stopped accepting commits after failing to persist one")]
+    Halted,
+    #[error("Expected sequence {expected} in the event log but received {found}")]
+    SequenceGap { expected: u64, found: u64 },
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("Expected sequence {expected} in the event log but found {found}")]
+    SequenceGap { expected: u64, found: u64 },
+    #[error("Failed to replay the transaction at sequence {sequence}: {source}")]
+    Ledger { sequence: u64, source: LedgerError },
+}
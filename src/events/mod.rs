@@ -0,0 +1,607 @@
+//! Ordered log of committed transactions.
+//! Every state-changing transaction committed by the processor is appended with a
+//! monotonically increasing sequence number and broadcast to live subscribers. Transactions
+//! touching different accounts are applied to the ledger concurrently, while those sharing an
+//! account are numbered in the order they were applied to it. Each entry is persisted as part
+//! of its commit, entries completed together being written in one batch, so sequence numbers
+//! are never reused after a restart and subscribers can resume from any of them.
+
+pub mod error;
+pub mod history;
+pub mod replay;
+
+use {
+    crate::{
+        events::error::EventLogError,
+        fees::FeeCharge,
+        metrics::COMMITTED_EVENTS_TOTAL,
+        models::{Account, Instruction, InstructionKind, Posting, PostingKind},
+        persistence::SharedBackend,
+    },
+    chrono::{DateTime, Utc},
+    dashmap::DashMap,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::BTreeMap,
+        sync::{
+            Arc, Condvar, Mutex, MutexGuard, RwLock,
+            atomic::{AtomicBool, AtomicU64, Ordering},
+        },
+    },
+    tokio::sync::broadcast,
+    tracing::error,
+    uuid::Uuid,
+};
+
+const DEFAULT_BROADCAST_CAPACITY: usize = 1024;
+const ACCOUNT_LOCK_STRIPES: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommittedTransaction {
+    pub sequence: u64,
+    pub transaction_id: Uuid,
+    pub instruction: Instruction,
+    pub postings: Vec<Posting>,
+    pub fee: Option<FeeCharge>,
+    // Set for account creations, whose account ID is only known after the commit.
+    pub created_account_id: Option<Uuid>,
+    // Submission time of the transaction, the same on every node replicating it, unlike the
+    // commit time. The Unix epoch for transactions logged before it was recorded.
+    #[serde(default)]
+    pub submitted_at: DateTime<Utc>,
+    pub committed_at: DateTime<Utc>,
+}
+
+impl CommittedTransaction {
+    /// Every account the transaction touched, without duplicates.
+    pub fn accounts(&self) -> Vec<Uuid> {
+        let mut accounts: Vec<Uuid> = self.created_account_id.into_iter().collect();
+        for posting in &self.postings {
+            if !accounts.contains(&posting.account_id) {
+                accounts.push(posting.account_id);
+            }
+        }
+        accounts
+    }
+
+    /// Net amount credited to the account by the transaction, negative when it was debited.
+    pub fn net_amount(&self, account_id: Uuid) -> i128 {
+        self.postings
+            .iter()
+            .filter(|p| p.account_id == account_id)
+            .map(|p| match p.kind {
+                PostingKind::Debit => -i128::from(p.amount),
+                PostingKind::Credit => i128::from(p.amount),
+            })
+            .sum()
+    }
+
+    /// Whether the transaction moved money in or out of, or created, the given account.
+    pub fn touches_account(&self, account_id: Uuid) -> bool {
+        self.created_account_id == Some(account_id)
+            || self.postings.iter().any(|p| p.account_id == account_id)
+    }
+}
+
+/// Subscriber-side filter. Empty filters match every event.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    pub account_id: Option<Uuid>,
+    pub instruction_kinds: Vec<InstructionKind>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &CommittedTransaction) -> bool {
+        self.account_id.is_none_or(|id| event.touches_account(id))
+            && (self.instruction_kinds.is_empty()
+                || self.instruction_kinds.contains(&event.instruction.kind()))
+    }
+}
+
+pub struct Subscription {
+    /// Entries already in the log from the requested sequence on.
+    pub backlog: Vec<Arc<CommittedTransaction>>,
+    /// Yields exactly the entries appended after the backlog, with no gap or overlap.
+    pub receiver: broadcast::Receiver<Arc<CommittedTransaction>>,
+    /// Sequence number of the last entry in the log when subscribing.
+    pub last_sequence: u64,
+}
+
+/// An entry touching an account, with the account balance right after it. Together they act
+/// as balance checkpoints, so historical balances need no replay.
+#[derive(Debug, Clone, Copy)]
+struct IndexEntry {
+    sequence: u64,
+    committed_at: DateTime<Utc>,
+    balance: u64,
+}
+
+/// Applied transactions waiting to be written through the journal and published.
+#[derive(Default)]
+struct Pending {
+    // Entries following the last published one, by sequence number.
+    ready: BTreeMap<u64, CommittedTransaction>,
+    // Sequence number of the last published entry.
+    published: u64,
+    // Set while a committer writes a batch through the journal on behalf of the others.
+    flushing: bool,
+}
+
+pub struct EventLog {
+    entries: RwLock<Vec<Arc<CommittedTransaction>>>,
+    // Entries touching each account, in ascending sequence order.
+    account_index: DashMap<Uuid, Vec<IndexEntry>>,
+    // Sequence number of each committed transaction.
+    transaction_index: DashMap<Uuid, u64>,
+    // Sequence number of the next transaction applied.
+    next_sequence: AtomicU64,
+    // Striped by account ID. Held from applying a transaction to numbering it, for the
+    // accounts it touches, so the log orders the transactions of an account as they were
+    // applied to it.
+    account_locks: Box<[Mutex<()>]>,
+    // Held shared by every commit in flight, and exclusively to read between commits.
+    in_flight: RwLock<()>,
+    pending: Mutex<Pending>,
+    // Notified whenever a batch was published, or failed to be.
+    flushed: Condvar,
+    // Held while appending published entries and broadcasting them.
+    publish_lock: Mutex<()>,
+    // Every entry is written through it before being broadcast.
+    journal: Option<SharedBackend>,
+    // Set once the journal fails: the ledger may then hold a transaction the log lost.
+    halted: AtomicBool,
+    sender: broadcast::Sender<Arc<CommittedTransaction>>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new(vec![], DEFAULT_BROADCAST_CAPACITY)
+    }
+}
+
+impl EventLog {
+    /// Creates a log from previously persisted entries, which must be sorted by sequence.
+    pub fn new(entries: Vec<CommittedTransaction>, broadcast_capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(broadcast_capacity.max(1));
+
+        let log = EventLog {
+            entries: RwLock::new(Vec::new()),
+            account_index: DashMap::new(),
+            transaction_index: DashMap::new(),
+            next_sequence: AtomicU64::new(entries.last().map_or(0, |last| last.sequence) + 1),
+            account_locks: (0..ACCOUNT_LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
+            in_flight: RwLock::new(()),
+            pending: Mutex::new(Pending {
+                published: entries.last().map_or(0, |last| last.sequence),
+                ..Default::default()
+            }),
+            flushed: Condvar::new(),
+            publish_lock: Mutex::new(()),
+            journal: None,
+            halted: AtomicBool::new(false),
+            sender,
+        };
+        for entry in &entries {
+            log.index(entry);
+        }
+        *log.entries.write().unwrap_or_else(|e| e.into_inner()) =
+            entries.into_iter().map(Arc::new).collect();
+
+        log
+    }
+
+    /// Persists every entry through `journal` as part of its commit.
+    pub fn with_journal(mut self, journal: SharedBackend) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Whether a journal write failed. The log then refuses commits until the node restarts.
+    pub fn is_halted(&self) -> bool {
+        self.halted.load(Ordering::SeqCst)
+    }
+
+    fn index(&self, entry: &CommittedTransaction) {
+        self.transaction_index
+            .insert(entry.transaction_id, entry.sequence);
+        for account_id in entry.accounts() {
+            let mut index = self.account_index.entry(account_id).or_default();
+            let balance = index.last().map_or(0, |last| last.balance);
+            index.push(IndexEntry {
+                sequence: entry.sequence,
+                committed_at: entry.committed_at,
+                // Entries are logged in the order the ledger applied them, so they never take
+                // a balance out of range.
+                balance: u64::try_from(i128::from(balance) + entry.net_amount(account_id))
+                    .unwrap_or_default(),
+            });
+        }
+    }
+
+    /// Applies a transaction with `apply` and numbers it with the next sequence number while
+    /// holding the locks of the accounts it touches, then stores and broadcasts it once every
+    /// transaction numbered before it was. Nothing is logged if `apply` fails.
+    pub fn commit<E: From<EventLogError>>(
+        &self,
+        transaction_id: Uuid,
+        submitted_at: DateTime<Utc>,
+        instruction: Instruction,
+        postings: Vec<Posting>,
+        fee: Option<FeeCharge>,
+        apply: impl FnOnce() -> Result<(), E>,
+    ) -> Result<Arc<CommittedTransaction>, E> {
+        let created_account_id = matches!(instruction, Instruction::CreateAccount(_))
+            .then(|| Account::id_for(transaction_id));
+        let _in_flight = self.in_flight.read().unwrap_or_else(|e| e.into_inner());
+        if self.is_halted() {
+            return Err(EventLogError::Halted.into());
+        }
+
+        // Commit times are taken under the locks too, so they follow the order of the
+        // transactions of each account.
+        let (sequence, committed_at) = {
+            let accounts = postings.iter().map(|posting| posting.account_id);
+            let _accounts = self.lock_accounts(accounts.chain(created_account_id));
+            apply()?;
+            (
+                self.next_sequence.fetch_add(1, Ordering::SeqCst),
+                Utc::now(),
+            )
+        };
+        let event = CommittedTransaction {
+            sequence,
+            transaction_id,
+            instruction,
+            postings,
+            fee,
+            created_account_id,
+            submitted_at,
+            committed_at,
+        };
+        Ok(self.publish(event)?)
+    }
+
+    /// Appends a transaction submitted now that changes nothing outside the log.
+    pub fn append(
+        &self,
+        transaction_id: Uuid,
+        instruction: Instruction,
+        postings: Vec<Posting>,
+        fee: Option<FeeCharge>,
+    ) -> Result<Arc<CommittedTransaction>, EventLogError> {
+        self.commit(
+            transaction_id,
+            Utc::now(),
+            instruction,
+            postings,
+            fee,
+            || Ok(()),
+        )
+    }
+
+    /// Stores a transaction committed by the primary as is, keeping its sequence number and
+    /// commit time, applies it with `apply` and broadcasts it. Fails if the transaction does
+    /// not directly follow the last entry. Replicated transactions come one at a time from a
+    /// single stream, and are never mixed with local commits.
+    pub fn replicate<E: From<EventLogError>>(
+        &self,
+        event: CommittedTransaction,
+        apply: impl FnOnce(&CommittedTransaction) -> Result<(), E>,
+    ) -> Result<Arc<CommittedTransaction>, E> {
+        let _in_flight = self.in_flight.read().unwrap_or_else(|e| e.into_inner());
+        if self.is_halted() {
+            return Err(EventLogError::Halted.into());
+        }
+
+        let expected = self.next_sequence.load(Ordering::SeqCst);
+        if event.sequence != expected {
+            return Err(EventLogError::SequenceGap {
+                expected,
+                found: event.sequence,
+            }
+            .into());
+        }
+        apply(&event)?;
+        self.next_sequence.store(expected + 1, Ordering::SeqCst);
+        Ok(self.publish(event)?)
+    }
+
+    /// Locks the stripes of the given accounts, in a fixed order so that commits cannot
+    /// deadlock.
+    fn lock_accounts(&self, accounts: impl Iterator<Item = Uuid>) -> Vec<MutexGuard<'_, ()>> {
+        let mut stripes: Vec<usize> = accounts
+            .map(|id| (id.as_u128() % self.account_locks.len() as u128) as usize)
+            .collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        stripes
+            .into_iter()
+            .map(|stripe| {
+                self.account_locks[stripe]
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+            })
+            .collect()
+    }
+
+    /// Queues a numbered entry and waits until it was written through the journal and
+    /// broadcast. The first committer finding the entries following the last published one
+    /// ready writes all of them in one batch, for the others as well.
+    fn publish(
+        &self,
+        event: CommittedTransaction,
+    ) -> Result<Arc<CommittedTransaction>, EventLogError> {
+        let sequence = event.sequence;
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.ready.insert(sequence, event);
+        loop {
+            if pending.published >= sequence {
+                drop(pending);
+                return self.get(sequence).ok_or(EventLogError::Halted);
+            }
+            if self.is_halted() {
+                return Err(EventLogError::Halted);
+            }
+
+            let next = pending.published + 1;
+            if pending.flushing || pending.ready.first_key_value().map(|(s, _)| *s) != Some(next) {
+                pending = self
+                    .flushed
+                    .wait(pending)
+                    .unwrap_or_else(|e| e.into_inner());
+                continue;
+            }
+
+            let mut batch = Vec::new();
+            while let Some(entry) = pending.ready.first_entry() {
+                if *entry.key() != next + batch.len() as u64 {
+                    break;
+                }
+                batch.push(Arc::new(entry.remove()));
+            }
+            let last = next + batch.len() as u64 - 1;
+            pending.flushing = true;
+            drop(pending);
+
+            let flushed = self.flush(&batch);
+            pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            pending.flushing = false;
+            if flushed.is_ok() {
+                pending.published = last;
+            }
+            self.flushed.notify_all();
+            flushed?;
+        }
+    }
+
+    /// Writes consecutive entries through the journal, then stores and broadcasts them. Only
+    /// one batch is flushed at a time.
+    fn flush(&self, batch: &[Arc<CommittedTransaction>]) -> Result<(), EventLogError> {
+        if let Some(journal) = &self.journal {
+            let written = journal
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .append_events(batch);
+            if let Err(source) = written {
+                self.halted.store(true, Ordering::SeqCst);
+                let sequence = batch.first().map_or(0, |event| event.sequence);
+                error!(
+                    "Failed to persist the transactions from sequence {}, refusing further commits: {}",
+                    sequence, source
+                );
+                return Err(EventLogError::Persistence { sequence, source });
+            }
+        }
+
+        let _publish = self.publish_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+        for event in batch {
+            entries.push(event.clone());
+            self.index(event);
+        }
+        drop(entries);
+
+        for event in batch {
+            // No receivers is not an error: events are still kept in the log.
+            let _ = self.sender.send(event.clone());
+            COMMITTED_EVENTS_TOTAL.inc();
+        }
+        Ok(())
+    }
+
+    /// Sequence number of the last committed transaction, or 0 if the log is empty.
+    pub fn last_sequence(&self) -> u64 {
+        self.entries
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .last()
+            .map_or(0, |last| last.sequence)
+    }
+
+    /// Returns every entry with a sequence number greater than or equal to `from_sequence`.
+    pub fn since(&self, from_sequence: u64) -> Vec<Arc<CommittedTransaction>> {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        let start = entries.partition_point(|e| e.sequence < from_sequence);
+        entries[start..].to_vec()
+    }
+
+    /// Returns the entry with the given sequence number.
+    pub fn get(&self, sequence: u64) -> Option<Arc<CommittedTransaction>> {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        entries
+            .binary_search_by_key(&sequence, |e| e.sequence)
+            .ok()
+            .map(|position| entries[position].clone())
+    }
+
+    /// Returns the entry of a committed transaction.
+    pub fn find(&self, transaction_id: Uuid) -> Option<Arc<CommittedTransaction>> {
+        let sequence = *self.transaction_index.get(&transaction_id)?;
+        self.get(sequence)
+    }
+
+    /// Returns a copy of the whole log.
+    pub fn entries(&self) -> Vec<Arc<CommittedTransaction>> {
+        self.since(0)
+    }
+
+    /// Runs `read` between commits and returns it with the log at that point, so that ledger
+    /// state read by it reflects exactly the returned entries.
+    pub fn read_consistent<T>(
+        &self,
+        read: impl FnOnce() -> T,
+    ) -> (Vec<Arc<CommittedTransaction>>, T) {
+        let _in_flight = self.in_flight.write().unwrap_or_else(|e| e.into_inner());
+        (self.entries(), read())
+    }
+
+    /// Subscribes to new entries. When `from_sequence` is given, the entries already in the
+    /// log from that sequence on are returned as a backlog.
+    pub fn subscribe(&self, from_sequence: Option<u64>) -> Subscription {
+        let _publish = self.publish_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        Subscription {
+            backlog: from_sequence
+                .map(|from| self.since(from))
+                .unwrap_or_default(),
+            receiver: self.sender.subscribe(),
+            last_sequence: self.last_sequence(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::models::DepositInstruction};
+
+    fn deposit(log: &EventLog, account_id: Uuid) -> Arc<CommittedTransaction> {
+        log.append(
+            Uuid::new_v4(),
+            Instruction::Deposit(DepositInstruction {
+                destination_account_id: account_id,
+                amount: 10,
+            }),
+            vec![Posting::credit(account_id, 10)],
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_subscribe_resumes_without_gaps() {
+        let log = EventLog::default();
+        let account_id = Uuid::new_v4();
+        for _ in 0..3 {
+            deposit(&log, account_id);
+        }
+
+        let mut subscription = log.subscribe(Some(2));
+        let next = deposit(&log, account_id);
+
+        assert_eq!(
+            subscription
+                .backlog
+                .iter()
+                .map(|e| e.sequence)
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(subscription.last_sequence, 3);
+        assert_eq!(
+            subscription.receiver.try_recv().unwrap().sequence,
+            next.sequence
+        );
+        assert_eq!(log.last_sequence(), 4);
+    }
+
+    #[test]
+    fn test_commits_on_other_accounts_do_not_wait() {
+        let log = Arc::new(EventLog::default());
+        let (blocked, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let (applying, started) = std::sync::mpsc::channel();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let slow = std::thread::spawn({
+            let log = log.clone();
+            move || {
+                log.commit(
+                    Uuid::new_v4(),
+                    Utc::now(),
+                    Instruction::Deposit(DepositInstruction {
+                        destination_account_id: blocked,
+                        amount: 10,
+                    }),
+                    vec![Posting::credit(blocked, 10)],
+                    None,
+                    || -> Result<(), EventLogError> {
+                        applying.send(()).unwrap();
+                        released.recv().unwrap();
+                        Ok(())
+                    },
+                )
+                .unwrap()
+            }
+        });
+        started.recv().unwrap();
+
+        // Committed and published while the other transaction is still being applied.
+        assert_eq!(deposit(&log, other).sequence, 1);
+        release.send(()).unwrap();
+        assert_eq!(slow.join().unwrap().sequence, 2);
+    }
+
+    #[test]
+    fn test_concurrent_commits_are_numbered_without_gaps() {
+        let log = Arc::new(EventLog::default());
+        let mut subscription = log.subscribe(None);
+        let shared = Uuid::new_v4();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let log = log.clone();
+                std::thread::spawn(move || {
+                    let own = Uuid::new_v4();
+                    for _ in 0..50 {
+                        deposit(&log, own);
+                        deposit(&log, shared);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let sequences: Vec<u64> = log.entries().iter().map(|e| e.sequence).collect();
+        assert_eq!(sequences, (1..=800).collect::<Vec<_>>());
+        for sequence in 1..=800 {
+            assert_eq!(subscription.receiver.try_recv().unwrap().sequence, sequence);
+        }
+        let history = log.account_entries(shared);
+        assert_eq!(history.len(), 400);
+        assert!(history.windows(2).all(|w| w[0].sequence < w[1].sequence));
+        assert_eq!(log.balance_as_of(shared, Utc::now()), 4000);
+    }
+
+    #[test]
+    fn test_filter_by_account_and_kind() {
+        let log = EventLog::default();
+        let account_id = Uuid::new_v4();
+        let event = deposit(&log, account_id);
+
+        let by_account = EventFilter {
+            account_id: Some(account_id),
+            ..Default::default()
+        };
+        let other_account = EventFilter {
+            account_id: Some(Uuid::new_v4()),
+            ..Default::default()
+        };
+        let transfers_only = EventFilter {
+            instruction_kinds: vec![InstructionKind::Transfer],
+            ..Default::default()
+        };
+
+        assert!(by_account.matches(&event));
+        assert!(!other_account.matches(&event));
+        assert!(!transfers_only.matches(&event));
+    }
+}
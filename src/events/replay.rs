@@ -0,0 +1,416 @@
+//! Rebuilds the ledger from the commit log.
+//! The log records the account created or the postings applied by every committed transaction.
+//! Replaying it on an empty ledger yields the accounts, balances and processed transactions the
+//! ledger held after its last entry, so the log can act as the source of truth and a checkpoint
+//! can be verified against it.
+
+use {
+    crate::{
+        events::{CommittedTransaction, error::ReplayError},
+        ledger::{Ledger, error::LedgerError, interface::LedgerInterface},
+        models::{Account, AccountType, Instruction, PostingKind},
+    },
+    dashmap::{DashMap, DashSet},
+    std::{collections::BTreeSet, fmt},
+    uuid::Uuid,
+};
+
+/// Applies one logged transaction to the ledger and marks it as processed.
+pub fn apply(
+    ledger: &dyn LedgerInterface,
+    event: &CommittedTransaction,
+) -> Result<(), LedgerError> {
+    // The revenue account is created from configuration, never through the log.
+    if let Some(fee) = &event.fee {
+        ledger.ensure_account(fee.revenue_account_id, AccountType::Revenue)?;
+    }
+
+    match &event.instruction {
+        Instruction::CreateAccount(create) => {
+            // Accounts created before IDs were derived from transactions keep their logged ID.
+            let account_id = event
+                .created_account_id
+                .unwrap_or_else(|| Account::id_for(event.transaction_id));
+            ledger.insert_account(Account {
+                uuid: account_id,
+                keys: create.keys.clone(),
+                account_type: create.account_type,
+                ..Default::default()
+            })?;
+            ledger.mark_transaction_processed(event.transaction_id)
+        }
+        // Decisions move no money: an approved transaction is logged on its own.
+        Instruction::Review(_) => ledger.mark_transaction_processed(event.transaction_id),
+        // Like the revenue account, the escrow account blocked funds are credited to comes from
+        // configuration.
+        Instruction::OpenDispute(_) | Instruction::UpdateDispute(_) => {
+            if matches!(event.instruction, Instruction::OpenDispute(_)) {
+                for posting in event
+                    .postings
+                    .iter()
+                    .filter(|p| p.kind == PostingKind::Credit)
+                {
+                    ledger.ensure_account(posting.account_id, AccountType::Escrow)?;
+                }
+            }
+            if event.postings.is_empty() {
+                ledger.mark_transaction_processed(event.transaction_id)
+            } else {
+                ledger.commit_postings(event.transaction_id, &event.postings)
+            }
+        }
+        _ => ledger.commit_postings(event.transaction_id, &event.postings),
+    }
+}
+
+/// Replays a whole log, which must start at sequence 1, and returns the number of transactions
+/// applied.
+pub fn replay(
+    ledger: &dyn LedgerInterface,
+    events: &[CommittedTransaction],
+) -> Result<usize, ReplayError> {
+    let mut expected = 1;
+
+    for event in events {
+        if event.sequence != expected {
+            return Err(ReplayError::SequenceGap {
+                expected,
+                found: event.sequence,
+            });
+        }
+        expected += 1;
+
+        apply(ledger, event).map_err(|source| ReplayError::Ledger {
+            sequence: event.sequence,
+            source,
+        })?;
+    }
+
+    Ok(events.len())
+}
+
+/// Applies the logged transactions a checkpointed ledger has not processed yet, in sequence
+/// order, and returns them. The log is written on every commit but the checkpoint only on
+/// shutdown, so after a crash the log runs ahead of it.
+pub fn recover<'a>(
+    ledger: &dyn LedgerInterface,
+    events: &'a [CommittedTransaction],
+) -> Result<Vec<&'a CommittedTransaction>, ReplayError> {
+    let mut recovered = Vec::new();
+    for event in events {
+        let processed = ledger
+            .is_transaction_processed(event.transaction_id)
+            .map_err(|source| ReplayError::Ledger {
+                sequence: event.sequence,
+                source,
+            })?;
+        if processed {
+            continue;
+        }
+
+        apply(ledger, event).map_err(|source| ReplayError::Ledger {
+            sequence: event.sequence,
+            source,
+        })?;
+        recovered.push(event);
+    }
+    Ok(recovered)
+}
+
+/// Difference between a ledger snapshot and the replay of the commit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence {
+    /// The log creates or moves money on an account missing from the snapshot.
+    MissingAccount(Uuid),
+    /// The snapshot holds money on an account the log never touched.
+    UnloggedAccount(Uuid),
+    BalanceMismatch {
+        account_id: Uuid,
+        balance: u64,
+        replayed_balance: u64,
+    },
+    /// Keys, account type or transaction history differ.
+    AccountMismatch {
+        account_id: Uuid,
+        field: &'static str,
+    },
+    /// A logged transaction is not marked processed in the snapshot.
+    MissingProcessedTransaction(Uuid),
+    /// A transaction is marked processed in the snapshot but missing from the log.
+    UnloggedProcessedTransaction(Uuid),
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::MissingAccount(account_id) => {
+                write!(
+                    f,
+                    "Account {account_id} is in the log but not in the snapshot"
+                )
+            }
+            Divergence::UnloggedAccount(account_id) => {
+                write!(
+                    f,
+                    "Account {account_id} is in the snapshot but not in the log"
+                )
+            }
+            Divergence::BalanceMismatch {
+                account_id,
+                balance,
+                replayed_balance,
+            } => write!(
+                f,
+                "Account {account_id} has a balance of {balance} but the replay yields {replayed_balance}"
+            ),
+            Divergence::AccountMismatch { account_id, field } => {
+                write!(f, "Account {account_id} {field} differs from the replay")
+            }
+            Divergence::MissingProcessedTransaction(transaction_id) => write!(
+                f,
+                "Transaction {transaction_id} is in the log but not marked processed"
+            ),
+            Divergence::UnloggedProcessedTransaction(transaction_id) => write!(
+                f,
+                "Transaction {transaction_id} is marked processed but not in the log"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    pub events_replayed: usize,
+    pub accounts_checked: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+impl ReplayReport {
+    pub fn is_ok(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+/// Replays the log on an empty ledger and compares the result with a snapshot of the ledger.
+/// Account histories are compared regardless of order.
+pub fn verify_snapshot(
+    accounts: &DashMap<Uuid, Account>,
+    processed_transactions: &DashSet<Uuid>,
+    events: &[CommittedTransaction],
+) -> Result<ReplayReport, ReplayError> {
+    let replayed = Ledger::default();
+    let events_replayed = replay(&replayed, events)?;
+    let mut divergences = Vec::new();
+
+    for entry in replayed.accounts.iter() {
+        let expected = entry.value();
+        let Some(account) = accounts.get(entry.key()) else {
+            divergences.push(Divergence::MissingAccount(expected.uuid));
+            continue;
+        };
+
+        if account.balance != expected.balance {
+            divergences.push(Divergence::BalanceMismatch {
+                account_id: expected.uuid,
+                balance: account.balance,
+                replayed_balance: expected.balance,
+            });
+        }
+        let history = |account: &Account| -> BTreeSet<Uuid> {
+            account.transaction_history.iter().copied().collect()
+        };
+        for (field, differs) in [
+            ("keys", account.keys != expected.keys),
+            (
+                "account type",
+                account.account_type != expected.account_type,
+            ),
+            (
+                "transaction history",
+                history(&account) != history(expected),
+            ),
+        ] {
+            if differs {
+                divergences.push(Divergence::AccountMismatch {
+                    account_id: expected.uuid,
+                    field,
+                });
+            }
+        }
+    }
+
+    // System accounts, such as the fee revenue account, are created from configuration rather
+    // than through the log, so untouched ones are expected.
+    for account in accounts.iter() {
+        let untouched = account.balance == 0 && account.transaction_history.is_empty();
+        if !untouched && !replayed.accounts.contains_key(account.key()) {
+            divergences.push(Divergence::UnloggedAccount(*account.key()));
+        }
+    }
+
+    for transaction_id in replayed.processed_transactions.iter() {
+        if !processed_transactions.contains(&*transaction_id) {
+            divergences.push(Divergence::MissingProcessedTransaction(*transaction_id));
+        }
+    }
+    for transaction_id in processed_transactions.iter() {
+        if !replayed.processed_transactions.contains(&*transaction_id) {
+            divergences.push(Divergence::UnloggedProcessedTransaction(*transaction_id));
+        }
+    }
+
+    Ok(ReplayReport {
+        events_replayed,
+        accounts_checked: accounts.len(),
+        divergences,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            events::EventLog,
+            fees::{FeeEngine, FeeRule, FeeSchedule},
+            models::{
+                CreateAccountInstruction, DepositInstruction, InstructionKind, Transaction,
+                TransactionStatus, TransferInstruction,
+            },
+            transaction_processor::{
+                TransactionProcessor,
+                interface::{TransactionProcessorInterface, TransactionResult},
+            },
+        },
+        chrono::Utc,
+        std::sync::Arc,
+    };
+
+    fn transaction(instruction: Instruction) -> Transaction {
+        Transaction {
+            id: Uuid::new_v4(),
+            instruction,
+            status: TransactionStatus::Pending,
+            timestamp: Utc::now(),
+        }
+    }
+
+    fn logged(log: &EventLog) -> Vec<CommittedTransaction> {
+        log.entries()
+            .iter()
+            .map(|event| (**event).clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_replay_matches_the_ledger() {
+        let ledger = Arc::new(Ledger::default());
+        let revenue_id = Uuid::new_v4();
+        ledger
+            .ensure_account(revenue_id, AccountType::Revenue)
+            .unwrap();
+        let fee_engine = FeeEngine::new(
+            revenue_id,
+            vec![FeeRule {
+                name: "transfer".to_string(),
+                instruction: Some(InstructionKind::Transfer),
+                account_type: None,
+                schedule: FeeSchedule::Flat { amount: 1 },
+            }],
+        );
+        let processor =
+            TransactionProcessor::new(ledger.clone(), DashMap::new()).with_fee_engine(fee_engine);
+
+        let mut account_ids = Vec::new();
+        for _ in 0..2 {
+            let create = transaction(Instruction::CreateAccount(CreateAccountInstruction::new(
+                vec![],
+            )));
+            let create_id = create.id;
+            let Ok(TransactionResult::AccountCreated(account_id)) =
+                processor.process_transaction(create)
+            else {
+                panic!("account not created");
+            };
+            assert_eq!(account_id, Account::id_for(create_id));
+            account_ids.push(account_id);
+        }
+        let deposit = transaction(Instruction::Deposit(DepositInstruction {
+            destination_account_id: account_ids[0],
+            amount: 100,
+        }));
+        processor.process_transaction(deposit).unwrap();
+        for amount in [30, 1000] {
+            let _ = processor.process_transaction(transaction(Instruction::Transfer(
+                TransferInstruction {
+                    source_account_id: account_ids[0],
+                    destination_account_id: account_ids[1],
+                    amount,
+                },
+            )));
+        }
+
+        let events = logged(&processor.events);
+        let (accounts, processed) = ledger.snapshot().unwrap();
+        let report = verify_snapshot(&accounts, &processed, &events).unwrap();
+        assert!(report.is_ok(), "{:?}", report.divergences);
+        assert_eq!(report.events_replayed, 4);
+
+        accounts.get_mut(&account_ids[1]).unwrap().balance += 1;
+        let transfer_id = events[3].transaction_id;
+        processed.remove(&transfer_id);
+        let report = verify_snapshot(&accounts, &processed, &events).unwrap();
+        assert_eq!(
+            report.divergences,
+            vec![
+                Divergence::BalanceMismatch {
+                    account_id: account_ids[1],
+                    balance: 31,
+                    replayed_balance: 30,
+                },
+                Divergence::MissingProcessedTransaction(transfer_id),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recover_applies_transactions_missing_from_the_checkpoint() {
+        let ledger = Arc::new(Ledger::default());
+        let processor = TransactionProcessor::new(ledger.clone(), DashMap::new());
+        let create = transaction(Instruction::CreateAccount(CreateAccountInstruction::new(
+            vec![],
+        )));
+        let account_id = Account::id_for(create.id);
+        processor.process_transaction(create).unwrap();
+        let (accounts, processed) = ledger.snapshot().unwrap();
+        for amount in [10, 20] {
+            processor
+                .process_transaction(transaction(Instruction::Deposit(DepositInstruction {
+                    destination_account_id: account_id,
+                    amount,
+                })))
+                .unwrap();
+        }
+
+        // The checkpoint predates both deposits.
+        let checkpointed = Ledger::new(accounts, processed);
+        let events = logged(&processor.events);
+        let recovered = recover(&checkpointed, &events).unwrap();
+        assert_eq!(
+            recovered.iter().map(|e| e.sequence).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(checkpointed.get_account(account_id).unwrap().balance, 30);
+        assert!(recover(&checkpointed, &events).unwrap().is_empty());
+
+        let mut events = logged(&processor.events);
+        events.remove(1);
+        assert!(matches!(
+            replay(&Ledger::default(), &events),
+            Err(ReplayError::SequenceGap {
+                expected: 2,
+                found: 3
+            })
+        ));
+    }
+}
@@ -0,0 +1,9 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FeeError {
+    #[error("Fee calculation overflowed")]
+    Overflow,
+    #[error("Fee of {fee} exceeds the deposited amount of {amount}")]
+    FeeExceedsAmount { fee: u64, amount: u64 },
+}
@@ -0,0 +1,242 @@
+//! Fee engine evaluated by the transaction processor.
+//! Fees are charged on top of transfers and deducted from deposits, and are credited to a
+//! revenue account in the same commit as the instruction itself.
+
+pub mod error;
+
+use {
+    crate::{
+        config::FeesConfig,
+        fees::error::FeeError,
+        models::{AccountType, InstructionKind},
+    },
+    serde::{Deserialize, Serialize},
+    uuid::Uuid,
+};
+
+const BASIS_POINTS_DENOMINATOR: u128 = 10_000;
+
+/// How the fee amount is derived from the instruction amount.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeeSchedule {
+    /// A fixed amount per instruction.
+    Flat { amount: u64 },
+    /// A fraction of the amount in basis points (1/100 of a percent), clamped to `[min, max]`.
+    Percentage {
+        basis_points: u32,
+        #[serde(default)]
+        min: u64,
+        #[serde(default)]
+        max: Option<u64>,
+    },
+    /// The first tier whose `up_to` bound covers the amount is applied.
+    Tiered { tiers: Vec<FeeTier> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeTier {
+    /// Inclusive upper bound of the amount covered by this tier. `None` means unbounded.
+    #[serde(default)]
+    pub up_to: Option<u64>,
+    #[serde(default)]
+    pub flat: u64,
+    #[serde(default)]
+    pub basis_points: u32,
+}
+
+/// A fee rule. Filters left empty match everything; the first matching rule wins.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeRule {
+    pub name: String,
+    #[serde(default)]
+    pub instruction: Option<InstructionKind>,
+    #[serde(default)]
+    pub account_type: Option<AccountType>,
+    pub schedule: FeeSchedule,
+}
+
+/// Fee charged for a committed instruction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeCharge {
+    pub amount: u64,
+    pub revenue_account_id: Uuid,
+    pub rule: String,
+}
+
+pub struct FeeEngine {
+    pub revenue_account_id: Uuid,
+    rules: Vec<FeeRule>,
+}
+
+impl FeeEngine {
+    pub fn new(revenue_account_id: Uuid, rules: Vec<FeeRule>) -> Self {
+        FeeEngine {
+            revenue_account_id,
+            rules,
+        }
+    }
+
+    /// Builds the engine from configuration, returning `None` when fees are disabled.
+    pub fn from_config(config: &FeesConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+
+        Some(FeeEngine::new(
+            config.revenue_account_id,
+            config.rules.clone(),
+        ))
+    }
+
+    /// Computes the fee for an instruction of `kind` moving `amount`, where `account_type`
+    /// is the type of the account paying the fee. Returns `None` when no rule matches or
+    /// the resulting fee is zero.
+    pub fn evaluate(
+        &self,
+        kind: InstructionKind,
+        account_type: AccountType,
+        amount: u64,
+    ) -> Result<Option<FeeCharge>, FeeError> {
+        // The revenue account never pays fees to itself.
+        if account_type == AccountType::Revenue {
+            return Ok(None);
+        }
+
+        let Some(rule) = self.rules.iter().find(|rule| {
+            rule.instruction.is_none_or(|k| k == kind)
+                && rule.account_type.is_none_or(|t| t == account_type)
+        }) else {
+            return Ok(None);
+        };
+
+        let fee = rule.schedule.compute(amount)?;
+        if fee == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(FeeCharge {
+            amount: fee,
+            revenue_account_id: self.revenue_account_id,
+            rule: rule.name.clone(),
+        }))
+    }
+}
+
+impl FeeSchedule {
+    pub fn compute(&self, amount: u64) -> Result<u64, FeeError> {
+        match self {
+            FeeSchedule::Flat { amount: fee } => Ok(*fee),
+            FeeSchedule::Percentage {
+                basis_points,
+                min,
+                max,
+            } => {
+                let fee = percentage(amount, *basis_points)?.max(*min);
+                Ok(max.map_or(fee, |max| fee.min(max)))
+            }
+            FeeSchedule::Tiered { tiers } => {
+                let Some(tier) = tiers
+                    .iter()
+                    .find(|tier| tier.up_to.is_none_or(|up_to| amount <= up_to))
+                else {
+                    return Ok(0);
+                };
+
+                tier.flat
+                    .checked_add(percentage(amount, tier.basis_points)?)
+                    .ok_or(FeeError::Overflow)
+            }
+        }
+    }
+}
+
+fn percentage(amount: u64, basis_points: u32) -> Result<u64, FeeError> {
+    let fee = amount as u128 * basis_points as u128 / BASIS_POINTS_DENOMINATOR;
+    u64::try_from(fee).map_err(|_| FeeError::Overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(
+        instruction: Option<InstructionKind>,
+        account_type: Option<AccountType>,
+        schedule: FeeSchedule,
+    ) -> FeeRule {
+        FeeRule {
+            name: "test".to_string(),
+            instruction,
+            account_type,
+            schedule,
+        }
+    }
+
+    #[test]
+    fn test_percentage_is_clamped() {
+        let schedule = FeeSchedule::Percentage {
+            basis_points: 150,
+            min: 5,
+            max: Some(100),
+        };
+
+        assert_eq!(schedule.compute(100).unwrap(), 5);
+        assert_eq!(schedule.compute(2_000).unwrap(), 30);
+        assert_eq!(schedule.compute(1_000_000).unwrap(), 100);
+    }
+
+    #[test]
+    fn test_tiered_picks_first_covering_tier() {
+        let schedule = FeeSchedule::Tiered {
+            tiers: vec![
+                FeeTier {
+                    up_to: Some(1_000),
+                    flat: 1,
+                    basis_points: 0,
+                },
+                FeeTier {
+                    up_to: None,
+                    flat: 2,
+                    basis_points: 100,
+                },
+            ],
+        };
+
+        assert_eq!(schedule.compute(1_000).unwrap(), 1);
+        assert_eq!(schedule.compute(5_000).unwrap(), 52);
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let engine = FeeEngine::new(
+            Uuid::new_v4(),
+            vec![
+                rule(
+                    Some(InstructionKind::Transfer),
+                    Some(AccountType::Merchant),
+                    FeeSchedule::Flat { amount: 7 },
+                ),
+                rule(
+                    Some(InstructionKind::Transfer),
+                    None,
+                    FeeSchedule::Flat { amount: 3 },
+                ),
+            ],
+        );
+
+        let merchant = engine
+            .evaluate(InstructionKind::Transfer, AccountType::Merchant, 100)
+            .unwrap();
+        let personal = engine
+            .evaluate(InstructionKind::Transfer, AccountType::Personal, 100)
+            .unwrap();
+        let deposit = engine
+            .evaluate(InstructionKind::Deposit, AccountType::Personal, 100)
+            .unwrap();
+
+        assert_eq!(merchant.unwrap().amount, 7);
+        assert_eq!(personal.unwrap().amount, 3);
+        assert!(deposit.is_none());
+    }
+}
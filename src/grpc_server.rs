@@ -1,17 +1,42 @@
 use {
     crate::{
-        config::GrpcConfig,
+        auth::{AuthInterceptor, Authenticator, ClientIdentity, Scope},
+        backup::{backup_processor, error::BackupError},
+        config::{GrpcConfig, ReplicationRole},
+        disputes::{self, Dispute, error::DisputeError},
+        events::{CommittedTransaction, EventFilter, EventLog, history::HistoryQuery},
+        fees::FeeCharge,
+        invariants::InvariantChecker,
+        limits::{Limiter, layer::LimitLayer},
+        metrics::{EVENT_SUBSCRIBER_LAGS_TOTAL, EVENT_SUBSCRIBERS},
         models::{
-            CreateAccountInstruction, DepositInstruction, Transaction, TransactionStatus,
-            TransferInstruction,
+            AccountType, CreateAccountInstruction, DepositInstruction, Instruction,
+            InstructionKind, OpenDisputeInstruction, Posting, PostingKind, ReviewDecision,
+            ReviewInstruction, Transaction, TransactionStatus, TransferInstruction,
+            UpdateDisputeInstruction,
         },
+        raft::{RaftNode, error::RaftError, transport::RaftGrpcServer},
+        receipts::{Receipt, ReceiptSigner, SignedReceipt},
+        replication::ReplicationState,
+        risk::{error::RiskError, store::HeldTransaction},
+        statements::{
+            DEFAULT_STATEMENT_CURRENCY, Statement, StatementFormat, error::StatementError,
+        },
+        submission::{SubmissionQueue, SubmissionStatus, error::SubmissionError},
+        tls::grpc_server_tls,
         transaction_processor::{
             TransactionProcessor,
             interface::{TransactionProcessorInterface, TransactionResult},
         },
+        webhooks::{self, Webhook, error::WebhookError, store::WebhookStore},
+    },
+    chrono::{DateTime, SecondsFormat, Utc},
+    std::{convert::TryFrom, path::PathBuf, pin::Pin, str::FromStr, sync::Arc},
+    tokio_stream::{Stream, wrappers::ReceiverStream},
+    tonic::{
+        Request, Response, Status, service::interceptor::InterceptedService, transport::Server,
     },
-    std::{convert::TryFrom, str::FromStr, sync::Arc},
-    tonic::{Request, Response, Status, transport::Server},
+    tower::Layer,
     tracing::{error, info},
     uuid::Uuid,
 };
@@ -21,13 +46,600 @@ pub mod server {
 }
 
 use server::{
-    CreateAccountRequest, CreateAccountResponse, DepositRequest, GenericResponse,
-    GetBalanceRequest, GetBalanceResponse, TransferRequest,
+    CheckInvariantsRequest, CheckInvariantsResponse, CreateAccountRequest, CreateAccountResponse,
+    CreateBackupRequest, CreateBackupResponse, DeleteWebhookRequest, DepositRequest, DisputeAction,
+    DisputeStatus, FeeDetails, GenericResponse, GetBalanceRequest, GetBalanceResponse,
+    GetStatementRequest, GetStatementResponse, InstructionType, ListDisputesRequest,
+    ListDisputesResponse, ListHeldTransactionsRequest, ListHeldTransactionsResponse,
+    ListTransactionsRequest, ListTransactionsResponse, ListWebhooksRequest, ListWebhooksResponse,
+    OpenDisputeRequest, PromoteRequest, PromoteResponse, RegisterWebhookRequest,
+    RegisterWebhookResponse, ReplicateRequest, ReplicatedTransaction, ReviewTransactionRequest,
+    SubmissionState, SubmitTransactionRequest, SubmitTransactionResponse, SubscribeEventsRequest,
+    TransactionEvent, TransactionStatusRequest, TransactionStatusResponse, TransferRequest,
+    UpdateDisputeRequest, WebhookEventType, WebhookRegistration,
     grpc_service_server::{GrpcService, GrpcServiceServer},
+    raft_service_server::RaftServiceServer,
+    submit_transaction_request,
 };
 
+impl From<FeeCharge> for FeeDetails {
+    fn from(fee: FeeCharge) -> Self {
+        FeeDetails {
+            amount: fee.amount,
+            revenue_account_id: fee.revenue_account_id.to_string(),
+            rule: fee.rule,
+        }
+    }
+}
+
+impl From<SignedReceipt> for server::Receipt {
+    fn from(signed: SignedReceipt) -> Self {
+        let receipt = signed.receipt;
+        server::Receipt {
+            transaction_id: receipt.transaction_id.to_string(),
+            source_account_id: receipt
+                .source_account_id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            destination_account_id: receipt.destination_account_id.to_string(),
+            amount: receipt.amount,
+            committed_at: receipt
+                .committed_at
+                .to_rfc3339_opts(SecondsFormat::Nanos, true),
+            sequence: receipt.sequence,
+            signature: signed.signature,
+            public_key: signed.public_key,
+            fee: receipt.fee,
+            net_amount: receipt.net_amount,
+        }
+    }
+}
+
+impl TryFrom<server::Receipt> for SignedReceipt {
+    type Error = Status;
+    fn try_from(receipt: server::Receipt) -> Result<Self, Self::Error> {
+        let parse_id = |id: &str| {
+            Uuid::parse_str(id).map_err(|_| Status::invalid_argument("Invalid receipt account ID"))
+        };
+
+        Ok(SignedReceipt {
+            receipt: Receipt {
+                transaction_id: Uuid::parse_str(&receipt.transaction_id)
+                    .map_err(|_| Status::invalid_argument("Invalid receipt transaction ID"))?,
+                source_account_id: if receipt.source_account_id.is_empty() {
+                    None
+                } else {
+                    Some(parse_id(&receipt.source_account_id)?)
+                },
+                destination_account_id: parse_id(&receipt.destination_account_id)?,
+                amount: receipt.amount,
+                fee: receipt.fee,
+                net_amount: receipt.net_amount,
+                committed_at: DateTime::parse_from_rfc3339(&receipt.committed_at)
+                    .map_err(|_| Status::invalid_argument("Invalid receipt commit time"))?
+                    .with_timezone(&Utc),
+                sequence: receipt.sequence,
+            },
+            signature: receipt.signature,
+            public_key: receipt.public_key,
+        })
+    }
+}
+
+impl From<server::AccountType> for AccountType {
+    fn from(account_type: server::AccountType) -> Self {
+        match account_type {
+            server::AccountType::Personal => AccountType::Personal,
+            server::AccountType::Merchant => AccountType::Merchant,
+        }
+    }
+}
+
+impl From<InstructionKind> for InstructionType {
+    fn from(kind: InstructionKind) -> Self {
+        match kind {
+            InstructionKind::Transfer => InstructionType::Transfer,
+            InstructionKind::Deposit => InstructionType::Deposit,
+            InstructionKind::CreateAccount => InstructionType::CreateAccount,
+            InstructionKind::OpenDispute => InstructionType::OpenDispute,
+            InstructionKind::UpdateDispute => InstructionType::UpdateDispute,
+            InstructionKind::GetBalance | InstructionKind::Review => InstructionType::Unspecified,
+        }
+    }
+}
+
+impl From<Posting> for server::Posting {
+    fn from(posting: Posting) -> Self {
+        let kind = match posting.kind {
+            PostingKind::Debit => server::PostingKind::Debit,
+            PostingKind::Credit => server::PostingKind::Credit,
+        };
+
+        server::Posting {
+            account_id: posting.account_id.to_string(),
+            kind: kind.into(),
+            amount: posting.amount,
+        }
+    }
+}
+
+impl From<&CommittedTransaction> for TransactionEvent {
+    fn from(event: &CommittedTransaction) -> Self {
+        TransactionEvent {
+            sequence: event.sequence,
+            transaction_id: event.transaction_id.to_string(),
+            instruction_type: InstructionType::from(event.instruction.kind()).into(),
+            postings: event.postings.iter().cloned().map(Into::into).collect(),
+            fee: event.fee.clone().map(FeeDetails::from),
+            created_account_id: event
+                .created_account_id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            committed_at: event.committed_at.to_rfc3339(),
+        }
+    }
+}
+
+impl TryFrom<&SubscribeEventsRequest> for EventFilter {
+    type Error = Status;
+    fn try_from(req: &SubscribeEventsRequest) -> Result<Self, Self::Error> {
+        let account_id = if req.account_id.is_empty() {
+            None
+        } else {
+            Some(
+                Uuid::parse_str(&req.account_id)
+                    .map_err(|_| Status::invalid_argument("Invalid account ID"))?,
+            )
+        };
+
+        let instruction_kinds = req
+            .instruction_types()
+            .map(InstructionKind::try_from)
+            .collect::<Result<_, _>>()?;
+
+        Ok(EventFilter {
+            account_id,
+            instruction_kinds,
+        })
+    }
+}
+
+impl TryFrom<InstructionType> for InstructionKind {
+    type Error = Status;
+    fn try_from(instruction_type: InstructionType) -> Result<Self, Self::Error> {
+        match instruction_type {
+            InstructionType::Transfer => Ok(InstructionKind::Transfer),
+            InstructionType::Deposit => Ok(InstructionKind::Deposit),
+            InstructionType::CreateAccount => Ok(InstructionKind::CreateAccount),
+            InstructionType::OpenDispute => Ok(InstructionKind::OpenDispute),
+            InstructionType::UpdateDispute => Ok(InstructionKind::UpdateDispute),
+            InstructionType::Unspecified => {
+                Err(Status::invalid_argument("Invalid instruction type"))
+            }
+        }
+    }
+}
+
+/// Parses an optional RFC 3339 timestamp, where an empty string means no value.
+fn parse_timestamp(value: &str) -> Result<Option<DateTime<Utc>>, Status> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| Some(time.with_timezone(&Utc)))
+        .map_err(|_| Status::invalid_argument(format!("Invalid timestamp: {value}")))
+}
+
+impl TryFrom<&ListTransactionsRequest> for HistoryQuery {
+    type Error = Status;
+    fn try_from(req: &ListTransactionsRequest) -> Result<Self, Self::Error> {
+        let cursor = if req.page_token.is_empty() {
+            None
+        } else {
+            Some(
+                req.page_token
+                    .parse()
+                    .map_err(|_| Status::invalid_argument("Invalid page token"))?,
+            )
+        };
+
+        Ok(HistoryQuery {
+            cursor,
+            page_size: (req.page_size > 0).then_some(req.page_size as usize),
+            from: parse_timestamp(&req.from)?,
+            to: parse_timestamp(&req.to)?,
+            instruction_kinds: req
+                .instruction_types()
+                .map(InstructionKind::try_from)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl From<WebhookEventType> for webhooks::WebhookEventType {
+    fn from(event_type: WebhookEventType) -> Self {
+        match event_type {
+            WebhookEventType::MoneyReceived => webhooks::WebhookEventType::MoneyReceived,
+            WebhookEventType::MoneySent => webhooks::WebhookEventType::MoneySent,
+        }
+    }
+}
+
+impl From<webhooks::WebhookEventType> for WebhookEventType {
+    fn from(event_type: webhooks::WebhookEventType) -> Self {
+        match event_type {
+            webhooks::WebhookEventType::MoneyReceived => WebhookEventType::MoneyReceived,
+            webhooks::WebhookEventType::MoneySent => WebhookEventType::MoneySent,
+        }
+    }
+}
+
+impl From<Webhook> for WebhookRegistration {
+    fn from(webhook: Webhook) -> Self {
+        WebhookRegistration {
+            webhook_id: webhook.id.to_string(),
+            account_id: webhook.account_id.to_string(),
+            url: webhook.url,
+            event_types: webhook
+                .event_types
+                .into_iter()
+                .map(|t| WebhookEventType::from(t).into())
+                .collect(),
+        }
+    }
+}
+
+impl From<WebhookError> for Status {
+    fn from(error: WebhookError) -> Self {
+        match error {
+            WebhookError::NotFound => Status::not_found(error.to_string()),
+            WebhookError::InvalidUrl(_) => Status::invalid_argument(error.to_string()),
+            _ => {
+                error!("Webhook store error: {}", error);
+                Status::internal("Webhook store error")
+            }
+        }
+    }
+}
+
+impl From<HeldTransaction> for server::HeldTransaction {
+    fn from(held: HeldTransaction) -> Self {
+        let mut response = server::HeldTransaction {
+            transaction_id: held.transaction.id.to_string(),
+            rule: held.rule,
+            held_at: held.held_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            ..Default::default()
+        };
+        if let Instruction::Transfer(transfer) = held.transaction.instruction {
+            response.source_account_id = transfer.source_account_id.to_string();
+            response.destination_account_id = transfer.destination_account_id.to_string();
+            response.amount = transfer.amount;
+        }
+        response
+    }
+}
+
+impl From<disputes::DisputeStatus> for DisputeStatus {
+    fn from(status: disputes::DisputeStatus) -> Self {
+        match status {
+            disputes::DisputeStatus::Open => DisputeStatus::Open,
+            disputes::DisputeStatus::UnderAnalysis => DisputeStatus::UnderAnalysis,
+            disputes::DisputeStatus::Returned => DisputeStatus::Returned,
+            disputes::DisputeStatus::Released => DisputeStatus::Released,
+            disputes::DisputeStatus::Expired => DisputeStatus::Expired,
+        }
+    }
+}
+
+impl From<Dispute> for server::Dispute {
+    fn from(dispute: Dispute) -> Self {
+        server::Dispute {
+            dispute_id: dispute.id.to_string(),
+            disputed_transaction_id: dispute.disputed_transaction_id.to_string(),
+            payer_account_id: dispute.payer_account_id.to_string(),
+            payee_account_id: dispute.payee_account_id.to_string(),
+            amount: dispute.amount,
+            blocked_amount: dispute.blocked_amount,
+            status: DisputeStatus::from(dispute.status).into(),
+            opened_at: dispute
+                .opened_at
+                .to_rfc3339_opts(SecondsFormat::Micros, true),
+            deadline: dispute
+                .deadline
+                .to_rfc3339_opts(SecondsFormat::Micros, true),
+        }
+    }
+}
+
+impl From<DisputeError> for Status {
+    fn from(error: DisputeError) -> Self {
+        error!("Dispute store error: {}", error);
+        Status::internal("Dispute store error")
+    }
+}
+
+impl From<RiskError> for Status {
+    fn from(error: RiskError) -> Self {
+        error!("Review queue error: {}", error);
+        Status::internal("Review queue error")
+    }
+}
+
+impl From<StatementError> for Status {
+    fn from(error: StatementError) -> Self {
+        match error {
+            StatementError::InvalidRange => Status::invalid_argument(error.to_string()),
+            StatementError::InconsistentLog { .. } => {
+                error!("Statement generation failed: {}", error);
+                Status::data_loss(error.to_string())
+            }
+        }
+    }
+}
+
+impl From<BackupError> for Status {
+    fn from(error: BackupError) -> Self {
+        match error {
+            BackupError::AlreadyExists(_) => Status::already_exists(error.to_string()),
+            BackupError::InconsistentSnapshot(_) => Status::unavailable(error.to_string()),
+            _ => {
+                error!("Backup failed: {}", error);
+                Status::internal("Backup failed")
+            }
+        }
+    }
+}
+
+impl From<server::StatementFormat> for StatementFormat {
+    fn from(format: server::StatementFormat) -> Self {
+        match format {
+            server::StatementFormat::Csv => StatementFormat::Csv,
+            server::StatementFormat::Ofx => StatementFormat::Ofx,
+        }
+    }
+}
+
+impl From<SubmissionError> for Status {
+    fn from(error: SubmissionError) -> Self {
+        match error {
+            SubmissionError::QueueFull => Status::resource_exhausted(error.to_string()),
+            SubmissionError::QueueClosed => Status::unavailable(error.to_string()),
+        }
+    }
+}
+
+fn transaction_status_response(id: Uuid, status: SubmissionStatus) -> TransactionStatusResponse {
+    let mut response = TransactionStatusResponse {
+        transaction_id: id.to_string(),
+        ..Default::default()
+    };
+
+    match status {
+        SubmissionStatus::Queued => response.set_state(SubmissionState::Queued),
+        SubmissionStatus::Processing => response.set_state(SubmissionState::Processing),
+        SubmissionStatus::Completed(result) => {
+            response.set_state(SubmissionState::Completed);
+            match result {
+                TransactionResult::AccountCreated(account_id) => {
+                    response.created_account_id = account_id.to_string();
+                }
+                TransactionResult::Success { fee, .. } => response.fee = fee.map(FeeDetails::from),
+                TransactionResult::Balance(_) => {}
+            }
+        }
+        SubmissionStatus::Failed(error_message) => {
+            response.set_state(SubmissionState::Failed);
+            response.error_message = error_message;
+        }
+    }
+
+    response
+}
+
 pub struct QuasarGrpcServer {
     processor: Arc<TransactionProcessor>,
+    submissions: Arc<SubmissionQueue>,
+    webhooks: Option<Arc<WebhookStore>>,
+    backup_directory: Option<PathBuf>,
+    replication: Arc<ReplicationState>,
+    raft: Option<Arc<RaftNode>>,
+    receipt_signer: Option<Arc<ReceiptSigner>>,
+    auth: Option<Arc<Authenticator>>,
+    limiter: Option<Arc<Limiter>>,
+}
+
+impl QuasarGrpcServer {
+    pub fn new(processor: Arc<TransactionProcessor>, submissions: Arc<SubmissionQueue>) -> Self {
+        QuasarGrpcServer {
+            processor,
+            submissions,
+            webhooks: None,
+            backup_directory: None,
+            replication: Arc::new(ReplicationState::new(ReplicationRole::Primary)),
+            raft: None,
+            receipt_signer: None,
+            auth: None,
+            limiter: None,
+        }
+    }
+
+    pub fn with_webhooks(mut self, webhooks: Arc<WebhookStore>) -> Self {
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    pub fn with_backup_directory(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.backup_directory = Some(directory.into());
+        self
+    }
+
+    pub fn with_replication(mut self, replication: Arc<ReplicationState>) -> Self {
+        self.replication = replication;
+        self
+    }
+
+    /// Commits writes through the Raft log when clustered, and serves the other members'
+    /// messages next to the public API. Forwarded writes name the client that submitted them.
+    pub fn with_raft(mut self, raft: Arc<RaftNode>) -> Self {
+        self.raft = Some(raft);
+        self
+    }
+
+    /// Signs a receipt for every committed transfer and deposit.
+    pub fn with_receipt_signer(mut self, signer: Arc<ReceiptSigner>) -> Self {
+        self.receipt_signer = Some(signer);
+        self
+    }
+
+    /// Requires clients to authenticate, and checks their scopes on every call.
+    pub fn with_auth(mut self, authenticator: Arc<Authenticator>) -> Self {
+        self.auth = Some(authenticator);
+        self
+    }
+
+    /// Rate limits clients and accounts, and sheds load when requests become slow.
+    pub fn with_limiter(mut self, limiter: Arc<Limiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// The client attached by the auth interceptor, when authentication is enabled.
+    fn client<T>(&self, request: &Request<T>) -> Result<Option<Arc<ClientIdentity>>, Status> {
+        if self.auth.is_none() {
+            return Ok(None);
+        }
+        request
+            .extensions()
+            .get::<Arc<ClientIdentity>>()
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| Status::unauthenticated("Request was not authenticated"))
+    }
+
+    fn authorize<T>(&self, request: &Request<T>, scope: Scope) -> Result<(), Status> {
+        if let (Some(auth), Some(client)) = (&self.auth, self.client(request)?) {
+            auth.authorize(&client, scope)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the scope, and that the client owns the account unless it may access any.
+    fn authorize_account<T>(
+        &self,
+        request: &Request<T>,
+        scope: Scope,
+        account_id: Uuid,
+    ) -> Result<(), Status> {
+        if let (Some(auth), Some(client)) = (&self.auth, self.client(request)?) {
+            auth.authorize_account(&client, scope, account_id)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the client may execute the transaction, stamping account creations with it.
+    fn authorize_transaction<T>(
+        &self,
+        request: &Request<T>,
+        transaction: &mut Transaction,
+    ) -> Result<(), Status> {
+        if let (Some(auth), Some(client)) = (&self.auth, self.client(request)?) {
+            auth.authorize_transaction(&client, transaction)?;
+        }
+        if let Some(limiter) = &self.limiter {
+            limiter.check_transaction(transaction)?;
+        }
+        Ok(())
+    }
+
+    fn receipt(
+        &self,
+        transaction: &Transaction,
+        fee: Option<&FeeCharge>,
+        sequence: u64,
+        committed_at: DateTime<Utc>,
+    ) -> Option<server::Receipt> {
+        let receipt = Receipt::new(transaction, fee, sequence, committed_at)?;
+        Some(self.receipt_signer.as_ref()?.sign(receipt).into())
+    }
+
+    /// Executes a write, through the cluster leader when clustered, which authorizes the
+    /// request's client again. Failures of the transaction itself are returned as a message
+    /// for the response, while failing to reach a commit is an error status.
+    async fn execute<T>(
+        &self,
+        request: &Request<T>,
+        transaction: Transaction,
+    ) -> Result<Result<TransactionResult, String>, Status> {
+        let Some(raft) = &self.raft else {
+            return Ok(self
+                .processor
+                .process_transaction(transaction)
+                .map_err(|e| e.to_string()));
+        };
+
+        let client_id = self.client(request)?.map(|client| client.id.clone());
+        match raft.propose(transaction, client_id).await {
+            Ok(result) => Ok(Ok(result)),
+            Err(RaftError::Rejected(message)) => Ok(Err(message)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Followers apply the primary's commits only, and reject every write until promoted.
+    fn ensure_writable(&self) -> Result<(), Status> {
+        if self.replication.is_follower() {
+            return Err(Status::failed_precondition(
+                "This instance is a read-only follower",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Runs a dispute instruction, reporting its failure in the response.
+    async fn dispute_response<T>(
+        &self,
+        request: &Request<T>,
+        transaction: Transaction,
+    ) -> Result<Response<GenericResponse>, Status> {
+        match self.execute(request, transaction).await? {
+            Ok(TransactionResult::Success { .. }) => {
+                info!("Successfully processed dispute request");
+                Ok(Response::new(GenericResponse {
+                    success: true,
+                    ..Default::default()
+                }))
+            }
+            Err(e) => Ok(Response::new(GenericResponse {
+                success: false,
+                error_message: e.to_string(),
+                ..Default::default()
+            })),
+            _ => Err(Status::internal("Unexpected processor result")),
+        }
+    }
+
+    fn balance_as_of(
+        &self,
+        account_id: Uuid,
+        at: DateTime<Utc>,
+    ) -> Result<Response<GetBalanceResponse>, Status> {
+        self.processor
+            .ledger
+            .get_account(account_id)
+            .map_err(|_| Status::not_found("Account not found"))?;
+
+        Ok(Response::new(GetBalanceResponse {
+            balance: self.processor.events.balance_as_of(account_id, at),
+            success: true,
+            ..Default::default()
+        }))
+    }
+
+    fn webhook_store(&self) -> Result<&WebhookStore, Status> {
+        self.webhooks
+            .as_deref()
+            .ok_or_else(|| Status::failed_precondition("Webhooks are disabled"))
+    }
 }
 
 impl TryFrom<TransferRequest> for Transaction {
@@ -52,11 +664,16 @@ impl TryFrom<TransferRequest> for Transaction {
 impl TryFrom<CreateAccountRequest> for Transaction {
     type Error = Status;
     fn try_from(req: CreateAccountRequest) -> Result<Self, Self::Error> {
+        let account_type = server::AccountType::try_from(req.account_type)
+            .map_err(|_| Status::invalid_argument("Invalid account type"))?;
+
         Ok(Transaction {
             id: Uuid::parse_str(&req.transaction_id)
                 .map_err(|_| Status::invalid_argument("Invalid transaction ID"))?,
             instruction: crate::models::Instruction::CreateAccount(CreateAccountInstruction {
                 keys: vec![],
+                account_type: account_type.into(),
+                owner: None,
             }),
             status: TransactionStatus::Pending,
             timestamp: chrono::Utc::now(),
@@ -99,15 +716,150 @@ impl TryFrom<GetBalanceRequest> for Transaction {
     }
 }
 
+impl TryFrom<ReviewTransactionRequest> for Transaction {
+    type Error = Status;
+    fn try_from(req: ReviewTransactionRequest) -> Result<Self, Self::Error> {
+        Ok(Transaction {
+            id: Uuid::parse_str(&req.transaction_id)
+                .map_err(|_| Status::invalid_argument("Invalid transaction ID"))?,
+            instruction: Instruction::Review(ReviewInstruction {
+                held_transaction_id: Uuid::parse_str(&req.held_transaction_id)
+                    .map_err(|_| Status::invalid_argument("Invalid held transaction ID"))?,
+                decision: if req.approve {
+                    ReviewDecision::Approve
+                } else {
+                    ReviewDecision::Reject
+                },
+            }),
+            status: TransactionStatus::Pending,
+            timestamp: chrono::Utc::now(),
+        })
+    }
+}
+
+impl TryFrom<OpenDisputeRequest> for Transaction {
+    type Error = Status;
+    fn try_from(req: OpenDisputeRequest) -> Result<Self, Self::Error> {
+        Ok(Transaction {
+            id: Uuid::parse_str(&req.transaction_id)
+                .map_err(|_| Status::invalid_argument("Invalid transaction ID"))?,
+            instruction: Instruction::OpenDispute(OpenDisputeInstruction {
+                disputed_transaction_id: Uuid::parse_str(&req.disputed_transaction_id)
+                    .map_err(|_| Status::invalid_argument("Invalid disputed transaction ID"))?,
+                amount: req.amount,
+            }),
+            status: TransactionStatus::Pending,
+            timestamp: chrono::Utc::now(),
+        })
+    }
+}
+
+impl TryFrom<UpdateDisputeRequest> for Transaction {
+    type Error = Status;
+    fn try_from(req: UpdateDisputeRequest) -> Result<Self, Self::Error> {
+        let action = match req.action() {
+            DisputeAction::StartAnalysis => crate::models::DisputeAction::StartAnalysis,
+            DisputeAction::Return => crate::models::DisputeAction::Return,
+            DisputeAction::Release => crate::models::DisputeAction::Release,
+            DisputeAction::Unspecified => {
+                return Err(Status::invalid_argument("Missing dispute action"));
+            }
+        };
+        Ok(Transaction {
+            id: Uuid::parse_str(&req.transaction_id)
+                .map_err(|_| Status::invalid_argument("Invalid transaction ID"))?,
+            instruction: Instruction::UpdateDispute(UpdateDisputeInstruction {
+                dispute_id: Uuid::parse_str(&req.dispute_id)
+                    .map_err(|_| Status::invalid_argument("Invalid dispute ID"))?,
+                action,
+            }),
+            status: TransactionStatus::Pending,
+            timestamp: chrono::Utc::now(),
+        })
+    }
+}
+
+impl TryFrom<SubmitTransactionRequest> for Transaction {
+    type Error = Status;
+    fn try_from(req: SubmitTransactionRequest) -> Result<Self, Self::Error> {
+        match req.instruction {
+            Some(submit_transaction_request::Instruction::Transfer(req)) => req.try_into(),
+            Some(submit_transaction_request::Instruction::Deposit(req)) => req.try_into(),
+            Some(submit_transaction_request::Instruction::CreateAccount(req)) => req.try_into(),
+            None => Err(Status::invalid_argument("Missing instruction")),
+        }
+    }
+}
+
+/// Streams the log from `from_sequence` on, or only new entries when it is not given, then every
+/// entry appended afterwards. `map` returns `None` for entries that are not sent.
+fn stream_log<T: Send + 'static>(
+    events: Arc<EventLog>,
+    from_sequence: Option<u64>,
+    map: impl Fn(&CommittedTransaction) -> Option<Result<T, Status>> + Send + 'static,
+) -> ReceiverStream<Result<T, Status>> {
+    let subscription = events.subscribe(from_sequence);
+    let mut receiver = subscription.receiver;
+
+    let (sender, stream_receiver) = tokio::sync::mpsc::channel(128);
+    tokio::spawn(async move {
+        EVENT_SUBSCRIBERS.inc();
+        let mut last_sent =
+            from_sequence.map_or(subscription.last_sequence, |from| from.saturating_sub(1));
+        let mut pending = subscription.backlog;
+
+        'stream: loop {
+            for event in pending.drain(..) {
+                // Events replayed after a lag may overlap with what was already sent.
+                if event.sequence <= last_sent {
+                    continue;
+                }
+                last_sent = event.sequence;
+                if let Some(item) = map(&event)
+                    && sender.send(item).await.is_err()
+                {
+                    break 'stream;
+                }
+            }
+
+            match receiver.recv().await {
+                Ok(event) => pending.push(event),
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    // The subscriber fell behind the broadcast buffer: catch up from the log.
+                    EVENT_SUBSCRIBER_LAGS_TOTAL.inc();
+                    info!(
+                        "Event subscriber lagged by {} events, replaying from the log",
+                        skipped
+                    );
+                    pending = events.since(last_sent + 1);
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+        EVENT_SUBSCRIBERS.dec();
+    });
+
+    ReceiverStream::new(stream_receiver)
+}
+
 #[tonic::async_trait]
 impl GrpcService for QuasarGrpcServer {
+    type WatchTransactionStream =
+        Pin<Box<dyn Stream<Item = Result<TransactionStatusResponse, Status>> + Send>>;
+    type SubscribeEventsStream =
+        Pin<Box<dyn Stream<Item = Result<TransactionEvent, Status>> + Send>>;
+    type ReplicateStream =
+        Pin<Box<dyn Stream<Item = Result<ReplicatedTransaction, Status>> + Send>>;
+
     async fn create_account(
         &self,
         request: Request<CreateAccountRequest>,
     ) -> Result<Response<CreateAccountResponse>, Status> {
-        let domain_transaction = request.into_inner().try_into()?;
+        self.ensure_writable()?;
+        let mut domain_transaction: Transaction = request.get_ref().clone().try_into()?;
+        self.authorize_transaction(&request, &mut domain_transaction)?;
 
-        match self.processor.process_transaction(domain_transaction) {
+        match self.execute(&request, domain_transaction).await? {
             Ok(TransactionResult::AccountCreated(id)) => {
                 info!("Successfully processed create_account request");
 
@@ -134,19 +886,30 @@ impl GrpcService for QuasarGrpcServer {
         &self,
         request: Request<TransferRequest>,
     ) -> Result<Response<GenericResponse>, Status> {
-        let domain_transaction = request.into_inner().try_into()?;
+        self.ensure_writable()?;
+        let mut domain_transaction: Transaction = request.get_ref().clone().try_into()?;
+        self.authorize_transaction(&request, &mut domain_transaction)?;
 
-        match self.processor.process_transaction(domain_transaction) {
-            Ok(TransactionResult::Success) => {
+        match self.execute(&request, domain_transaction.clone()).await? {
+            Ok(TransactionResult::Success {
+                fee,
+                sequence,
+                committed_at,
+            }) => {
                 info!("Successfully processed transfer request");
+                let receipt =
+                    self.receipt(&domain_transaction, fee.as_ref(), sequence, committed_at);
                 Ok(Response::new(GenericResponse {
                     success: true,
+                    fee: fee.map(FeeDetails::from),
+                    receipt,
                     ..Default::default()
                 }))
             }
             Err(e) => Ok(Response::new(GenericResponse {
                 success: false,
                 error_message: e.to_string(),
+                ..Default::default()
             })),
             _ => Err(Status::internal("Unexpected processor result")),
         }
@@ -156,19 +919,30 @@ impl GrpcService for QuasarGrpcServer {
         &self,
         request: Request<DepositRequest>,
     ) -> Result<Response<GenericResponse>, Status> {
-        let domain_transaction = request.into_inner().try_into()?;
+        self.ensure_writable()?;
+        let mut domain_transaction: Transaction = request.get_ref().clone().try_into()?;
+        self.authorize_transaction(&request, &mut domain_transaction)?;
 
-        match self.processor.process_transaction(domain_transaction) {
-            Ok(TransactionResult::Success) => {
+        match self.execute(&request, domain_transaction.clone()).await? {
+            Ok(TransactionResult::Success {
+                fee,
+                sequence,
+                committed_at,
+            }) => {
                 info!("Successfully processed deposit request");
+                let receipt =
+                    self.receipt(&domain_transaction, fee.as_ref(), sequence, committed_at);
                 Ok(Response::new(GenericResponse {
                     success: true,
+                    fee: fee.map(FeeDetails::from),
+                    receipt,
                     ..Default::default()
                 }))
             }
             Err(e) => Ok(Response::new(GenericResponse {
                 success: false,
                 error_message: e.to_string(),
+                ..Default::default()
             })),
             _ => Err(Status::internal("Unexpected processor result")),
         }
@@ -178,7 +952,15 @@ impl GrpcService for QuasarGrpcServer {
         &self,
         request: Request<GetBalanceRequest>,
     ) -> Result<Response<GetBalanceResponse>, Status> {
-        let domain_transaction = request.into_inner().try_into()?;
+        let account_id = Uuid::parse_str(&request.get_ref().account_id)
+            .map_err(|_| Status::invalid_argument("Invalid account ID"))?;
+        self.authorize_account(&request, Scope::Read, account_id)?;
+        let request = request.into_inner();
+        if let Some(at) = parse_timestamp(&request.balance_as_of)? {
+            return self.balance_as_of(account_id, at);
+        }
+
+        let domain_transaction = request.try_into()?;
 
         match self.processor.process_transaction(domain_transaction) {
             Ok(TransactionResult::Balance(amount)) => {
@@ -197,11 +979,413 @@ impl GrpcService for QuasarGrpcServer {
             _ => Err(Status::internal("Unexpected processor result")),
         }
     }
+
+    async fn submit_transaction(
+        &self,
+        request: Request<SubmitTransactionRequest>,
+    ) -> Result<Response<SubmitTransactionResponse>, Status> {
+        self.ensure_writable()?;
+        if self.raft.is_some() {
+            return Err(Status::failed_precondition(
+                "Asynchronous submission is not available in cluster mode",
+            ));
+        }
+        let mut domain_transaction: Transaction = request.get_ref().clone().try_into()?;
+        self.authorize_transaction(&request, &mut domain_transaction)?;
+        let id = self.submissions.submit(domain_transaction)?;
+
+        Ok(Response::new(SubmitTransactionResponse {
+            transaction_id: id.to_string(),
+        }))
+    }
+
+    async fn get_transaction_status(
+        &self,
+        request: Request<TransactionStatusRequest>,
+    ) -> Result<Response<TransactionStatusResponse>, Status> {
+        self.authorize(&request, Scope::Read)?;
+        let id = Uuid::parse_str(&request.into_inner().transaction_id)
+            .map_err(|_| Status::invalid_argument("Invalid transaction ID"))?;
+
+        let status = self
+            .submissions
+            .status(id)
+            .ok_or_else(|| Status::not_found("Transaction was not submitted"))?;
+
+        Ok(Response::new(transaction_status_response(id, status)))
+    }
+
+    async fn watch_transaction(
+        &self,
+        request: Request<TransactionStatusRequest>,
+    ) -> Result<Response<Self::WatchTransactionStream>, Status> {
+        self.authorize(&request, Scope::Read)?;
+        let id = Uuid::parse_str(&request.into_inner().transaction_id)
+            .map_err(|_| Status::invalid_argument("Invalid transaction ID"))?;
+
+        let mut receiver = self
+            .submissions
+            .watch(id)
+            .ok_or_else(|| Status::not_found("Transaction was not submitted"))?;
+
+        // Forward every status change up to and including the first terminal one.
+        let (sender, stream_receiver) = tokio::sync::mpsc::channel(4);
+        tokio::spawn(async move {
+            loop {
+                let status = receiver.borrow_and_update().clone();
+                let terminal = status.is_terminal();
+                if sender
+                    .send(Ok(transaction_status_response(id, status)))
+                    .await
+                    .is_err()
+                    || terminal
+                    || receiver.changed().await.is_err()
+                {
+                    break;
+                }
+            }
+        });
+        let stream = ReceiverStream::new(stream_receiver);
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn subscribe_events(
+        &self,
+        request: Request<SubscribeEventsRequest>,
+    ) -> Result<Response<Self::SubscribeEventsStream>, Status> {
+        let filter = EventFilter::try_from(request.get_ref())?;
+        // Events of every account are only streamed to administrators.
+        match filter.account_id {
+            Some(account_id) => self.authorize_account(&request, Scope::Read, account_id)?,
+            None => self.authorize(&request, Scope::Admin)?,
+        }
+        let request = request.into_inner();
+        let stream = stream_log(
+            self.processor.events.clone(),
+            request.from_sequence,
+            move |event| filter.matches(event).then(|| Ok(event.into())),
+        );
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn replicate(
+        &self,
+        request: Request<ReplicateRequest>,
+    ) -> Result<Response<Self::ReplicateStream>, Status> {
+        self.authorize(&request, Scope::Replicate)?;
+        let from_sequence = request.into_inner().from_sequence.max(1);
+        let stream = stream_log(
+            self.processor.events.clone(),
+            Some(from_sequence),
+            |event| {
+                Some(
+                    serde_json::to_vec(event)
+                        .map(|payload| ReplicatedTransaction {
+                            sequence: event.sequence,
+                            payload,
+                        })
+                        .map_err(|e| Status::internal(e.to_string())),
+                )
+            },
+        );
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn promote(
+        &self,
+        request: Request<PromoteRequest>,
+    ) -> Result<Response<PromoteResponse>, Status> {
+        self.authorize(&request, Scope::Admin)?;
+        let promoted = self.replication.promote();
+        if promoted {
+            info!("Promoted to primary, now accepting writes");
+        }
+
+        Ok(Response::new(PromoteResponse {
+            promoted,
+            last_sequence: self.processor.events.last_sequence(),
+        }))
+    }
+
+    async fn list_transactions(
+        &self,
+        request: Request<ListTransactionsRequest>,
+    ) -> Result<Response<ListTransactionsResponse>, Status> {
+        let account_id = Uuid::parse_str(&request.get_ref().account_id)
+            .map_err(|_| Status::invalid_argument("Invalid account ID"))?;
+        self.authorize_account(&request, Scope::Read, account_id)?;
+        let request = request.into_inner();
+        let query = HistoryQuery::try_from(&request)?;
+
+        self.processor
+            .ledger
+            .get_account(account_id)
+            .map_err(|_| Status::not_found("Account not found"))?;
+
+        let page = self.processor.events.account_history(account_id, &query);
+        Ok(Response::new(ListTransactionsResponse {
+            transactions: page
+                .transactions
+                .iter()
+                .map(|event| event.as_ref().into())
+                .collect(),
+            next_page_token: page
+                .next_cursor
+                .map(|cursor| cursor.to_string())
+                .unwrap_or_default(),
+        }))
+    }
+
+    async fn get_statement(
+        &self,
+        request: Request<GetStatementRequest>,
+    ) -> Result<Response<GetStatementResponse>, Status> {
+        let account_id = Uuid::parse_str(&request.get_ref().account_id)
+            .map_err(|_| Status::invalid_argument("Invalid account ID"))?;
+        self.authorize_account(&request, Scope::Read, account_id)?;
+        let request = request.into_inner();
+        let from = parse_timestamp(&request.from)?.unwrap_or(DateTime::UNIX_EPOCH);
+        let to = parse_timestamp(&request.to)?.unwrap_or_else(Utc::now);
+        let format = StatementFormat::from(request.format());
+        let currency = if request.currency.is_empty() {
+            DEFAULT_STATEMENT_CURRENCY
+        } else {
+            &request.currency
+        };
+
+        self.processor
+            .ledger
+            .get_account(account_id)
+            .map_err(|_| Status::not_found("Account not found"))?;
+
+        let entries = self.processor.events.account_entries(account_id);
+        let statement =
+            Statement::generate(account_id, from, to, entries.iter().map(|e| e.as_ref()))?;
+
+        Ok(Response::new(GetStatementResponse {
+            content: statement.render(format, currency).into_bytes(),
+            content_type: format.content_type().to_string(),
+            opening_balance: statement.opening_balance,
+            closing_balance: statement.closing_balance,
+        }))
+    }
+
+    async fn check_invariants(
+        &self,
+        request: Request<CheckInvariantsRequest>,
+    ) -> Result<Response<CheckInvariantsResponse>, Status> {
+        self.authorize(&request, Scope::Admin)?;
+        let report = InvariantChecker::new(self.processor.clone())
+            .check()
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        Ok(Response::new(CheckInvariantsResponse {
+            ok: report.is_ok(),
+            accounts_checked: report.accounts_checked as u64,
+            transactions_checked: report.transactions_checked as u64,
+            violations: report.violations.iter().map(ToString::to_string).collect(),
+        }))
+    }
+
+    async fn create_backup(
+        &self,
+        request: Request<CreateBackupRequest>,
+    ) -> Result<Response<CreateBackupResponse>, Status> {
+        self.authorize(&request, Scope::Admin)?;
+        let directory = self
+            .backup_directory
+            .as_ref()
+            .ok_or_else(|| Status::failed_precondition("Backups are not configured"))?
+            .join(format!(
+                "quasar-{}",
+                Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+            ));
+
+        let manifest = backup_processor(&self.processor, directory.clone()).await?;
+        info!("Wrote backup to {}", directory.display());
+
+        Ok(Response::new(CreateBackupResponse {
+            path: directory.display().to_string(),
+            created_at: manifest.created_at.to_rfc3339(),
+            sha256: manifest.sha256,
+            size: manifest.size,
+            accounts: manifest.accounts as u64,
+            transactions: manifest.transactions as u64,
+            events: manifest.events as u64,
+        }))
+    }
+
+    async fn register_webhook(
+        &self,
+        request: Request<RegisterWebhookRequest>,
+    ) -> Result<Response<RegisterWebhookResponse>, Status> {
+        let account_id = Uuid::parse_str(&request.get_ref().account_id)
+            .map_err(|_| Status::invalid_argument("Invalid account ID"))?;
+        self.authorize_account(&request, Scope::Write, account_id)?;
+        let request = request.into_inner();
+        let event_types = request
+            .event_types()
+            .map(webhooks::WebhookEventType::from)
+            .collect::<Vec<_>>();
+        if event_types.is_empty() {
+            return Err(Status::invalid_argument(
+                "At least one event type is required",
+            ));
+        }
+
+        // Registering for an unknown account would silently never fire.
+        self.processor
+            .ledger
+            .get_account(account_id)
+            .map_err(|_| Status::not_found("Account not found"))?;
+
+        let webhook =
+            self.webhook_store()?
+                .register(account_id, request.url, request.secret, event_types)?;
+        info!(
+            "Registered webhook {} for account {}",
+            webhook.id, account_id
+        );
+
+        Ok(Response::new(RegisterWebhookResponse {
+            webhook_id: webhook.id.to_string(),
+        }))
+    }
+
+    async fn delete_webhook(
+        &self,
+        request: Request<DeleteWebhookRequest>,
+    ) -> Result<Response<GenericResponse>, Status> {
+        self.authorize(&request, Scope::Write)?;
+        let webhook_id = Uuid::parse_str(&request.get_ref().webhook_id)
+            .map_err(|_| Status::invalid_argument("Invalid webhook ID"))?;
+
+        let store = self.webhook_store()?;
+        let webhook = store.get(webhook_id)?;
+        self.authorize_account(&request, Scope::Write, webhook.account_id)?;
+        store.delete(webhook_id)?;
+
+        Ok(Response::new(GenericResponse {
+            success: true,
+            ..Default::default()
+        }))
+    }
+
+    async fn list_webhooks(
+        &self,
+        request: Request<ListWebhooksRequest>,
+    ) -> Result<Response<ListWebhooksResponse>, Status> {
+        let account_id = &request.get_ref().account_id;
+        // Registrations of every account are only listed to administrators.
+        let account_id = if account_id.is_empty() {
+            self.authorize(&request, Scope::Admin)?;
+            None
+        } else {
+            let account_id = Uuid::parse_str(account_id)
+                .map_err(|_| Status::invalid_argument("Invalid account ID"))?;
+            self.authorize_account(&request, Scope::Read, account_id)?;
+            Some(account_id)
+        };
+
+        let webhooks = self.webhook_store()?.list(account_id)?;
+
+        Ok(Response::new(ListWebhooksResponse {
+            webhooks: webhooks.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn list_held_transactions(
+        &self,
+        request: Request<ListHeldTransactionsRequest>,
+    ) -> Result<Response<ListHeldTransactionsResponse>, Status> {
+        self.authorize(&request, Scope::Admin)?;
+        let risk_engine = self
+            .processor
+            .risk_engine()
+            .ok_or_else(|| Status::failed_precondition("Risk rules are disabled"))?;
+
+        Ok(Response::new(ListHeldTransactionsResponse {
+            transactions: risk_engine
+                .reviews
+                .pending()?
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }))
+    }
+
+    async fn review_transaction(
+        &self,
+        request: Request<ReviewTransactionRequest>,
+    ) -> Result<Response<GenericResponse>, Status> {
+        self.ensure_writable()?;
+        let mut domain_transaction: Transaction = request.get_ref().clone().try_into()?;
+        self.authorize_transaction(&request, &mut domain_transaction)?;
+
+        match self.execute(&request, domain_transaction).await? {
+            Ok(TransactionResult::Success { .. }) => {
+                info!("Successfully processed review request");
+                Ok(Response::new(GenericResponse {
+                    success: true,
+                    ..Default::default()
+                }))
+            }
+            Err(e) => Ok(Response::new(GenericResponse {
+                success: false,
+                error_message: e.to_string(),
+                ..Default::default()
+            })),
+            _ => Err(Status::internal("Unexpected processor result")),
+        }
+    }
+
+    async fn open_dispute(
+        &self,
+        request: Request<OpenDisputeRequest>,
+    ) -> Result<Response<GenericResponse>, Status> {
+        self.ensure_writable()?;
+        let mut domain_transaction: Transaction = request.get_ref().clone().try_into()?;
+        self.authorize_transaction(&request, &mut domain_transaction)?;
+        self.dispute_response(&request, domain_transaction).await
+    }
+
+    async fn update_dispute(
+        &self,
+        request: Request<UpdateDisputeRequest>,
+    ) -> Result<Response<GenericResponse>, Status> {
+        self.ensure_writable()?;
+        let mut domain_transaction: Transaction = request.get_ref().clone().try_into()?;
+        self.authorize_transaction(&request, &mut domain_transaction)?;
+        self.dispute_response(&request, domain_transaction).await
+    }
+
+    async fn list_disputes(
+        &self,
+        request: Request<ListDisputesRequest>,
+    ) -> Result<Response<ListDisputesResponse>, Status> {
+        self.authorize(&request, Scope::Admin)?;
+        let disputes = self
+            .processor
+            .disputes()
+            .ok_or_else(|| Status::failed_precondition("Disputes are disabled"))?;
+
+        Ok(Response::new(ListDisputesResponse {
+            disputes: disputes
+                .store
+                .active()?
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }))
+    }
 }
 
 pub async fn start_grpc_service(
     config: GrpcConfig,
-    processor: Arc<TransactionProcessor>,
+    service: QuasarGrpcServer,
     mut shutdown_receiver: tokio::sync::broadcast::Receiver<()>,
 ) {
     let address = format!("{}:{}", config.address, config.port);
@@ -213,8 +1397,6 @@ pub async fn start_grpc_service(
         }
     };
 
-    let service = QuasarGrpcServer { processor };
-
     let shutdown = async {
         shutdown_receiver.recv().await.ok();
         info!("gRPC server is shutting down...");
@@ -222,8 +1404,32 @@ pub async fn start_grpc_service(
 
     info!("Initializing gRPC server at {}", address);
 
-    if let Err(e) = Server::builder()
-        .add_service(GrpcServiceServer::new(service))
+    let raft = service.raft.clone().map(|node| match &service.auth {
+        Some(authenticator) => RaftGrpcServer::new(node).with_auth(authenticator.clone()),
+        None => RaftGrpcServer::new(node),
+    });
+    let interceptor = AuthInterceptor(service.auth.clone());
+    let limits = LimitLayer::new(service.limiter.clone());
+
+    let mut builder = Server::builder();
+    if let Some(tls) = &config.tls {
+        match grpc_server_tls(tls).and_then(|tls| Ok(builder.tls_config(tls)?)) {
+            Ok(tls_builder) => builder = tls_builder,
+            Err(e) => {
+                error!("Invalid gRPC TLS configuration: {}", e);
+                return;
+            }
+        }
+    }
+
+    // Cluster members talk to each other over the Raft service, authenticated with the
+    // cluster secret rather than as clients.
+    if let Err(e) = builder
+        .add_service(InterceptedService::new(
+            limits.layer(GrpcServiceServer::new(service)),
+            interceptor,
+        ))
+        .add_optional_service(raft.map(RaftServiceServer::new))
         .serve_with_shutdown(socket_addr, shutdown)
         .await
     {
@@ -0,0 +1,358 @@
+use {
+    crate::{
+        auth::{Authenticator, Scope, error::AuthError},
+        config::HttpConfig,
+        events::{CommittedTransaction, history::HistoryQuery},
+        models::InstructionKind,
+        tls::{PeerCertificateAcceptor, PeerCommonName, http_server_tls},
+        transaction_processor::TransactionProcessor,
+    },
+    axum::{
+        Extension, Json, Router,
+        extract::{Path, Query, State},
+        http::{HeaderMap, StatusCode},
+        routing::get,
+    },
+    axum_server::{Handle, tls_rustls::RustlsConfig},
+    chrono::{DateTime, Utc},
+    serde::{Deserialize, Serialize},
+    std::{sync::Arc, time::Duration},
+    tonic::{Code, Status, metadata::MetadataMap},
+    tracing::{error, info},
+    uuid::Uuid,
+};
+
+type HttpError = (StatusCode, Json<ErrorResponse>);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+fn http_error(status: StatusCode, message: impl Into<String>) -> HttpError {
+    (
+        status,
+        Json(ErrorResponse {
+            error: message.into(),
+        }),
+    )
+}
+
+/// Maps authentication and authorization failures as the gRPC API does.
+fn auth_error(error: AuthError) -> HttpError {
+    let status = Status::from(error);
+    let code = match status.code() {
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    http_error(code, status.message())
+}
+
+#[derive(Clone)]
+struct HttpState {
+    processor: Arc<TransactionProcessor>,
+    auth: Option<Arc<Authenticator>>,
+}
+
+/// Query string of `GET /accounts/{account_id}/transactions`, mirroring `ListTransactions`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListTransactionsParams {
+    pub page_size: Option<usize>,
+    pub page_token: Option<u64>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Comma-separated instruction types, e.g. `transfer,deposit`.
+    pub types: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListTransactionsResponse {
+    pub transactions: Vec<CommittedTransaction>,
+    pub next_page_token: Option<u64>,
+}
+
+impl TryFrom<ListTransactionsParams> for HistoryQuery {
+    type Error = HttpError;
+    fn try_from(params: ListTransactionsParams) -> Result<Self, Self::Error> {
+        let instruction_kinds = params
+            .types
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|kind| !kind.is_empty())
+            .map(|kind| match kind {
+                "transfer" => Ok(InstructionKind::Transfer),
+                "deposit" => Ok(InstructionKind::Deposit),
+                "create_account" => Ok(InstructionKind::CreateAccount),
+                "open_dispute" => Ok(InstructionKind::OpenDispute),
+                "update_dispute" => Ok(InstructionKind::UpdateDispute),
+                _ => Err(http_error(
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid instruction type: {kind}"),
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(HistoryQuery {
+            cursor: params.page_token,
+            page_size: params.page_size,
+            from: params.from,
+            to: params.to,
+            instruction_kinds,
+        })
+    }
+}
+
+async fn list_transactions(
+    State(HttpState { processor, auth }): State<HttpState>,
+    peer: Option<Extension<PeerCommonName>>,
+    headers: HeaderMap,
+    Path(account_id): Path<Uuid>,
+    Query(params): Query<ListTransactionsParams>,
+) -> Result<Json<ListTransactionsResponse>, HttpError> {
+    if let Some(auth) = auth {
+        let subject = peer.and_then(|Extension(PeerCommonName(subject))| subject);
+        let client = auth
+            .authenticate(&MetadataMap::from_headers(headers), subject.as_deref())
+            .map_err(auth_error)?;
+        auth.authorize_account(&client, Scope::Read, account_id)
+            .map_err(auth_error)?;
+    }
+    let query = HistoryQuery::try_from(params)?;
+
+    processor
+        .ledger
+        .get_account(account_id)
+        .map_err(|_| http_error(StatusCode::NOT_FOUND, "Account not found"))?;
+
+    let page = processor.events.account_history(account_id, &query);
+    Ok(Json(ListTransactionsResponse {
+        transactions: page
+            .transactions
+            .iter()
+            .map(|event| event.as_ref().clone())
+            .collect(),
+        next_page_token: page.next_cursor,
+    }))
+}
+
+/// With an authenticator, clients authenticate as on the gRPC API, with an API key, a bearer
+/// token or the client certificate added to their requests by [`PeerCertificateAcceptor`], and
+/// need the same scopes and account ownership.
+pub fn router(processor: Arc<TransactionProcessor>, auth: Option<Arc<Authenticator>>) -> Router {
+    Router::new()
+        .route(
+            "/accounts/{account_id}/transactions",
+            get(list_transactions),
+        )
+        .with_state(HttpState { processor, auth })
+}
+
+pub async fn start_http_service(
+    config: HttpConfig,
+    processor: Arc<TransactionProcessor>,
+    auth: Option<Arc<Authenticator>>,
+    mut shutdown_receiver: tokio::sync::broadcast::Receiver<()>,
+) {
+    let address = format!("{}:{}", config.address, config.port);
+    let listener = match tokio::net::TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind HTTP server at {}: {}", address, e);
+            return;
+        }
+    };
+
+    if let Some(tls) = &config.tls {
+        let tls = match http_server_tls(tls) {
+            Ok(tls) => RustlsConfig::from_config(Arc::new(tls)),
+            Err(e) => {
+                error!("Invalid HTTP TLS configuration: {}", e);
+                return;
+            }
+        };
+        let handle = Handle::new();
+        let shutdown_handle = handle.clone();
+        tokio::spawn(async move {
+            shutdown_receiver.recv().await.ok();
+            info!("HTTP server is shutting down...");
+            shutdown_handle.graceful_shutdown(Some(Duration::from_secs(5)));
+        });
+
+        info!("Initializing HTTPS server at {}", address);
+
+        let listener = match listener.into_std() {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Failed to set up HTTPS listener at {}: {}", address, e);
+                return;
+            }
+        };
+        if let Err(e) = axum_server::from_tcp(listener)
+            .acceptor(PeerCertificateAcceptor::new(tls))
+            .handle(handle)
+            .serve(router(processor, auth).into_make_service())
+            .await
+        {
+            error!("Error in HTTP server: {}", e);
+        }
+        return;
+    }
+
+    let shutdown = async move {
+        shutdown_receiver.recv().await.ok();
+        info!("HTTP server is shutting down...");
+    };
+
+    info!("Initializing HTTP server at {}", address);
+
+    if let Err(e) = axum::serve(listener, router(processor, auth))
+        .with_graceful_shutdown(shutdown)
+        .await
+    {
+        error!("Error in HTTP server: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            auth::{API_KEY_HEADER, store::OwnershipStore},
+            config::{AuthClient, AuthConfig},
+            ledger::Ledger,
+            models::{
+                CreateAccountInstruction, DepositInstruction, Instruction, Transaction,
+                TransactionStatus,
+            },
+            transaction_processor::interface::{TransactionProcessorInterface, TransactionResult},
+        },
+        dashmap::DashMap,
+    };
+
+    fn process(processor: &TransactionProcessor, instruction: Instruction) -> TransactionResult {
+        processor
+            .process_transaction(Transaction {
+                id: Uuid::new_v4(),
+                instruction,
+                status: TransactionStatus::Pending,
+                timestamp: Utc::now(),
+            })
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_list_transactions_paginates() {
+        let processor = Arc::new(TransactionProcessor::new(
+            Arc::new(Ledger::default()),
+            DashMap::new(),
+        ));
+        let TransactionResult::AccountCreated(account_id) = process(
+            &processor,
+            Instruction::CreateAccount(CreateAccountInstruction::new(vec![])),
+        ) else {
+            panic!("expected an account");
+        };
+        for amount in [10, 20] {
+            process(
+                &processor,
+                Instruction::Deposit(DepositInstruction {
+                    destination_account_id: account_id,
+                    amount,
+                }),
+            );
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(processor, None)).await });
+
+        let url = format!("http://{address}/accounts/{account_id}/transactions");
+        let page: ListTransactionsResponse =
+            reqwest::get(format!("{url}?types=deposit&page_size=1"))
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+        assert_eq!(page.transactions.len(), 1);
+        assert_eq!(page.transactions[0].postings[0].amount, 20);
+
+        let page: ListTransactionsResponse = reqwest::get(format!(
+            "{url}?types=deposit&page_size=1&page_token={}",
+            page.next_page_token.unwrap()
+        ))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+        assert_eq!(page.transactions[0].postings[0].amount, 10);
+        assert_eq!(page.next_page_token, None);
+
+        let missing = reqwest::get(format!(
+            "http://{address}/accounts/{}/transactions",
+            Uuid::new_v4()
+        ))
+        .await
+        .unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_list_transactions_requires_owning_the_account() {
+        let config = AuthConfig {
+            enabled: true,
+            jwt_secret: String::new(),
+            clients: vec![AuthClient {
+                id: "shop".to_string(),
+                scopes: vec![Scope::Read],
+                accounts: vec![],
+                api_keys: vec!["shop-key".to_string()],
+                certificate_subjects: vec![],
+            }],
+        };
+        let owners = Arc::new(OwnershipStore::open(":memory:").unwrap());
+        let auth = Arc::new(Authenticator::new(&config, owners.clone()).unwrap());
+        let processor = Arc::new(
+            TransactionProcessor::new(Arc::new(Ledger::default()), DashMap::new())
+                .with_owners(owners),
+        );
+        let accounts = [Some("shop".to_string()), None].map(|owner| {
+            let instruction = CreateAccountInstruction {
+                owner,
+                ..CreateAccountInstruction::new(vec![])
+            };
+            match process(&processor, Instruction::CreateAccount(instruction)) {
+                TransactionResult::AccountCreated(account_id) => account_id,
+                result => panic!("unexpected result {result:?}"),
+            }
+        });
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(processor, Some(auth))).await });
+
+        let client = reqwest::Client::new();
+        let [owned, unowned] = accounts
+            .map(|account_id| format!("http://{address}/accounts/{account_id}/transactions"));
+        let anonymous = client.get(&owned).send().await.unwrap();
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+        let listed = client
+            .get(&owned)
+            .header(API_KEY_HEADER, "shop-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(listed.status(), StatusCode::OK);
+        let denied = client
+            .get(&unowned)
+            .header(API_KEY_HEADER, "shop-key")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(denied.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,379 @@
+//! Global ledger invariants.
+//! Checks that money is conserved (account balances add up to everything ever deposited), that
+//! each balance matches the commit log, and that processed transactions, stored transactions and
+//! account histories agree with each other.
+
+use {
+    crate::{
+        events::CommittedTransaction,
+        ledger::error::LedgerError,
+        metrics::{
+            INVARIANT_CHECK_TIME_SECONDS, INVARIANT_CHECKS_TOTAL, INVARIANT_VIOLATIONS,
+            INVARIANT_VIOLATIONS_TOTAL,
+        },
+        models::{Account, Instruction, PostingKind, Transaction},
+        transaction_processor::TransactionProcessor,
+    },
+    dashmap::{DashMap, DashSet},
+    std::{collections::HashMap, fmt, sync::Arc, time::Duration},
+    tokio::{sync::broadcast, time::interval},
+    tracing::{error, info},
+    uuid::Uuid,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The sum of all balances differs from the total deposited.
+    MoneyNotConserved {
+        total_balance: u128,
+        total_deposited: u128,
+    },
+    /// An account balance differs from the balance recorded by the commit log.
+    BalanceMismatch {
+        account_id: Uuid,
+        balance: u64,
+        logged_balance: i128,
+    },
+    /// The commit log moves money on an account the ledger does not know.
+    UnknownLoggedAccount(Uuid),
+    /// A transaction is marked processed but was never stored.
+    UnknownProcessedTransaction(Uuid),
+    /// A transaction is in the commit log but not marked processed.
+    UnprocessedCommittedTransaction { sequence: u64, transaction_id: Uuid },
+    /// An account history references a transaction that is not marked processed.
+    UnprocessedHistoryEntry {
+        account_id: Uuid,
+        transaction_id: Uuid,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::MoneyNotConserved {
+                total_balance,
+                total_deposited,
+            } => write!(
+                f,
+                "Money is not conserved: balances add up to {total_balance} but {total_deposited} was deposited"
+            ),
+            Violation::BalanceMismatch {
+                account_id,
+                balance,
+                logged_balance,
+            } => write!(
+                f,
+                "Account {account_id} has a balance of {balance} but the commit log yields {logged_balance}"
+            ),
+            Violation::UnknownLoggedAccount(account_id) => {
+                write!(f, "Commit log moves money on unknown account {account_id}")
+            }
+            Violation::UnknownProcessedTransaction(transaction_id) => write!(
+                f,
+                "Transaction {transaction_id} is marked processed but was never stored"
+            ),
+            Violation::UnprocessedCommittedTransaction {
+                sequence,
+                transaction_id,
+            } => write!(
+                f,
+                "Transaction {transaction_id} is committed at sequence {sequence} but not marked processed"
+            ),
+            Violation::UnprocessedHistoryEntry {
+                account_id,
+                transaction_id,
+            } => write!(
+                f,
+                "Account {account_id} history references unprocessed transaction {transaction_id}"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InvariantReport {
+    pub accounts_checked: usize,
+    pub transactions_checked: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl InvariantReport {
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Checks every invariant over a consistent view of the state. `entries` must be in sequence
+/// order and every one of them must already be reflected in `accounts` and `processed`.
+pub fn check_state<'a>(
+    accounts: &DashMap<Uuid, Account>,
+    processed: &DashSet<Uuid>,
+    transactions: &DashMap<Uuid, Transaction>,
+    entries: impl IntoIterator<Item = &'a CommittedTransaction>,
+) -> InvariantReport {
+    let mut report = InvariantReport {
+        accounts_checked: accounts.len(),
+        transactions_checked: processed.len(),
+        violations: Vec::new(),
+    };
+
+    let mut total_deposited: u128 = 0;
+    let mut logged_balances: HashMap<Uuid, i128> = HashMap::new();
+    for entry in entries {
+        if !processed.contains(&entry.transaction_id) {
+            report
+                .violations
+                .push(Violation::UnprocessedCommittedTransaction {
+                    sequence: entry.sequence,
+                    transaction_id: entry.transaction_id,
+                });
+        }
+        if let Instruction::Deposit(deposit) = &entry.instruction {
+            total_deposited += u128::from(deposit.amount);
+        }
+        for posting in &entry.postings {
+            *logged_balances.entry(posting.account_id).or_default() += match posting.kind {
+                PostingKind::Debit => -i128::from(posting.amount),
+                PostingKind::Credit => i128::from(posting.amount),
+            };
+        }
+    }
+
+    let mut total_balance: u128 = 0;
+    for account in accounts.iter() {
+        total_balance += u128::from(account.balance);
+
+        let logged_balance = logged_balances.remove(&account.uuid).unwrap_or_default();
+        if logged_balance != i128::from(account.balance) {
+            report.violations.push(Violation::BalanceMismatch {
+                account_id: account.uuid,
+                balance: account.balance,
+                logged_balance,
+            });
+        }
+
+        for transaction_id in &account.transaction_history {
+            if !processed.contains(transaction_id) {
+                report.violations.push(Violation::UnprocessedHistoryEntry {
+                    account_id: account.uuid,
+                    transaction_id: *transaction_id,
+                });
+            }
+        }
+    }
+    report.violations.extend(
+        logged_balances
+            .into_keys()
+            .map(Violation::UnknownLoggedAccount),
+    );
+
+    if total_balance != total_deposited {
+        report.violations.push(Violation::MoneyNotConserved {
+            total_balance,
+            total_deposited,
+        });
+    }
+
+    for transaction_id in processed.iter() {
+        if !transactions.contains_key(&*transaction_id) {
+            report
+                .violations
+                .push(Violation::UnknownProcessedTransaction(*transaction_id));
+        }
+    }
+
+    report
+}
+
+/// Runs invariant checks against a live transaction processor.
+pub struct InvariantChecker {
+    processor: Arc<TransactionProcessor>,
+}
+
+impl InvariantChecker {
+    pub fn new(processor: Arc<TransactionProcessor>) -> Self {
+        InvariantChecker { processor }
+    }
+
+    fn check_once(&self) -> Result<InvariantReport, LedgerError> {
+        let (entries, snapshot) = self
+            .processor
+            .events
+            .read_consistent(|| self.processor.ledger.snapshot());
+        let (accounts, processed) = snapshot?;
+
+        Ok(check_state(
+            &accounts,
+            &processed,
+            &self.processor.transactions,
+            entries.iter().map(|entry| entry.as_ref()),
+        ))
+    }
+
+    /// Checks every invariant over the log and a snapshot of the ledger taken between commits.
+    pub fn check(&self) -> Result<InvariantReport, LedgerError> {
+        let report = measure!(INVARIANT_CHECK_TIME_SECONDS, { self.check_once()? });
+        INVARIANT_CHECKS_TOTAL.inc();
+
+        INVARIANT_VIOLATIONS.set(report.violations.len() as f64);
+        INVARIANT_VIOLATIONS_TOTAL.inc_by(report.violations.len() as f64);
+        for violation in &report.violations {
+            error!("Ledger invariant violated: {}", violation);
+        }
+
+        Ok(report)
+    }
+}
+
+pub async fn start_invariant_checker(
+    checker: InvariantChecker,
+    interval_seconds: u64,
+    mut shutdown_receiver: broadcast::Receiver<()>,
+) {
+    let mut ticker = interval(Duration::from_secs(interval_seconds.max(1)));
+    // The first tick completes immediately; there is nothing worth checking at startup.
+    ticker.tick().await;
+
+    info!("Invariant checker initialized");
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                match checker.check() {
+                    Ok(report) if report.is_ok() => info!(
+                        "Ledger invariants hold over {} accounts and {} transactions",
+                        report.accounts_checked, report.transactions_checked
+                    ),
+                    Ok(report) => error!(
+                        "Found {} ledger invariant violations",
+                        report.violations.len()
+                    ),
+                    Err(e) => error!("Failed to check ledger invariants: {}", e),
+                }
+            }
+            _ = shutdown_receiver.recv() => {
+                info!("Shutting down invariant checker...");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            ledger::{Ledger, interface::LedgerInterface},
+            models::{
+                CreateAccountInstruction, DepositInstruction, TransactionStatus,
+                TransferInstruction,
+            },
+            transaction_processor::interface::{TransactionProcessorInterface, TransactionResult},
+        },
+        chrono::Utc,
+    };
+
+    fn process(processor: &TransactionProcessor, instruction: Instruction) -> TransactionResult {
+        processor
+            .process_transaction(Transaction {
+                id: Uuid::new_v4(),
+                instruction,
+                status: TransactionStatus::Pending,
+                timestamp: Utc::now(),
+            })
+            .unwrap()
+    }
+
+    fn create_account(processor: &TransactionProcessor) -> Uuid {
+        match process(
+            processor,
+            Instruction::CreateAccount(CreateAccountInstruction::new(vec![])),
+        ) {
+            TransactionResult::AccountCreated(account_id) => account_id,
+            result => panic!("unexpected result {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_detects_money_created_outside_the_log() {
+        let ledger = Arc::new(Ledger::default());
+        let processor = Arc::new(TransactionProcessor::new(ledger.clone(), DashMap::new()));
+        let source = create_account(&processor);
+        let destination = create_account(&processor);
+        process(
+            &processor,
+            Instruction::Deposit(DepositInstruction {
+                destination_account_id: source,
+                amount: 100,
+            }),
+        );
+        process(
+            &processor,
+            Instruction::Transfer(TransferInstruction {
+                source_account_id: source,
+                destination_account_id: destination,
+                amount: 40,
+            }),
+        );
+
+        let checker = InvariantChecker::new(processor.clone());
+        assert!(checker.check().unwrap().is_ok());
+
+        // Bypasses the processor, so nothing records where the money came from.
+        let unlogged = Uuid::new_v4();
+        ledger
+            .deposit_into_account(unlogged, destination, 5)
+            .unwrap();
+
+        let report = checker.check().unwrap();
+        assert_eq!(
+            report.violations,
+            vec![
+                Violation::BalanceMismatch {
+                    account_id: destination,
+                    balance: 45,
+                    logged_balance: 40,
+                },
+                Violation::UnprocessedHistoryEntry {
+                    account_id: destination,
+                    transaction_id: unlogged,
+                },
+                Violation::MoneyNotConserved {
+                    total_balance: 105,
+                    total_deposited: 100,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detects_bookkeeping_gaps() {
+        let account_id = Uuid::new_v4();
+        let missing = Uuid::new_v4();
+        let accounts = DashMap::new();
+        accounts.insert(
+            account_id,
+            Account {
+                uuid: account_id,
+                transaction_history: vec![missing],
+                ..Default::default()
+            },
+        );
+        let processed = DashSet::new();
+        let stray = Uuid::new_v4();
+        processed.insert(stray);
+
+        let report = check_state(&accounts, &processed, &DashMap::new(), []);
+        assert_eq!(
+            report.violations,
+            vec![
+                Violation::UnprocessedHistoryEntry {
+                    account_id,
+                    transaction_id: missing,
+                },
+                Violation::UnknownProcessedTransaction(stray),
+            ]
+        );
+    }
+}
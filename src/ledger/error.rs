@@ -16,4 +16,10 @@ pub enum LedgerError {
     TransactionAlreadyProcessed,
     #[error("Insufficient funds")]
     InsufficientFunds,
+    #[error("Account balance would overflow")]
+    BalanceOverflow,
+    #[error("Ledger invariant violated: {0}")]
+    InvariantViolation(String),
+    #[error("Ledger shard {0} is unavailable")]
+    ShardUnavailable(usize),
 }
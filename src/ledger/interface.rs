@@ -1,14 +1,29 @@
 use {
     crate::{
         ledger::error::LedgerError,
-        models::{Account, Key},
+        models::{Account, AccountType, Key, Posting},
     },
+    dashmap::{DashMap, DashSet},
     uuid::Uuid,
 };
 
 pub trait LedgerInterface {
-    /// Creates a new account with the given keys and returns its UUID.
-    fn create_account(&self, keys: Vec<Key>) -> Result<Uuid, LedgerError>;
+    /// Creates the account opened by a transaction with the given keys and returns its UUID,
+    /// which is derived from the transaction ID.
+    fn create_account(
+        &self,
+        transaction_id: Uuid,
+        keys: Vec<Key>,
+        account_type: AccountType,
+    ) -> Result<Uuid, LedgerError>;
+
+    /// Inserts an empty account with a fixed ID if it does not exist yet. Used for system
+    /// accounts, such as the fee revenue account, whose IDs come from configuration.
+    fn ensure_account(&self, id: Uuid, account_type: AccountType) -> Result<(), LedgerError>;
+
+    /// Inserts an account created elsewhere, keeping its ID, unless it already exists. Used by
+    /// followers replaying the primary's commit log.
+    fn insert_account(&self, account: Account) -> Result<(), LedgerError>;
 
     /// Gets a clone of an account by its UUID.
     fn get_account(&self, id: Uuid) -> Result<Account, LedgerError>;
@@ -22,12 +37,42 @@ pub trait LedgerInterface {
         amount: u64,
     ) -> Result<(), LedgerError>;
 
+    /// Atomically applies every posting of a transaction and marks it as processed.
+    /// Either all postings are applied or none of them are.
+    fn commit_postings(
+        &self,
+        transaction_id: Uuid,
+        postings: &[Posting],
+    ) -> Result<(), LedgerError>;
+
     /// Checks if a transaction ID has already been processed.
     fn is_transaction_processed(&self, transaction_id: Uuid) -> Result<bool, LedgerError>;
 
     /// Marks a transaction ID as processed.
     fn mark_transaction_processed(&self, transaction_id: Uuid) -> Result<(), LedgerError>;
 
-    /// Deposits an amount into the specified account.
-    fn deposit_into_account(&self, account_id: Uuid, amount: u64) -> Result<(), LedgerError>;
+    /// Deposits an amount into the specified account, recording `transaction_id` in its
+    /// history.
+    fn deposit_into_account(
+        &self,
+        transaction_id: Uuid,
+        account_id: Uuid,
+        amount: u64,
+    ) -> Result<(), LedgerError>;
+
+    /// Returns the number of accounts in the ledger.
+    fn account_count(&self) -> Result<usize, LedgerError>;
+
+    /// Returns a point-in-time copy of every account and processed transaction ID,
+    /// used to persist the ledger state.
+    fn snapshot(&self) -> Result<(DashMap<Uuid, Account>, DashSet<Uuid>), LedgerError>;
+
+    /// Overwrites accounts and marks transactions as processed from a copy taken with
+    /// `snapshot`, such as one installed from a cluster leader. Accounts missing from it are
+    /// kept as they are.
+    fn restore(
+        &self,
+        accounts: DashMap<Uuid, Account>,
+        processed_transactions: DashSet<Uuid>,
+    ) -> Result<(), LedgerError>;
 }
@@ -1,12 +1,13 @@
 pub mod error;
 pub mod interface;
+pub mod sharded;
 use {
     crate::{
         ledger::{error::LedgerError, interface::LedgerInterface},
         metrics::ACCOUNTS_CREATED_TOTAL,
-        models::{Account, Key},
+        models::{Account, AccountType, Key, Posting, PostingKind},
     },
-    dashmap::{DashMap, DashSet},
+    dashmap::{DashMap, DashSet, mapref::entry::Entry},
     uuid::Uuid,
 };
 
@@ -25,20 +26,72 @@ impl Default for Ledger {
 impl Ledger {
     pub fn new(accounts: DashMap<Uuid, Account>, processed_transactions: DashSet<Uuid>) -> Self {
         Ledger {
-            accounts: accounts,
+            accounts,
             processed_transactions,
         }
     }
+
+    /// Undoes the postings applied by a transaction that failed part way. A credit can only
+    /// fail to revert if a concurrent transaction spent it in between, which breaks the ledger
+    /// invariants and is reported as such.
+    fn revert(&self, debits: &[&Posting], credits: &[&Posting]) -> LedgerError {
+        let mut violation = None;
+        for posting in credits {
+            if let Some(mut account) = self.accounts.get_mut(&posting.account_id) {
+                match account.balance.checked_sub(posting.amount) {
+                    Some(balance) => account.balance = balance,
+                    None => {
+                        violation = Some(LedgerError::InvariantViolation(format!(
+                            "cannot revert a credit of {} on account {} holding {}",
+                            posting.amount, posting.account_id, account.balance
+                        )));
+                    }
+                }
+            }
+        }
+        for posting in debits {
+            if let Some(mut account) = self.accounts.get_mut(&posting.account_id) {
+                account.balance += posting.amount;
+            }
+        }
+        if let Some(violation) = &violation {
+            tracing::error!("{}", violation);
+        }
+        violation.unwrap_or(LedgerError::BalanceOverflow)
+    }
 }
 
 impl LedgerInterface for Ledger {
-    fn create_account(&self, keys: Vec<Key>) -> Result<Uuid, LedgerError> {
-        let (account_id, account) = Account::new(keys);
+    fn create_account(
+        &self,
+        transaction_id: Uuid,
+        keys: Vec<Key>,
+        account_type: AccountType,
+    ) -> Result<Uuid, LedgerError> {
+        let (account_id, mut account) = Account::new(transaction_id, keys);
+        account.account_type = account_type;
         self.accounts.insert(account_id, account);
         ACCOUNTS_CREATED_TOTAL.inc();
         Ok(account_id)
     }
 
+    fn ensure_account(&self, id: Uuid, account_type: AccountType) -> Result<(), LedgerError> {
+        self.accounts.entry(id).or_insert_with(|| Account {
+            uuid: id,
+            account_type,
+            ..Default::default()
+        });
+        Ok(())
+    }
+
+    fn insert_account(&self, account: Account) -> Result<(), LedgerError> {
+        if let Entry::Vacant(entry) = self.accounts.entry(account.uuid) {
+            entry.insert(account);
+            ACCOUNTS_CREATED_TOTAL.inc();
+        }
+        Ok(())
+    }
+
     fn get_account(&self, id: Uuid) -> Result<Account, LedgerError> {
         match self.accounts.get(&id) {
             Some(entry) => Ok(entry.value().clone()),
@@ -53,28 +106,74 @@ impl LedgerInterface for Ledger {
         dest_id: Uuid,
         amount: u64,
     ) -> Result<(), LedgerError> {
+        self.commit_postings(
+            transaction_id,
+            &[
+                Posting::debit(source_id, amount),
+                Posting::credit(dest_id, amount),
+            ],
+        )
+    }
+
+    fn commit_postings(
+        &self,
+        transaction_id: Uuid,
+        postings: &[Posting],
+    ) -> Result<(), LedgerError> {
+        // Accounts are never removed, so once every account is known to exist the credits
+        // below cannot fail for lack of an account. Locks are taken one account at a time to
+        // avoid deadlocking on accounts that live in the same DashMap shard.
+        if postings
+            .iter()
+            .any(|posting| !self.accounts.contains_key(&posting.account_id))
         {
-            let mut source = self
+            return Err(LedgerError::AccountNotFound);
+        }
+
+        let mut applied_debits = Vec::new();
+        for posting in postings.iter().filter(|p| p.kind == PostingKind::Debit) {
+            let mut account = self
                 .accounts
-                .get_mut(&source_id)
+                .get_mut(&posting.account_id)
                 .ok_or(LedgerError::AccountNotFound)?;
 
-            if source.balance < amount {
+            if account.balance < posting.amount {
+                drop(account);
+                self.revert(&applied_debits, &[]);
                 return Err(LedgerError::InsufficientFunds);
             }
 
-            source.balance -= amount;
-            source.transaction_history.push(transaction_id);
+            account.balance -= posting.amount;
+            applied_debits.push(posting);
         }
 
-        {
-            let mut dest = self
+        let mut applied_credits = Vec::new();
+        for posting in postings.iter().filter(|p| p.kind == PostingKind::Credit) {
+            let mut account = self
                 .accounts
-                .get_mut(&dest_id)
+                .get_mut(&posting.account_id)
                 .ok_or(LedgerError::AccountNotFound)?;
 
-            dest.balance += amount;
-            dest.transaction_history.push(transaction_id);
+            match account.balance.checked_add(posting.amount) {
+                Some(balance) => account.balance = balance,
+                None => {
+                    drop(account);
+                    return Err(self.revert(&applied_debits, &applied_credits));
+                }
+            }
+            applied_credits.push(posting);
+        }
+
+        let mut touched_accounts: Vec<Uuid> = Vec::with_capacity(postings.len());
+        for posting in postings {
+            if !touched_accounts.contains(&posting.account_id) {
+                touched_accounts.push(posting.account_id);
+            }
+        }
+        for account_id in touched_accounts {
+            if let Some(mut account) = self.accounts.get_mut(&account_id) {
+                account.transaction_history.push(transaction_id);
+            }
         }
 
         self.processed_transactions.insert(transaction_id);
@@ -91,14 +190,45 @@ impl LedgerInterface for Ledger {
         Ok(())
     }
 
-    fn deposit_into_account(&self, account_id: Uuid, amount: u64) -> Result<(), LedgerError> {
+    fn deposit_into_account(
+        &self,
+        transaction_id: Uuid,
+        account_id: Uuid,
+        amount: u64,
+    ) -> Result<(), LedgerError> {
         let mut account = self
             .accounts
             .get_mut(&account_id)
             .ok_or(LedgerError::AccountNotFound)?;
 
-        account.balance = account.balance.saturating_add(amount);
+        account.balance = account
+            .balance
+            .checked_add(amount)
+            .ok_or(LedgerError::BalanceOverflow)?;
+        account.transaction_history.push(transaction_id);
+
+        Ok(())
+    }
+
+    fn account_count(&self) -> Result<usize, LedgerError> {
+        Ok(self.accounts.len())
+    }
+
+    fn snapshot(&self) -> Result<(DashMap<Uuid, Account>, DashSet<Uuid>), LedgerError> {
+        Ok((self.accounts.clone(), self.processed_transactions.clone()))
+    }
 
+    fn restore(
+        &self,
+        accounts: DashMap<Uuid, Account>,
+        processed_transactions: DashSet<Uuid>,
+    ) -> Result<(), LedgerError> {
+        for (id, account) in accounts {
+            self.accounts.insert(id, account);
+        }
+        for id in processed_transactions {
+            self.processed_transactions.insert(id);
+        }
         Ok(())
     }
 }
@@ -111,7 +241,7 @@ mod tests {
     fn test_create_account() {
         let ledger = Ledger::new(DashMap::new(), DashSet::new());
         let keys = vec![Key::Email("test@test.com".to_string())];
-        let account_id_result = ledger.create_account(keys);
+        let account_id_result = ledger.create_account(Uuid::new_v4(), keys, AccountType::Personal);
         assert!(account_id_result.is_ok());
         let account_id = account_id_result.unwrap();
 
@@ -122,7 +252,9 @@ mod tests {
     #[test]
     fn test_get_existing_account() {
         let ledger = Ledger::new(DashMap::new(), DashSet::new());
-        let account_id = ledger.create_account(vec![]).unwrap();
+        let account_id = ledger
+            .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+            .unwrap();
         let account_result = ledger.get_account(account_id);
         assert!(account_result.is_ok());
         assert_eq!(account_result.unwrap().uuid, account_id);
@@ -131,8 +263,12 @@ mod tests {
     #[test]
     fn test_commit_transfer_and_is_processed() {
         let ledger = Ledger::new(DashMap::new(), DashSet::new());
-        let source_id = ledger.create_account(vec![]).unwrap();
-        let dest_id = ledger.create_account(vec![]).unwrap();
+        let source_id = ledger
+            .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+            .unwrap();
+        let dest_id = ledger
+            .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+            .unwrap();
 
         let mut source_account = ledger.get_account(source_id).unwrap();
         let mut dest_account = ledger.get_account(dest_id).unwrap();
@@ -175,4 +311,36 @@ mod tests {
 
         assert!(ledger.is_transaction_processed(tx_id).unwrap());
     }
+
+    #[test]
+    fn test_overflowing_credit_reverts_every_posting() {
+        let ledger = Ledger::new(DashMap::new(), DashSet::new());
+        let [source_id, first_id, second_id] = [(); 3].map(|()| {
+            ledger
+                .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+                .unwrap()
+        });
+        ledger.accounts.get_mut(&source_id).unwrap().balance = 100;
+        ledger.accounts.get_mut(&first_id).unwrap().balance = 10;
+        ledger.accounts.get_mut(&second_id).unwrap().balance = u64::MAX - 10;
+
+        let transaction_id = Uuid::new_v4();
+        let result = ledger.commit_postings(
+            transaction_id,
+            &[
+                Posting::debit(source_id, 100),
+                Posting::credit(first_id, 50),
+                Posting::credit(second_id, 50),
+            ],
+        );
+
+        assert!(matches!(result, Err(LedgerError::BalanceOverflow)));
+        assert_eq!(ledger.get_account(source_id).unwrap().balance, 100);
+        assert_eq!(ledger.get_account(first_id).unwrap().balance, 10);
+        assert_eq!(
+            ledger.get_account(second_id).unwrap().balance,
+            u64::MAX - 10
+        );
+        assert!(!ledger.is_transaction_processed(transaction_id).unwrap());
+    }
 }
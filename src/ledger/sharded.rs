@@ -0,0 +1,591 @@
+//! Sharded single-writer ledger.
+//! Accounts are partitioned into N shards by account ID. Each shard is owned by a dedicated
+//! thread that consumes commands from a bounded queue, so no account is ever touched by two
+//! threads and no locks are needed. Postings spanning several shards are committed in two
+//! phases: debits are applied first and compensated if any shard rejects them, then credits.
+//! Shards are not locked together, so readers may see a transaction spanning several shards
+//! half applied, with its debits landed and its credits not yet. Only committed transactions
+//! are logged, so the log and any state rebuilt from it never show such an intermediate state.
+
+use {
+    crate::{
+        ledger::{error::LedgerError, interface::LedgerInterface},
+        metrics::ACCOUNTS_CREATED_TOTAL,
+        models::{Account, AccountType, Key, Posting, PostingKind},
+    },
+    dashmap::{DashMap, DashSet},
+    std::{
+        collections::{HashMap, HashSet},
+        sync::mpsc::{Receiver, Sender, SyncSender, channel, sync_channel},
+        thread::JoinHandle,
+    },
+    uuid::Uuid,
+};
+
+enum ShardCommand {
+    InsertAccount {
+        account: Account,
+        replace: bool,
+        reply: Sender<()>,
+    },
+    GetAccount {
+        id: Uuid,
+        reply: Sender<Option<Account>>,
+    },
+    ContainsAccounts {
+        ids: Vec<Uuid>,
+        reply: Sender<bool>,
+    },
+    Apply {
+        transaction_id: Uuid,
+        postings: Vec<Posting>,
+        reply: Sender<Result<(), LedgerError>>,
+    },
+    Revert {
+        transaction_id: Uuid,
+        postings: Vec<Posting>,
+        reply: Sender<Result<(), LedgerError>>,
+    },
+    IsProcessed {
+        id: Uuid,
+        reply: Sender<bool>,
+    },
+    MarkProcessed {
+        id: Uuid,
+        reply: Sender<()>,
+    },
+    Count {
+        reply: Sender<usize>,
+    },
+    Snapshot {
+        reply: Sender<(Vec<Account>, Vec<Uuid>)>,
+    },
+}
+
+/// State owned exclusively by one shard thread.
+#[derive(Default)]
+struct Shard {
+    accounts: HashMap<Uuid, Account>,
+    processed_transactions: HashSet<Uuid>,
+}
+
+impl Shard {
+    fn run(mut self, receiver: Receiver<ShardCommand>) {
+        // Replies are best effort: a caller that gave up waiting is not an error for the shard.
+        while let Ok(command) = receiver.recv() {
+            match command {
+                ShardCommand::InsertAccount {
+                    account,
+                    replace,
+                    reply,
+                } => {
+                    if replace || !self.accounts.contains_key(&account.uuid) {
+                        self.accounts.insert(account.uuid, account);
+                    }
+                    let _ = reply.send(());
+                }
+                ShardCommand::GetAccount { id, reply } => {
+                    let _ = reply.send(self.accounts.get(&id).cloned());
+                }
+                ShardCommand::ContainsAccounts { ids, reply } => {
+                    let _ = reply.send(ids.iter().all(|id| self.accounts.contains_key(id)));
+                }
+                ShardCommand::Apply {
+                    transaction_id,
+                    postings,
+                    reply,
+                } => {
+                    let _ = reply.send(self.apply(transaction_id, &postings));
+                }
+                ShardCommand::Revert {
+                    transaction_id,
+                    postings,
+                    reply,
+                } => {
+                    let _ = reply.send(self.revert(transaction_id, &postings));
+                }
+                ShardCommand::IsProcessed { id, reply } => {
+                    let _ = reply.send(self.processed_transactions.contains(&id));
+                }
+                ShardCommand::MarkProcessed { id, reply } => {
+                    self.processed_transactions.insert(id);
+                    let _ = reply.send(());
+                }
+                ShardCommand::Count { reply } => {
+                    let _ = reply.send(self.accounts.len());
+                }
+                ShardCommand::Snapshot { reply } => {
+                    let _ = reply.send((
+                        self.accounts.values().cloned().collect(),
+                        self.processed_transactions.iter().copied().collect(),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Applies all postings or none of them. Since the shard is single-threaded, validating
+    /// everything before mutating is enough to make the batch atomic.
+    fn apply(&mut self, transaction_id: Uuid, postings: &[Posting]) -> Result<(), LedgerError> {
+        for (account_id, balance) in self.apply_balances(postings)? {
+            if let Some(account) = self.accounts.get_mut(&account_id) {
+                account.balance = balance;
+                account.transaction_history.push(transaction_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The balances of the accounts touched by the postings once they are applied.
+    fn apply_balances(&self, postings: &[Posting]) -> Result<HashMap<Uuid, u64>, LedgerError> {
+        let mut balances: HashMap<Uuid, u64> = HashMap::new();
+        for posting in postings {
+            let balance = match balances.get(&posting.account_id) {
+                Some(balance) => *balance,
+                None => {
+                    self.accounts
+                        .get(&posting.account_id)
+                        .ok_or(LedgerError::AccountNotFound)?
+                        .balance
+                }
+            };
+
+            let balance = match posting.kind {
+                PostingKind::Debit => balance
+                    .checked_sub(posting.amount)
+                    .ok_or(LedgerError::InsufficientFunds)?,
+                PostingKind::Credit => balance
+                    .checked_add(posting.amount)
+                    .ok_or(LedgerError::BalanceOverflow)?,
+            };
+            balances.insert(posting.account_id, balance);
+        }
+        Ok(balances)
+    }
+
+    /// Undoes postings previously applied by [`Shard::apply`]. Fails without changing
+    /// anything if a balance moved in between so that the postings no longer revert, which
+    /// would otherwise create or destroy money.
+    fn revert(&mut self, transaction_id: Uuid, postings: &[Posting]) -> Result<(), LedgerError> {
+        let reverted: Vec<Posting> = postings
+            .iter()
+            .map(|posting| Posting {
+                kind: match posting.kind {
+                    PostingKind::Debit => PostingKind::Credit,
+                    PostingKind::Credit => PostingKind::Debit,
+                },
+                ..posting.clone()
+            })
+            .collect();
+        let balances = self.apply_balances(&reverted).map_err(|e| {
+            LedgerError::InvariantViolation(format!(
+                "cannot revert transaction {transaction_id}: {e}"
+            ))
+        })?;
+
+        for (account_id, balance) in balances {
+            if let Some(account) = self.accounts.get_mut(&account_id) {
+                account.balance = balance;
+                if account.transaction_history.last() == Some(&transaction_id) {
+                    account.transaction_history.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct ShardedLedger {
+    senders: Vec<SyncSender<ShardCommand>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl ShardedLedger {
+    /// Spawns `shard_count` shard threads, each with a queue of `queue_capacity` commands,
+    /// and partitions the given state between them.
+    pub fn new(
+        accounts: DashMap<Uuid, Account>,
+        processed_transactions: DashSet<Uuid>,
+        shard_count: usize,
+        queue_capacity: usize,
+    ) -> Self {
+        let shard_count = shard_count.max(1);
+        let mut shards: Vec<Shard> = (0..shard_count).map(|_| Shard::default()).collect();
+
+        for (id, account) in accounts {
+            shards[shard_index(id, shard_count)]
+                .accounts
+                .insert(id, account);
+        }
+        for id in processed_transactions {
+            shards[shard_index(id, shard_count)]
+                .processed_transactions
+                .insert(id);
+        }
+
+        let mut senders = Vec::with_capacity(shard_count);
+        let mut handles = Vec::with_capacity(shard_count);
+        for (index, shard) in shards.into_iter().enumerate() {
+            let (sender, receiver) = sync_channel(queue_capacity);
+            let handle = std::thread::Builder::new()
+                .name(format!("ledger-shard-{index}"))
+                .spawn(move || shard.run(receiver))
+                .expect("Failed to spawn ledger shard thread");
+
+            senders.push(sender);
+            handles.push(handle);
+        }
+
+        ShardedLedger { senders, handles }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.senders.len()
+    }
+
+    fn shard_for(&self, id: Uuid) -> usize {
+        shard_index(id, self.senders.len())
+    }
+
+    /// Sends a command to a shard and blocks until it replies. Sending blocks while the
+    /// shard queue is full, which applies backpressure to callers.
+    fn call<T>(
+        &self,
+        shard: usize,
+        command: impl FnOnce(Sender<T>) -> ShardCommand,
+    ) -> Result<T, LedgerError> {
+        let (reply, response) = channel();
+        self.senders[shard]
+            .send(command(reply))
+            .map_err(|_| LedgerError::ShardUnavailable(shard))?;
+        response
+            .recv()
+            .map_err(|_| LedgerError::ShardUnavailable(shard))
+    }
+
+    /// Reverts the batches applied by a transaction that failed with `error`, which is
+    /// returned unless the revert itself fails.
+    fn revert(
+        &self,
+        transaction_id: Uuid,
+        applied: Vec<(usize, Vec<Posting>)>,
+        error: LedgerError,
+    ) -> LedgerError {
+        let mut violation = None;
+        for (shard, postings) in applied {
+            let reverted = self
+                .call(shard, |reply| ShardCommand::Revert {
+                    transaction_id,
+                    postings,
+                    reply,
+                })
+                .and_then(|result| result);
+            if let Err(e) = reverted {
+                tracing::error!("Failed to revert transaction {}: {}", transaction_id, e);
+                violation.get_or_insert(e);
+            }
+        }
+        violation.unwrap_or(error)
+    }
+}
+
+impl Drop for ShardedLedger {
+    fn drop(&mut self) {
+        // Closing the queues makes every shard thread leave its loop.
+        self.senders.clear();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn shard_index(id: Uuid, shard_count: usize) -> usize {
+    (id.as_u128() % shard_count as u128) as usize
+}
+
+impl LedgerInterface for ShardedLedger {
+    fn create_account(
+        &self,
+        transaction_id: Uuid,
+        keys: Vec<Key>,
+        account_type: AccountType,
+    ) -> Result<Uuid, LedgerError> {
+        let (account_id, mut account) = Account::new(transaction_id, keys);
+        account.account_type = account_type;
+        self.call(self.shard_for(account_id), |reply| {
+            ShardCommand::InsertAccount {
+                account,
+                replace: true,
+                reply,
+            }
+        })?;
+        ACCOUNTS_CREATED_TOTAL.inc();
+        Ok(account_id)
+    }
+
+    fn ensure_account(&self, id: Uuid, account_type: AccountType) -> Result<(), LedgerError> {
+        let account = Account {
+            uuid: id,
+            account_type,
+            ..Default::default()
+        };
+        self.call(self.shard_for(id), |reply| ShardCommand::InsertAccount {
+            account,
+            replace: false,
+            reply,
+        })
+    }
+
+    fn insert_account(&self, account: Account) -> Result<(), LedgerError> {
+        self.call(self.shard_for(account.uuid), |reply| {
+            ShardCommand::InsertAccount {
+                account,
+                replace: false,
+                reply,
+            }
+        })
+    }
+
+    fn get_account(&self, id: Uuid) -> Result<Account, LedgerError> {
+        self.call(self.shard_for(id), |reply| ShardCommand::GetAccount {
+            id,
+            reply,
+        })?
+        .ok_or(LedgerError::AccountNotFound)
+    }
+
+    fn transfer(
+        &self,
+        transaction_id: Uuid,
+        source_id: Uuid,
+        dest_id: Uuid,
+        amount: u64,
+    ) -> Result<(), LedgerError> {
+        self.commit_postings(
+            transaction_id,
+            &[
+                Posting::debit(source_id, amount),
+                Posting::credit(dest_id, amount),
+            ],
+        )
+    }
+
+    fn commit_postings(
+        &self,
+        transaction_id: Uuid,
+        postings: &[Posting],
+    ) -> Result<(), LedgerError> {
+        let mut by_shard: HashMap<usize, Vec<Posting>> = HashMap::new();
+        for posting in postings {
+            by_shard
+                .entry(self.shard_for(posting.account_id))
+                .or_default()
+                .push(posting.clone());
+        }
+
+        // Accounts are never removed, so checking existence up front guarantees that shards
+        // holding only credits can fail solely on overflow once the debits went through.
+        for (shard, shard_postings) in &by_shard {
+            let ids = shard_postings.iter().map(|p| p.account_id).collect();
+            if !self.call(*shard, |reply| ShardCommand::ContainsAccounts {
+                ids,
+                reply,
+            })? {
+                return Err(LedgerError::AccountNotFound);
+            }
+        }
+
+        // Shards holding debits go first, so a rejected debit aborts before any credit lands.
+        let mut batches: Vec<(usize, Vec<Posting>)> = by_shard.into_iter().collect();
+        batches.sort_by_key(|(_, postings)| !postings.iter().any(|p| p.kind == PostingKind::Debit));
+
+        let mut applied = Vec::with_capacity(batches.len());
+        for (shard, postings) in batches {
+            let result = self.call(shard, |reply| ShardCommand::Apply {
+                transaction_id,
+                postings: postings.clone(),
+                reply,
+            });
+            match result {
+                Ok(Ok(())) => applied.push((shard, postings)),
+                Ok(Err(e)) | Err(e) => return Err(self.revert(transaction_id, applied, e)),
+            }
+        }
+
+        self.mark_transaction_processed(transaction_id)
+    }
+
+    fn is_transaction_processed(&self, transaction_id: Uuid) -> Result<bool, LedgerError> {
+        self.call(self.shard_for(transaction_id), |reply| {
+            ShardCommand::IsProcessed {
+                id: transaction_id,
+                reply,
+            }
+        })
+    }
+
+    fn mark_transaction_processed(&self, transaction_id: Uuid) -> Result<(), LedgerError> {
+        self.call(self.shard_for(transaction_id), |reply| {
+            ShardCommand::MarkProcessed {
+                id: transaction_id,
+                reply,
+            }
+        })
+    }
+
+    fn deposit_into_account(
+        &self,
+        transaction_id: Uuid,
+        account_id: Uuid,
+        amount: u64,
+    ) -> Result<(), LedgerError> {
+        self.call(self.shard_for(account_id), |reply| ShardCommand::Apply {
+            transaction_id,
+            postings: vec![Posting::credit(account_id, amount)],
+            reply,
+        })?
+    }
+
+    fn account_count(&self) -> Result<usize, LedgerError> {
+        let mut count = 0;
+        for shard in 0..self.shard_count() {
+            count += self.call(shard, |reply| ShardCommand::Count { reply })?;
+        }
+        Ok(count)
+    }
+
+    fn snapshot(&self) -> Result<(DashMap<Uuid, Account>, DashSet<Uuid>), LedgerError> {
+        let accounts = DashMap::new();
+        let processed_transactions = DashSet::new();
+        for shard in 0..self.shard_count() {
+            let (shard_accounts, shard_processed) =
+                self.call(shard, |reply| ShardCommand::Snapshot { reply })?;
+            for account in shard_accounts {
+                accounts.insert(account.uuid, account);
+            }
+            for id in shard_processed {
+                processed_transactions.insert(id);
+            }
+        }
+        Ok((accounts, processed_transactions))
+    }
+
+    fn restore(
+        &self,
+        accounts: DashMap<Uuid, Account>,
+        processed_transactions: DashSet<Uuid>,
+    ) -> Result<(), LedgerError> {
+        for (id, account) in accounts {
+            self.call(self.shard_for(id), |reply| ShardCommand::InsertAccount {
+                account,
+                replace: true,
+                reply,
+            })?;
+        }
+        for id in processed_transactions {
+            self.mark_transaction_processed(id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn funded_ledger(shards: usize) -> (ShardedLedger, Vec<Uuid>) {
+        let ledger = ShardedLedger::new(DashMap::new(), DashSet::new(), shards, 16);
+        let ids: Vec<Uuid> = (0..8)
+            .map(|_| {
+                ledger
+                    .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+                    .unwrap()
+            })
+            .collect();
+        for id in &ids {
+            ledger
+                .commit_postings(Uuid::new_v4(), &[Posting::credit(*id, 100)])
+                .unwrap();
+        }
+        (ledger, ids)
+    }
+
+    #[test]
+    fn test_cross_shard_transfer() {
+        let (ledger, ids) = funded_ledger(4);
+        let (source, dest) = ids
+            .iter()
+            .flat_map(|a| ids.iter().map(move |b| (*a, *b)))
+            .find(|(a, b)| ledger.shard_for(*a) != ledger.shard_for(*b))
+            .unwrap();
+
+        let transaction_id = Uuid::new_v4();
+        ledger.transfer(transaction_id, source, dest, 60).unwrap();
+
+        assert_eq!(ledger.get_account(source).unwrap().balance, 40);
+        assert_eq!(ledger.get_account(dest).unwrap().balance, 160);
+        assert!(ledger.is_transaction_processed(transaction_id).unwrap());
+    }
+
+    #[test]
+    fn test_failed_debit_is_reverted_across_shards() {
+        let (ledger, ids) = funded_ledger(4);
+        let (first, second) = ids
+            .iter()
+            .flat_map(|a| ids.iter().map(move |b| (*a, *b)))
+            .find(|(a, b)| ledger.shard_for(*a) != ledger.shard_for(*b))
+            .unwrap();
+        let revenue = ids.iter().copied().find(|id| *id != first && *id != second);
+
+        let result = ledger.commit_postings(
+            Uuid::new_v4(),
+            &[
+                Posting::debit(first, 50),
+                Posting::debit(second, 500),
+                Posting::credit(revenue.unwrap(), 550),
+            ],
+        );
+
+        assert!(matches!(result, Err(LedgerError::InsufficientFunds)));
+        let (accounts, _) = ledger.snapshot().unwrap();
+        assert!(accounts.iter().all(|account| account.balance == 100));
+    }
+
+    #[test]
+    fn test_deposit_records_the_transaction() {
+        let (ledger, ids) = funded_ledger(2);
+        let transaction_id = Uuid::new_v4();
+        ledger
+            .deposit_into_account(transaction_id, ids[0], 5)
+            .unwrap();
+
+        let account = ledger.get_account(ids[0]).unwrap();
+        assert_eq!(account.balance, 105);
+        assert_eq!(account.transaction_history.last(), Some(&transaction_id));
+    }
+
+    #[test]
+    fn test_revert_fails_once_the_credit_is_spent() {
+        let mut shard = Shard::default();
+        let account_id = Uuid::new_v4();
+        shard.accounts.insert(
+            account_id,
+            Account {
+                uuid: account_id,
+                ..Default::default()
+            },
+        );
+        let credit = [Posting::credit(account_id, 50)];
+        shard.apply(Uuid::new_v4(), &credit).unwrap();
+        shard
+            .apply(Uuid::new_v4(), &[Posting::debit(account_id, 30)])
+            .unwrap();
+
+        assert!(matches!(
+            shard.revert(Uuid::new_v4(), &credit),
+            Err(LedgerError::InvariantViolation(_))
+        ));
+        assert_eq!(shard.accounts[&account_id].balance, 20);
+    }
+}
@@ -1,78 +1,497 @@
 use {
     crate::{
-        grpc_server::start_grpc_service, ledger::Ledger, logging::init_logging,
-        metrics::handler::start_metrics_pusher, persistence::Persistence,
-        transaction_processor::TransactionProcessor,
+        audit::{AuditLog, start_audit_log, store::AuditStore},
+        auth::{ApiKey, Authenticator, store::OwnershipStore},
+        config::{ExecutionMode, ReplicationRole},
+        disputes::{DisputeManager, start_deadline_monitor, store::DisputeStore},
+        events::{
+            EventLog,
+            replay::{recover, replay},
+        },
+        fees::FeeEngine,
+        grpc_server::{QuasarGrpcServer, start_grpc_service},
+        http_server::start_http_service,
+        invariants::{InvariantChecker, start_invariant_checker},
+        ledger::{Ledger, interface::LedgerInterface, sharded::ShardedLedger},
+        limits::Limiter,
+        logging::init_logging,
+        metrics::handler::start_metrics_pusher,
+        models::{AccountType, Transaction, TransactionStatus},
+        persistence::{SharedBackend, open_backend},
+        raft::{RaftNode, start_raft, store::RaftStore, transport::GrpcTransport},
+        receipts::ReceiptSigner,
+        replication::{Follower, ReplicationState, start_follower},
+        risk::{RiskEngine, load_rules, store::ReviewStore},
+        submission::{SubmissionQueue, start_submission_workers},
+        transaction_processor::{
+            TransactionProcessor,
+            middleware::{Middleware, MiddlewareChain},
+        },
+        webhooks::{
+            dispatcher::{WebhookDispatcher, start_webhook_dispatcher},
+            store::WebhookStore,
+        },
+    },
+    std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
     },
-    std::sync::Arc,
     tokio::signal::ctrl_c,
-    tracing::{error, info},
+    tracing::{error, info, warn},
 };
 
+#[macro_use]
+pub mod macros;
+pub mod audit;
+pub mod auth;
+pub mod backup;
 pub mod config;
+pub mod disputes;
+pub mod events;
+pub mod fees;
 pub mod grpc_server;
+pub mod http_server;
+pub mod invariants;
 pub mod ledger;
+pub mod limits;
 pub mod logging;
-#[macro_use]
-pub mod macros;
 pub mod metrics;
 pub mod models;
 pub mod persistence;
+pub mod raft;
+pub mod receipts;
+pub mod replication;
+pub mod risk;
+pub mod statements;
+pub mod submission;
+pub mod tls;
 pub mod transaction_processor;
+pub mod webhooks;
 
 pub struct Quasar {
     pub transaction_processor: Arc<TransactionProcessor>,
     pub config: config::QuasarServerConfig,
-    pub persistence: Persistence,
-    ledger: Arc<Ledger>,
+    pub persistence: SharedBackend,
+    pub submissions: Arc<SubmissionQueue>,
+    pub webhooks: Option<Arc<WebhookStore>>,
+    pub replication: Arc<ReplicationState>,
+    pub raft: Option<Arc<RaftNode>>,
+    pub audit: Option<Arc<AuditLog>>,
+    pub receipt_signer: Option<Arc<ReceiptSigner>>,
+    pub authenticator: Option<Arc<Authenticator>>,
+    ledger: Arc<dyn LedgerInterface + Send + Sync>,
 }
 
 impl Quasar {
-    pub fn new(config: config::QuasarServerConfig) -> Self {
-        let persistence = Persistence::new(&config.persistence.db_path)
-            .expect("Failed to initialize persistence");
+    pub fn new(config: config::QuasarServerConfig) -> Result<Self, String> {
+        Quasar::with_middleware(config, Vec::new())
+    }
+
+    /// Like [`Quasar::new`], with custom middlewares run around every instruction. They are
+    /// placed where `execution.middleware` lists their names.
+    pub fn with_middleware(
+        config: config::QuasarServerConfig,
+        middleware: Vec<Arc<dyn Middleware>>,
+    ) -> Result<Self, String> {
+        if config.replication.role == ReplicationRole::Follower
+            && config.replication.primary_url.is_empty()
+        {
+            return Err("Followers need replication.primary_url".to_string());
+        }
+        if config.cluster.enabled && config.replication.role == ReplicationRole::Follower {
+            return Err("Cluster members cannot be replication followers".to_string());
+        }
+        if config.cluster.enabled
+            && config
+                .cluster
+                .peers
+                .iter()
+                .any(|peer| peer.id == config.cluster.node_id)
+        {
+            return Err("cluster.peers must not list this node itself".to_string());
+        }
+        if config.cluster.enabled && config.cluster.secret.is_empty() {
+            return Err("Cluster members need cluster.secret".to_string());
+        }
+
+        if config.audit.enabled && config.audit.checkpoint_key.is_empty() {
+            return Err("The audit log needs audit.checkpoint_key".to_string());
+        }
+
+        let mut account_types = HashMap::new();
+        if config.fees.enabled {
+            account_types.insert(config.fees.revenue_account_id, AccountType::Revenue);
+        }
+        if config.disputes.enabled {
+            account_types.insert(config.disputes.escrow_account_id, AccountType::Escrow);
+        }
+        let mut persistence = open_backend(&config.persistence, account_types)
+            .map_err(|e| format!("Failed to initialize persistence: {e}"))?;
+        let owners = if config.auth.enabled {
+            Some(Arc::new(
+                OwnershipStore::open(&config.persistence.db_path)
+                    .map_err(|e| format!("Failed to initialize account ownership store: {e}"))?,
+            ))
+        } else {
+            None
+        };
+
+        let (accounts, transactions, processed_transactions) = persistence
+            .load_state()
+            .map_err(|e| format!("Failed to load state: {e}"))?;
+        let events = persistence
+            .load_events()
+            .map_err(|e| format!("Failed to load event log: {e}"))?;
 
-        let (accounts, transactions, processed_transactions) =
-            persistence.load_state().expect("Failed to load state");
+        let (accounts, processed_transactions) = if config.events.event_sourced {
+            let projection = Ledger::default();
+            replay(&projection, &events)
+                .map_err(|e| format!("Failed to rebuild the ledger from the event log: {e}"))?;
+            (projection.accounts, projection.processed_transactions)
+        } else {
+            let checkpointed = Ledger::new(accounts, processed_transactions);
+            let recovered = recover(&checkpointed, &events)
+                .map_err(|e| format!("Failed to recover the event log: {e}"))?;
+            if !recovered.is_empty() {
+                warn!(
+                    "Recovered {} transactions logged after the last checkpoint",
+                    recovered.len()
+                );
+            }
+            for event in recovered {
+                // The owner may not have been recorded before the crash.
+                if let Some(owners) = &owners {
+                    owners
+                        .record(event)
+                        .map_err(|e| format!("Failed to record account owners: {e}"))?;
+                }
+                transactions.insert(
+                    event.transaction_id,
+                    Transaction {
+                        id: event.transaction_id,
+                        instruction: event.instruction.clone(),
+                        status: TransactionStatus::Completed,
+                        timestamp: event.committed_at,
+                    },
+                );
+            }
+            (checkpointed.accounts, checkpointed.processed_transactions)
+        };
 
-        let ledger = Arc::new(Ledger::new(accounts, processed_transactions));
+        let ledger: Arc<dyn LedgerInterface + Send + Sync> = match config.execution.mode {
+            ExecutionMode::Dashmap => Arc::new(Ledger::new(accounts, processed_transactions)),
+            ExecutionMode::Sharded => Arc::new(ShardedLedger::new(
+                accounts,
+                processed_transactions,
+                config.execution.shards,
+                config.execution.queue_capacity,
+            )),
+        };
 
-        let transaction_processor =
-            Arc::new(TransactionProcessor::new(ledger.clone(), transactions));
+        let persistence: SharedBackend = Arc::new(Mutex::new(persistence));
+        let events = Arc::new(
+            EventLog::new(events, config.events.broadcast_capacity)
+                .with_journal(persistence.clone()),
+        );
+        let middleware = MiddlewareChain::from_config(&config.execution.middleware, middleware)
+            .map_err(|e| format!("Invalid execution.middleware: {e}"))?;
+        let mut transaction_processor = TransactionProcessor::new(ledger.clone(), transactions)
+            .with_event_log(events)
+            .with_middleware_chain(middleware);
 
-        Quasar {
+        if let Some(fee_engine) = FeeEngine::from_config(&config.fees) {
+            ledger
+                .ensure_account(fee_engine.revenue_account_id, AccountType::Revenue)
+                .map_err(|e| format!("Failed to create fee revenue account: {e}"))?;
+            transaction_processor = transaction_processor.with_fee_engine(fee_engine);
+        }
+
+        if let Some(owners) = &owners {
+            transaction_processor = transaction_processor.with_owners(owners.clone());
+        }
+
+        if config.risk.enabled {
+            let rules = load_rules(&config.risk.rules_path)
+                .map_err(|e| format!("Invalid risk rules in {}: {e}", config.risk.rules_path))?;
+            let reviews = ReviewStore::open(&config.persistence.db_path)
+                .map_err(|e| format!("Failed to initialize review queue: {e}"))?;
+            info!("Screening transfers with {} risk rules", rules.len());
+            transaction_processor = transaction_processor
+                .with_risk_engine(RiskEngine::new(Arc::new(reviews)).with_rules(rules));
+        }
+
+        if config.disputes.enabled {
+            let escrow_account_id = config.disputes.escrow_account_id;
+            ledger
+                .ensure_account(escrow_account_id, AccountType::Escrow)
+                .map_err(|e| format!("Failed to create dispute escrow account: {e}"))?;
+            let store = DisputeStore::open(&config.persistence.db_path)
+                .map_err(|e| format!("Failed to initialize dispute store: {e}"))?;
+            transaction_processor = transaction_processor.with_disputes(DisputeManager::new(
+                escrow_account_id,
+                chrono::Duration::hours(config.disputes.analysis_deadline_hours as i64),
+                Arc::new(store),
+            ));
+        }
+
+        let transaction_processor = Arc::new(transaction_processor);
+
+        let submissions = Arc::new(
+            SubmissionQueue::new(config.submission.queue_capacity).with_retention(
+                Duration::from_secs(config.submission.status_ttl_seconds),
+                config.submission.max_finished_statuses,
+            ),
+        );
+
+        let webhooks = if config.webhooks.enabled {
+            Some(Arc::new(
+                WebhookStore::open(&config.persistence.db_path)
+                    .map_err(|e| format!("Failed to initialize webhook store: {e}"))?,
+            ))
+        } else {
+            None
+        };
+
+        let audit = if config.audit.enabled {
+            let store = AuditStore::open(&config.persistence.db_path)
+                .map_err(|e| format!("Failed to initialize audit log: {e}"))?;
+            Some(Arc::new(AuditLog::new(&config.audit, Arc::new(store))))
+        } else {
+            None
+        };
+
+        let receipt_signer = if config.receipts.enabled {
+            Some(Arc::new(
+                ReceiptSigner::from_hex(&config.receipts.signing_key)
+                    .map_err(|e| format!("Invalid receipts.signing_key: {e}"))?,
+            ))
+        } else {
+            None
+        };
+
+        let authenticator = match owners {
+            Some(owners) => Some(Arc::new(
+                Authenticator::new(&config.auth, owners).map_err(|e| e.to_string())?,
+            )),
+            None => None,
+        };
+
+        let replication = Arc::new(ReplicationState::new(config.replication.role));
+
+        let raft = if config.cluster.enabled {
+            let transport = GrpcTransport::new(
+                &config.cluster.peers,
+                Duration::from_millis(config.cluster.rpc_timeout_ms),
+                config.cluster.tls.as_ref(),
+                &config.cluster.secret,
+            )
+            .map_err(|e| format!("Invalid cluster configuration: {e}"))?;
+            let store = RaftStore::open(&config.persistence.db_path)
+                .map_err(|e| format!("Failed to initialize Raft store: {e}"))?;
+            let node = RaftNode::new(
+                config.cluster.clone(),
+                transaction_processor.clone(),
+                Arc::new(transport),
+                Arc::new(store),
+            )
+            .map_err(|e| format!("Failed to load the Raft state: {e}"))?;
+            Some(Arc::new(node))
+        } else {
+            None
+        };
+
+        Ok(Quasar {
             transaction_processor,
             config,
             persistence,
+            submissions,
+            webhooks,
+            replication,
+            raft,
+            audit,
+            receipt_signer,
+            authenticator,
             ledger,
+        })
+    }
+
+    /// Logs the corrupt rows set aside or repaired while loading the persisted state.
+    fn report_corruptions(&self) {
+        let report = self
+            .persistence
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .load_report();
+        if report.is_empty() {
+            return;
         }
+
+        for corrupt in &report.quarantined {
+            warn!("Quarantined corrupt row: {}", corrupt);
+        }
+        for corrupt in &report.repaired {
+            warn!("Repaired corrupt row: {}", corrupt);
+        }
+        warn!(
+            "Started with {} quarantined and {} repaired rows",
+            report.quarantined.len(),
+            report.repaired.len()
+        );
     }
 
     pub async fn run(&mut self) -> Result<(), String> {
         let (shutdown_sender, _) = tokio::sync::broadcast::channel::<()>(1);
         let mut services = tokio::task::JoinSet::new();
         let _logging_guard = init_logging(self.config.debug);
+        self.report_corruptions();
 
         // Metrics pusher service
         let metrics_config = self.config.metrics.clone();
         let shutdown_receiver = shutdown_sender.subscribe();
 
-        info!("Initializing with {} accounts", self.ledger.accounts.len());
+        info!(
+            "Initializing with {} accounts",
+            self.ledger.account_count().map_err(|e| e.to_string())?
+        );
 
-        // TODO: add REST API service here
         {
             services.spawn(async move {
                 start_metrics_pusher(metrics_config, shutdown_receiver).await;
             });
         }
 
+        // REST API service
+        {
+            let processor = Arc::clone(&self.transaction_processor);
+            let http_config = self.config.http.clone();
+            let authenticator = self.authenticator.clone();
+            let shutdown_receiver = shutdown_sender.subscribe();
+            services.spawn(async move {
+                start_http_service(http_config, processor, authenticator, shutdown_receiver).await
+            });
+        }
+
+        // Asynchronous submission workers
+        {
+            let submissions = Arc::clone(&self.submissions);
+            let processor = Arc::clone(&self.transaction_processor);
+            let workers = self.config.submission.workers;
+            let shutdown_receiver = shutdown_sender.subscribe();
+            services.spawn(async move {
+                start_submission_workers(submissions, processor, workers, shutdown_receiver).await
+            });
+        }
+
+        // Webhook dispatcher
+        if let Some(store) = &self.webhooks {
+            let dispatcher = WebhookDispatcher::new(self.config.webhooks.clone(), store.clone());
+            let events = Arc::clone(&self.transaction_processor.events);
+            let shutdown_receiver = shutdown_sender.subscribe();
+            services.spawn(async move {
+                start_webhook_dispatcher(dispatcher, events, shutdown_receiver).await
+            });
+        }
+
+        // Hash-chained audit log
+        if let Some(audit) = &self.audit {
+            let audit = Arc::clone(audit);
+            let events = Arc::clone(&self.transaction_processor.events);
+            let shutdown_receiver = shutdown_sender.subscribe();
+            services.spawn(async move { start_audit_log(audit, events, shutdown_receiver).await });
+        }
+
+        // Periodic invariant checks
+        if self.config.invariants.enabled {
+            let checker = InvariantChecker::new(Arc::clone(&self.transaction_processor));
+            let interval_seconds = self.config.invariants.interval_seconds;
+            let shutdown_receiver = shutdown_sender.subscribe();
+            services.spawn(async move {
+                start_invariant_checker(checker, interval_seconds, shutdown_receiver).await
+            });
+        }
+
+        // Release of disputes past their deadline
+        if self.config.disputes.enabled {
+            let processor = Arc::clone(&self.transaction_processor);
+            let raft = self.raft.clone();
+            let replication = Arc::clone(&self.replication);
+            let interval_seconds = self.config.disputes.check_interval_seconds;
+            let shutdown_receiver = shutdown_sender.subscribe();
+            services.spawn(async move {
+                start_deadline_monitor(
+                    processor,
+                    raft,
+                    replication,
+                    interval_seconds,
+                    shutdown_receiver,
+                )
+                .await
+            });
+        }
+
+        // Replication from the primary
+        if self.replication.is_follower() {
+            let mut follower = Follower::new(
+                Arc::clone(&self.transaction_processor),
+                self.config.replication.primary_url.clone(),
+            )
+            .with_api_key(
+                ApiKey::new(&self.config.replication.api_key).map_err(|e| e.to_string())?,
+            );
+            if let Some(tls) = &self.config.replication.tls {
+                follower = follower.with_tls(tls.clone());
+            }
+            let replication = Arc::clone(&self.replication);
+            let reconnect_interval_ms = self.config.replication.reconnect_interval_ms;
+            let shutdown_receiver = shutdown_sender.subscribe();
+            services.spawn(async move {
+                start_follower(
+                    follower,
+                    replication,
+                    reconnect_interval_ms,
+                    shutdown_receiver,
+                )
+                .await
+            });
+        }
+
+        // Raft consensus between cluster members
+        if let Some(raft) = &self.raft {
+            let raft = Arc::clone(raft);
+            let shutdown_receiver = shutdown_sender.subscribe();
+            services.spawn(async move { start_raft(raft, shutdown_receiver).await });
+        }
+
         // gRPC service
         {
-            let grpc_processor = Arc::clone(&self.transaction_processor);
+            let mut grpc_service = QuasarGrpcServer::new(
+                Arc::clone(&self.transaction_processor),
+                Arc::clone(&self.submissions),
+            )
+            .with_backup_directory(&self.config.backup.directory)
+            .with_replication(Arc::clone(&self.replication));
+            if let Some(store) = &self.webhooks {
+                grpc_service = grpc_service.with_webhooks(store.clone());
+            }
+            if let Some(raft) = &self.raft {
+                grpc_service = grpc_service.with_raft(raft.clone());
+            }
+            if let Some(signer) = &self.receipt_signer {
+                info!("Signing receipts with public key {}", signer.public_key());
+                grpc_service = grpc_service.with_receipt_signer(signer.clone());
+            }
+            if let Some(authenticator) = &self.authenticator {
+                grpc_service = grpc_service.with_auth(authenticator.clone());
+            }
+            if self.config.limits.enabled {
+                grpc_service =
+                    grpc_service.with_limiter(Arc::new(Limiter::new(&self.config.limits)));
+            }
             let grpc_config = self.config.grpc.clone();
             let shutdown_receiver = shutdown_sender.subscribe();
             services.spawn(async move {
-                start_grpc_service(grpc_config, grpc_processor, shutdown_receiver).await
+                start_grpc_service(grpc_config, grpc_service, shutdown_receiver).await
             })
         };
 
@@ -82,7 +501,17 @@ impl Quasar {
                 services.abort_all();
                 tracing::info!("Shutdown signal received, stopping services...");
 
-                self.persistence.save_state(&self.ledger.accounts, &self.transaction_processor.transactions, &self.ledger.processed_transactions).expect("Failed to save state");
+                // Every commit is already in the stored log. A checkpoint must never be ahead of
+                // it, which it would be once the log failed to store one.
+                let (accounts, processed_transactions) = self.ledger.snapshot().map_err(|e| e.to_string())?;
+                if self.transaction_processor.events.is_halted() {
+                    error!("Skipping the checkpoint: the event log failed to store a commit");
+                } else {
+                    self.persistence.lock().unwrap_or_else(|e| e.into_inner()).checkpoint(&accounts, &self.transaction_processor.transactions, &processed_transactions).expect("Failed to save state");
+                }
+                if let Some(audit) = &self.audit {
+                    audit.append_new_events(&self.transaction_processor.events).expect("Failed to save audit log");
+                }
 
                 tracing::info!("State saved successfully");
             }
@@ -0,0 +1,48 @@
+use {
+    std::time::Duration,
+    thiserror::Error,
+    tonic::{Status, metadata::MetadataValue},
+};
+
+/// Metadata key of the retry hint sent with throttled requests, in milliseconds.
+pub const RETRY_AFTER_HEADER: &str = "retry-after-ms";
+
+#[derive(Debug, Error)]
+pub enum LimitError {
+    #[error("Rate limit exceeded for client {client}")]
+    ClientRateLimited {
+        client: String,
+        retry_after: Duration,
+    },
+    #[error("Rate limit exceeded for account {account_id}")]
+    AccountRateLimited {
+        account_id: uuid::Uuid,
+        retry_after: Duration,
+    },
+    #[error("Server is overloaded")]
+    Overloaded { retry_after: Duration },
+}
+
+impl LimitError {
+    /// How long the caller should wait before trying again.
+    pub fn retry_after(&self) -> Duration {
+        match self {
+            LimitError::ClientRateLimited { retry_after, .. }
+            | LimitError::AccountRateLimited { retry_after, .. }
+            | LimitError::Overloaded { retry_after } => *retry_after,
+        }
+    }
+}
+
+impl From<LimitError> for Status {
+    fn from(error: LimitError) -> Self {
+        let retry_after_ms = error.retry_after().as_millis().max(1);
+        let mut status =
+            Status::resource_exhausted(format!("{error}, retry in {retry_after_ms} ms"));
+        status.metadata_mut().insert(
+            RETRY_AFTER_HEADER,
+            MetadataValue::from(retry_after_ms as u64),
+        );
+        status
+    }
+}
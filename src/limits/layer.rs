@@ -0,0 +1,109 @@
+use {
+    crate::{auth::ClientIdentity, limits::Limiter},
+    std::{
+        future::Future,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    },
+    tonic::{
+        Status,
+        codegen::http,
+        server::NamedService,
+        transport::server::{TcpConnectInfo, TlsConnectInfo},
+    },
+    tower::{Layer, Service},
+};
+
+/// Applies client rate limits and load shedding to a gRPC service, or lets everything through
+/// without a limiter. Meant to run behind the auth interceptor, so that authenticated clients
+/// are limited by identity.
+#[derive(Clone)]
+pub struct LimitLayer {
+    limiter: Option<Arc<Limiter>>,
+}
+
+impl LimitLayer {
+    pub fn new(limiter: Option<Arc<Limiter>>) -> Self {
+        LimitLayer { limiter }
+    }
+}
+
+impl<S> Layer<S> for LimitLayer {
+    type Service = LimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LimitService {
+            inner,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LimitService<S> {
+    inner: S,
+    limiter: Option<Arc<Limiter>>,
+}
+
+/// Authenticated client ID, or else the client's IP address.
+fn client_key<B>(request: &http::Request<B>) -> String {
+    let extensions = request.extensions();
+    if let Some(client) = extensions.get::<Arc<ClientIdentity>>() {
+        return client.id.clone();
+    }
+    extensions
+        .get::<TcpConnectInfo>()
+        .or_else(|| {
+            extensions
+                .get::<TlsConnectInfo<TcpConnectInfo>>()
+                .map(TlsConnectInfo::get_ref)
+        })
+        .and_then(TcpConnectInfo::remote_addr)
+        .map(|address| address.ip().to_string())
+        .unwrap_or_default()
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for LimitService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let admitted = self.limiter.as_ref().map(|limiter| {
+            limiter
+                .check_client(&client_key(&request))
+                .and_then(|()| limiter.admit())
+        });
+        let permit = match admitted.transpose() {
+            Ok(permit) => permit,
+            Err(e) => {
+                let response = Status::from(e).into_http();
+                return Box::pin(async move { Ok(response) });
+            }
+        };
+
+        // The clone is ready only once polled, so keep the service that was.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let response = inner.call(request).await;
+            drop(permit);
+            response
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for LimitService<S> {
+    const NAME: &'static str = S::NAME;
+}
@@ -0,0 +1,352 @@
+//! Rate limiting and load shedding of the gRPC API.
+//! Every client, identified by its authenticated identity or else by its address, and every
+//! account moving money has a token bucket refilled at a steady rate. Independently, the number
+//! of requests in flight is capped by a limit that adapts to processing latency: it shrinks
+//! while requests take longer than the configured threshold and grows back once they are fast
+//! again, so excess load is rejected early instead of queueing up. Rejected requests get a
+//! RESOURCE_EXHAUSTED status with a retry hint.
+
+pub mod error;
+pub mod layer;
+
+use {
+    crate::{
+        config::LimitsConfig,
+        limits::error::LimitError,
+        metrics::{CONCURRENCY_LIMIT, REQUESTS_SHED_TOTAL, REQUESTS_THROTTLED_TOTAL},
+        models::{Instruction, Transaction},
+    },
+    dashmap::DashMap,
+    std::{
+        hash::Hash,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::{Duration, Instant},
+    },
+    uuid::Uuid,
+};
+
+// Idle buckets are dropped once this many keys are tracked.
+const MAX_TRACKED_KEYS: usize = 100_000;
+
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token buckets of `burst` tokens refilled at `rate` tokens per second, one per key.
+pub struct KeyedRateLimiter<K> {
+    rate: f64,
+    burst: f64,
+    buckets: DashMap<K, TokenBucket>,
+}
+
+impl<K: Eq + Hash + Clone> KeyedRateLimiter<K> {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        KeyedRateLimiter {
+            rate,
+            burst: f64::from(burst.max(1)),
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Takes a token from the key's bucket, or returns how long until one is available.
+    pub fn acquire(&self, key: &K, now: Instant) -> Result<(), Duration> {
+        if self.buckets.len() > MAX_TRACKED_KEYS {
+            self.prune(now);
+        }
+
+        let mut bucket = self
+            .buckets
+            .entry(key.clone())
+            .or_insert_with(|| TokenBucket {
+                tokens: self.burst,
+                updated_at: now,
+            });
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    /// Drops the buckets that would be full by now, which behave like new ones.
+    fn prune(&self, now: Instant) {
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated_at);
+            bucket.tokens + elapsed.as_secs_f64() * self.rate < self.burst
+        });
+    }
+}
+
+pub struct Limiter {
+    clients: Option<KeyedRateLimiter<String>>,
+    accounts: Option<KeyedRateLimiter<Uuid>>,
+    in_flight: AtomicUsize,
+    concurrency_limit: AtomicUsize,
+    min_concurrent_requests: usize,
+    max_concurrent_requests: usize,
+    shed_latency: Duration,
+}
+
+impl Limiter {
+    pub fn new(config: &LimitsConfig) -> Self {
+        let max_concurrent_requests = config.max_concurrent_requests.max(1);
+        CONCURRENCY_LIMIT.set(max_concurrent_requests as f64);
+        Limiter {
+            clients: (config.client_rate > 0.0)
+                .then(|| KeyedRateLimiter::new(config.client_rate, config.client_burst)),
+            accounts: (config.account_rate > 0.0)
+                .then(|| KeyedRateLimiter::new(config.account_rate, config.account_burst)),
+            in_flight: AtomicUsize::new(0),
+            concurrency_limit: AtomicUsize::new(max_concurrent_requests),
+            min_concurrent_requests: config
+                .min_concurrent_requests
+                .clamp(1, max_concurrent_requests),
+            max_concurrent_requests,
+            shed_latency: Duration::from_millis(config.shed_latency_ms),
+        }
+    }
+
+    pub fn check_client(&self, client: &str) -> Result<(), LimitError> {
+        let Some(clients) = &self.clients else {
+            return Ok(());
+        };
+        clients
+            .acquire(&client.to_string(), Instant::now())
+            .map_err(|retry_after| {
+                REQUESTS_THROTTLED_TOTAL.inc();
+                LimitError::ClientRateLimited {
+                    client: client.to_string(),
+                    retry_after,
+                }
+            })
+    }
+
+    pub fn check_account(&self, account_id: Uuid) -> Result<(), LimitError> {
+        let Some(accounts) = &self.accounts else {
+            return Ok(());
+        };
+        accounts
+            .acquire(&account_id, Instant::now())
+            .map_err(|retry_after| {
+                REQUESTS_THROTTLED_TOTAL.inc();
+                LimitError::AccountRateLimited {
+                    account_id,
+                    retry_after,
+                }
+            })
+    }
+
+    /// Checks the limit of the account whose balance the transaction changes on request: the
+    /// payer of a transfer, or the credited account of a deposit.
+    pub fn check_transaction(&self, transaction: &Transaction) -> Result<(), LimitError> {
+        match &transaction.instruction {
+            Instruction::Transfer(transfer) => self.check_account(transfer.source_account_id),
+            Instruction::Deposit(deposit) => self.check_account(deposit.destination_account_id),
+            Instruction::CreateAccount(_)
+            | Instruction::GetBalance(_)
+            | Instruction::Review(_)
+            | Instruction::OpenDispute(_)
+            | Instruction::UpdateDispute(_) => Ok(()),
+        }
+    }
+
+    /// Admits a request if fewer than the current concurrency limit are in flight. The
+    /// request's latency is accounted for when the permit is dropped.
+    pub fn admit(self: &Arc<Self>) -> Result<Permit, LimitError> {
+        let limit = self.concurrency_limit.load(Ordering::Relaxed);
+        let admitted = self
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                (in_flight < limit).then_some(in_flight + 1)
+            })
+            .is_ok();
+        if !admitted {
+            REQUESTS_SHED_TOTAL.inc();
+            return Err(LimitError::Overloaded {
+                retry_after: self.shed_latency,
+            });
+        }
+
+        Ok(Permit {
+            limiter: self.clone(),
+            started_at: Instant::now(),
+        })
+    }
+
+    pub fn concurrency_limit(&self) -> usize {
+        self.concurrency_limit.load(Ordering::Relaxed)
+    }
+
+    /// Additive increase while requests are fast, multiplicative decrease while they are slow.
+    fn record_latency(&self, latency: Duration) {
+        let slow = latency > self.shed_latency;
+        let updated =
+            self.concurrency_limit
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |limit| {
+                    let next = if slow {
+                        (limit - limit.div_ceil(10)).max(self.min_concurrent_requests)
+                    } else {
+                        (limit + 1).min(self.max_concurrent_requests)
+                    };
+                    (next != limit).then_some(next)
+                });
+        if let Ok(previous) = updated {
+            CONCURRENCY_LIMIT.set(self.concurrency_limit() as f64);
+            if slow && previous == self.max_concurrent_requests {
+                tracing::warn!(
+                    "Requests take longer than {:?}, shedding load",
+                    self.shed_latency
+                );
+            }
+        }
+    }
+}
+
+/// A request admitted by [`Limiter::admit`], counted as in flight until dropped.
+pub struct Permit {
+    limiter: Arc<Limiter>,
+    started_at: Instant,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.in_flight.fetch_sub(1, Ordering::AcqRel);
+        self.limiter.record_latency(self.started_at.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            auth::AuthInterceptor,
+            grpc_server::{
+                QuasarGrpcServer,
+                server::{
+                    GetBalanceRequest, grpc_service_client::GrpcServiceClient,
+                    grpc_service_server::GrpcServiceServer,
+                },
+            },
+            ledger::Ledger,
+            limits::{error::RETRY_AFTER_HEADER, layer::LimitLayer},
+            submission::SubmissionQueue,
+            transaction_processor::TransactionProcessor,
+        },
+        tokio_stream::wrappers::TcpListenerStream,
+        tonic::{Code, service::interceptor::InterceptedService, transport::Server},
+        tower::Layer,
+    };
+
+    fn config() -> LimitsConfig {
+        LimitsConfig {
+            enabled: true,
+            client_rate: 10.0,
+            client_burst: 2,
+            account_rate: 0.0,
+            account_burst: 1,
+            max_concurrent_requests: 20,
+            min_concurrent_requests: 2,
+            shed_latency_ms: 50,
+        }
+    }
+
+    #[test]
+    fn test_token_bucket_refills_at_rate() {
+        let limiter = KeyedRateLimiter::new(10.0, 2);
+        let start = Instant::now();
+        assert!(limiter.acquire(&"a", start).is_ok());
+        assert!(limiter.acquire(&"a", start).is_ok());
+        let retry_after = limiter.acquire(&"a", start).unwrap_err();
+        assert_eq!(retry_after, Duration::from_millis(100));
+        // Buckets are independent.
+        assert!(limiter.acquire(&"b", start).is_ok());
+
+        let later = start + Duration::from_millis(150);
+        assert!(limiter.acquire(&"a", later).is_ok());
+        assert!(limiter.acquire(&"a", later).is_err());
+    }
+
+    #[test]
+    fn test_concurrency_limit_adapts_to_latency() {
+        let limiter = Arc::new(Limiter::new(&config()));
+        assert!(limiter.check_account(Uuid::new_v4()).is_ok());
+
+        let permits: Vec<_> = (0..20).map(|_| limiter.admit().unwrap()).collect();
+        assert!(matches!(
+            limiter.admit(),
+            Err(LimitError::Overloaded { .. })
+        ));
+        drop(permits);
+
+        for _ in 0..50 {
+            limiter.record_latency(Duration::from_millis(200));
+        }
+        assert_eq!(limiter.concurrency_limit(), 2);
+        let _first = limiter.admit().unwrap();
+        let _second = limiter.admit().unwrap();
+        assert!(limiter.admit().is_err());
+
+        for _ in 0..5 {
+            limiter.record_latency(Duration::from_millis(1));
+        }
+        assert_eq!(limiter.concurrency_limit(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_throttled_requests_get_a_retry_hint() {
+        let processor = Arc::new(TransactionProcessor::new(
+            Arc::new(Ledger::default()),
+            DashMap::new(),
+        ));
+        let limiter = Arc::new(Limiter::new(&LimitsConfig {
+            client_rate: 1.0,
+            ..config()
+        }));
+        let service = QuasarGrpcServer::new(processor, Arc::new(SubmissionQueue::new(8)))
+            .with_limiter(limiter.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(InterceptedService::new(
+                    LimitLayer::new(Some(limiter)).layer(GrpcServiceServer::new(service)),
+                    AuthInterceptor(None),
+                ))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let mut client = GrpcServiceClient::connect(format!("http://{address}"))
+            .await
+            .unwrap();
+        let get_balance = || GetBalanceRequest {
+            transaction_id: Uuid::new_v4().to_string(),
+            account_id: Uuid::new_v4().to_string(),
+            ..Default::default()
+        };
+
+        // The burst of two is used up, and the bucket refills at one request per second.
+        client.get_balance(get_balance()).await.unwrap();
+        client.get_balance(get_balance()).await.unwrap();
+        let status = client.get_balance(get_balance()).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        let retry_after_ms: u64 = status
+            .metadata()
+            .get(RETRY_AFTER_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after_ms > 0 && retry_after_ms <= 1000);
+    }
+}
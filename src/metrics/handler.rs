@@ -144,10 +144,10 @@ pub fn inject_job_label(metrics: &str, job: &str, instance: &str) -> String {
 
 pub fn counter(name: &str, help: &str) -> Counter {
     let counter = Counter::new(name, help).unwrap();
-    if let Err(e) = REGISTRY.register(Box::new(counter.clone())) {
-        if !e.to_string().contains("already registered") {
-            error!("Failed to register counter {}: {}", name, e);
-        }
+    if let Err(e) = REGISTRY.register(Box::new(counter.clone()))
+        && !e.to_string().contains("already registered")
+    {
+        error!("Failed to register counter {}: {}", name, e);
     }
     counter
 }
@@ -160,20 +160,20 @@ pub fn counter_vec(name: &str, help: &str, labels: &[&str]) -> CounterVec {
             .collect(),
     );
     let counter_vec = CounterVec::new(opts, labels).unwrap();
-    if let Err(e) = REGISTRY.register(Box::new(counter_vec.clone())) {
-        if !e.to_string().contains("already registered") {
-            error!("Failed to register counter vector {}: {}", name, e);
-        }
+    if let Err(e) = REGISTRY.register(Box::new(counter_vec.clone()))
+        && !e.to_string().contains("already registered")
+    {
+        error!("Failed to register counter vector {}: {}", name, e);
     }
     counter_vec
 }
 
 pub fn gauge(name: &str, help: &str) -> Gauge {
     let gauge = Gauge::new(name, help).unwrap();
-    if let Err(e) = REGISTRY.register(Box::new(gauge.clone())) {
-        if !e.to_string().contains("already registered") {
-            error!("Failed to register gauge {}: {}", name, e);
-        }
+    if let Err(e) = REGISTRY.register(Box::new(gauge.clone()))
+        && !e.to_string().contains("already registered")
+    {
+        error!("Failed to register gauge {}: {}", name, e);
     }
     gauge
 }
@@ -181,10 +181,10 @@ pub fn gauge(name: &str, help: &str) -> Gauge {
 pub fn gauge_vec(name: &str, help: &str, labels: &[&str]) -> GaugeVec {
     let opts = prometheus::opts!(name, help);
     let gauge_vec = GaugeVec::new(opts, labels).unwrap();
-    if let Err(e) = REGISTRY.register(Box::new(gauge_vec.clone())) {
-        if !e.to_string().contains("already registered") {
-            error!("Failed to register gauge vector {}: {}", name, e);
-        }
+    if let Err(e) = REGISTRY.register(Box::new(gauge_vec.clone()))
+        && !e.to_string().contains("already registered")
+    {
+        error!("Failed to register gauge vector {}: {}", name, e);
     }
     gauge_vec
 }
@@ -207,10 +207,10 @@ pub fn histogram_fast_ops(name: &str, help: &str) -> Histogram {
     let opts = prometheus::histogram_opts!(name, help, buckets);
     let histogram = Histogram::with_opts(opts).unwrap();
 
-    if let Err(e) = REGISTRY.register(Box::new(histogram.clone())) {
-        if !e.to_string().contains("already registered") {
-            error!("Failed to register histogram {}: {}", name, e);
-        }
+    if let Err(e) = REGISTRY.register(Box::new(histogram.clone()))
+        && !e.to_string().contains("already registered")
+    {
+        error!("Failed to register histogram {}: {}", name, e);
     }
 
     histogram
@@ -234,10 +234,10 @@ pub fn histogram_slow_ops(name: &str, help: &str) -> Histogram {
     let opts = prometheus::histogram_opts!(name, help, buckets);
     let histogram = Histogram::with_opts(opts).unwrap();
 
-    if let Err(e) = REGISTRY.register(Box::new(histogram.clone())) {
-        if !e.to_string().contains("already registered") {
-            error!("Failed to register histogram {}: {}", name, e);
-        }
+    if let Err(e) = REGISTRY.register(Box::new(histogram.clone()))
+        && !e.to_string().contains("already registered")
+    {
+        error!("Failed to register histogram {}: {}", name, e);
     }
 
     histogram
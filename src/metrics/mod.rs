@@ -1,6 +1,6 @@
 use {
-    crate::metrics::handler::{counter, histogram_fast_ops, histogram_slow_ops},
-    prometheus::{Counter, Histogram},
+    crate::metrics::handler::{counter, gauge, histogram_fast_ops, histogram_slow_ops},
+    prometheus::{Counter, Gauge, Histogram},
 };
 pub mod handler;
 lazy_static::lazy_static!(
@@ -28,4 +28,97 @@ lazy_static::lazy_static!(
 
     pub static ref GET_BALANCE_TIME_SECONDS: Histogram =
         histogram_fast_ops("get_balance_time_seconds", "Total time spent getting account balance in seconds");
+
+    pub static ref COMMITTED_EVENTS_TOTAL: Counter =
+        counter("committed_events_total", "Total number of transactions appended to the event log");
+
+    pub static ref EVENT_SUBSCRIBERS: Gauge =
+        gauge("event_subscribers", "Number of connected event feed subscribers");
+
+    pub static ref EVENT_SUBSCRIBER_LAGS_TOTAL: Counter =
+        counter("event_subscriber_lags_total", "Total number of times a slow event subscriber fell behind the broadcast and was replayed from the log");
+
+    pub static ref WEBHOOK_DELIVERIES_SUCCEEDED_TOTAL: Counter =
+        counter("webhook_deliveries_succeeded_total", "Total number of webhook deliveries acknowledged by the receiver");
+
+    pub static ref WEBHOOK_DELIVERIES_FAILED_TOTAL: Counter =
+        counter("webhook_deliveries_failed_total", "Total number of failed webhook delivery attempts that will be retried");
+
+    pub static ref WEBHOOK_DELIVERIES_DEAD_TOTAL: Counter =
+        counter("webhook_deliveries_dead_total", "Total number of webhook deliveries abandoned after exhausting retries");
+
+    pub static ref WEBHOOK_OUTBOX_DEPTH: Gauge =
+        gauge("webhook_outbox_depth", "Number of webhook deliveries waiting to be attempted");
+
+    pub static ref WEBHOOK_DELIVERY_TIME_SECONDS: Histogram =
+        histogram_slow_ops("webhook_delivery_time_seconds", "Time spent on each webhook delivery attempt in seconds");
+
+    pub static ref SUBMISSION_QUEUE_DEPTH: Gauge =
+        gauge("submission_queue_depth", "Number of submitted transactions waiting to be processed");
+
+    pub static ref SUBMISSIONS_REJECTED_TOTAL: Counter =
+        counter("submissions_rejected_total", "Total number of submissions rejected because the queue was full");
+
+    pub static ref INVARIANT_CHECKS_TOTAL: Counter =
+        counter("invariant_checks_total", "Total number of ledger invariant checks run");
+
+    pub static ref INVARIANT_VIOLATIONS: Gauge =
+        gauge("invariant_violations", "Number of ledger invariant violations found by the last check");
+
+    pub static ref INVARIANT_VIOLATIONS_TOTAL: Counter =
+        counter("invariant_violations_total", "Total number of ledger invariant violations found");
+
+    pub static ref INVARIANT_CHECK_TIME_SECONDS: Histogram =
+        histogram_slow_ops("invariant_check_time_seconds", "Time spent checking ledger invariants in seconds");
+
+    pub static ref REPLICATED_EVENTS_TOTAL: Counter =
+        counter("replicated_events_total", "Total number of committed transactions applied from the primary");
+
+    pub static ref REPLICATION_APPLIED_SEQUENCE: Gauge =
+        gauge("replication_applied_sequence", "Sequence number of the last committed transaction received from the primary");
+
+    pub static ref RAFT_TERM: Gauge =
+        gauge("raft_term", "Current Raft term of this cluster node");
+
+    pub static ref RAFT_IS_LEADER: Gauge =
+        gauge("raft_is_leader", "Whether this cluster node is the Raft leader (1) or not (0)");
+
+    pub static ref RAFT_COMMIT_INDEX: Gauge =
+        gauge("raft_commit_index", "Index of the last Raft log entry known to be committed");
+
+    pub static ref RAFT_ELECTIONS_TOTAL: Counter =
+        counter("raft_elections_total", "Total number of elections started by this cluster node");
+
+    pub static ref RAFT_SNAPSHOTS_TOTAL: Counter =
+        counter("raft_snapshots_total", "Total number of Raft log compactions into a ledger snapshot");
+
+    pub static ref AUDIT_CHAIN_SEQUENCE: Gauge =
+        gauge("audit_chain_sequence", "Sequence number of the last committed transaction appended to the audit log");
+
+    pub static ref AUDIT_CHECKPOINTS_TOTAL: Counter =
+        counter("audit_checkpoints_total", "Total number of signed audit log checkpoints stored");
+
+    pub static ref AUTH_REJECTIONS_TOTAL: Counter =
+        counter("auth_rejections_total", "Total number of gRPC requests rejected as unauthenticated or unauthorized");
+
+    pub static ref REQUESTS_THROTTLED_TOTAL: Counter =
+        counter("requests_throttled_total", "Total number of gRPC requests rejected by a client or account rate limit");
+
+    pub static ref REQUESTS_SHED_TOTAL: Counter =
+        counter("requests_shed_total", "Total number of gRPC requests rejected because the concurrency limit was reached");
+
+    pub static ref CONCURRENCY_LIMIT: Gauge =
+        gauge("concurrency_limit", "Number of gRPC requests currently allowed in flight, lowered while processing is slow");
+
+    pub static ref TRANSFERS_DENIED_TOTAL: Counter =
+        counter("transfers_denied_total", "Total number of transfers denied by a risk rule");
+
+    pub static ref TRANSFERS_HELD_TOTAL: Counter =
+        counter("transfers_held_total", "Total number of transfers held for review by a risk rule");
+    pub static ref DISPUTES_OPENED_TOTAL: Counter =
+        counter("disputes_opened_total", "Total number of disputes opened against transfers");
+    pub static ref DISPUTES_EXPIRED_TOTAL: Counter = counter(
+        "disputes_expired_total",
+        "Total number of disputes released to the payee at their deadline"
+    );
 );
@@ -5,7 +5,7 @@ use {
 };
 
 /// Represents a possible identifier for an account.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Key {
     CPF(String),
     Email(String),
@@ -13,6 +13,27 @@ pub enum Key {
     Random(String),
 }
 
+impl Key {
+    /// The key itself, whatever its type.
+    pub fn value(&self) -> &str {
+        match self {
+            Key::CPF(value) | Key::Email(value) | Key::Phone(value) | Key::Random(value) => value,
+        }
+    }
+}
+
+/// Category of an account, used to select which fee rules apply to it.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountType {
+    #[default]
+    Personal,
+    Merchant,
+    Revenue,
+    // Holds the funds blocked by open disputes.
+    Escrow,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TransactionStatus {
     Pending,
@@ -20,12 +41,42 @@ pub enum TransactionStatus {
     Failed,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Instruction {
     Transfer(TransferInstruction),
     CreateAccount(CreateAccountInstruction),
     Deposit(DepositInstruction),
     GetBalance(GetBalanceInstruction),
+    Review(ReviewInstruction),
+    OpenDispute(OpenDisputeInstruction),
+    UpdateDispute(UpdateDisputeInstruction),
+}
+
+/// Discriminant of an [`Instruction`], without its payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstructionKind {
+    Transfer,
+    CreateAccount,
+    Deposit,
+    GetBalance,
+    Review,
+    OpenDispute,
+    UpdateDispute,
+}
+
+impl Instruction {
+    pub fn kind(&self) -> InstructionKind {
+        match self {
+            Instruction::Transfer(_) => InstructionKind::Transfer,
+            Instruction::CreateAccount(_) => InstructionKind::CreateAccount,
+            Instruction::Deposit(_) => InstructionKind::Deposit,
+            Instruction::GetBalance(_) => InstructionKind::GetBalance,
+            Instruction::Review(_) => InstructionKind::Review,
+            Instruction::OpenDispute(_) => InstructionKind::OpenDispute,
+            Instruction::UpdateDispute(_) => InstructionKind::UpdateDispute,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,56 +87,152 @@ pub struct Transaction {
     pub timestamp: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TransferInstruction {
     pub source_account_id: Uuid,
     pub destination_account_id: Uuid,
     pub amount: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CreateAccountInstruction {
     pub keys: Vec<Key>,
+    #[serde(default)]
+    pub account_type: AccountType,
+    // Client the account is created for, set by the API from its credentials. Recorded as the
+    // owner by every node committing or replicating the creation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
 }
 
 impl CreateAccountInstruction {
     pub fn new(keys: Vec<Key>) -> Self {
-        CreateAccountInstruction { keys }
+        CreateAccountInstruction {
+            keys,
+            account_type: AccountType::default(),
+            owner: None,
+        }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DepositInstruction {
     pub destination_account_id: Uuid,
     pub amount: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GetBalanceInstruction {
     pub account_id: Uuid,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewDecision {
+    Approve,
+    Reject,
+}
+
+/// Decision on a transaction held for review by the risk engine. Approving it commits the held
+/// transaction as it was submitted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReviewInstruction {
+    pub held_transaction_id: Uuid,
+    pub decision: ReviewDecision,
+}
+
+/// Opens a dispute against a committed transfer, blocking up to `amount` of the payee's
+/// balance. The dispute is identified by the ID of this transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenDisputeInstruction {
+    pub disputed_transaction_id: Uuid,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeAction {
+    StartAnalysis,
+    /// Returns the blocked funds to the payer.
+    Return,
+    /// Releases the blocked funds to the payee.
+    Release,
+    /// Releases the blocked funds once the analysis deadline has passed.
+    Expire,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateDisputeInstruction {
+    pub dispute_id: Uuid,
+    pub action: DisputeAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PostingKind {
+    Debit,
+    Credit,
+}
+
+/// A single balance movement on one account. A transaction commits one or more postings at once.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Posting {
+    pub account_id: Uuid,
+    pub kind: PostingKind,
+    pub amount: u64,
+}
+
+impl Posting {
+    pub fn debit(account_id: Uuid, amount: u64) -> Self {
+        Posting {
+            account_id,
+            kind: PostingKind::Debit,
+            amount,
+        }
+    }
+
+    pub fn credit(account_id: Uuid, amount: u64) -> Self {
+        Posting {
+            account_id,
+            kind: PostingKind::Credit,
+            amount,
+        }
+    }
+}
+
 /// Account is very simplified, since we don't really care about user data
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     pub uuid: Uuid,
     pub balance: u64,
     pub keys: Vec<Key>,
+    #[serde(default)]
+    pub account_type: AccountType,
     // Using indirection to avoid data duplication. The vector stores transaction IDs.
     pub transaction_history: Vec<Uuid>,
 }
 
+// Namespace of the name-based account IDs derived from creating transaction IDs.
+const ACCOUNT_ID_NAMESPACE: Uuid = Uuid::from_u128(0x6f1d_5c3e_8a42_4b7e_9d0c_2e51_a7f3_b864);
+
 impl Account {
-    pub fn new(keys: Vec<Key>) -> (Uuid, Self) {
-        let uuid = Uuid::new_v4();
+    /// Creates the account opened by the given transaction. Its ID is derived from the
+    /// transaction ID, so replaying the transaction always yields the same account.
+    pub fn new(transaction_id: Uuid, keys: Vec<Key>) -> (Uuid, Self) {
+        let uuid = Account::id_for(transaction_id);
 
         let account = Account {
             uuid,
             balance: 0,
             keys,
+            account_type: AccountType::default(),
             transaction_history: vec![],
         };
 
         (uuid, account)
     }
+
+    /// ID of the account created by the given transaction.
+    pub fn id_for(transaction_id: Uuid) -> Uuid {
+        Uuid::new_v5(&ACCOUNT_ID_NAMESPACE, transaction_id.as_bytes())
+    }
 }
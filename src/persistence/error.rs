@@ -0,0 +1,31 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PersistenceError {
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Encoding error: {0}")]
+    Encoding(#[from] serde_json::Error),
+    #[error("Database schema version {found} is newer than the supported version {supported}")]
+    SchemaTooNew { found: u32, supported: u32 },
+    #[error("{table}.{column} value {value} does not fit in a database integer")]
+    OutOfRange {
+        table: &'static str,
+        column: &'static str,
+        value: u64,
+    },
+    #[error("Corrupt data: {0}")]
+    CorruptRow(#[from] CorruptRow),
+}
+
+/// A stored row that could not be decoded. For the file backend, the table is `journal` and the
+/// row ID is the line number.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{table} row {row_id}: {reason}")]
+pub struct CorruptRow {
+    pub table: String,
+    pub row_id: i64,
+    pub reason: String,
+}
@@ -0,0 +1,210 @@
+use {
+    crate::{
+        config::CorruptionPolicy,
+        events::CommittedTransaction,
+        models::{Account, Transaction},
+        persistence::{
+            LoadReport, LoadedState, PersistenceBackend,
+            error::{CorruptRow, PersistenceError},
+        },
+    },
+    dashmap::{DashMap, DashSet},
+    serde::{Deserialize, Serialize},
+    std::{
+        fs::{File, OpenOptions},
+        io::Write,
+        sync::Arc,
+    },
+    tracing::warn,
+    uuid::Uuid,
+};
+
+/// A line of the journal.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum JournalRecord {
+    Checkpoint {
+        accounts: Vec<Account>,
+        transactions: Vec<Transaction>,
+        processed_transactions: Vec<Uuid>,
+    },
+    Event(CommittedTransaction),
+}
+
+/// Writes checkpoints and committed transactions as JSON lines to a single append-only
+/// journal. The state is the last checkpoint in the journal and the log is every event line.
+///
+/// The journal is never rewritten, so quarantined lines stay in place and are skipped on every
+/// load. Lines cannot be partially repaired: the repair policy quarantines them as well.
+pub struct FileBackend {
+    path: String,
+    journal: File,
+    policy: CorruptionPolicy,
+    report: LoadReport,
+    // Read from the journal on the first append.
+    last_sequence: Option<u64>,
+}
+
+impl FileBackend {
+    pub fn open(path: &str) -> Result<Self, PersistenceError> {
+        let mut backend = FileBackend {
+            path: path.to_string(),
+            journal: OpenOptions::new().create(true).append(true).open(path)?,
+            policy: CorruptionPolicy::default(),
+            report: LoadReport::default(),
+            last_sequence: None,
+        };
+        backend.truncate_torn_tail()?;
+        Ok(backend)
+    }
+
+    pub fn with_corruption_policy(mut self, policy: CorruptionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// A crash while appending can leave a partial last line. It is dropped so that the
+    /// next record starts on a line of its own.
+    fn truncate_torn_tail(&mut self) -> Result<(), PersistenceError> {
+        let content = std::fs::read(&self.path)?;
+        if content.is_empty() || content.ends_with(b"\n") {
+            return Ok(());
+        }
+
+        let complete = content
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map_or(0, |position| position + 1);
+        warn!(
+            "Dropping {} bytes of a partially written record at the end of {}",
+            content.len() - complete,
+            self.path
+        );
+        self.journal.set_len(complete as u64)?;
+        Ok(())
+    }
+
+    fn records(&mut self) -> Result<Vec<JournalRecord>, PersistenceError> {
+        let content = std::fs::read_to_string(&self.path)?;
+        let mut records = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(record) => records.push(record),
+                Err(e) => {
+                    let corrupt = CorruptRow {
+                        table: "journal".to_string(),
+                        row_id: index as i64 + 1,
+                        reason: e.to_string(),
+                    };
+                    if self.policy == CorruptionPolicy::Fail {
+                        return Err(corrupt.into());
+                    }
+                    // Both loads read the whole journal: report each line once.
+                    if !self.report.quarantined.contains(&corrupt) {
+                        self.report.quarantined.push(corrupt);
+                    }
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    fn append(&mut self, records: &[JournalRecord]) -> Result<(), PersistenceError> {
+        let mut buffer = String::new();
+        for record in records {
+            buffer.push_str(&serde_json::to_string(record)?);
+            buffer.push('\n');
+        }
+        self.journal.write_all(buffer.as_bytes())?;
+        self.journal.sync_data()?;
+        Ok(())
+    }
+}
+
+impl PersistenceBackend for FileBackend {
+    fn load_state(&mut self) -> Result<LoadedState, PersistenceError> {
+        let accounts = DashMap::new();
+        let transactions = DashMap::new();
+        let processed = DashSet::new();
+
+        let checkpoint = self
+            .records()?
+            .into_iter()
+            .rev()
+            .find_map(|record| match record {
+                JournalRecord::Checkpoint {
+                    accounts,
+                    transactions,
+                    processed_transactions,
+                } => Some((accounts, transactions, processed_transactions)),
+                JournalRecord::Event(_) => None,
+            });
+        if let Some((saved_accounts, saved_transactions, saved_processed)) = checkpoint {
+            for account in saved_accounts {
+                accounts.insert(account.uuid, account);
+            }
+            for transaction in saved_transactions {
+                transactions.insert(transaction.id, transaction);
+            }
+            for id in saved_processed {
+                processed.insert(id);
+            }
+        }
+
+        Ok((accounts, transactions, processed))
+    }
+
+    fn load_events(&mut self) -> Result<Vec<CommittedTransaction>, PersistenceError> {
+        Ok(self
+            .records()?
+            .into_iter()
+            .filter_map(|record| match record {
+                JournalRecord::Event(event) => Some(event),
+                JournalRecord::Checkpoint { .. } => None,
+            })
+            .collect())
+    }
+
+    fn load_report(&self) -> LoadReport {
+        self.report.clone()
+    }
+
+    fn checkpoint(
+        &mut self,
+        accounts: &DashMap<Uuid, Account>,
+        transactions: &DashMap<Uuid, Transaction>,
+        processed_transactions: &DashSet<Uuid>,
+    ) -> Result<(), PersistenceError> {
+        self.append(&[JournalRecord::Checkpoint {
+            accounts: accounts.iter().map(|a| a.value().clone()).collect(),
+            transactions: transactions.iter().map(|t| t.value().clone()).collect(),
+            processed_transactions: processed_transactions.iter().map(|id| *id).collect(),
+        }])
+    }
+
+    fn append_events(
+        &mut self,
+        events: &[Arc<CommittedTransaction>],
+    ) -> Result<(), PersistenceError> {
+        let last_sequence = match self.last_sequence {
+            Some(sequence) => sequence,
+            None => self.load_events()?.last().map_or(0, |e| e.sequence),
+        };
+        let records: Vec<JournalRecord> = events
+            .iter()
+            .filter(|event| event.sequence > last_sequence)
+            .map(|event| JournalRecord::Event(event.as_ref().clone()))
+            .collect();
+        if records.is_empty() {
+            self.last_sequence = Some(last_sequence);
+            return Ok(());
+        }
+
+        self.append(&records)?;
+        self.last_sequence = events.iter().map(|e| e.sequence).max();
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use {
+    crate::{
+        events::CommittedTransaction,
+        models::{Account, Transaction},
+        persistence::{LoadedState, PersistenceBackend, error::PersistenceError},
+    },
+    dashmap::{DashMap, DashSet},
+    std::sync::Arc,
+    uuid::Uuid,
+};
+
+/// Keeps checkpoints and the log in memory. Nothing survives the process.
+#[derive(Default)]
+pub struct MemoryBackend {
+    accounts: DashMap<Uuid, Account>,
+    transactions: DashMap<Uuid, Transaction>,
+    processed_transactions: DashSet<Uuid>,
+    events: Vec<CommittedTransaction>,
+}
+
+impl PersistenceBackend for MemoryBackend {
+    fn load_state(&mut self) -> Result<LoadedState, PersistenceError> {
+        Ok((
+            self.accounts.clone(),
+            self.transactions.clone(),
+            self.processed_transactions.clone(),
+        ))
+    }
+
+    fn load_events(&mut self) -> Result<Vec<CommittedTransaction>, PersistenceError> {
+        Ok(self.events.clone())
+    }
+
+    fn checkpoint(
+        &mut self,
+        accounts: &DashMap<Uuid, Account>,
+        transactions: &DashMap<Uuid, Transaction>,
+        processed_transactions: &DashSet<Uuid>,
+    ) -> Result<(), PersistenceError> {
+        self.accounts = accounts.clone();
+        self.transactions = transactions.clone();
+        self.processed_transactions = processed_transactions.clone();
+        Ok(())
+    }
+
+    fn append_events(
+        &mut self,
+        events: &[Arc<CommittedTransaction>],
+    ) -> Result<(), PersistenceError> {
+        let last_sequence = self.events.last().map_or(0, |last| last.sequence);
+        self.events.extend(
+            events
+                .iter()
+                .filter(|event| event.sequence > last_sequence)
+                .map(|event| event.as_ref().clone()),
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,17 @@
+CREATE TABLE accounts (
+    uuid TEXT PRIMARY KEY,
+    balance INTEGER NOT NULL,
+    keys TEXT NOT NULL,
+    transaction_history TEXT NOT NULL
+);
+
+CREATE TABLE transactions (
+    id TEXT PRIMARY KEY,
+    instruction TEXT NOT NULL,
+    status TEXT NOT NULL,
+    timestamp TEXT NOT NULL
+);
+
+CREATE TABLE processed_transactions (
+    id TEXT PRIMARY KEY
+);
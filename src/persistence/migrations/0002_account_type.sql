@@ -0,0 +1,2 @@
+-- Account types are stored JSON-encoded, like the other structured columns.
+ALTER TABLE accounts ADD COLUMN account_type TEXT NOT NULL DEFAULT '"personal"';
@@ -0,0 +1,5 @@
+CREATE TABLE committed_transactions (
+    sequence INTEGER PRIMARY KEY,
+    transaction_id TEXT NOT NULL,
+    event TEXT NOT NULL
+);
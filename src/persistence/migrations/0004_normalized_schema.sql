@@ -0,0 +1,106 @@
+-- Replaces the JSON-encoded columns with tables and typed columns that can be queried directly.
+-- Keys and enum values are stored as snake_case names and timestamps as fixed-width UTC
+-- RFC 3339 text with microseconds, so they sort chronologically.
+
+CREATE TABLE accounts_normalized (
+    uuid TEXT PRIMARY KEY,
+    balance INTEGER NOT NULL CHECK (balance >= 0),
+    account_type TEXT NOT NULL
+);
+INSERT INTO accounts_normalized (uuid, balance, account_type)
+SELECT uuid, CAST(balance AS INTEGER), json_extract(account_type, '$')
+FROM accounts;
+
+CREATE TABLE account_keys (
+    account_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (account_id, position)
+);
+INSERT INTO account_keys (account_id, position, kind, value)
+SELECT a.uuid, k.key, lower(v.key), v.value
+FROM accounts a, json_each(a.keys) k, json_each(k.value) v;
+
+-- Transaction history of each account, in commit order.
+CREATE TABLE account_transactions (
+    account_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    transaction_id TEXT NOT NULL,
+    PRIMARY KEY (account_id, position)
+);
+INSERT INTO account_transactions (account_id, position, transaction_id)
+SELECT a.uuid, h.key, h.value
+FROM accounts a, json_each(a.transaction_history) h;
+
+DROP TABLE accounts;
+ALTER TABLE accounts_normalized RENAME TO accounts;
+
+CREATE TABLE transactions_normalized (
+    id TEXT PRIMARY KEY,
+    kind TEXT NOT NULL,
+    -- Transfers only.
+    source_account_id TEXT,
+    -- Transfers and deposits.
+    destination_account_id TEXT,
+    -- Balance inquiries only.
+    account_id TEXT,
+    -- Transfers and deposits.
+    amount INTEGER,
+    -- Account creations only.
+    account_type TEXT,
+    status TEXT NOT NULL,
+    timestamp TEXT NOT NULL
+);
+INSERT INTO transactions_normalized
+SELECT
+    id,
+    CASE (SELECT key FROM json_each(instruction))
+        WHEN 'Transfer' THEN 'transfer'
+        WHEN 'Deposit' THEN 'deposit'
+        WHEN 'CreateAccount' THEN 'create_account'
+        WHEN 'GetBalance' THEN 'get_balance'
+    END,
+    json_extract(instruction, '$.Transfer.source_account_id'),
+    COALESCE(
+        json_extract(instruction, '$.Transfer.destination_account_id'),
+        json_extract(instruction, '$.Deposit.destination_account_id')
+    ),
+    json_extract(instruction, '$.GetBalance.account_id'),
+    COALESCE(
+        json_extract(instruction, '$.Transfer.amount'),
+        json_extract(instruction, '$.Deposit.amount')
+    ),
+    CASE WHEN json_type(instruction, '$.CreateAccount') IS NOT NULL
+        THEN COALESCE(json_extract(instruction, '$.CreateAccount.account_type'), 'personal')
+    END,
+    lower(json_extract(status, '$')),
+    -- Legacy timestamps are RFC 3339 with a "+00:00" offset and 0 to 9 fractional digits.
+    substr(timestamp, 1, 19) || '.' || substr(
+        CASE WHEN substr(timestamp, 20, 1) = '.'
+            THEN substr(timestamp, 21, length(timestamp) - 26)
+            ELSE ''
+        END || '000000', 1, 6
+    ) || 'Z'
+FROM transactions;
+
+-- Keys of the account opened by an account creation.
+CREATE TABLE transaction_keys (
+    transaction_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (transaction_id, position)
+);
+INSERT INTO transaction_keys (transaction_id, position, kind, value)
+SELECT t.id, k.key, lower(v.key), v.value
+FROM transactions t, json_each(t.instruction, '$.CreateAccount.keys') k, json_each(k.value) v;
+
+DROP TABLE transactions;
+ALTER TABLE transactions_normalized RENAME TO transactions;
+
+CREATE INDEX account_transactions_transaction_id ON account_transactions (transaction_id);
+CREATE INDEX transactions_timestamp ON transactions (timestamp);
+CREATE INDEX transactions_source_account_id ON transactions (source_account_id);
+CREATE INDEX transactions_destination_account_id ON transactions (destination_account_id);
+CREATE INDEX committed_transactions_transaction_id ON committed_transactions (transaction_id);
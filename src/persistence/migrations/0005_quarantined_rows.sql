@@ -0,0 +1,10 @@
+-- Rows set aside at startup because they could not be decoded. The payload holds the original
+-- columns as a JSON object.
+CREATE TABLE quarantined_rows (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    table_name TEXT NOT NULL,
+    row_id INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    quarantined_at TEXT NOT NULL
+);
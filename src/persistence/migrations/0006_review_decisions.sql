@@ -0,0 +1,4 @@
+-- Decisions on transactions held for review by the risk engine.
+ALTER TABLE transactions ADD COLUMN held_transaction_id TEXT;
+-- "approve" or "reject".
+ALTER TABLE transactions ADD COLUMN decision TEXT;
@@ -0,0 +1,5 @@
+-- Dispute instructions. Opening a dispute also uses the amount column.
+ALTER TABLE transactions ADD COLUMN disputed_transaction_id TEXT;
+ALTER TABLE transactions ADD COLUMN dispute_id TEXT;
+-- "start_analysis", "return", "release" or "expire".
+ALTER TABLE transactions ADD COLUMN dispute_action TEXT;
@@ -0,0 +1,37 @@
+-- Database written before schema versioning, once the commit log was persisted.
+CREATE TABLE accounts (
+    uuid TEXT PRIMARY KEY,
+    balance INTEGER NOT NULL,
+    keys TEXT NOT NULL,
+    account_type TEXT NOT NULL,
+    transaction_history TEXT NOT NULL
+);
+CREATE TABLE transactions (
+    id TEXT PRIMARY KEY,
+    instruction TEXT NOT NULL,
+    status TEXT NOT NULL,
+    timestamp TEXT NOT NULL
+);
+CREATE TABLE processed_transactions (
+    id TEXT PRIMARY KEY
+);
+CREATE TABLE committed_transactions (
+    sequence INTEGER PRIMARY KEY,
+    transaction_id TEXT NOT NULL,
+    event TEXT NOT NULL
+);
+
+INSERT INTO accounts VALUES (
+    '6f1c2f4e-4d0a-4d89-9a53-3c1c8a9e2b10', '150', '[]', '"personal"',
+    '["0b7e6b1e-1c84-4a59-8f0e-2f4d6a7c9e01"]'
+);
+INSERT INTO transactions VALUES (
+    '0b7e6b1e-1c84-4a59-8f0e-2f4d6a7c9e01',
+    '{"Deposit":{"destination_account_id":"6f1c2f4e-4d0a-4d89-9a53-3c1c8a9e2b10","amount":150}}',
+    '"Pending"', '2025-11-03T10:15:00+00:00'
+);
+INSERT INTO processed_transactions VALUES ('0b7e6b1e-1c84-4a59-8f0e-2f4d6a7c9e01');
+INSERT INTO committed_transactions VALUES (
+    1, '0b7e6b1e-1c84-4a59-8f0e-2f4d6a7c9e01',
+    '{"sequence":1,"transaction_id":"0b7e6b1e-1c84-4a59-8f0e-2f4d6a7c9e01","instruction":{"Deposit":{"destination_account_id":"6f1c2f4e-4d0a-4d89-9a53-3c1c8a9e2b10","amount":150}},"postings":[{"account_id":"6f1c2f4e-4d0a-4d89-9a53-3c1c8a9e2b10","kind":"Credit","amount":150}],"fee":null,"created_account_id":null,"committed_at":"2025-11-03T10:15:00Z"}'
+);
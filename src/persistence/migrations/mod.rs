@@ -0,0 +1,301 @@
+//! Versioned schema of the SQLite store.
+//! Migrations are embedded in the binary and applied in order at startup, each in its own
+//! transaction. Applied versions are recorded in the `schema_migrations` table.
+
+use {
+    crate::persistence::error::PersistenceError,
+    chrono::Utc,
+    rusqlite::{Connection, OptionalExtension, params},
+    tracing::info,
+};
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Every migration, in the order they must be applied.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "account_type",
+        sql: include_str!("0002_account_type.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "committed_transactions",
+        sql: include_str!("0003_committed_transactions.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "normalized_schema",
+        sql: include_str!("0004_normalized_schema.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "quarantined_rows",
+        sql: include_str!("0005_quarantined_rows.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "review_decisions",
+        sql: include_str!("0006_review_decisions.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "disputes",
+        sql: include_str!("0007_disputes.sql"),
+    },
+];
+
+/// Version of the schema this binary writes.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Brings the database up to the latest version. Refuses to touch a database whose schema is
+/// newer than this binary knows about.
+pub fn migrate(conn: &mut Connection) -> Result<(), PersistenceError> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    let mut current = current_version(conn)?;
+    if current == 0 {
+        current = adopt_unversioned(conn)?;
+    }
+
+    let latest = latest_version();
+    if current > latest {
+        return Err(PersistenceError::SchemaTooNew {
+            found: current,
+            supported: latest,
+        });
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration.sql)?;
+        record(&tx, migration)?;
+        tx.commit()?;
+        info!(
+            "Applied schema migration {} ({})",
+            migration.version, migration.name
+        );
+    }
+
+    Ok(())
+}
+
+/// Highest applied version, or 0 for a database that was never versioned.
+pub fn current_version(conn: &Connection) -> Result<u32, PersistenceError> {
+    Ok(conn
+        .query_row("SELECT MAX(version) FROM schema_migrations", [], |row| {
+            row.get::<_, Option<u32>>(0)
+        })?
+        .unwrap_or(0))
+}
+
+/// Databases written before versioning existed have no `schema_migrations` rows. Their version
+/// is inferred from the tables and columns present, and recorded as applied.
+fn adopt_unversioned(conn: &mut Connection) -> Result<u32, PersistenceError> {
+    let version = if !table_exists(conn, "accounts")? {
+        0
+    } else if table_exists(conn, "committed_transactions")? {
+        3
+    } else if column_exists(conn, "accounts", "account_type")? {
+        2
+    } else {
+        1
+    };
+
+    if version > 0 {
+        let tx = conn.transaction()?;
+        for migration in MIGRATIONS.iter().filter(|m| m.version <= version) {
+            record(&tx, migration)?;
+        }
+        tx.commit()?;
+        info!("Adopted unversioned database at schema version {}", version);
+    }
+
+    Ok(version)
+}
+
+fn record(conn: &Connection, migration: &Migration) -> Result<(), PersistenceError> {
+    conn.execute(
+        "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+        params![migration.version, migration.name, Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+fn table_exists(conn: &Connection, table: &str) -> Result<bool, PersistenceError> {
+    Ok(conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [table],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+fn column_exists(conn: &Connection, table: &str, column: &str) -> Result<bool, PersistenceError> {
+    Ok(conn
+        .query_row(
+            "SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2",
+            [table, column],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            models::AccountType,
+            persistence::{PersistenceBackend, sqlite::SqliteBackend},
+        },
+        uuid::Uuid,
+    };
+
+    const ACCOUNT_ID: &str = "6f1c2f4e-4d0a-4d89-9a53-3c1c8a9e2b10";
+
+    /// Writes a fixture database to a temporary file, as an older release would have left it.
+    fn fixture(sql: &str) -> String {
+        let path = std::env::temp_dir()
+            .join(format!("quasar-fixture-{}.db", Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        Connection::open(&path).unwrap().execute_batch(sql).unwrap();
+        path
+    }
+
+    fn assert_loads(sql: &str, account_type: AccountType, transactions: usize, events: usize) {
+        let path = fixture(sql);
+        let mut backend = SqliteBackend::new(&path).unwrap();
+
+        let (accounts, loaded_transactions, processed) = backend.load_state().unwrap();
+        let account = accounts.get(&Uuid::parse_str(ACCOUNT_ID).unwrap()).unwrap();
+        assert_eq!(account.balance, 150);
+        assert_eq!(account.account_type, account_type);
+        assert_eq!(account.transaction_history.len(), 1);
+        assert_eq!(loaded_transactions.len(), transactions);
+        assert_eq!(processed.len(), 1);
+        assert_eq!(backend.load_events().unwrap().len(), events);
+        drop(backend);
+
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(current_version(&conn).unwrap(), latest_version());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_migrates_fixtures_from_older_versions() {
+        assert_loads(include_str!("fixtures/v1.sql"), AccountType::Personal, 2, 0);
+        assert_loads(include_str!("fixtures/v2.sql"), AccountType::Merchant, 1, 0);
+        assert_loads(include_str!("fixtures/v3.sql"), AccountType::Personal, 1, 1);
+    }
+
+    #[test]
+    fn test_normalized_schema_is_queryable() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("fixtures/v1.sql")).unwrap();
+        migrate(&mut conn).unwrap();
+
+        let deposit: (String, u64, String) = conn
+            .query_row(
+                "SELECT destination_account_id, amount, timestamp FROM transactions WHERE kind = 'deposit'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            deposit,
+            (
+                ACCOUNT_ID.to_string(),
+                150,
+                "2025-11-03T10:15:00.000000Z".to_string()
+            )
+        );
+
+        let keys: Vec<(String, String)> = conn
+            .prepare("SELECT kind, value FROM transaction_keys ORDER BY position")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                ("email".to_string(), "alice@example.com".to_string()),
+                ("phone".to_string(), "+5511999990000".to_string()),
+            ]
+        );
+
+        let first: String = conn
+            .query_row(
+                "SELECT timestamp FROM transactions ORDER BY timestamp LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(first, "2025-11-03T10:14:59.123456Z");
+
+        let email: String = conn
+            .query_row(
+                "SELECT value FROM account_keys WHERE account_id = ?1 AND kind = 'email'",
+                [ACCOUNT_ID],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(email, "alice@example.com");
+    }
+
+    #[test]
+    fn test_fresh_database_gets_every_migration() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        // Running again is a no-op.
+        migrate(&mut conn).unwrap();
+
+        let applied: u32 = conn
+            .query_row("SELECT COUNT(*) FROM schema_migrations", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(applied as usize, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn test_refuses_newer_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, 'future', '')",
+            [latest_version() + 1],
+        )
+        .unwrap();
+
+        assert!(matches!(
+            migrate(&mut conn),
+            Err(PersistenceError::SchemaTooNew { found, supported })
+                if found == latest_version() + 1 && supported == latest_version()
+        ));
+    }
+}
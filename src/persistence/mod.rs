@@ -0,0 +1,431 @@
+//! Durable storage of the ledger state and of the commit log.
+//! Backends store periodic checkpoints of accounts, transactions and processed transaction IDs,
+//! plus the append-only log of committed transactions.
+
+pub mod error;
+pub mod file;
+pub mod memory;
+pub mod migrations;
+pub mod sqlite;
+
+use {
+    crate::{
+        config::{PersistenceBackendKind, PersistenceConfig},
+        events::CommittedTransaction,
+        models::{Account, AccountType, Transaction},
+        persistence::{
+            error::{CorruptRow, PersistenceError},
+            file::FileBackend,
+            memory::MemoryBackend,
+            sqlite::SqliteBackend,
+        },
+    },
+    dashmap::{DashMap, DashSet},
+    std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    },
+    uuid::Uuid,
+};
+
+/// Accounts, transactions and processed transaction IDs restored from storage.
+pub type LoadedState = (
+    DashMap<Uuid, Account>,
+    DashMap<Uuid, Transaction>,
+    DashSet<Uuid>,
+);
+
+/// A backend shared by the event log, which writes every commit through it, and the
+/// checkpoints taken on shutdown.
+pub type SharedBackend = Arc<Mutex<Box<dyn PersistenceBackend>>>;
+
+/// Corrupt rows met while loading, and what was done with them.
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    /// Rows left out of the loaded state.
+    pub quarantined: Vec<CorruptRow>,
+    /// Rows loaded with one or more fields replaced by a default.
+    pub repaired: Vec<CorruptRow>,
+}
+
+impl LoadReport {
+    pub fn is_empty(&self) -> bool {
+        self.quarantined.is_empty() && self.repaired.is_empty()
+    }
+}
+
+pub trait PersistenceBackend: Send {
+    /// Loads the last checkpoint, or an empty state if none was saved. Corrupt rows are handled
+    /// according to the backend's [`CorruptionPolicy`](crate::config::CorruptionPolicy).
+    fn load_state(&mut self) -> Result<LoadedState, PersistenceError>;
+
+    /// Loads the committed transaction log ordered by sequence number.
+    fn load_events(&mut self) -> Result<Vec<CommittedTransaction>, PersistenceError>;
+
+    /// Corrupt rows quarantined or repaired by the loads so far.
+    fn load_report(&self) -> LoadReport {
+        LoadReport::default()
+    }
+
+    /// Replaces the stored checkpoint with the given state.
+    fn checkpoint(
+        &mut self,
+        accounts: &DashMap<Uuid, Account>,
+        transactions: &DashMap<Uuid, Transaction>,
+        processed_transactions: &DashSet<Uuid>,
+    ) -> Result<(), PersistenceError>;
+
+    /// Appends committed transactions to the log. Entries already stored are skipped.
+    fn append_events(
+        &mut self,
+        events: &[Arc<CommittedTransaction>],
+    ) -> Result<(), PersistenceError>;
+}
+
+/// Opens the backend selected in the configuration. `account_types` are the types of the
+/// accounts the configuration creates, which corrupt rows are repaired with.
+pub fn open_backend(
+    config: &PersistenceConfig,
+    account_types: HashMap<Uuid, AccountType>,
+) -> Result<Box<dyn PersistenceBackend>, PersistenceError> {
+    Ok(match config.backend {
+        PersistenceBackendKind::Sqlite => Box::new(
+            SqliteBackend::new(&config.db_path)?
+                .with_corruption_policy(config.on_corruption)
+                .with_account_types(account_types),
+        ),
+        PersistenceBackendKind::Memory => Box::new(MemoryBackend::default()),
+        PersistenceBackendKind::File => Box::new(
+            FileBackend::open(&config.file_path)?.with_corruption_policy(config.on_corruption),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            config::CorruptionPolicy,
+            events::EventLog,
+            models::{
+                CreateAccountInstruction, DepositInstruction, Instruction, Posting,
+                TransactionStatus,
+            },
+        },
+        chrono::Utc,
+        std::{fs::OpenOptions, io::Write},
+    };
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("quasar-{name}-{}", Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn assert_round_trip(backend: &mut dyn PersistenceBackend) {
+        let (account_id, account) = Account::new(Uuid::new_v4(), vec![]);
+        let accounts = DashMap::new();
+        accounts.insert(account_id, account);
+        let transaction = Transaction {
+            id: Uuid::new_v4(),
+            instruction: Instruction::Deposit(DepositInstruction {
+                destination_account_id: account_id,
+                amount: 10,
+            }),
+            status: TransactionStatus::Pending,
+            timestamp: Utc::now(),
+        };
+        let transactions = DashMap::new();
+        transactions.insert(transaction.id, transaction.clone());
+        let processed = DashSet::new();
+        processed.insert(transaction.id);
+
+        let log = EventLog::default();
+        log.append(
+            transaction.id,
+            transaction.instruction.clone(),
+            vec![Posting::credit(account_id, 10)],
+            None,
+        )
+        .unwrap();
+
+        backend.append_events(&log.entries()).unwrap();
+        backend
+            .checkpoint(&DashMap::new(), &DashMap::new(), &DashSet::new())
+            .unwrap();
+        backend
+            .checkpoint(&accounts, &transactions, &processed)
+            .unwrap();
+        // Appending the whole log again only stores the new entry.
+        let event = log
+            .append(
+                Uuid::new_v4(),
+                transaction.instruction.clone(),
+                vec![Posting::credit(account_id, 10)],
+                None,
+            )
+            .unwrap();
+        backend.append_events(&log.entries()).unwrap();
+
+        let (loaded_accounts, loaded_transactions, loaded_processed) =
+            backend.load_state().unwrap();
+        assert_eq!(loaded_accounts.len(), 1);
+        assert_eq!(loaded_accounts.get(&account_id).unwrap().uuid, account_id);
+        assert_eq!(loaded_transactions.len(), 1);
+        assert!(loaded_processed.contains(&transaction.id));
+
+        let events = backend.load_events().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1], *event);
+    }
+
+    #[test]
+    fn test_backends_round_trip() {
+        assert_round_trip(&mut MemoryBackend::default());
+        assert_round_trip(&mut SqliteBackend::new(":memory:").unwrap());
+
+        let path = temp_path("journal");
+        assert_round_trip(&mut FileBackend::open(&path).unwrap());
+        // Reopening replays the journal.
+        let mut reopened = FileBackend::open(&path).unwrap();
+        assert_eq!(reopened.load_events().unwrap().len(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_commits_survive_a_crash_without_checkpoint() {
+        let path = temp_path("crash.db");
+        let deposit = |log: &EventLog| {
+            let account_id = Uuid::new_v4();
+            log.append(
+                Uuid::new_v4(),
+                Instruction::Deposit(DepositInstruction {
+                    destination_account_id: account_id,
+                    amount: 10,
+                }),
+                vec![Posting::credit(account_id, 10)],
+                None,
+            )
+            .unwrap()
+        };
+        let open = || -> SharedBackend {
+            Arc::new(Mutex::new(Box::new(SqliteBackend::new(&path).unwrap())))
+        };
+
+        let journal = open();
+        let log = EventLog::new(vec![], 16).with_journal(journal.clone());
+        deposit(&log);
+        deposit(&log);
+        drop((log, journal));
+
+        // Sequence numbers carry on from the stored log rather than being reused.
+        let journal = open();
+        let stored = journal.lock().unwrap().load_events().unwrap();
+        assert_eq!(stored.len(), 2);
+        let log = EventLog::new(stored, 16).with_journal(journal.clone());
+        assert_eq!(deposit(&log).sequence, 3);
+        assert_eq!(journal.lock().unwrap().load_events().unwrap().len(), 3);
+
+        drop((log, journal));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_sqlite_refuses_balances_beyond_i64() {
+        let mut backend = SqliteBackend::new(":memory:").unwrap();
+        let (account_id, mut account) = Account::new(Uuid::new_v4(), vec![]);
+        let accounts = DashMap::new();
+        accounts.insert(account_id, account.clone());
+        backend
+            .checkpoint(&accounts, &DashMap::new(), &DashSet::new())
+            .unwrap();
+
+        account.balance = u64::MAX;
+        accounts.insert(account_id, account);
+        assert!(matches!(
+            backend.checkpoint(&accounts, &DashMap::new(), &DashSet::new()),
+            Err(PersistenceError::OutOfRange {
+                table: "accounts",
+                column: "balance",
+                value: u64::MAX,
+            })
+        ));
+        // The previous checkpoint is kept.
+        let (loaded, _, _) = backend.load_state().unwrap();
+        assert_eq!(loaded.get(&account_id).unwrap().balance, 0);
+    }
+
+    #[test]
+    fn test_file_backend_drops_torn_tail() {
+        let path = temp_path("torn");
+        let mut backend = FileBackend::open(&path).unwrap();
+        backend
+            .checkpoint(&DashMap::new(), &DashMap::new(), &DashSet::new())
+            .unwrap();
+        drop(backend);
+
+        let mut journal = OpenOptions::new().append(true).open(&path).unwrap();
+        journal.write_all(b"{\"record\":\"event\",\"seq").unwrap();
+        drop(journal);
+
+        let mut backend = FileBackend::open(&path).unwrap();
+        backend
+            .checkpoint(&DashMap::new(), &DashMap::new(), &DashSet::new())
+            .unwrap();
+        assert!(backend.load_state().is_ok());
+        assert!(backend.load_events().unwrap().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Writes one valid account, then corrupts the database behind the backend's back.
+    fn corrupt_database(path: &str) -> Uuid {
+        let (account_id, account) = Account::new(Uuid::new_v4(), vec![]);
+        let accounts = DashMap::new();
+        accounts.insert(account_id, account);
+        let mut backend = SqliteBackend::new(path).unwrap();
+        backend
+            .checkpoint(&accounts, &DashMap::new(), &DashSet::new())
+            .unwrap();
+        drop(backend);
+
+        let conn = rusqlite::Connection::open(path).unwrap();
+        conn.execute_batch(&format!(
+            "UPDATE accounts SET account_type = 'gold' WHERE uuid = '{account_id}';
+             INSERT INTO accounts (uuid, balance, account_type) VALUES ('not-a-uuid', 10, 'personal');
+             INSERT INTO committed_transactions (sequence, transaction_id, event) VALUES (1, '{account_id}', '{{');"
+        ))
+        .unwrap();
+        account_id
+    }
+
+    #[test]
+    fn test_sqlite_corruption_policies() {
+        let path = temp_path("corrupt.db");
+        corrupt_database(&path);
+        let mut backend = SqliteBackend::new(&path).unwrap();
+        match backend.load_state() {
+            Err(PersistenceError::CorruptRow(corrupt)) => assert_eq!(corrupt.table, "accounts"),
+            other => panic!("expected a corrupt row, got {:?}", other.map(|_| ())),
+        }
+        drop(backend);
+
+        // Quarantine sets both accounts aside, and the next load finds a clean database.
+        let mut backend = SqliteBackend::new(&path)
+            .unwrap()
+            .with_corruption_policy(CorruptionPolicy::Quarantine);
+        let (accounts, _, _) = backend.load_state().unwrap();
+        assert!(accounts.is_empty());
+        assert!(backend.load_events().unwrap().is_empty());
+        let report = backend.load_report();
+        assert_eq!(report.quarantined.len(), 3);
+        assert!(report.repaired.is_empty());
+        drop(backend);
+
+        let mut backend = SqliteBackend::new(&path).unwrap();
+        assert!(backend.load_state().unwrap().0.is_empty());
+        assert!(backend.load_report().is_empty());
+        drop(backend);
+        let quarantined: i64 = rusqlite::Connection::open(&path)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM quarantined_rows", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(quarantined, 3);
+        std::fs::remove_file(&path).unwrap();
+
+        // Repair restores the type of the account known from the configuration, and
+        // quarantines the other rows.
+        let account_id = corrupt_database(&path);
+        let mut backend = SqliteBackend::new(&path)
+            .unwrap()
+            .with_corruption_policy(CorruptionPolicy::Repair)
+            .with_account_types(HashMap::from([(account_id, AccountType::Revenue)]));
+        let (accounts, _, _) = backend.load_state().unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(
+            accounts.get(&account_id).unwrap().account_type,
+            AccountType::Revenue
+        );
+        backend.load_events().unwrap();
+        let report = backend.load_report();
+        assert_eq!(report.repaired.len(), 1);
+        assert_eq!(report.quarantined.len(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_sqlite_repair_recovers_account_types_from_the_log() {
+        let path = temp_path("repair.db");
+        let accounts = DashMap::new();
+        let mut events = Vec::new();
+        for (sequence, account_type) in [(1, AccountType::Revenue), (2, AccountType::Merchant)] {
+            let (account_id, mut account) = Account::new(Uuid::new_v4(), vec![]);
+            account.account_type = account_type;
+            accounts.insert(account_id, account);
+            events.push(Arc::new(CommittedTransaction {
+                sequence,
+                transaction_id: Uuid::new_v4(),
+                instruction: Instruction::CreateAccount(CreateAccountInstruction {
+                    account_type,
+                    ..CreateAccountInstruction::new(vec![])
+                }),
+                postings: vec![],
+                fee: None,
+                created_account_id: Some(account_id),
+                submitted_at: Utc::now(),
+                committed_at: Utc::now(),
+            }));
+        }
+        // Created outside the log, and not known from the configuration either.
+        let (unknown_id, account) = Account::new(Uuid::new_v4(), vec![]);
+        accounts.insert(unknown_id, account);
+        let mut backend = SqliteBackend::new(&path).unwrap();
+        backend
+            .checkpoint(&accounts, &DashMap::new(), &DashSet::new())
+            .unwrap();
+        backend.append_events(&events).unwrap();
+        drop(backend);
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute("UPDATE accounts SET account_type = 'gold'", [])
+            .unwrap();
+
+        let mut backend = SqliteBackend::new(&path)
+            .unwrap()
+            .with_corruption_policy(CorruptionPolicy::Repair);
+        let (loaded, _, _) = backend.load_state().unwrap();
+        for event in &events {
+            let account_id = event.created_account_id.unwrap();
+            assert_eq!(
+                loaded.get(&account_id).unwrap().account_type,
+                accounts.get(&account_id).unwrap().account_type
+            );
+        }
+        assert!(!loaded.contains_key(&unknown_id));
+        let report = backend.load_report();
+        assert_eq!(report.repaired.len(), 2);
+        assert_eq!(report.quarantined.len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_file_backend_corrupt_line() {
+        let path = temp_path("corrupt");
+        std::fs::write(&path, "{\"record\":\"unknown\"}\n").unwrap();
+
+        let mut backend = FileBackend::open(&path).unwrap();
+        assert!(matches!(
+            backend.load_events(),
+            Err(PersistenceError::CorruptRow(CorruptRow { row_id: 1, .. }))
+        ));
+
+        let mut backend = backend.with_corruption_policy(CorruptionPolicy::Quarantine);
+        assert!(backend.load_state().is_ok());
+        assert!(backend.load_events().unwrap().is_empty());
+        assert_eq!(backend.load_report().quarantined.len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,793 @@
+use {
+    crate::{
+        config::CorruptionPolicy,
+        events::CommittedTransaction,
+        models::{
+            Account, AccountType, CreateAccountInstruction, DepositInstruction, DisputeAction,
+            GetBalanceInstruction, Instruction, InstructionKind, Key, OpenDisputeInstruction,
+            ReviewDecision, ReviewInstruction, Transaction, TransactionStatus, TransferInstruction,
+            UpdateDisputeInstruction,
+        },
+        persistence::{
+            LoadReport, LoadedState, PersistenceBackend,
+            error::{CorruptRow, PersistenceError},
+            migrations,
+        },
+    },
+    chrono::{DateTime, SecondsFormat, Utc},
+    dashmap::{DashMap, DashSet},
+    rusqlite::{
+        Connection, Result, Row, params,
+        types::{FromSql, ValueRef},
+    },
+    std::{collections::HashMap, sync::Arc},
+    uuid::Uuid,
+};
+
+/// Corrupt rows are moved to the `quarantined_rows` table. Repaired values are only fixed in
+/// memory, and written back by the next checkpoint.
+pub struct SqliteBackend {
+    conn: Connection,
+    policy: CorruptionPolicy,
+    account_types: HashMap<Uuid, AccountType>,
+    report: LoadReport,
+}
+
+impl SqliteBackend {
+    pub fn new(db_path: &str) -> Result<Self, PersistenceError> {
+        let mut conn = Connection::open(db_path)?;
+        migrations::migrate(&mut conn)?;
+        Ok(SqliteBackend {
+            conn,
+            policy: CorruptionPolicy::default(),
+            account_types: HashMap::new(),
+            report: LoadReport::default(),
+        })
+    }
+
+    pub fn with_corruption_policy(mut self, policy: CorruptionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Types of the accounts created from the configuration rather than through the log, such
+    /// as the fee revenue and dispute escrow accounts, used to repair a corrupt type.
+    pub fn with_account_types(mut self, account_types: HashMap<Uuid, AccountType>) -> Self {
+        self.account_types = account_types;
+        self
+    }
+
+    /// The type an account was created with, known from the configuration or recorded by its
+    /// creation in the commit log.
+    fn created_account_type(
+        &self,
+        account_id: Uuid,
+    ) -> Result<Option<AccountType>, PersistenceError> {
+        if let Some(account_type) = self.account_types.get(&account_id) {
+            return Ok(Some(*account_type));
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT event FROM committed_transactions WHERE instr(event, ?1) > 0")?;
+        let mut rows = stmt.query([account_id.to_string()])?;
+        while let Some(row) = rows.next()? {
+            let Ok(event) = serde_json::from_str::<CommittedTransaction>(&row.get::<_, String>(0)?)
+            else {
+                continue;
+            };
+            if let Instruction::CreateAccount(create) = &event.instruction
+                && event.created_account_id == Some(account_id)
+            {
+                return Ok(Some(create.account_type));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Loads `(rowid, owner, kind, value)` key rows, grouped by owner in position order.
+    fn load_keys(
+        &self,
+        table: &str,
+        query: &str,
+        corruptions: &mut Corruptions,
+    ) -> Result<HashMap<Uuid, Vec<Key>>, PersistenceError> {
+        let mut keys: HashMap<Uuid, Vec<Key>> = HashMap::new();
+        let mut stmt = self.conn.prepare(query)?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let row_id: i64 = row.get(0)?;
+            let decoded = uuid_column(row, 1).and_then(|owner| {
+                let kind: String = column(row, 2)?;
+                let key = parse_key(&kind, column(row, 3)?)
+                    .ok_or_else(|| format!("unknown key kind {kind:?}"))?;
+                Ok((owner, key))
+            });
+            match decoded {
+                Ok((owner, key)) => keys.entry(owner).or_default().push(key),
+                Err(reason) => corruptions.reject(table, row_id, reason)?,
+            }
+        }
+        Ok(keys)
+    }
+
+    fn read_state(&self, corruptions: &mut Corruptions) -> Result<LoadedState, PersistenceError> {
+        let mut account_keys = self.load_keys(
+            "account_keys",
+            "SELECT rowid, account_id, kind, value FROM account_keys ORDER BY account_id, position",
+            corruptions,
+        )?;
+
+        let mut histories: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+        let mut stmt = self.conn.prepare(
+            "SELECT rowid, account_id, transaction_id FROM account_transactions ORDER BY account_id, position",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let row_id: i64 = row.get(0)?;
+            match uuid_column(row, 1).and_then(|owner| Ok((owner, uuid_column(row, 2)?))) {
+                Ok((owner, transaction_id)) => {
+                    histories.entry(owner).or_default().push(transaction_id)
+                }
+                Err(reason) => corruptions.reject("account_transactions", row_id, reason)?,
+            }
+        }
+
+        let accounts = DashMap::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT rowid, uuid, balance, account_type FROM accounts")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let row_id: i64 = row.get(0)?;
+            let (uuid, balance) =
+                match uuid_column(row, 1).and_then(|uuid| Ok((uuid, column::<u64>(row, 2)?))) {
+                    Ok(decoded) => decoded,
+                    Err(reason) => {
+                        corruptions.reject("accounts", row_id, reason)?;
+                        continue;
+                    }
+                };
+            // The type changes how fees and disputes treat the account, so it is never guessed.
+            let account_type = parse_column(row, 3, parse_account_type);
+            let recovered = match account_type {
+                Err(_) if corruptions.policy == CorruptionPolicy::Repair => {
+                    self.created_account_type(uuid)?
+                }
+                _ => None,
+            };
+            let Some(account_type) =
+                corruptions.repair("accounts", row_id, account_type, recovered)?
+            else {
+                continue;
+            };
+            let account = Account {
+                uuid,
+                balance,
+                keys: account_keys.remove(&uuid).unwrap_or_default(),
+                account_type,
+                transaction_history: histories.remove(&uuid).unwrap_or_default(),
+            };
+            accounts.insert(uuid, account);
+        }
+
+        let mut transaction_keys = self.load_keys(
+            "transaction_keys",
+            "SELECT rowid, transaction_id, kind, value FROM transaction_keys ORDER BY transaction_id, position",
+            corruptions,
+        )?;
+
+        let transactions = DashMap::new();
+        let mut stmt = self.conn.prepare(
+            "SELECT rowid, id, kind, source_account_id, destination_account_id, account_id, amount, account_type, status, timestamp, held_transaction_id, decision, disputed_transaction_id, dispute_id, dispute_action
+             FROM transactions",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let row_id: i64 = row.get(0)?;
+            let decoded = uuid_column(row, 1).and_then(|id| {
+                let columns = InstructionColumns {
+                    kind: column(row, 2)?,
+                    source_account_id: optional_uuid_column(row, 3)?,
+                    destination_account_id: optional_uuid_column(row, 4)?,
+                    account_id: optional_uuid_column(row, 5)?,
+                    amount: column(row, 6)?,
+                    account_type: match column::<Option<String>>(row, 7)? {
+                        Some(_) => Some(parse_column(row, 7, parse_account_type)?),
+                        None => None,
+                    },
+                    keys: transaction_keys.remove(&id).unwrap_or_default(),
+                    held_transaction_id: optional_uuid_column(row, 10)?,
+                    decision: match column::<Option<String>>(row, 11)? {
+                        Some(_) => Some(parse_column(row, 11, parse_decision)?),
+                        None => None,
+                    },
+                    disputed_transaction_id: optional_uuid_column(row, 12)?,
+                    dispute_id: optional_uuid_column(row, 13)?,
+                    dispute_action: match column::<Option<String>>(row, 14)? {
+                        Some(_) => Some(parse_column(row, 14, parse_dispute_action)?),
+                        None => None,
+                    },
+                };
+                Ok((id, columns.into_instruction()?))
+            });
+            let (id, instruction) = match decoded {
+                Ok(decoded) => decoded,
+                Err(reason) => {
+                    corruptions.reject("transactions", row_id, reason)?;
+                    continue;
+                }
+            };
+            let Some(status) = corruptions.repair(
+                "transactions",
+                row_id,
+                parse_column(row, 8, parse_status),
+                Some(TransactionStatus::Pending),
+            )?
+            else {
+                continue;
+            };
+            let Some(timestamp) = corruptions.repair(
+                "transactions",
+                row_id,
+                parse_column(row, 9, parse_timestamp),
+                Some(DateTime::UNIX_EPOCH),
+            )?
+            else {
+                continue;
+            };
+            transactions.insert(
+                id,
+                Transaction {
+                    id,
+                    instruction,
+                    status,
+                    timestamp,
+                },
+            );
+        }
+
+        let processed_transactions = DashSet::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT rowid, id FROM processed_transactions")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let row_id: i64 = row.get(0)?;
+            match uuid_column(row, 1) {
+                Ok(id) => {
+                    processed_transactions.insert(id);
+                }
+                Err(reason) => corruptions.reject("processed_transactions", row_id, reason)?,
+            }
+        }
+
+        Ok((accounts, transactions, processed_transactions))
+    }
+
+    /// Moves the rejected rows to `quarantined_rows` and records the outcome in the report.
+    fn quarantine(&mut self, corruptions: Corruptions) -> Result<(), PersistenceError> {
+        if !corruptions.rejected.is_empty() {
+            let quarantined_at = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
+            let tx = self.conn.transaction()?;
+            for corrupt in &corruptions.rejected {
+                tx.execute(
+                    "INSERT INTO quarantined_rows (table_name, row_id, reason, payload, quarantined_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        corrupt.table,
+                        corrupt.row_id,
+                        corrupt.reason,
+                        row_payload(&tx, &corrupt.table, corrupt.row_id)?,
+                        quarantined_at,
+                    ],
+                )?;
+                tx.execute(
+                    &format!("DELETE FROM {} WHERE rowid = ?1", corrupt.table),
+                    [corrupt.row_id],
+                )?;
+            }
+            tx.commit()?;
+        }
+
+        self.report.quarantined.extend(corruptions.rejected);
+        self.report.repaired.extend(corruptions.repaired);
+        Ok(())
+    }
+}
+
+/// Instruction spread over the typed columns of the `transactions` table.
+struct InstructionColumns {
+    kind: String,
+    source_account_id: Option<Uuid>,
+    destination_account_id: Option<Uuid>,
+    account_id: Option<Uuid>,
+    amount: Option<u64>,
+    account_type: Option<AccountType>,
+    keys: Vec<Key>,
+    held_transaction_id: Option<Uuid>,
+    decision: Option<ReviewDecision>,
+    disputed_transaction_id: Option<Uuid>,
+    dispute_id: Option<Uuid>,
+    dispute_action: Option<DisputeAction>,
+}
+
+impl From<&Instruction> for InstructionColumns {
+    fn from(instruction: &Instruction) -> Self {
+        let mut columns = InstructionColumns {
+            kind: instruction_kind_name(instruction.kind()).to_string(),
+            source_account_id: None,
+            destination_account_id: None,
+            account_id: None,
+            amount: None,
+            account_type: None,
+            keys: Vec::new(),
+            held_transaction_id: None,
+            decision: None,
+            disputed_transaction_id: None,
+            dispute_id: None,
+            dispute_action: None,
+        };
+        match instruction {
+            Instruction::Transfer(transfer) => {
+                columns.source_account_id = Some(transfer.source_account_id);
+                columns.destination_account_id = Some(transfer.destination_account_id);
+                columns.amount = Some(transfer.amount);
+            }
+            Instruction::Deposit(deposit) => {
+                columns.destination_account_id = Some(deposit.destination_account_id);
+                columns.amount = Some(deposit.amount);
+            }
+            Instruction::CreateAccount(create) => {
+                columns.account_type = Some(create.account_type);
+                columns.keys = create.keys.clone();
+            }
+            Instruction::GetBalance(get_balance) => {
+                columns.account_id = Some(get_balance.account_id);
+            }
+            Instruction::Review(review) => {
+                columns.held_transaction_id = Some(review.held_transaction_id);
+                columns.decision = Some(review.decision);
+            }
+            Instruction::OpenDispute(open) => {
+                columns.disputed_transaction_id = Some(open.disputed_transaction_id);
+                columns.amount = Some(open.amount);
+            }
+            Instruction::UpdateDispute(update) => {
+                columns.dispute_id = Some(update.dispute_id);
+                columns.dispute_action = Some(update.action);
+            }
+        }
+        columns
+    }
+}
+
+impl InstructionColumns {
+    fn into_instruction(self) -> Result<Instruction, String> {
+        let missing = |column: &str| format!("{} transaction without {}", self.kind, column);
+        Ok(match self.kind.as_str() {
+            "transfer" => Instruction::Transfer(TransferInstruction {
+                source_account_id: self
+                    .source_account_id
+                    .ok_or_else(|| missing("source_account_id"))?,
+                destination_account_id: self
+                    .destination_account_id
+                    .ok_or_else(|| missing("destination_account_id"))?,
+                amount: self.amount.ok_or_else(|| missing("amount"))?,
+            }),
+            "deposit" => Instruction::Deposit(DepositInstruction {
+                destination_account_id: self
+                    .destination_account_id
+                    .ok_or_else(|| missing("destination_account_id"))?,
+                amount: self.amount.ok_or_else(|| missing("amount"))?,
+            }),
+            // Owners are recorded from the commit log, which keeps them.
+            "create_account" => Instruction::CreateAccount(CreateAccountInstruction {
+                account_type: self.account_type.unwrap_or_default(),
+                keys: self.keys,
+                owner: None,
+            }),
+            "get_balance" => Instruction::GetBalance(GetBalanceInstruction {
+                account_id: self.account_id.ok_or_else(|| missing("account_id"))?,
+            }),
+            "review" => Instruction::Review(ReviewInstruction {
+                held_transaction_id: self
+                    .held_transaction_id
+                    .ok_or_else(|| missing("held_transaction_id"))?,
+                decision: self.decision.ok_or_else(|| missing("decision"))?,
+            }),
+            "open_dispute" => Instruction::OpenDispute(OpenDisputeInstruction {
+                disputed_transaction_id: self
+                    .disputed_transaction_id
+                    .ok_or_else(|| missing("disputed_transaction_id"))?,
+                amount: self.amount.ok_or_else(|| missing("amount"))?,
+            }),
+            "update_dispute" => Instruction::UpdateDispute(UpdateDisputeInstruction {
+                dispute_id: self.dispute_id.ok_or_else(|| missing("dispute_id"))?,
+                action: self
+                    .dispute_action
+                    .ok_or_else(|| missing("dispute_action"))?,
+            }),
+            kind => return Err(format!("unknown transaction kind {kind}")),
+        })
+    }
+}
+
+fn instruction_kind_name(kind: InstructionKind) -> &'static str {
+    match kind {
+        InstructionKind::Transfer => "transfer",
+        InstructionKind::Deposit => "deposit",
+        InstructionKind::CreateAccount => "create_account",
+        InstructionKind::GetBalance => "get_balance",
+        InstructionKind::Review => "review",
+        InstructionKind::OpenDispute => "open_dispute",
+        InstructionKind::UpdateDispute => "update_dispute",
+    }
+}
+
+fn account_type_name(account_type: AccountType) -> &'static str {
+    match account_type {
+        AccountType::Personal => "personal",
+        AccountType::Merchant => "merchant",
+        AccountType::Revenue => "revenue",
+        AccountType::Escrow => "escrow",
+    }
+}
+
+fn parse_account_type(value: &str) -> Option<AccountType> {
+    match value {
+        "personal" => Some(AccountType::Personal),
+        "merchant" => Some(AccountType::Merchant),
+        "revenue" => Some(AccountType::Revenue),
+        "escrow" => Some(AccountType::Escrow),
+        _ => None,
+    }
+}
+
+fn decision_name(decision: ReviewDecision) -> &'static str {
+    match decision {
+        ReviewDecision::Approve => "approve",
+        ReviewDecision::Reject => "reject",
+    }
+}
+
+fn parse_decision(value: &str) -> Option<ReviewDecision> {
+    match value {
+        "approve" => Some(ReviewDecision::Approve),
+        "reject" => Some(ReviewDecision::Reject),
+        _ => None,
+    }
+}
+
+fn dispute_action_name(action: DisputeAction) -> &'static str {
+    match action {
+        DisputeAction::StartAnalysis => "start_analysis",
+        DisputeAction::Return => "return",
+        DisputeAction::Release => "release",
+        DisputeAction::Expire => "expire",
+    }
+}
+
+fn parse_dispute_action(value: &str) -> Option<DisputeAction> {
+    match value {
+        "start_analysis" => Some(DisputeAction::StartAnalysis),
+        "return" => Some(DisputeAction::Return),
+        "release" => Some(DisputeAction::Release),
+        "expire" => Some(DisputeAction::Expire),
+        _ => None,
+    }
+}
+
+fn status_name(status: &TransactionStatus) -> &'static str {
+    match status {
+        TransactionStatus::Pending => "pending",
+        TransactionStatus::Completed => "completed",
+        TransactionStatus::Failed => "failed",
+    }
+}
+
+fn parse_status(value: &str) -> Option<TransactionStatus> {
+    match value {
+        "pending" => Some(TransactionStatus::Pending),
+        "completed" => Some(TransactionStatus::Completed),
+        "failed" => Some(TransactionStatus::Failed),
+        _ => None,
+    }
+}
+
+fn key_columns(key: &Key) -> (&'static str, &str) {
+    match key {
+        Key::CPF(value) => ("cpf", value),
+        Key::Email(value) => ("email", value),
+        Key::Phone(value) => ("phone", value),
+        Key::Random(value) => ("random", value),
+    }
+}
+
+fn parse_key(kind: &str, value: String) -> Option<Key> {
+    match kind {
+        "cpf" => Some(Key::CPF(value)),
+        "email" => Some(Key::Email(value)),
+        "phone" => Some(Key::Phone(value)),
+        "random" => Some(Key::Random(value)),
+        _ => None,
+    }
+}
+
+/// Reads a column, describing a missing or mistyped value as a reason for rejecting the row.
+/// SQLite integers are signed: larger values are refused rather than stored wrapped.
+fn sql_integer(
+    table: &'static str,
+    column: &'static str,
+    value: u64,
+) -> Result<i64, PersistenceError> {
+    i64::try_from(value).map_err(|_| PersistenceError::OutOfRange {
+        table,
+        column,
+        value,
+    })
+}
+
+fn column<T: FromSql>(row: &Row, column: usize) -> Result<T, String> {
+    row.get(column).map_err(|e| e.to_string())
+}
+
+fn parse_column<T>(
+    row: &Row,
+    column: usize,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<T, String> {
+    let value: String = self::column(row, column)?;
+    parse(&value).ok_or_else(|| {
+        let name = row.as_ref().column_name(column).unwrap_or("?");
+        format!("invalid {name} {value:?}")
+    })
+}
+
+fn uuid_column(row: &Row, column: usize) -> Result<Uuid, String> {
+    parse_column(row, column, |value| Uuid::parse_str(value).ok())
+}
+
+fn optional_uuid_column(row: &Row, column: usize) -> Result<Option<Uuid>, String> {
+    match self::column::<Option<String>>(row, column)? {
+        Some(_) => uuid_column(row, column).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .ok()
+}
+
+/// Copies a row's columns into a JSON object, so that it can be kept after the row is deleted.
+fn row_payload(conn: &Connection, table: &str, row_id: i64) -> Result<String> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {table} WHERE rowid = ?1"))?;
+    let names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    stmt.query_row([row_id], |row| {
+        let mut payload = serde_json::Map::new();
+        for (index, name) in names.iter().enumerate() {
+            let value = match row.get_ref(index)? {
+                ValueRef::Null => serde_json::Value::Null,
+                ValueRef::Integer(value) => value.into(),
+                ValueRef::Real(value) => value.into(),
+                ValueRef::Text(value) => String::from_utf8_lossy(value).into(),
+                ValueRef::Blob(value) => value.to_vec().into(),
+            };
+            payload.insert(name.clone(), value);
+        }
+        Ok(serde_json::Value::Object(payload).to_string())
+    })
+}
+
+/// Corrupt rows met during one load, sorted out according to the corruption policy.
+struct Corruptions {
+    policy: CorruptionPolicy,
+    rejected: Vec<CorruptRow>,
+    repaired: Vec<CorruptRow>,
+}
+
+impl Corruptions {
+    fn new(policy: CorruptionPolicy) -> Self {
+        Corruptions {
+            policy,
+            rejected: Vec::new(),
+            repaired: Vec::new(),
+        }
+    }
+
+    /// Leaves a row out of the loaded state, or fails the load under [`CorruptionPolicy::Fail`].
+    fn reject(&mut self, table: &str, row_id: i64, reason: String) -> Result<(), PersistenceError> {
+        let corrupt = CorruptRow {
+            table: table.to_string(),
+            row_id,
+            reason,
+        };
+        match self.policy {
+            CorruptionPolicy::Fail => Err(corrupt.into()),
+            CorruptionPolicy::Quarantine | CorruptionPolicy::Repair => {
+                self.rejected.push(corrupt);
+                Ok(())
+            }
+        }
+    }
+
+    /// Falls back to `replacement` for a field that has a safe one, under
+    /// [`CorruptionPolicy::Repair`]. Otherwise the row is rejected and `None` is returned.
+    fn repair<T>(
+        &mut self,
+        table: &str,
+        row_id: i64,
+        value: Result<T, String>,
+        replacement: Option<T>,
+    ) -> Result<Option<T>, PersistenceError> {
+        match (value, replacement) {
+            (Ok(value), _) => Ok(Some(value)),
+            (Err(reason), Some(replacement)) if self.policy == CorruptionPolicy::Repair => {
+                self.repaired.push(CorruptRow {
+                    table: table.to_string(),
+                    row_id,
+                    reason,
+                });
+                Ok(Some(replacement))
+            }
+            (Err(reason), _) => self.reject(table, row_id, reason).map(|_| None),
+        }
+    }
+}
+
+impl PersistenceBackend for SqliteBackend {
+    fn checkpoint(
+        &mut self,
+        accounts: &DashMap<Uuid, Account>,
+        transactions: &DashMap<Uuid, Transaction>,
+        processed_transactions: &DashSet<Uuid>,
+    ) -> Result<(), PersistenceError> {
+        let tx = self.conn.transaction()?;
+        for table in [
+            "accounts",
+            "account_keys",
+            "account_transactions",
+            "transactions",
+            "transaction_keys",
+            "processed_transactions",
+        ] {
+            tx.execute(&format!("DELETE FROM {table}"), [])?;
+        }
+
+        {
+            let mut insert_account = tx.prepare(
+                "INSERT INTO accounts (uuid, balance, account_type) VALUES (?1, ?2, ?3)",
+            )?;
+            let mut insert_key = tx.prepare(
+                "INSERT INTO account_keys (account_id, position, kind, value) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            let mut insert_history = tx.prepare(
+                "INSERT INTO account_transactions (account_id, position, transaction_id) VALUES (?1, ?2, ?3)",
+            )?;
+
+            for account in accounts.iter() {
+                let account_id = account.uuid.to_string();
+                insert_account.execute(params![
+                    account_id,
+                    sql_integer("accounts", "balance", account.balance)?,
+                    account_type_name(account.account_type),
+                ])?;
+                for (position, key) in account.keys.iter().enumerate() {
+                    let (kind, value) = key_columns(key);
+                    insert_key.execute(params![account_id, position, kind, value])?;
+                }
+                for (position, transaction_id) in account.transaction_history.iter().enumerate() {
+                    insert_history.execute(params![
+                        account_id,
+                        position,
+                        transaction_id.to_string()
+                    ])?;
+                }
+            }
+        }
+
+        {
+            let mut insert_transaction = tx.prepare(
+                "INSERT INTO transactions (id, kind, source_account_id, destination_account_id, account_id, amount, account_type, status, timestamp, held_transaction_id, decision, disputed_transaction_id, dispute_id, dispute_action)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            )?;
+            let mut insert_key = tx.prepare(
+                "INSERT INTO transaction_keys (transaction_id, position, kind, value) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+
+            for transaction in transactions.iter() {
+                let id = transaction.id.to_string();
+                let columns = InstructionColumns::from(&transaction.instruction);
+                insert_transaction.execute(params![
+                    id,
+                    columns.kind,
+                    columns.source_account_id.map(|id| id.to_string()),
+                    columns.destination_account_id.map(|id| id.to_string()),
+                    columns.account_id.map(|id| id.to_string()),
+                    columns
+                        .amount
+                        .map(|amount| sql_integer("transactions", "amount", amount))
+                        .transpose()?,
+                    columns.account_type.map(account_type_name),
+                    status_name(&transaction.status),
+                    transaction
+                        .timestamp
+                        .to_rfc3339_opts(SecondsFormat::Micros, true),
+                    columns.held_transaction_id.map(|id| id.to_string()),
+                    columns.decision.map(decision_name),
+                    columns.disputed_transaction_id.map(|id| id.to_string()),
+                    columns.dispute_id.map(|id| id.to_string()),
+                    columns.dispute_action.map(dispute_action_name),
+                ])?;
+                for (position, key) in columns.keys.iter().enumerate() {
+                    let (kind, value) = key_columns(key);
+                    insert_key.execute(params![id, position, kind, value])?;
+                }
+            }
+        }
+
+        {
+            let mut insert_processed =
+                tx.prepare("INSERT INTO processed_transactions (id) VALUES (?1)")?;
+            for transaction_id in processed_transactions.iter() {
+                insert_processed.execute([transaction_id.to_string()])?;
+            }
+        }
+
+        Ok(tx.commit()?)
+    }
+
+    fn append_events(
+        &mut self,
+        events: &[Arc<CommittedTransaction>],
+    ) -> Result<(), PersistenceError> {
+        let tx = self.conn.transaction()?;
+
+        // The event log is append-only: entries already stored are left untouched.
+        for event in events {
+            let payload = serde_json::to_string(event.as_ref())?;
+
+            tx.execute(
+                "INSERT OR IGNORE INTO committed_transactions (sequence, transaction_id, event) VALUES (?1, ?2, ?3)",
+                params![event.sequence, event.transaction_id.to_string(), payload],
+            )?;
+        }
+
+        Ok(tx.commit()?)
+    }
+
+    fn load_state(&mut self) -> Result<LoadedState, PersistenceError> {
+        let mut corruptions = Corruptions::new(self.policy);
+        let state = self.read_state(&mut corruptions)?;
+        self.quarantine(corruptions)?;
+        Ok(state)
+    }
+
+    fn load_events(&mut self) -> Result<Vec<CommittedTransaction>, PersistenceError> {
+        let mut corruptions = Corruptions::new(self.policy);
+        let mut events = Vec::new();
+        {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT rowid, event FROM committed_transactions ORDER BY sequence")?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                let row_id: i64 = row.get(0)?;
+                let decoded = column::<String>(row, 1)
+                    .and_then(|event| serde_json::from_str(&event).map_err(|e| e.to_string()));
+                match decoded {
+                    Ok(event) => events.push(event),
+                    Err(reason) => corruptions.reject("committed_transactions", row_id, reason)?,
+                }
+            }
+        }
+        self.quarantine(corruptions)?;
+        Ok(events)
+    }
+
+    fn load_report(&self) -> LoadReport {
+        self.report.clone()
+    }
+}
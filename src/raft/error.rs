@@ -0,0 +1,37 @@
+use {
+    serde::{Deserialize, Serialize},
+    thiserror::Error,
+};
+
+/// Serializable, as the leader returns it to followers forwarding their writes.
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize, Deserialize)]
+pub enum RaftError {
+    #[error("No cluster leader is known")]
+    NoLeader,
+    #[error("This node is not the cluster leader")]
+    NotLeader,
+    #[error("Leadership changed before the transaction was committed")]
+    LeadershipLost,
+    #[error("The transaction was not committed within {0} ms")]
+    Timeout(u64),
+    #[error("Node {node} is unreachable: {reason}")]
+    Unreachable { node: u64, reason: String },
+    // The leader refused a forwarded write for the client that submitted it.
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("Invalid Raft message: {0}")]
+    Decode(String),
+    #[error("Invalid cluster configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Raft storage error: {0}")]
+    Storage(String),
+    // The transaction was committed to the log but failed, e.g. for lack of funds.
+    #[error("{0}")]
+    Rejected(String),
+}
+
+impl From<rusqlite::Error> for RaftError {
+    fn from(error: rusqlite::Error) -> Self {
+        RaftError::Storage(error.to_string())
+    }
+}
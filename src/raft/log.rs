@@ -0,0 +1,190 @@
+//! Raft log, kept in memory and written through to the Raft store. Applied entries are
+//! compacted into a snapshot of the ledger, after which the log only holds the entries
+//! following it.
+
+use {
+    crate::raft::{LogEntry, Snapshot, error::RaftError, store::RaftStore},
+    std::sync::Arc,
+};
+
+pub struct RaftLog {
+    store: Arc<RaftStore>,
+    snapshot: Option<Arc<Snapshot>>,
+    // Entries after the snapshot, with contiguous indexes.
+    entries: Vec<LogEntry>,
+}
+
+impl RaftLog {
+    /// Loads the log left in the store by a previous run.
+    pub fn load(store: Arc<RaftStore>) -> Result<Self, RaftError> {
+        let snapshot = store.snapshot()?.map(Arc::new);
+        let entries = store.entries()?;
+        Ok(RaftLog {
+            store,
+            snapshot,
+            entries,
+        })
+    }
+
+    pub fn snapshot(&self) -> Option<Arc<Snapshot>> {
+        self.snapshot.clone()
+    }
+
+    /// Index of the last entry covered by the snapshot, or 0 without one.
+    pub fn snapshot_index(&self) -> u64 {
+        self.snapshot.as_ref().map_or(0, |s| s.last_index)
+    }
+
+    fn snapshot_term(&self) -> u64 {
+        self.snapshot.as_ref().map_or(0, |s| s.last_term)
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.entries
+            .last()
+            .map_or(self.snapshot_index(), |entry| entry.index)
+    }
+
+    pub fn last_term(&self) -> u64 {
+        self.entries
+            .last()
+            .map_or(self.snapshot_term(), |entry| entry.term)
+    }
+
+    /// Term of the entry at `index`, including the last one covered by the snapshot. `None`
+    /// if the log does not hold it.
+    pub fn term(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot_index() {
+            return Some(self.snapshot_term());
+        }
+        self.entry(index).map(|entry| entry.term)
+    }
+
+    pub fn entry(&self, index: u64) -> Option<&LogEntry> {
+        let offset = index.checked_sub(self.snapshot_index() + 1)?;
+        self.entries.get(usize::try_from(offset).ok()?)
+    }
+
+    /// Up to `max` entries from `index` on.
+    pub fn entries_from(&self, index: u64, max: usize) -> Vec<LogEntry> {
+        let offset = index.saturating_sub(self.snapshot_index() + 1) as usize;
+        self.entries
+            .iter()
+            .skip(offset)
+            .take(max)
+            .cloned()
+            .collect()
+    }
+
+    /// Appends entries following the last one, once they are stored.
+    pub fn append(&mut self, entries: Vec<LogEntry>) -> Result<(), RaftError> {
+        debug_assert!(
+            entries
+                .iter()
+                .zip(self.last_index() + 1..)
+                .all(|(entry, index)| entry.index == index)
+        );
+        self.store.append(&entries)?;
+        self.entries.extend(entries);
+        Ok(())
+    }
+
+    /// Removes the entry at `index` and every entry after it.
+    pub fn truncate(&mut self, index: u64) -> Result<(), RaftError> {
+        self.store.truncate(index)?;
+        let offset = index.saturating_sub(self.snapshot_index() + 1) as usize;
+        self.entries.truncate(offset);
+        Ok(())
+    }
+
+    /// First index holding the same term as the entry at `index`. Returned to the leader on a
+    /// conflict so that it skips the whole conflicting term at once.
+    pub fn first_index_of_term(&self, index: u64) -> u64 {
+        let term = self.term(index);
+        let mut first = index;
+        while first > self.snapshot_index() + 1 && self.term(first - 1) == term {
+            first -= 1;
+        }
+        first
+    }
+
+    /// Replaces the entries up to the snapshot's last index by the snapshot. Entries following
+    /// it are kept only if the log agrees with the snapshot, which is always the case when
+    /// compacting the node's own applied entries.
+    pub fn compact(&mut self, snapshot: Snapshot) -> Result<(), RaftError> {
+        if snapshot.last_index <= self.snapshot_index() {
+            return Ok(());
+        }
+
+        let agrees = self.term(snapshot.last_index) == Some(snapshot.last_term);
+        self.store.compact(&snapshot, agrees)?;
+        if agrees {
+            let covered = (snapshot.last_index - self.snapshot_index()) as usize;
+            self.entries.drain(..covered);
+        } else {
+            self.entries.clear();
+        }
+        self.snapshot = Some(Arc::new(snapshot));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::raft::Command};
+
+    fn entry(index: u64, term: u64) -> LogEntry {
+        LogEntry {
+            index,
+            term,
+            command: Command::Noop,
+        }
+    }
+
+    fn snapshot(last_index: u64, last_term: u64) -> Snapshot {
+        Snapshot {
+            last_index,
+            last_term,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compaction_keeps_indexes() {
+        let store = Arc::new(RaftStore::open(":memory:").unwrap());
+        let mut log = RaftLog::load(store.clone()).unwrap();
+        log.append(
+            [(1, 1), (2, 1), (3, 2), (4, 2), (5, 2)]
+                .map(|(i, t)| entry(i, t))
+                .into(),
+        )
+        .unwrap();
+        assert_eq!(log.first_index_of_term(5), 3);
+
+        log.compact(snapshot(3, 2)).unwrap();
+        assert_eq!(log.snapshot_index(), 3);
+        assert_eq!(log.term(3), Some(2));
+        assert!(log.entry(3).is_none());
+        assert_eq!(log.entry(4).unwrap().index, 4);
+        assert_eq!(log.entries_from(2, 10).len(), 2);
+        assert_eq!(log.first_index_of_term(5), 4);
+
+        log.truncate(5).unwrap();
+        assert_eq!(log.last_index(), 4);
+        log.append(vec![entry(5, 3)]).unwrap();
+        assert_eq!(log.last_term(), 3);
+
+        let reloaded = RaftLog::load(store.clone()).unwrap();
+        assert_eq!(reloaded.snapshot_index(), 3);
+        assert_eq!(reloaded.last_index(), 5);
+        assert_eq!(reloaded.term(4), Some(2));
+        assert_eq!(reloaded.last_term(), 3);
+
+        // A snapshot installed from a leader that disagrees with the log replaces all of it.
+        log.compact(snapshot(6, 4)).unwrap();
+        assert_eq!(log.last_index(), 6);
+        assert_eq!(log.last_term(), 4);
+        assert!(log.entries_from(0, 10).is_empty());
+        assert_eq!(RaftLog::load(store).unwrap().last_index(), 6);
+    }
+}
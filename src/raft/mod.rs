@@ -0,0 +1,1726 @@
+//! Raft consensus between the members of a cluster.
+//! Every write is appended to a replicated log and only applied, through the transaction
+//! processor, once a majority of the members stored it. All members apply the same entries in
+//! the same order, so they end up with the same ledger and commit log, only commit times being
+//! taken from each node's clock. Decisions depending on time, such as risk velocity checks, use
+//! the submission time carried by the entry instead. Writes received by a follower are
+//! forwarded to the leader, while reads are served from the local state.
+//!
+//! The current term, the vote cast in it and the Raft log are stored before the node answers
+//! its peers, while the ledger is checkpointed and journaled as on a single node. A restarted
+//! node resumes from them and catches up from the leader: entries following the last snapshot
+//! are applied again, and those it had already applied are skipped by transaction ID.
+
+pub mod error;
+pub mod log;
+pub mod store;
+pub mod transport;
+
+use {
+    crate::{
+        config::ClusterConfig,
+        events::{CommittedTransaction, error::EventLogError},
+        ledger::error::LedgerError,
+        metrics::{
+            RAFT_COMMIT_INDEX, RAFT_ELECTIONS_TOTAL, RAFT_IS_LEADER, RAFT_SNAPSHOTS_TOTAL,
+            RAFT_TERM,
+        },
+        models::{Account, Transaction},
+        raft::{error::RaftError, log::RaftLog, store::RaftStore, transport::RaftTransport},
+        transaction_processor::{
+            TransactionProcessor,
+            interface::{TransactionProcessorInterface, TransactionResult},
+        },
+    },
+    rand::Rng,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        future::Future,
+        sync::{Arc, Mutex, MutexGuard},
+        time::Duration,
+    },
+    tokio::{
+        sync::{Notify, broadcast, oneshot},
+        time::Instant,
+    },
+    tracing::{debug, error, info, warn},
+    uuid::Uuid,
+};
+
+pub type NodeId = u64;
+
+// How often elections and heartbeats are due is checked.
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+// Entries sent to a follower in a single request while it catches up.
+const MAX_ENTRIES_PER_REQUEST: usize = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Command {
+    /// Appended by every new leader, as entries from earlier terms are only committed along
+    /// with one of the current term.
+    Noop,
+    /// Account IDs are derived from transaction IDs, so every member applying a transaction
+    /// ends up with the same ledger.
+    Transaction(Transaction),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub index: u64,
+    pub term: u64,
+    pub command: Command,
+}
+
+/// State of the ledger right after applying the entry at `last_index`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub last_index: u64,
+    pub last_term: u64,
+    pub accounts: Vec<Account>,
+    pub processed_transactions: Vec<Uuid>,
+    pub transactions: Vec<Transaction>,
+    // Last commit log sequence at that point. The log itself is only read when the snapshot
+    // is sent.
+    pub last_sequence: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendEntriesRequest {
+    pub term: u64,
+    pub leader_id: NodeId,
+    pub prev_log_index: u64,
+    pub prev_log_term: u64,
+    pub entries: Vec<LogEntry>,
+    pub leader_commit: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendEntriesResponse {
+    pub term: u64,
+    pub success: bool,
+    // On success the last index matching the leader's log, otherwise the index to retry from.
+    pub last_index: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteRequest {
+    pub term: u64,
+    pub candidate_id: NodeId,
+    pub last_log_index: u64,
+    pub last_log_term: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteResponse {
+    pub term: u64,
+    pub vote_granted: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallSnapshotRequest {
+    pub term: u64,
+    pub leader_id: NodeId,
+    pub snapshot: Snapshot,
+    // Commit log entries up to the snapshot's last sequence.
+    pub events: Vec<CommittedTransaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallSnapshotResponse {
+    pub term: u64,
+}
+
+/// A write received by a follower, with the client that submitted it, which the leader
+/// authorizes again before committing the write.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardRequest {
+    pub transaction: Transaction,
+    // None for writes of the cluster itself, such as expired disputes, and when the follower
+    // does not authenticate its clients.
+    pub client_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Point-in-time view of a node, for monitoring and tests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RaftStatus {
+    pub id: NodeId,
+    pub role: Role,
+    pub term: u64,
+    pub leader: Option<NodeId>,
+    pub last_index: u64,
+    pub commit_index: u64,
+    pub last_applied: u64,
+}
+
+// Replication progress of a follower, tracked by the leader.
+struct Progress {
+    next_index: u64,
+    match_index: u64,
+    // Only one request per follower is in flight, so responses arrive in order.
+    in_flight: bool,
+}
+
+struct Proposal {
+    term: u64,
+    reply: oneshot::Sender<Result<TransactionResult, RaftError>>,
+}
+
+enum Message {
+    Append(AppendEntriesRequest),
+    Snapshot(InstallSnapshotRequest),
+}
+
+struct RaftState {
+    term: u64,
+    voted_for: Option<NodeId>,
+    role: Role,
+    leader: Option<NodeId>,
+    log: RaftLog,
+    commit_index: u64,
+    last_applied: u64,
+    election_deadline: Instant,
+    heartbeat_due: Instant,
+    votes: HashSet<NodeId>,
+    progress: HashMap<NodeId, Progress>,
+    // Writes proposed on this node, by log index, waiting for their entry to be applied.
+    pending: BTreeMap<u64, Proposal>,
+}
+
+pub struct RaftNode {
+    id: NodeId,
+    peers: Vec<NodeId>,
+    config: ClusterConfig,
+    processor: Arc<TransactionProcessor>,
+    transport: Arc<dyn RaftTransport>,
+    store: Arc<RaftStore>,
+    state: Mutex<RaftState>,
+    // Wakes the applier once entries are committed.
+    committed: Notify,
+    // Held while committed entries or a snapshot are applied, so that only one of them
+    // changes the ledger at a time. Taken before the state lock.
+    applying: Mutex<()>,
+}
+
+impl RaftNode {
+    /// Resumes from the term, vote and log left in `store` by a previous run.
+    pub fn new(
+        config: ClusterConfig,
+        processor: Arc<TransactionProcessor>,
+        transport: Arc<dyn RaftTransport>,
+        store: Arc<RaftStore>,
+    ) -> Result<Self, RaftError> {
+        let (term, voted_for) = store.hard_state()?;
+        let log = RaftLog::load(store.clone())?;
+        // The ledger recovered on startup holds at least the entries covered by the snapshot.
+        let applied = log.snapshot_index();
+        RAFT_TERM.set(term as f64);
+
+        let node = RaftNode {
+            id: config.node_id,
+            peers: config.peers.iter().map(|peer| peer.id).collect(),
+            config,
+            processor,
+            transport,
+            store,
+            state: Mutex::new(RaftState {
+                term,
+                voted_for,
+                role: Role::Follower,
+                leader: None,
+                log,
+                commit_index: applied,
+                last_applied: applied,
+                election_deadline: Instant::now(),
+                heartbeat_due: Instant::now(),
+                votes: HashSet::new(),
+                progress: HashMap::new(),
+                pending: BTreeMap::new(),
+            }),
+            committed: Notify::new(),
+            applying: Mutex::new(()),
+        };
+        node.lock().election_deadline = Instant::now() + node.election_timeout();
+        Ok(node)
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn status(&self) -> RaftStatus {
+        let state = self.lock();
+        RaftStatus {
+            id: self.id,
+            role: state.role,
+            term: state.term,
+            leader: state.leader,
+            last_index: state.log.last_index(),
+            commit_index: state.commit_index,
+            last_applied: state.last_applied,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RaftState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn majority(&self) -> usize {
+        let members = self.peers.len() + 1;
+        members / 2 + 1
+    }
+
+    fn election_timeout(&self) -> Duration {
+        let min = self.config.election_timeout_min_ms;
+        let max = self.config.election_timeout_max_ms.max(min);
+        Duration::from_millis(rand::rng().random_range(min..=max))
+    }
+
+    /// Runs a request to another node, bounded by the RPC timeout.
+    async fn call<T>(
+        &self,
+        target: NodeId,
+        request: impl Future<Output = Result<T, RaftError>>,
+    ) -> Result<T, RaftError> {
+        tokio::time::timeout(Duration::from_millis(self.config.rpc_timeout_ms), request)
+            .await
+            .unwrap_or_else(|_| {
+                Err(RaftError::Unreachable {
+                    node: target,
+                    reason: "request timed out".to_string(),
+                })
+            })
+    }
+
+    /// Commits a write through the cluster and returns its result once applied locally.
+    /// Followers forward it to the leader, along with the ID of the client submitting it.
+    pub async fn propose(
+        self: &Arc<Self>,
+        transaction: Transaction,
+        client_id: Option<String>,
+    ) -> Result<TransactionResult, RaftError> {
+        let leader = {
+            let state = self.lock();
+            match (state.role, state.leader) {
+                (Role::Leader, _) => None,
+                (_, Some(leader)) => Some(leader),
+                (_, None) => return Err(RaftError::NoLeader),
+            }
+        };
+
+        match leader {
+            // The leader only answers once the write is applied, so this waits as long as a
+            // proposal rather than a single request.
+            Some(leader) => {
+                let timeout_ms = self.config.proposal_timeout_ms;
+                tokio::time::timeout(
+                    Duration::from_millis(timeout_ms),
+                    self.transport.forward(
+                        leader,
+                        ForwardRequest {
+                            transaction,
+                            client_id,
+                        },
+                    ),
+                )
+                .await
+                .unwrap_or(Err(RaftError::Timeout(timeout_ms)))
+            }
+            None => self.handle_forward(transaction).await,
+        }
+    }
+
+    /// Commits a write as the leader. Forwarded writes are not forwarded again, so that nodes
+    /// disagreeing on the leader during an election do not bounce them around.
+    pub async fn handle_forward(
+        self: &Arc<Self>,
+        transaction: Transaction,
+    ) -> Result<TransactionResult, RaftError> {
+        let receiver = {
+            let mut state = self.lock();
+            if state.role != Role::Leader {
+                return Err(RaftError::NotLeader);
+            }
+
+            let index = state.log.last_index() + 1;
+            let term = state.term;
+            state.log.append(vec![LogEntry {
+                index,
+                term,
+                command: Command::Transaction(transaction),
+            }])?;
+
+            let (reply, receiver) = oneshot::channel();
+            state.pending.insert(index, Proposal { term, reply });
+            // A cluster of one commits right away.
+            self.advance_commit(&mut state);
+            receiver
+        };
+        self.replicate_all();
+
+        let timeout_ms = self.config.proposal_timeout_ms;
+        match tokio::time::timeout(Duration::from_millis(timeout_ms), receiver).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(RaftError::LeadershipLost),
+            Err(_) => Err(RaftError::Timeout(timeout_ms)),
+        }
+    }
+
+    /// Starts an election or sends heartbeats when they are due.
+    pub fn tick(self: &Arc<Self>) {
+        let now = Instant::now();
+        let mut state = self.lock();
+        if state.role == Role::Leader {
+            if now >= state.heartbeat_due {
+                state.heartbeat_due =
+                    now + Duration::from_millis(self.config.heartbeat_interval_ms);
+                drop(state);
+                self.replicate_all();
+            }
+        } else if now >= state.election_deadline {
+            self.start_election(state);
+        }
+    }
+
+    fn start_election(self: &Arc<Self>, mut state: MutexGuard<'_, RaftState>) {
+        state.election_deadline = Instant::now() + self.election_timeout();
+        let term = state.term + 1;
+        if let Err(e) = self.store.save_hard_state(term, Some(self.id)) {
+            error!("Failed to store the vote for term {}: {}", term, e);
+            return;
+        }
+        state.term = term;
+        state.role = Role::Candidate;
+        state.voted_for = Some(self.id);
+        state.leader = None;
+        state.votes = HashSet::from([self.id]);
+        RAFT_TERM.set(state.term as f64);
+        RAFT_ELECTIONS_TOTAL.inc();
+        info!(
+            "Node {} starting an election for term {}",
+            self.id, state.term
+        );
+
+        if state.votes.len() >= self.majority() {
+            self.become_leader(&mut state);
+            return;
+        }
+
+        let request = VoteRequest {
+            term: state.term,
+            candidate_id: self.id,
+            last_log_index: state.log.last_index(),
+            last_log_term: state.log.last_term(),
+        };
+        drop(state);
+
+        for &peer in &self.peers {
+            let node = self.clone();
+            let request = request.clone();
+            tokio::spawn(async move {
+                let term = request.term;
+                let result = node
+                    .call(peer, node.transport.request_vote(peer, request))
+                    .await;
+                node.on_vote_response(peer, term, result);
+            });
+        }
+    }
+
+    fn on_vote_response(
+        self: &Arc<Self>,
+        peer: NodeId,
+        term: u64,
+        result: Result<VoteResponse, RaftError>,
+    ) {
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("Vote request to node {} failed: {}", peer, e);
+                return;
+            }
+        };
+
+        let mut state = self.lock();
+        if response.term > state.term {
+            self.step_down(&mut state, response.term);
+            return;
+        }
+        if state.role != Role::Candidate || state.term != term || !response.vote_granted {
+            return;
+        }
+
+        state.votes.insert(peer);
+        if state.votes.len() >= self.majority() {
+            self.become_leader(&mut state);
+            drop(state);
+            self.replicate_all();
+        }
+    }
+
+    fn become_leader(&self, state: &mut RaftState) {
+        info!("Node {} elected leader for term {}", self.id, state.term);
+        state.role = Role::Leader;
+        state.leader = Some(self.id);
+        state.heartbeat_due =
+            Instant::now() + Duration::from_millis(self.config.heartbeat_interval_ms);
+        let next_index = state.log.last_index() + 1;
+        state.progress = self
+            .peers
+            .iter()
+            .map(|&peer| {
+                let progress = Progress {
+                    next_index,
+                    match_index: 0,
+                    in_flight: false,
+                };
+                (peer, progress)
+            })
+            .collect();
+        RAFT_IS_LEADER.set(1.0);
+
+        let index = state.log.last_index() + 1;
+        let term = state.term;
+        let noop = LogEntry {
+            index,
+            term,
+            command: Command::Noop,
+        };
+        if let Err(e) = state.log.append(vec![noop]) {
+            error!("Failed to store the entry opening term {}: {}", term, e);
+        }
+        self.advance_commit(state);
+    }
+
+    /// Follows `leader`, adopting `term` once it is stored if it is newer.
+    fn become_follower(
+        &self,
+        state: &mut RaftState,
+        term: u64,
+        leader: Option<NodeId>,
+    ) -> Result<(), RaftError> {
+        if term > state.term {
+            self.store.save_hard_state(term, None)?;
+            state.term = term;
+            state.voted_for = None;
+            RAFT_TERM.set(term as f64);
+        }
+        if state.role == Role::Leader {
+            info!("Node {} stepping down in term {}", self.id, state.term);
+            RAFT_IS_LEADER.set(0.0);
+            // Their writes may still be committed by the next leader, but their callers are
+            // not left waiting for an entry this node no longer drives.
+            for (_, proposal) in std::mem::take(&mut state.pending) {
+                let _ = proposal.reply.send(Err(RaftError::LeadershipLost));
+            }
+        }
+        if leader.is_some() && state.leader != leader {
+            info!(
+                "Node {} following leader {:?} in term {}",
+                self.id, leader, state.term
+            );
+        }
+        state.role = Role::Follower;
+        state.leader = leader;
+        state.progress.clear();
+        Ok(())
+    }
+
+    /// Steps down on a response from a peer in a newer term.
+    fn step_down(&self, state: &mut RaftState, term: u64) {
+        if let Err(e) = self.become_follower(state, term, None) {
+            error!("Failed to store term {}: {}", term, e);
+        }
+    }
+
+    /// Sends every follower the entries it is missing, or a heartbeat if it has them all.
+    fn replicate_all(self: &Arc<Self>) {
+        for &peer in &self.peers {
+            self.replicate(peer);
+        }
+    }
+
+    fn replicate(self: &Arc<Self>, peer: NodeId) {
+        let message = {
+            let mut state = self.lock();
+            if state.role != Role::Leader {
+                return;
+            }
+            let Some(progress) = state.progress.get_mut(&peer) else {
+                return;
+            };
+            if progress.in_flight {
+                return;
+            }
+            progress.in_flight = true;
+            let next_index = progress.next_index;
+
+            match state.log.snapshot() {
+                // The entries the follower needs were compacted away.
+                Some(snapshot) if next_index <= snapshot.last_index => {
+                    let events = self
+                        .processor
+                        .events
+                        .since(1)
+                        .into_iter()
+                        .take_while(|event| event.sequence <= snapshot.last_sequence)
+                        .map(|event| event.as_ref().clone())
+                        .collect();
+                    Message::Snapshot(InstallSnapshotRequest {
+                        term: state.term,
+                        leader_id: self.id,
+                        snapshot: snapshot.as_ref().clone(),
+                        events,
+                    })
+                }
+                _ => {
+                    let prev_log_index = next_index - 1;
+                    Message::Append(AppendEntriesRequest {
+                        term: state.term,
+                        leader_id: self.id,
+                        prev_log_index,
+                        prev_log_term: state.log.term(prev_log_index).unwrap_or_default(),
+                        entries: state.log.entries_from(next_index, MAX_ENTRIES_PER_REQUEST),
+                        leader_commit: state.commit_index,
+                    })
+                }
+            }
+        };
+
+        let node = self.clone();
+        tokio::spawn(async move {
+            match message {
+                Message::Append(request) => {
+                    let term = request.term;
+                    let result = node
+                        .call(peer, node.transport.append_entries(peer, request))
+                        .await;
+                    node.on_append_response(peer, term, result);
+                }
+                Message::Snapshot(request) => {
+                    let term = request.term;
+                    let last_index = request.snapshot.last_index;
+                    let result = node
+                        .call(peer, node.transport.install_snapshot(peer, request))
+                        .await;
+                    node.on_snapshot_response(peer, term, last_index, result);
+                }
+            }
+        });
+    }
+
+    fn on_append_response(
+        self: &Arc<Self>,
+        peer: NodeId,
+        term: u64,
+        result: Result<AppendEntriesResponse, RaftError>,
+    ) {
+        let mut state = self.lock();
+        if let Some(progress) = state.progress.get_mut(&peer) {
+            progress.in_flight = false;
+        }
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("Replication to node {} failed: {}", peer, e);
+                return;
+            }
+        };
+        if response.term > state.term {
+            self.step_down(&mut state, response.term);
+            return;
+        }
+        if state.role != Role::Leader || state.term != term {
+            return;
+        }
+
+        let last_index = state.log.last_index();
+        let Some(progress) = state.progress.get_mut(&peer) else {
+            return;
+        };
+        if response.success {
+            progress.match_index = progress.match_index.max(response.last_index);
+            progress.next_index = progress.match_index + 1;
+        } else {
+            progress.next_index = response
+                .last_index
+                .min(progress.next_index)
+                .max(progress.match_index + 1);
+        }
+        let behind = progress.next_index <= last_index || !response.success;
+
+        if response.success {
+            self.advance_commit(&mut state);
+        }
+        drop(state);
+        if behind {
+            self.replicate(peer);
+        }
+    }
+
+    fn on_snapshot_response(
+        self: &Arc<Self>,
+        peer: NodeId,
+        term: u64,
+        last_index: u64,
+        result: Result<InstallSnapshotResponse, RaftError>,
+    ) {
+        let mut state = self.lock();
+        if let Some(progress) = state.progress.get_mut(&peer) {
+            progress.in_flight = false;
+        }
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Sending a snapshot to node {} failed: {}", peer, e);
+                return;
+            }
+        };
+        if response.term > state.term {
+            self.step_down(&mut state, response.term);
+            return;
+        }
+        if state.role != Role::Leader || state.term != term {
+            return;
+        }
+
+        if let Some(progress) = state.progress.get_mut(&peer) {
+            progress.match_index = progress.match_index.max(last_index);
+            progress.next_index = progress.match_index + 1;
+        }
+        drop(state);
+        self.replicate(peer);
+    }
+
+    /// Commits the entries of the current term stored by a majority, with every entry before
+    /// them, and wakes the applier.
+    fn advance_commit(&self, state: &mut RaftState) {
+        if state.role != Role::Leader {
+            return;
+        }
+
+        let mut index = state.log.last_index();
+        while index > state.commit_index && state.log.term(index) == Some(state.term) {
+            let replicas = 1 + state
+                .progress
+                .values()
+                .filter(|progress| progress.match_index >= index)
+                .count();
+            if replicas >= self.majority() {
+                state.commit_index = index;
+                self.committed.notify_one();
+                break;
+            }
+            index -= 1;
+        }
+    }
+
+    /// Applies the committed entries, outside of the state lock so that the node keeps
+    /// answering its peers and accepting proposals meanwhile.
+    fn apply_committed(&self) {
+        let _applying = self.applying.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let entries = {
+                let state = self.lock();
+                let committed = state.commit_index.saturating_sub(state.last_applied);
+                if committed == 0 {
+                    break;
+                }
+                let max = (committed as usize).min(MAX_ENTRIES_PER_REQUEST);
+                state.log.entries_from(state.last_applied + 1, max)
+            };
+            if entries.is_empty() {
+                error!("Committed entries are missing from the log");
+                break;
+            }
+
+            let results: Vec<_> = entries
+                .into_iter()
+                .map(|entry| {
+                    let result = match entry.command {
+                        Command::Noop => Err(RaftError::LeadershipLost),
+                        Command::Transaction(transaction) => self
+                            .processor
+                            .process_transaction(transaction)
+                            .map_err(|e| RaftError::Rejected(e.to_string())),
+                    };
+                    (entry.index, entry.term, result)
+                })
+                .collect();
+
+            let mut state = self.lock();
+            for (index, term, result) in results {
+                state.last_applied = index;
+                if let Some(proposal) = state.pending.remove(&index) {
+                    // The entry proposed at this index was replaced by another leader's.
+                    let result = if proposal.term == term {
+                        result
+                    } else {
+                        Err(RaftError::LeadershipLost)
+                    };
+                    let _ = proposal.reply.send(result);
+                }
+            }
+            RAFT_COMMIT_INDEX.set(state.commit_index as f64);
+
+            let applied = state.last_applied;
+            if applied - state.log.snapshot_index() >= self.config.snapshot_threshold.max(1) {
+                let last_term = state.log.term(applied).unwrap_or_default();
+                drop(state);
+                self.compact(applied, last_term);
+            }
+        }
+    }
+
+    /// Compacts the log up to the last applied entry. Called by the applier, so the ledger is
+    /// exactly at that entry.
+    fn compact(&self, last_index: u64, last_term: u64) {
+        let snapshot = match self.take_snapshot(last_index, last_term) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                error!("Failed to snapshot the ledger: {}", e);
+                return;
+            }
+        };
+        match self.lock().log.compact(snapshot) {
+            Ok(()) => {
+                debug!("Compacted the Raft log up to {}", last_index);
+                RAFT_SNAPSHOTS_TOTAL.inc();
+            }
+            Err(e) => error!("Failed to store the Raft snapshot: {}", e),
+        }
+    }
+
+    fn take_snapshot(&self, last_index: u64, last_term: u64) -> Result<Snapshot, LedgerError> {
+        let (accounts, processed_transactions) = self.processor.ledger.snapshot()?;
+        Ok(Snapshot {
+            last_index,
+            last_term,
+            accounts: accounts.into_iter().map(|(_, account)| account).collect(),
+            processed_transactions: processed_transactions.into_iter().collect(),
+            transactions: self
+                .processor
+                .transactions
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect(),
+            last_sequence: self.processor.events.last_sequence(),
+        })
+    }
+
+    /// Removes the entries from `index` on, failing the writes proposed for them.
+    fn truncate(&self, state: &mut RaftState, index: u64) -> Result<(), RaftError> {
+        state.log.truncate(index)?;
+        for (_, proposal) in state.pending.split_off(&index) {
+            let _ = proposal.reply.send(Err(RaftError::LeadershipLost));
+        }
+        Ok(())
+    }
+
+    pub fn handle_append_entries(&self, request: AppendEntriesRequest) -> AppendEntriesResponse {
+        let mut state = self.lock();
+        let leader_id = request.leader_id;
+        match self.append_entries(&mut state, request) {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to store the entries from node {}: {}", leader_id, e);
+                AppendEntriesResponse {
+                    term: state.term,
+                    success: false,
+                    last_index: state.log.last_index() + 1,
+                }
+            }
+        }
+    }
+
+    /// Stores the entries of `request`, and only then acknowledges them.
+    fn append_entries(
+        &self,
+        state: &mut RaftState,
+        mut request: AppendEntriesRequest,
+    ) -> Result<AppendEntriesResponse, RaftError> {
+        let reject = |state: &RaftState, retry_from: u64| AppendEntriesResponse {
+            term: state.term,
+            success: false,
+            last_index: retry_from,
+        };
+        if request.term < state.term {
+            return Ok(reject(state, 0));
+        }
+        self.become_follower(state, request.term, Some(request.leader_id))?;
+        state.election_deadline = Instant::now() + self.election_timeout();
+
+        if request.prev_log_index > state.log.last_index() {
+            let retry_from = state.log.last_index() + 1;
+            return Ok(reject(state, retry_from));
+        }
+        // Entries covered by the snapshot were committed, so they always match.
+        if request.prev_log_index > state.log.snapshot_index()
+            && state.log.term(request.prev_log_index) != Some(request.prev_log_term)
+        {
+            let retry_from = state.log.first_index_of_term(request.prev_log_index);
+            return Ok(reject(state, retry_from));
+        }
+
+        // Entries are contiguous, so every entry after the first one missing from the log is
+        // missing too, once the conflicting ones are removed.
+        let last_index = request.prev_log_index + request.entries.len() as u64;
+        let snapshot_index = state.log.snapshot_index();
+        let missing = request.entries.iter().position(|entry| {
+            entry.index > snapshot_index && state.log.term(entry.index) != Some(entry.term)
+        });
+        if let Some(missing) = missing {
+            let entries = request.entries.split_off(missing);
+            if entries[0].index <= state.log.last_index() {
+                self.truncate(state, entries[0].index)?;
+            }
+            state.log.append(entries)?;
+        }
+
+        if request.leader_commit > state.commit_index {
+            state.commit_index = request.leader_commit.min(last_index);
+            self.committed.notify_one();
+        }
+
+        Ok(AppendEntriesResponse {
+            term: state.term,
+            success: true,
+            last_index,
+        })
+    }
+
+    pub fn handle_request_vote(&self, request: VoteRequest) -> VoteResponse {
+        let mut state = self.lock();
+        let reject = |state: &RaftState| VoteResponse {
+            term: state.term,
+            vote_granted: false,
+        };
+        if request.term > state.term
+            && let Err(e) = self.become_follower(&mut state, request.term, None)
+        {
+            error!("Failed to store term {}: {}", request.term, e);
+            return reject(&state);
+        }
+
+        // Only candidates with every committed entry can win, as a majority holds them all.
+        let up_to_date = (request.last_log_term, request.last_log_index)
+            >= (state.log.last_term(), state.log.last_index());
+        let vote_granted = request.term == state.term
+            && state
+                .voted_for
+                .is_none_or(|candidate| candidate == request.candidate_id)
+            && up_to_date;
+        if !vote_granted {
+            return reject(&state);
+        }
+
+        // Stored before it is cast, so that a restarted node does not vote twice in a term.
+        if let Err(e) = self
+            .store
+            .save_hard_state(state.term, Some(request.candidate_id))
+        {
+            error!(
+                "Failed to store the vote for node {}: {}",
+                request.candidate_id, e
+            );
+            return reject(&state);
+        }
+        state.voted_for = Some(request.candidate_id);
+        state.election_deadline = Instant::now() + self.election_timeout();
+
+        VoteResponse {
+            term: state.term,
+            vote_granted: true,
+        }
+    }
+
+    pub fn handle_install_snapshot(
+        &self,
+        request: InstallSnapshotRequest,
+    ) -> InstallSnapshotResponse {
+        let _applying = self.applying.lock().unwrap_or_else(|e| e.into_inner());
+        let mut state = self.lock();
+        if request.term < state.term {
+            return InstallSnapshotResponse { term: state.term };
+        }
+        if let Err(e) = self.become_follower(&mut state, request.term, Some(request.leader_id)) {
+            error!("Failed to store term {}: {}", request.term, e);
+            return InstallSnapshotResponse { term: state.term };
+        }
+        state.election_deadline = Instant::now() + self.election_timeout();
+
+        let snapshot = request.snapshot;
+        if snapshot.last_index <= state.last_applied {
+            return InstallSnapshotResponse { term: state.term };
+        }
+        if let Err(e) = self.restore(&snapshot, request.events) {
+            error!(
+                "Failed to install the snapshot from node {}: {}",
+                request.leader_id, e
+            );
+            return InstallSnapshotResponse { term: state.term };
+        }
+        info!(
+            "Installed a snapshot up to entry {} from node {}",
+            snapshot.last_index, request.leader_id
+        );
+
+        let last_index = snapshot.last_index;
+        if let Err(e) = state.log.compact(snapshot) {
+            error!("Failed to store the Raft snapshot: {}", e);
+        }
+        let pending = state.pending.split_off(&(last_index + 1));
+        for (_, proposal) in std::mem::replace(&mut state.pending, pending) {
+            let _ = proposal.reply.send(Err(RaftError::LeadershipLost));
+        }
+        state.commit_index = state.commit_index.max(last_index);
+        state.last_applied = last_index;
+        self.committed.notify_one();
+
+        InstallSnapshotResponse { term: state.term }
+    }
+
+    fn restore(
+        &self,
+        snapshot: &Snapshot,
+        events: Vec<CommittedTransaction>,
+    ) -> Result<(), LedgerError> {
+        self.processor.ledger.restore(
+            snapshot
+                .accounts
+                .iter()
+                .map(|account| (account.uuid, account.clone()))
+                .collect(),
+            snapshot.processed_transactions.iter().copied().collect(),
+        )?;
+        for transaction in &snapshot.transactions {
+            self.processor
+                .transactions
+                .insert(transaction.id, transaction.clone());
+        }
+
+        let last_sequence = self.processor.events.last_sequence();
+        for event in events {
+            if event.sequence <= last_sequence {
+                continue;
+            }
+            // The snapshot already holds the ledger state these transactions led to.
+            let stored = self
+                .processor
+                .events
+                .replicate(event, |_| Ok::<(), EventLogError>(()));
+            match stored {
+                Ok(event) => self.processor.record_owner(&event),
+                Err(e) => {
+                    error!("Failed to store the snapshot commit log: {}", e);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Drives elections and heartbeats, and applies committed entries, until shutdown.
+pub async fn start_raft(node: Arc<RaftNode>, mut shutdown_receiver: broadcast::Receiver<()>) {
+    info!("Starting Raft node {} with peers {:?}", node.id, node.peers);
+    tokio::spawn(run_applier(node.clone(), shutdown_receiver.resubscribe()));
+    let mut interval = tokio::time::interval(TICK_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => node.tick(),
+            _ = shutdown_receiver.recv() => {
+                info!("Shutting down Raft node...");
+                return;
+            }
+        }
+    }
+}
+
+/// Applies entries as they are committed, apart from elections and heartbeats so that a long
+/// batch does not delay them.
+async fn run_applier(node: Arc<RaftNode>, mut shutdown_receiver: broadcast::Receiver<()>) {
+    loop {
+        tokio::select! {
+            _ = node.committed.notified() => node.apply_committed(),
+            _ = shutdown_receiver.recv() => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            auth::{Authenticator, Scope, store::OwnershipStore},
+            config::{AuthClient, AuthConfig, ClusterPeer},
+            grpc_server::{
+                QuasarGrpcServer,
+                server::{
+                    CreateAccountRequest, DepositRequest, RaftMessage,
+                    grpc_service_server::GrpcService, raft_service_client::RaftServiceClient,
+                    raft_service_server::RaftServiceServer,
+                },
+            },
+            ledger::Ledger,
+            models::{
+                CreateAccountInstruction, DepositInstruction, Instruction, Posting,
+                TransactionStatus, TransferInstruction,
+            },
+            raft::transport::{GrpcTransport, RaftGrpcServer},
+            submission::SubmissionQueue,
+            transaction_processor::error::TransactionProcessorError,
+        },
+        chrono::Utc,
+        dashmap::DashMap,
+        std::sync::RwLock,
+        tokio_stream::wrappers::TcpListenerStream,
+        tonic::{Request, transport::Server},
+    };
+
+    /// Delivers messages between nodes of one process, unless either end is isolated.
+    #[derive(Default)]
+    struct LocalNetwork {
+        nodes: RwLock<HashMap<NodeId, Arc<RaftNode>>>,
+        isolated: Mutex<HashSet<NodeId>>,
+    }
+
+    impl LocalNetwork {
+        fn node(&self, from: NodeId, target: NodeId) -> Result<Arc<RaftNode>, RaftError> {
+            let isolated = self.isolated.lock().unwrap();
+            if isolated.contains(&from) || isolated.contains(&target) {
+                return Err(RaftError::Unreachable {
+                    node: target,
+                    reason: "partitioned".to_string(),
+                });
+            }
+            Ok(self.nodes.read().unwrap()[&target].clone())
+        }
+
+        fn isolate(&self, id: NodeId) {
+            self.isolated.lock().unwrap().insert(id);
+        }
+
+        fn heal(&self) {
+            self.isolated.lock().unwrap().clear();
+        }
+    }
+
+    struct LocalTransport {
+        from: NodeId,
+        network: Arc<LocalNetwork>,
+    }
+
+    #[tonic::async_trait]
+    impl RaftTransport for LocalTransport {
+        async fn append_entries(
+            &self,
+            target: NodeId,
+            request: AppendEntriesRequest,
+        ) -> Result<AppendEntriesResponse, RaftError> {
+            Ok(self
+                .network
+                .node(self.from, target)?
+                .handle_append_entries(request))
+        }
+
+        async fn request_vote(
+            &self,
+            target: NodeId,
+            request: VoteRequest,
+        ) -> Result<VoteResponse, RaftError> {
+            Ok(self
+                .network
+                .node(self.from, target)?
+                .handle_request_vote(request))
+        }
+
+        async fn install_snapshot(
+            &self,
+            target: NodeId,
+            request: InstallSnapshotRequest,
+        ) -> Result<InstallSnapshotResponse, RaftError> {
+            Ok(self
+                .network
+                .node(self.from, target)?
+                .handle_install_snapshot(request))
+        }
+
+        async fn forward(
+            &self,
+            target: NodeId,
+            request: ForwardRequest,
+        ) -> Result<TransactionResult, RaftError> {
+            self.network
+                .node(self.from, target)?
+                .handle_forward(request.transaction)
+                .await
+        }
+    }
+
+    fn cluster_config(node_id: NodeId, peers: &[(NodeId, String)]) -> ClusterConfig {
+        ClusterConfig {
+            enabled: true,
+            node_id,
+            peers: peers
+                .iter()
+                .filter(|(id, _)| *id != node_id)
+                .map(|(id, url)| ClusterPeer {
+                    id: *id,
+                    url: url.clone(),
+                })
+                .collect(),
+            heartbeat_interval_ms: 20,
+            election_timeout_min_ms: 100,
+            election_timeout_max_ms: 200,
+            snapshot_threshold: 1_000,
+            rpc_timeout_ms: 200,
+            proposal_timeout_ms: 500,
+            secret: "cluster-secret".to_string(),
+            tls: None,
+        }
+    }
+
+    fn processor() -> Arc<TransactionProcessor> {
+        Arc::new(TransactionProcessor::new(
+            Arc::new(Ledger::default()),
+            DashMap::new(),
+        ))
+    }
+
+    fn store() -> Arc<RaftStore> {
+        Arc::new(RaftStore::open(":memory:").unwrap())
+    }
+
+    fn local_cluster(
+        size: NodeId,
+        snapshot_threshold: u64,
+    ) -> (Arc<LocalNetwork>, Vec<Arc<RaftNode>>, broadcast::Sender<()>) {
+        let network = Arc::new(LocalNetwork::default());
+        let members: Vec<_> = (1..=size).map(|id| (id, String::new())).collect();
+        let (shutdown_sender, _) = broadcast::channel(1);
+
+        let nodes: Vec<_> = members
+            .iter()
+            .map(|(id, _)| {
+                let config = ClusterConfig {
+                    snapshot_threshold,
+                    ..cluster_config(*id, &members)
+                };
+                let transport = LocalTransport {
+                    from: *id,
+                    network: network.clone(),
+                };
+                Arc::new(RaftNode::new(config, processor(), Arc::new(transport), store()).unwrap())
+            })
+            .collect();
+        for node in &nodes {
+            network.nodes.write().unwrap().insert(node.id, node.clone());
+            tokio::spawn(start_raft(node.clone(), shutdown_sender.subscribe()));
+        }
+
+        (network, nodes, shutdown_sender)
+    }
+
+    async fn wait_for(condition: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("condition not reached in time");
+    }
+
+    /// Waits until the given nodes agree on a leader among them, and returns it.
+    async fn elected(nodes: &[&Arc<RaftNode>]) -> Arc<RaftNode> {
+        let agreed = || {
+            let leader = nodes[0].status().leader?;
+            let term = nodes[0].status().term;
+            nodes
+                .iter()
+                .all(|node| node.status().leader == Some(leader) && node.status().term == term)
+                .then(|| nodes.iter().find(|node| node.id == leader).cloned())
+                .flatten()
+        };
+        wait_for(|| agreed().is_some_and(|leader| leader.status().role == Role::Leader)).await;
+        agreed().unwrap().clone()
+    }
+
+    /// Waits until every node applied the whole log of the leader.
+    async fn converged(leader: &RaftNode, nodes: &[Arc<RaftNode>]) {
+        wait_for(|| {
+            let commit_index = leader.status().commit_index;
+            nodes
+                .iter()
+                .all(|node| node.status().last_applied == commit_index)
+        })
+        .await;
+    }
+
+    fn transaction(instruction: Instruction) -> Transaction {
+        Transaction {
+            id: Uuid::new_v4(),
+            instruction,
+            status: TransactionStatus::Pending,
+            timestamp: Utc::now(),
+        }
+    }
+
+    async fn create_account(node: &Arc<RaftNode>) -> Uuid {
+        match node
+            .propose(
+                transaction(Instruction::CreateAccount(CreateAccountInstruction::new(
+                    vec![],
+                ))),
+                None,
+            )
+            .await
+        {
+            Ok(TransactionResult::AccountCreated(account_id)) => account_id,
+            result => panic!("unexpected result {result:?}"),
+        }
+    }
+
+    fn deposit(account_id: Uuid, amount: u64) -> Transaction {
+        transaction(Instruction::Deposit(DepositInstruction {
+            destination_account_id: account_id,
+            amount,
+        }))
+    }
+
+    /// The commit log without commit times, which come from each node's clock.
+    fn log(node: &RaftNode) -> Vec<(u64, Uuid, Vec<Posting>, Option<Uuid>)> {
+        node.processor
+            .events
+            .entries()
+            .iter()
+            .map(|event| {
+                (
+                    event.sequence,
+                    event.transaction_id,
+                    event.postings.clone(),
+                    event.created_account_id,
+                )
+            })
+            .collect()
+    }
+
+    fn balance(node: &RaftNode, account_id: Uuid) -> u64 {
+        node.processor
+            .ledger
+            .get_account(account_id)
+            .unwrap()
+            .balance
+    }
+
+    #[tokio::test]
+    async fn test_cluster_survives_a_partitioned_leader() {
+        let (network, nodes, shutdown) = local_cluster(3, 1_000);
+        let leader = elected(&nodes.iter().collect::<Vec<_>>()).await;
+        let follower = nodes.iter().find(|node| node.id != leader.id).unwrap();
+
+        // Writes received by a follower are forwarded to the leader.
+        let source = create_account(follower).await;
+        let destination = create_account(&leader).await;
+        follower.propose(deposit(source, 100), None).await.unwrap();
+        let overdraft = follower
+            .propose(
+                transaction(Instruction::Transfer(TransferInstruction {
+                    source_account_id: source,
+                    destination_account_id: destination,
+                    amount: 500,
+                })),
+                None,
+            )
+            .await;
+        assert!(matches!(overdraft, Err(RaftError::Rejected(_))));
+        converged(&leader, &nodes).await;
+        for node in &nodes {
+            assert_eq!(balance(node, source), 100);
+            assert_eq!(log(node), log(&leader));
+        }
+
+        // The isolated leader cannot commit anymore, and the majority elects a new one.
+        network.isolate(leader.id);
+        assert_eq!(
+            leader.propose(deposit(source, 1_000), None).await,
+            Err(RaftError::Timeout(500))
+        );
+        let majority: Vec<_> = nodes.iter().filter(|node| node.id != leader.id).collect();
+        let new_leader = elected(&majority).await;
+        assert!(new_leader.status().term > leader.status().term);
+        majority[0]
+            .propose(deposit(source, 10), None)
+            .await
+            .unwrap();
+
+        // Once healed, the old leader drops its uncommitted write and catches up.
+        network.heal();
+        wait_for(|| leader.status().role == Role::Follower).await;
+        converged(&new_leader, &nodes).await;
+        for node in &nodes {
+            assert_eq!(balance(node, source), 110);
+            assert_eq!(log(node), log(&new_leader));
+        }
+
+        shutdown.send(()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stepping_down_fails_pending_proposals() {
+        let (network, nodes, shutdown) = local_cluster(3, 1_000);
+        let leader = elected(&nodes.iter().collect::<Vec<_>>()).await;
+
+        network.isolate(leader.id);
+        let proposal = tokio::spawn({
+            let leader = leader.clone();
+            async move { leader.propose(deposit(Uuid::new_v4(), 1), None).await }
+        });
+        wait_for(|| !leader.lock().pending.is_empty()).await;
+        let term = leader.status().term + 1;
+        leader.step_down(&mut leader.lock(), term);
+
+        // Failed right away rather than at the proposal timeout.
+        assert_eq!(proposal.await.unwrap(), Err(RaftError::LeadershipLost));
+        assert_eq!(leader.status().role, Role::Follower);
+
+        shutdown.send(()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restarted_node_keeps_its_term_vote_and_log() {
+        let path = std::env::temp_dir()
+            .join(format!("quasar-raft-{}.db", Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        let network = Arc::new(LocalNetwork::default());
+        let members = [(1, String::new())];
+        let start = || {
+            let transport = LocalTransport {
+                from: 1,
+                network: network.clone(),
+            };
+            let store = Arc::new(RaftStore::open(&path).unwrap());
+            let config = cluster_config(1, &members);
+            let node = RaftNode::new(config, processor(), Arc::new(transport), store).unwrap();
+            Arc::new(node)
+        };
+
+        let (shutdown, _) = broadcast::channel(1);
+        let node = start();
+        tokio::spawn(start_raft(node.clone(), shutdown.subscribe()));
+        wait_for(|| node.status().role == Role::Leader).await;
+        create_account(&node).await;
+        shutdown.send(()).unwrap();
+        let before = node.status();
+
+        let restarted = start();
+        let after = restarted.status();
+        assert_eq!(after.term, before.term);
+        assert_eq!(after.last_index, before.last_index);
+        let terms = |node: &RaftNode| {
+            let state = node.lock();
+            let entries = state.log.entries_from(1, 10);
+            entries
+                .iter()
+                .map(|e| (e.index, e.term))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(terms(&restarted), terms(&node));
+
+        // It voted for itself in that term, so it cannot vote for another candidate.
+        let vote = restarted.handle_request_vote(VoteRequest {
+            term: before.term,
+            candidate_id: 2,
+            last_log_index: before.last_index,
+            last_log_term: before.term,
+        });
+        assert!(!vote.vote_granted);
+    }
+
+    #[tokio::test]
+    async fn test_lagging_node_catches_up_from_a_snapshot() {
+        let (network, nodes, shutdown) = local_cluster(3, 5);
+        let leader = elected(&nodes.iter().collect::<Vec<_>>()).await;
+        let lagging = nodes.iter().find(|node| node.id != leader.id).unwrap();
+
+        network.isolate(lagging.id);
+        let account_id = create_account(&leader).await;
+        for _ in 0..20 {
+            leader.propose(deposit(account_id, 5), None).await.unwrap();
+        }
+        assert!(leader.lock().log.snapshot_index() > lagging.status().last_index);
+
+        network.heal();
+        converged(&leader, &nodes).await;
+        assert!(lagging.lock().log.snapshot_index() > 0);
+        assert_eq!(balance(lagging, account_id), 100);
+        assert_eq!(log(lagging), log(&leader));
+
+        // Transactions restored from the snapshot are not applied twice.
+        let restored = lagging.processor.events.get(2).unwrap().transaction_id;
+        let transaction = lagging
+            .processor
+            .transactions
+            .get(&restored)
+            .unwrap()
+            .clone();
+        assert!(matches!(
+            lagging.processor.process_transaction(transaction),
+            Err(TransactionProcessorError::TransactionAlreadyProcessed)
+        ));
+        assert_eq!(balance(lagging, account_id), 100);
+
+        shutdown.send(()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_grpc_cluster_forwards_writes_to_the_leader() {
+        let mut listeners = Vec::new();
+        let mut members = Vec::new();
+        for id in 1..=3 {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            members.push((id, format!("http://{}", listener.local_addr().unwrap())));
+            listeners.push(listener);
+        }
+
+        let (shutdown_sender, _) = broadcast::channel(1);
+        let mut nodes = Vec::new();
+        for ((id, _), listener) in members.iter().zip(listeners) {
+            let config = cluster_config(*id, &members);
+            let transport = GrpcTransport::new(
+                &config.peers,
+                Duration::from_millis(200),
+                None,
+                &config.secret,
+            )
+            .unwrap();
+            let node =
+                Arc::new(RaftNode::new(config, processor(), Arc::new(transport), store()).unwrap());
+            tokio::spawn(
+                Server::builder()
+                    .add_service(RaftServiceServer::new(RaftGrpcServer::new(node.clone())))
+                    .serve_with_incoming(TcpListenerStream::new(listener)),
+            );
+            tokio::spawn(start_raft(node.clone(), shutdown_sender.subscribe()));
+            nodes.push(node);
+        }
+
+        let leader = elected(&nodes.iter().collect::<Vec<_>>()).await;
+        let follower = nodes.iter().find(|node| node.id != leader.id).unwrap();
+        let service = QuasarGrpcServer::new(
+            follower.processor.clone(),
+            Arc::new(SubmissionQueue::new(8)),
+        )
+        .with_raft(follower.clone());
+
+        let created = service
+            .create_account(Request::new(CreateAccountRequest {
+                transaction_id: Uuid::new_v4().to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(created.success);
+        let account_id = Uuid::parse_str(&created.created_account_id).unwrap();
+        let deposited = service
+            .process_deposit(Request::new(DepositRequest {
+                transaction_id: Uuid::new_v4().to_string(),
+                destination_account_id: account_id.to_string(),
+                amount: 42,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(deposited.success);
+
+        converged(&leader, &nodes).await;
+        for node in &nodes {
+            assert_eq!(balance(node, account_id), 42);
+        }
+
+        shutdown_sender.send(()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_raft_service_requires_the_cluster_secret() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let members = [(1, url.clone())];
+        let node = Arc::new(
+            RaftNode::new(
+                cluster_config(1, &members),
+                processor(),
+                Arc::new(LocalTransport {
+                    from: 1,
+                    network: Arc::default(),
+                }),
+                store(),
+            )
+            .unwrap(),
+        );
+        tokio::spawn(
+            Server::builder()
+                .add_service(RaftServiceServer::new(RaftGrpcServer::new(node.clone())))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let (shutdown, _) = broadcast::channel(1);
+        tokio::spawn(start_raft(node.clone(), shutdown.subscribe()));
+        elected(&[&node]).await;
+        let commit_index = node.status().commit_index;
+
+        let mut client = RaftServiceClient::connect(url.clone()).await.unwrap();
+        let forward = RaftMessage {
+            payload: serde_json::to_vec(&ForwardRequest {
+                transaction: deposit(Uuid::new_v4(), 100),
+                client_id: None,
+            })
+            .unwrap(),
+        };
+        let statuses = [
+            client.append_entries(RaftMessage::default()).await,
+            client.request_vote(RaftMessage::default()).await,
+            client.install_snapshot(RaftMessage::default()).await,
+            client.forward(forward).await,
+        ];
+        for status in statuses {
+            assert_eq!(status.unwrap_err().code(), tonic::Code::Unauthenticated);
+        }
+
+        let peers = [ClusterPeer { id: 1, url }];
+        let impostor =
+            GrpcTransport::new(&peers, Duration::from_millis(200), None, "guessed").unwrap();
+        let forwarded = impostor
+            .forward(
+                1,
+                ForwardRequest {
+                    transaction: deposit(Uuid::new_v4(), 100),
+                    client_id: None,
+                },
+            )
+            .await;
+        assert!(matches!(forwarded, Err(RaftError::Unreachable { .. })));
+        assert_eq!(node.status().commit_index, commit_index);
+
+        shutdown.send(()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_leader_authorizes_forwarded_writes_again() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let members = [(1, url.clone())];
+        let node = Arc::new(
+            RaftNode::new(
+                cluster_config(1, &members),
+                processor(),
+                Arc::new(LocalTransport {
+                    from: 1,
+                    network: Arc::default(),
+                }),
+                store(),
+            )
+            .unwrap(),
+        );
+        let client = |id: &str, scopes: Vec<Scope>| AuthClient {
+            id: id.to_string(),
+            scopes,
+            accounts: vec![],
+            api_keys: vec![],
+            certificate_subjects: vec![],
+        };
+        let auth = AuthConfig {
+            enabled: true,
+            jwt_secret: String::new(),
+            clients: vec![
+                client("shop", vec![Scope::Read, Scope::Write]),
+                client("auditor", vec![Scope::Read]),
+            ],
+        };
+        let owners = Arc::new(OwnershipStore::open(":memory:").unwrap());
+        let authenticator = Arc::new(Authenticator::new(&auth, owners).unwrap());
+        tokio::spawn(
+            Server::builder()
+                .add_service(RaftServiceServer::new(
+                    RaftGrpcServer::new(node.clone()).with_auth(authenticator),
+                ))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let (shutdown, _) = broadcast::channel(1);
+        tokio::spawn(start_raft(node.clone(), shutdown.subscribe()));
+        elected(&[&node]).await;
+
+        let peers = [ClusterPeer { id: 1, url }];
+        let follower =
+            GrpcTransport::new(&peers, Duration::from_millis(500), None, "cluster-secret").unwrap();
+        let forward = |instruction: Instruction, client_id: &str| ForwardRequest {
+            transaction: transaction(instruction),
+            client_id: Some(client_id.to_string()),
+        };
+        let mut create = CreateAccountInstruction::new(vec![]);
+        create.owner = Some("auditor".to_string());
+
+        // The leader stamps the creation with the forwarding client, whatever the follower sent.
+        let created = follower
+            .forward(
+                1,
+                forward(Instruction::CreateAccount(create.clone()), "shop"),
+            )
+            .await
+            .unwrap();
+        let TransactionResult::AccountCreated(account_id) = created else {
+            panic!("unexpected result {created:?}");
+        };
+        let event = node.processor.events.get(1).unwrap();
+        assert_eq!(event.created_account_id, Some(account_id));
+        assert!(matches!(
+            event.instruction,
+            Instruction::CreateAccount(CreateAccountInstruction { owner: Some(ref owner), .. })
+                if owner == "shop"
+        ));
+
+        let denied = [
+            forward(Instruction::CreateAccount(create), "auditor"),
+            forward(
+                Instruction::Transfer(TransferInstruction {
+                    source_account_id: Uuid::new_v4(),
+                    destination_account_id: account_id,
+                    amount: 1,
+                }),
+                "shop",
+            ),
+        ];
+        for request in denied {
+            let result = follower.forward(1, request).await;
+            assert!(
+                matches!(result, Err(RaftError::Unauthorized(_))),
+                "{result:?}"
+            );
+        }
+        let unknown = follower
+            .forward(
+                1,
+                forward(
+                    Instruction::CreateAccount(CreateAccountInstruction::new(vec![])),
+                    "intruder",
+                ),
+            )
+            .await;
+        assert!(unknown.is_err());
+        assert_eq!(node.processor.events.last_sequence(), 1);
+
+        shutdown.send(()).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use {
+    crate::raft::{LogEntry, NodeId, Snapshot, error::RaftError},
+    rusqlite::{Connection, OptionalExtension, params},
+    serde::{Serialize, de::DeserializeOwned},
+    std::sync::{Mutex, MutexGuard},
+};
+
+/// SQLite-backed Raft state: the current term, the vote cast in it and the log. Uses its own
+/// connection, like the dispute store, and every write is durable before the node acts on it,
+/// so that a restarted node neither votes twice in a term nor forgets entries it acknowledged.
+pub struct RaftStore {
+    conn: Mutex<Connection>,
+}
+
+impl RaftStore {
+    pub fn open(db_path: &str) -> Result<Self, RaftError> {
+        let conn = Connection::open(db_path)?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS raft_state (
+                id INTEGER PRIMARY KEY CHECK (id = 0),
+                term INTEGER NOT NULL,
+                voted_for INTEGER
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS raft_log (
+                log_index INTEGER PRIMARY KEY,
+                term INTEGER NOT NULL,
+                command TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS raft_snapshot (
+                id INTEGER PRIMARY KEY CHECK (id = 0),
+                snapshot TEXT NOT NULL
+            )",
+            [],
+        )?;
+        Ok(RaftStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The current term and the candidate voted for in it, `(0, None)` on first start.
+    pub fn hard_state(&self) -> Result<(u64, Option<NodeId>), RaftError> {
+        let state = self
+            .conn()
+            .query_row("SELECT term, voted_for FROM raft_state", [], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?))
+            })
+            .optional()?;
+        Ok(state.map_or((0, None), |(term, voted_for)| {
+            (term as u64, voted_for.map(|id| id as NodeId))
+        }))
+    }
+
+    pub fn save_hard_state(&self, term: u64, voted_for: Option<NodeId>) -> Result<(), RaftError> {
+        self.conn().execute(
+            "INSERT INTO raft_state (id, term, voted_for) VALUES (0, ?1, ?2)
+             ON CONFLICT (id) DO UPDATE SET term = excluded.term, voted_for = excluded.voted_for",
+            params![term as i64, voted_for.map(|id| id as i64)],
+        )?;
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> Result<Option<Snapshot>, RaftError> {
+        let snapshot = self
+            .conn()
+            .query_row("SELECT snapshot FROM raft_snapshot", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()?;
+        snapshot.as_deref().map(decode).transpose()
+    }
+
+    /// Entries following the snapshot, in order.
+    pub fn entries(&self) -> Result<Vec<LogEntry>, RaftError> {
+        let conn = self.conn();
+        let mut statement =
+            conn.prepare("SELECT log_index, term, command FROM raft_log ORDER BY log_index")?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let (index, term, command) = row?;
+            entries.push(LogEntry {
+                index: index as u64,
+                term: term as u64,
+                command: decode(&command)?,
+            });
+        }
+        Ok(entries)
+    }
+
+    pub fn append(&self, entries: &[LogEntry]) -> Result<(), RaftError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        for entry in entries {
+            tx.execute(
+                "INSERT OR REPLACE INTO raft_log (log_index, term, command) VALUES (?1, ?2, ?3)",
+                params![
+                    entry.index as i64,
+                    entry.term as i64,
+                    encode(&entry.command)?
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Removes the entry at `index` and every entry after it.
+    pub fn truncate(&self, index: u64) -> Result<(), RaftError> {
+        self.conn().execute(
+            "DELETE FROM raft_log WHERE log_index >= ?1",
+            params![index as i64],
+        )?;
+        Ok(())
+    }
+
+    /// Replaces the snapshot, and with it the entries up to its last index. The following
+    /// entries are removed too unless `keep_following` is set.
+    pub fn compact(&self, snapshot: &Snapshot, keep_following: bool) -> Result<(), RaftError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO raft_snapshot (id, snapshot) VALUES (0, ?1)
+             ON CONFLICT (id) DO UPDATE SET snapshot = excluded.snapshot",
+            params![encode(snapshot)?],
+        )?;
+        if keep_following {
+            tx.execute(
+                "DELETE FROM raft_log WHERE log_index <= ?1",
+                params![snapshot.last_index as i64],
+            )?;
+        } else {
+            tx.execute("DELETE FROM raft_log", [])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<String, RaftError> {
+    serde_json::to_string(value).map_err(|e| RaftError::Storage(e.to_string()))
+}
+
+fn decode<T: DeserializeOwned>(value: &str) -> Result<T, RaftError> {
+    serde_json::from_str(value).map_err(|e| RaftError::Storage(e.to_string()))
+}
@@ -0,0 +1,307 @@
+//! Messaging between cluster members. Messages are JSON encoded and carried over the
+//! internal `RaftService` gRPC service, served next to the public API. Members send the shared
+//! cluster secret with every message and reject messages without it. Forwarded writes carry
+//! the client that submitted them, which the leader authorizes again before committing them.
+
+use {
+    crate::{
+        auth::Authenticator,
+        config::{ClientTlsConfig, ClusterPeer},
+        grpc_server::server::{
+            RaftMessage, raft_service_client::RaftServiceClient, raft_service_server::RaftService,
+        },
+        metrics::AUTH_REJECTIONS_TOTAL,
+        raft::{
+            AppendEntriesRequest, AppendEntriesResponse, ForwardRequest, InstallSnapshotRequest,
+            InstallSnapshotResponse, NodeId, RaftNode, VoteRequest, VoteResponse, error::RaftError,
+        },
+        tls,
+        transaction_processor::interface::TransactionResult,
+    },
+    serde::{Serialize, de::DeserializeOwned},
+    sha2::{Digest, Sha256},
+    std::{collections::HashMap, sync::Arc, time::Duration},
+    tonic::{
+        Code, Request, Response, Status,
+        metadata::AsciiMetadataValue,
+        service::{Interceptor, interceptor::InterceptedService},
+        transport::Channel,
+    },
+};
+
+pub const CLUSTER_SECRET_HEADER: &str = "x-cluster-secret";
+
+#[tonic::async_trait]
+pub trait RaftTransport: Send + Sync {
+    async fn append_entries(
+        &self,
+        target: NodeId,
+        request: AppendEntriesRequest,
+    ) -> Result<AppendEntriesResponse, RaftError>;
+
+    async fn request_vote(
+        &self,
+        target: NodeId,
+        request: VoteRequest,
+    ) -> Result<VoteResponse, RaftError>;
+
+    async fn install_snapshot(
+        &self,
+        target: NodeId,
+        request: InstallSnapshotRequest,
+    ) -> Result<InstallSnapshotResponse, RaftError>;
+
+    /// Asks the leader to commit a write received by a follower.
+    async fn forward(
+        &self,
+        target: NodeId,
+        request: ForwardRequest,
+    ) -> Result<TransactionResult, RaftError>;
+}
+
+fn encode<T: Serialize>(message: &T) -> Result<RaftMessage, RaftError> {
+    serde_json::to_vec(message)
+        .map(|payload| RaftMessage { payload })
+        .map_err(|e| RaftError::Decode(e.to_string()))
+}
+
+fn decode<T: DeserializeOwned>(message: RaftMessage) -> Result<T, RaftError> {
+    serde_json::from_slice(&message.payload).map_err(|e| RaftError::Decode(e.to_string()))
+}
+
+fn digest(secret: &[u8]) -> [u8; 32] {
+    Sha256::digest(secret).into()
+}
+
+/// Sends the cluster secret with every message to a peer.
+#[derive(Clone)]
+pub struct ClusterSecret(AsciiMetadataValue);
+
+impl Interceptor for ClusterSecret {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        request
+            .metadata_mut()
+            .insert(CLUSTER_SECRET_HEADER, self.0.clone());
+        Ok(request)
+    }
+}
+
+type PeerClient = RaftServiceClient<InterceptedService<Channel, ClusterSecret>>;
+
+pub struct GrpcTransport {
+    clients: HashMap<NodeId, PeerClient>,
+}
+
+impl GrpcTransport {
+    /// Channels connect lazily, so peers that are still starting up are not an error.
+    /// `timeout` only bounds connecting: the node bounds each request itself, and forwarded
+    /// writes wait longer than other requests.
+    pub fn new(
+        peers: &[ClusterPeer],
+        timeout: Duration,
+        tls: Option<&ClientTlsConfig>,
+        secret: &str,
+    ) -> Result<Self, RaftError> {
+        let secret = AsciiMetadataValue::try_from(secret)
+            .map(ClusterSecret)
+            .map_err(|_| {
+                RaftError::InvalidConfig("the cluster secret must be ASCII".to_string())
+            })?;
+        let mut clients = HashMap::new();
+        for peer in peers {
+            let endpoint =
+                tls::endpoint(peer.url.clone(), tls).map_err(|e| RaftError::Unreachable {
+                    node: peer.id,
+                    reason: e.to_string(),
+                })?;
+            let channel = endpoint.connect_timeout(timeout).connect_lazy();
+            clients.insert(
+                peer.id,
+                RaftServiceClient::with_interceptor(channel, secret.clone()),
+            );
+        }
+
+        Ok(GrpcTransport { clients })
+    }
+
+    async fn call<Req, Resp, F>(
+        &self,
+        target: NodeId,
+        request: &Req,
+        send: impl FnOnce(PeerClient, RaftMessage) -> F,
+    ) -> Result<Resp, RaftError>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+        F: Future<Output = Result<Response<RaftMessage>, Status>>,
+    {
+        let client = self
+            .clients
+            .get(&target)
+            .cloned()
+            .ok_or_else(|| RaftError::Unreachable {
+                node: target,
+                reason: "not a member of the cluster".to_string(),
+            })?;
+
+        let response =
+            send(client, encode(request)?)
+                .await
+                .map_err(|status| match status.code() {
+                    Code::PermissionDenied => RaftError::Unauthorized(status.message().to_string()),
+                    _ => RaftError::Unreachable {
+                        node: target,
+                        reason: status.message().to_string(),
+                    },
+                })?;
+        decode(response.into_inner())
+    }
+}
+
+#[tonic::async_trait]
+impl RaftTransport for GrpcTransport {
+    async fn append_entries(
+        &self,
+        target: NodeId,
+        request: AppendEntriesRequest,
+    ) -> Result<AppendEntriesResponse, RaftError> {
+        self.call(target, &request, |mut client, message| async move {
+            client.append_entries(message).await
+        })
+        .await
+    }
+
+    async fn request_vote(
+        &self,
+        target: NodeId,
+        request: VoteRequest,
+    ) -> Result<VoteResponse, RaftError> {
+        self.call(target, &request, |mut client, message| async move {
+            client.request_vote(message).await
+        })
+        .await
+    }
+
+    async fn install_snapshot(
+        &self,
+        target: NodeId,
+        request: InstallSnapshotRequest,
+    ) -> Result<InstallSnapshotResponse, RaftError> {
+        self.call(target, &request, |mut client, message| async move {
+            client.install_snapshot(message).await
+        })
+        .await
+    }
+
+    async fn forward(
+        &self,
+        target: NodeId,
+        request: ForwardRequest,
+    ) -> Result<TransactionResult, RaftError> {
+        self.call(target, &request, |mut client, message| async move {
+            client.forward(message).await
+        })
+        .await?
+    }
+}
+
+impl From<RaftError> for Status {
+    fn from(error: RaftError) -> Self {
+        match error {
+            RaftError::Timeout(_) => Status::deadline_exceeded(error.to_string()),
+            RaftError::Decode(_) => Status::invalid_argument(error.to_string()),
+            RaftError::Rejected(_) => Status::failed_precondition(error.to_string()),
+            RaftError::Unauthorized(_) => Status::permission_denied(error.to_string()),
+            _ => Status::unavailable(error.to_string()),
+        }
+    }
+}
+
+/// Serves the messages of the other cluster members to the local node.
+pub struct RaftGrpcServer {
+    node: Arc<RaftNode>,
+    // Digest of the cluster secret, so checking a message never compares secrets.
+    secret: [u8; 32],
+    auth: Option<Arc<Authenticator>>,
+}
+
+impl RaftGrpcServer {
+    pub fn new(node: Arc<RaftNode>) -> Self {
+        let secret = digest(node.config.secret.as_bytes());
+        RaftGrpcServer {
+            node,
+            secret,
+            auth: None,
+        }
+    }
+
+    /// Authorizes forwarded writes again for the clients that submitted them.
+    pub fn with_auth(mut self, authenticator: Arc<Authenticator>) -> Self {
+        self.auth = Some(authenticator);
+        self
+    }
+
+    fn authenticate<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let secret = request.metadata().get(CLUSTER_SECRET_HEADER);
+        if secret.is_some_and(|secret| digest(secret.as_bytes()) == self.secret) {
+            return Ok(());
+        }
+        AUTH_REJECTIONS_TOTAL.inc();
+        Err(Status::unauthenticated(
+            "Raft messages must carry the cluster secret",
+        ))
+    }
+}
+
+#[tonic::async_trait]
+impl RaftService for RaftGrpcServer {
+    async fn append_entries(
+        &self,
+        request: Request<RaftMessage>,
+    ) -> Result<Response<RaftMessage>, Status> {
+        self.authenticate(&request)?;
+        let response = self
+            .node
+            .handle_append_entries(decode(request.into_inner())?);
+        Ok(Response::new(encode(&response)?))
+    }
+
+    async fn request_vote(
+        &self,
+        request: Request<RaftMessage>,
+    ) -> Result<Response<RaftMessage>, Status> {
+        self.authenticate(&request)?;
+        let response = self.node.handle_request_vote(decode(request.into_inner())?);
+        Ok(Response::new(encode(&response)?))
+    }
+
+    async fn install_snapshot(
+        &self,
+        request: Request<RaftMessage>,
+    ) -> Result<Response<RaftMessage>, Status> {
+        self.authenticate(&request)?;
+        let response = self
+            .node
+            .handle_install_snapshot(decode(request.into_inner())?);
+        Ok(Response::new(encode(&response)?))
+    }
+
+    async fn forward(
+        &self,
+        request: Request<RaftMessage>,
+    ) -> Result<Response<RaftMessage>, Status> {
+        self.authenticate(&request)?;
+        let ForwardRequest {
+            mut transaction,
+            client_id,
+        } = decode(request.into_inner())?;
+        // The follower checked the client against its own view of account ownership, which
+        // may lag behind the leader's.
+        if let (Some(auth), Some(client_id)) = (&self.auth, client_id) {
+            let client = auth.client(&client_id)?;
+            auth.authorize_transaction(&client, &mut transaction)?;
+        }
+        let result = self.node.handle_forward(transaction).await;
+        Ok(Response::new(encode(&result)?))
+    }
+}
@@ -0,0 +1,13 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ReceiptError {
+    #[error("Invalid Ed25519 key: {0}")]
+    InvalidKey(String),
+    #[error("Invalid receipt signature encoding")]
+    InvalidSignature,
+    #[error("Receipt was signed by {0}, not by the expected key")]
+    UnexpectedKey(String),
+    #[error("Receipt signature does not match its contents")]
+    SignatureMismatch,
+}
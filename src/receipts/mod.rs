@@ -0,0 +1,308 @@
+//! Signed transaction receipts.
+//! For every committed transfer and deposit the server signs a receipt with its Ed25519 key, so
+//! payees can prove the payment happened to anyone holding the server's public key, without
+//! access to the server. Signatures cover a canonical text encoding of the receipt, so they do
+//! not depend on how the receipt is carried.
+
+pub mod error;
+
+use {
+    crate::{
+        fees::FeeCharge,
+        models::{Instruction, Transaction},
+        receipts::error::ReceiptError,
+    },
+    chrono::{DateTime, SecondsFormat, Utc},
+    ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey},
+    serde::{Deserialize, Serialize},
+    uuid::Uuid,
+};
+
+// Prefix of the canonical encoding, changed whenever the encoding changes.
+const RECEIPT_VERSION: &str = "quasar-receipt-v2";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub transaction_id: Uuid,
+    // None for deposits.
+    pub source_account_id: Option<Uuid>,
+    pub destination_account_id: Uuid,
+    /// Amount of the instruction.
+    pub amount: u64,
+    /// Fee charged by the server: on top of the amount for transfers, out of it for deposits.
+    pub fee: u64,
+    /// Amount credited to the destination account.
+    pub net_amount: u64,
+    pub committed_at: DateTime<Utc>,
+    /// Sequence number of the transaction in the commit log.
+    pub sequence: u64,
+}
+
+impl Receipt {
+    /// Receipt of a committed transaction charged `fee`, or None for instructions that move no
+    /// money.
+    pub fn new(
+        transaction: &Transaction,
+        fee: Option<&FeeCharge>,
+        sequence: u64,
+        committed_at: DateTime<Utc>,
+    ) -> Option<Self> {
+        let fee = fee.map_or(0, |fee| fee.amount);
+        let (source_account_id, destination_account_id, amount, net_amount) =
+            match &transaction.instruction {
+                Instruction::Transfer(transfer) => (
+                    Some(transfer.source_account_id),
+                    transfer.destination_account_id,
+                    transfer.amount,
+                    transfer.amount,
+                ),
+                // Deposit fees are deducted from the deposited amount.
+                Instruction::Deposit(deposit) => (
+                    None,
+                    deposit.destination_account_id,
+                    deposit.amount,
+                    deposit.amount.checked_sub(fee)?,
+                ),
+                Instruction::CreateAccount(_)
+                | Instruction::GetBalance(_)
+                | Instruction::Review(_)
+                | Instruction::OpenDispute(_)
+                | Instruction::UpdateDispute(_) => {
+                    return None;
+                }
+            };
+
+        Some(Receipt {
+            transaction_id: transaction.id,
+            source_account_id,
+            destination_account_id,
+            amount,
+            fee,
+            net_amount,
+            committed_at,
+            sequence,
+        })
+    }
+
+    /// The bytes covered by the signature: one `name:value` line per field, in a fixed order.
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        format!(
+            "{RECEIPT_VERSION}\ntransaction_id:{}\nsource_account_id:{}\ndestination_account_id:{}\namount:{}\nfee:{}\nnet_amount:{}\ncommitted_at:{}\nsequence:{}\n",
+            self.transaction_id,
+            self.source_account_id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+            self.destination_account_id,
+            self.amount,
+            self.fee,
+            self.net_amount,
+            self.committed_at
+                .to_rfc3339_opts(SecondsFormat::Nanos, true),
+            self.sequence,
+        )
+        .into_bytes()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedReceipt {
+    #[serde(flatten)]
+    pub receipt: Receipt,
+    /// Hex encoded Ed25519 signature of the canonical receipt.
+    pub signature: String,
+    /// Hex encoded public key of the signer.
+    pub public_key: String,
+}
+
+impl SignedReceipt {
+    /// Checks that the receipt was signed by the given hex encoded public key and was not
+    /// altered since.
+    pub fn verify(&self, public_key: &str) -> Result<(), ReceiptError> {
+        if !self.public_key.eq_ignore_ascii_case(public_key) {
+            return Err(ReceiptError::UnexpectedKey(self.public_key.clone()));
+        }
+
+        let key = parse_public_key(public_key)?;
+        let signature = hex::decode(&self.signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or(ReceiptError::InvalidSignature)?;
+        key.verify_strict(&self.receipt.canonical_bytes(), &signature)
+            .map_err(|_| ReceiptError::SignatureMismatch)
+    }
+}
+
+fn parse_public_key(public_key: &str) -> Result<VerifyingKey, ReceiptError> {
+    let bytes: [u8; 32] = hex::decode(public_key)
+        .map_err(|e| ReceiptError::InvalidKey(e.to_string()))?
+        .try_into()
+        .map_err(|_| ReceiptError::InvalidKey("public keys are 32 bytes long".to_string()))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| ReceiptError::InvalidKey(e.to_string()))
+}
+
+pub struct ReceiptSigner {
+    key: SigningKey,
+}
+
+impl ReceiptSigner {
+    /// Loads a signing key from its hex encoded 32 byte secret.
+    pub fn from_hex(secret: &str) -> Result<Self, ReceiptError> {
+        let bytes: [u8; 32] = hex::decode(secret.trim())
+            .map_err(|e| ReceiptError::InvalidKey(e.to_string()))?
+            .try_into()
+            .map_err(|_| ReceiptError::InvalidKey("secret keys are 32 bytes long".to_string()))?;
+        Ok(ReceiptSigner {
+            key: SigningKey::from_bytes(&bytes),
+        })
+    }
+
+    /// Creates a signer with a new random key.
+    pub fn generate() -> Self {
+        ReceiptSigner {
+            key: SigningKey::from_bytes(&rand::random()),
+        }
+    }
+
+    /// Hex encoded secret key, as expected by [`ReceiptSigner::from_hex`].
+    pub fn secret_key(&self) -> String {
+        hex::encode(self.key.to_bytes())
+    }
+
+    /// Hex encoded public key, handed to whoever verifies receipts.
+    pub fn public_key(&self) -> String {
+        hex::encode(self.key.verifying_key().to_bytes())
+    }
+
+    pub fn sign(&self, receipt: Receipt) -> SignedReceipt {
+        let signature = self.key.sign(&receipt.canonical_bytes());
+        SignedReceipt {
+            receipt,
+            signature: hex::encode(signature.to_bytes()),
+            public_key: self.public_key(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            fees::{FeeEngine, FeeRule, FeeSchedule},
+            grpc_server::server,
+            ledger::{Ledger, interface::LedgerInterface},
+            models::{
+                AccountType, DepositInstruction, InstructionKind, TransactionStatus,
+                TransferInstruction,
+            },
+            transaction_processor::{
+                TransactionProcessor,
+                interface::{TransactionProcessorInterface, TransactionResult},
+            },
+        },
+        dashmap::DashMap,
+        std::sync::Arc,
+    };
+
+    #[test]
+    fn test_receipt_signature_round_trip() {
+        let signer = ReceiptSigner::from_hex(&ReceiptSigner::generate().secret_key()).unwrap();
+        let transaction = Transaction {
+            id: Uuid::new_v4(),
+            instruction: Instruction::Transfer(TransferInstruction {
+                source_account_id: Uuid::new_v4(),
+                destination_account_id: Uuid::new_v4(),
+                amount: 250,
+            }),
+            status: TransactionStatus::Completed,
+            timestamp: Utc::now(),
+        };
+        let receipt = Receipt::new(&transaction, None, 7, Utc::now()).unwrap();
+        let signed = signer.sign(receipt);
+
+        // Receipts are verified after being carried over gRPC and as JSON.
+        let carried = SignedReceipt::try_from(server::Receipt::from(signed.clone())).unwrap();
+        assert_eq!(carried, signed);
+        let decoded: SignedReceipt =
+            serde_json::from_str(&serde_json::to_string(&carried).unwrap()).unwrap();
+        decoded.verify(&signer.public_key()).unwrap();
+
+        let mut altered = decoded.clone();
+        altered.receipt.amount = 2500;
+        assert!(matches!(
+            altered.verify(&signer.public_key()),
+            Err(ReceiptError::SignatureMismatch)
+        ));
+
+        let other = ReceiptSigner::generate();
+        assert!(matches!(
+            decoded.verify(&other.public_key()),
+            Err(ReceiptError::UnexpectedKey(_))
+        ));
+        let mut resigned = other.sign(decoded.receipt.clone());
+        resigned.public_key = signer.public_key();
+        assert!(matches!(
+            resigned.verify(&signer.public_key()),
+            Err(ReceiptError::SignatureMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_deposit_receipt_matches_the_postings() {
+        let ledger = Arc::new(Ledger::default());
+        let revenue_id = Uuid::new_v4();
+        ledger
+            .ensure_account(revenue_id, AccountType::Revenue)
+            .unwrap();
+        let processor = TransactionProcessor::new(ledger.clone(), DashMap::new()).with_fee_engine(
+            FeeEngine::new(
+                revenue_id,
+                vec![FeeRule {
+                    name: "deposit".to_string(),
+                    instruction: Some(InstructionKind::Deposit),
+                    account_type: None,
+                    schedule: FeeSchedule::Flat { amount: 3 },
+                }],
+            ),
+        );
+        let account_id = ledger
+            .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+            .unwrap();
+        let deposit = Transaction {
+            id: Uuid::new_v4(),
+            instruction: Instruction::Deposit(DepositInstruction {
+                destination_account_id: account_id,
+                amount: 100,
+            }),
+            status: TransactionStatus::Pending,
+            timestamp: Utc::now(),
+        };
+        let TransactionResult::Success {
+            fee,
+            sequence,
+            committed_at,
+        } = processor.process_transaction(deposit.clone()).unwrap()
+        else {
+            panic!("deposit not committed");
+        };
+
+        let receipt = Receipt::new(&deposit, fee.as_ref(), sequence, committed_at).unwrap();
+        assert_eq!(
+            (receipt.amount, receipt.fee, receipt.net_amount),
+            (100, 3, 97)
+        );
+        assert_eq!(
+            ledger.get_account(account_id).unwrap().balance,
+            receipt.net_amount
+        );
+
+        let signer = ReceiptSigner::generate();
+        let mut altered = signer.sign(receipt);
+        altered.verify(&signer.public_key()).unwrap();
+        altered.receipt.fee = 0;
+        assert!(matches!(
+            altered.verify(&signer.public_key()),
+            Err(ReceiptError::SignatureMismatch)
+        ));
+    }
+}
@@ -0,0 +1,31 @@
+use {
+    crate::{events::error::EventLogError, ledger::error::LedgerError, tls::error::TlsError},
+    thiserror::Error,
+};
+
+#[derive(Debug, Error)]
+pub enum ReplicationError {
+    #[error("Failed to connect to the primary: {0}")]
+    Connect(#[from] tonic::transport::Error),
+    #[error("Invalid connection settings for the primary: {0}")]
+    Tls(#[from] TlsError),
+    #[error("Replication stream failed: {0}")]
+    Stream(#[from] tonic::Status),
+    #[error("Invalid replicated transaction: {0}")]
+    Decode(#[from] serde_json::Error),
+    #[error("Failed to store the replicated transaction: {0}")]
+    EventLog(#[from] EventLogError),
+    #[error("Failed to apply the transaction at sequence {sequence}: {source}")]
+    Ledger { sequence: u64, source: LedgerError },
+}
+
+impl ReplicationError {
+    /// Whether reconnecting to the primary may resolve the error. Otherwise the follower's
+    /// state no longer matches the primary's and replication stops.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ReplicationError::Connect(_) | ReplicationError::Stream(_)
+        )
+    }
+}
@@ -0,0 +1,382 @@
+//! Primary/follower replication by log shipping.
+//! A follower streams the commit log of its primary, stores every entry under the primary's
+//! sequence number and applies its postings to its own ledger, so that both end up with the same
+//! accounts, balances and log. Followers only serve reads until they are promoted.
+
+pub mod error;
+
+use {
+    crate::{
+        auth::ApiKey,
+        config::{ClientTlsConfig, ReplicationRole},
+        events::{CommittedTransaction, replay},
+        grpc_server::server::{ReplicateRequest, grpc_service_client::GrpcServiceClient},
+        metrics::{REPLICATED_EVENTS_TOTAL, REPLICATION_APPLIED_SEQUENCE},
+        models::{Transaction, TransactionStatus},
+        replication::error::ReplicationError,
+        tls,
+        transaction_processor::TransactionProcessor,
+    },
+    std::{
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+        time::Duration,
+    },
+    tokio::sync::{Notify, broadcast},
+    tracing::{error, info, warn},
+};
+
+/// Role of this instance, shared by the gRPC service and the replication task.
+pub struct ReplicationState {
+    follower: AtomicBool,
+    promoted: Notify,
+}
+
+impl ReplicationState {
+    pub fn new(role: ReplicationRole) -> Self {
+        ReplicationState {
+            follower: AtomicBool::new(role == ReplicationRole::Follower),
+            promoted: Notify::new(),
+        }
+    }
+
+    /// Whether this instance replicates a primary and must reject writes.
+    pub fn is_follower(&self) -> bool {
+        self.follower.load(Ordering::SeqCst)
+    }
+
+    /// Makes this instance a primary and stops replication. Returns false if it already was one.
+    pub fn promote(&self) -> bool {
+        let promoted = self.follower.swap(false, Ordering::SeqCst);
+        if promoted {
+            self.promoted.notify_one();
+        }
+        promoted
+    }
+
+    async fn promoted(&self) {
+        self.promoted.notified().await
+    }
+}
+
+pub struct Follower {
+    processor: Arc<TransactionProcessor>,
+    primary_url: String,
+    api_key: ApiKey,
+    tls: Option<ClientTlsConfig>,
+}
+
+impl Follower {
+    pub fn new(processor: Arc<TransactionProcessor>, primary_url: impl Into<String>) -> Self {
+        Follower {
+            processor,
+            primary_url: primary_url.into(),
+            api_key: ApiKey::default(),
+            tls: None,
+        }
+    }
+
+    /// Authenticates to a primary requiring authentication.
+    pub fn with_api_key(mut self, api_key: ApiKey) -> Self {
+        self.api_key = api_key;
+        self
+    }
+
+    /// Connects to an https primary with these TLS settings.
+    pub fn with_tls(mut self, tls: ClientTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Applies a transaction committed by the primary to the ledger and stores it in the log.
+    pub fn apply(&mut self, event: CommittedTransaction) -> Result<(), ReplicationError> {
+        let ledger = &*self.processor.ledger;
+        let event = self.processor.events.replicate(event, |event| {
+            replay::apply(ledger, event).map_err(|source| ReplicationError::Ledger {
+                sequence: event.sequence,
+                source,
+            })
+        })?;
+        self.processor.record_owner(&event);
+        self.processor.transactions.insert(
+            event.transaction_id,
+            Transaction {
+                id: event.transaction_id,
+                instruction: event.instruction.clone(),
+                status: TransactionStatus::Completed,
+                timestamp: event.committed_at,
+            },
+        );
+
+        REPLICATED_EVENTS_TOTAL.inc();
+        REPLICATION_APPLIED_SEQUENCE.set(event.sequence as f64);
+        Ok(())
+    }
+
+    /// Streams the primary's log from the first sequence missing locally, until the stream
+    /// ends or fails.
+    async fn replicate(&mut self) -> Result<(), ReplicationError> {
+        let channel = tls::endpoint(self.primary_url.clone(), self.tls.as_ref())?
+            .connect()
+            .await?;
+        let mut client = GrpcServiceClient::with_interceptor(channel, self.api_key.clone());
+        let from_sequence = self.processor.events.last_sequence() + 1;
+        let mut stream = client
+            .replicate(ReplicateRequest { from_sequence })
+            .await?
+            .into_inner();
+        info!(
+            "Replicating from {} starting at sequence {}",
+            self.primary_url, from_sequence
+        );
+
+        while let Some(message) = stream.message().await? {
+            self.apply(serde_json::from_slice(&message.payload)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replicates the primary until promotion or shutdown, reconnecting whenever the stream is
+/// interrupted. Returns early, stopping the server, if the follower can no longer follow the
+/// log rather than serving reads that will never catch up.
+pub async fn start_follower(
+    mut follower: Follower,
+    state: Arc<ReplicationState>,
+    reconnect_interval_ms: u64,
+    mut shutdown_receiver: broadcast::Receiver<()>,
+) {
+    let reconnect_interval = Duration::from_millis(reconnect_interval_ms);
+
+    loop {
+        tokio::select! {
+            result = follower.replicate() => match result {
+                Ok(()) => warn!("Primary {} closed the replication stream", follower.primary_url),
+                Err(e) if e.is_retryable() => warn!("Replication from {} interrupted: {}", follower.primary_url, e),
+                Err(e) => {
+                    error!("Replication stopped: {}", e);
+                    return;
+                }
+            },
+            _ = state.promoted() => break,
+            _ = shutdown_receiver.recv() => {
+                info!("Shutting down replication...");
+                return;
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(reconnect_interval) => {}
+            _ = state.promoted() => break,
+            _ = shutdown_receiver.recv() => {
+                info!("Shutting down replication...");
+                return;
+            }
+        }
+    }
+
+    info!(
+        "Promoted to primary at sequence {}",
+        follower.processor.events.last_sequence()
+    );
+    // The server stops when any of its services does: only return on shutdown.
+    shutdown_receiver.recv().await.ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            events::error::EventLogError,
+            grpc_server::{
+                QuasarGrpcServer,
+                server::{
+                    DepositRequest, PromoteRequest, TransferRequest,
+                    grpc_service_server::GrpcService, grpc_service_server::GrpcServiceServer,
+                },
+            },
+            ledger::Ledger,
+            models::{
+                CreateAccountInstruction, DepositInstruction, Instruction, TransferInstruction,
+            },
+            submission::SubmissionQueue,
+            transaction_processor::interface::{TransactionProcessorInterface, TransactionResult},
+        },
+        chrono::Utc,
+        dashmap::DashMap,
+        tokio_stream::wrappers::TcpListenerStream,
+        tonic::{Code, Request, transport::Server},
+        uuid::Uuid,
+    };
+
+    fn processor() -> Arc<TransactionProcessor> {
+        Arc::new(TransactionProcessor::new(
+            Arc::new(Ledger::default()),
+            DashMap::new(),
+        ))
+    }
+
+    fn process(processor: &TransactionProcessor, instruction: Instruction) -> TransactionResult {
+        processor
+            .process_transaction(Transaction {
+                id: Uuid::new_v4(),
+                instruction,
+                status: TransactionStatus::Pending,
+                timestamp: Utc::now(),
+            })
+            .unwrap()
+    }
+
+    fn create_account(processor: &TransactionProcessor) -> Uuid {
+        match process(
+            processor,
+            Instruction::CreateAccount(CreateAccountInstruction::new(vec![])),
+        ) {
+            TransactionResult::AccountCreated(account_id) => account_id,
+            result => panic!("unexpected result {result:?}"),
+        }
+    }
+
+    fn deposit(processor: &TransactionProcessor, account_id: Uuid, amount: u64) {
+        process(
+            processor,
+            Instruction::Deposit(DepositInstruction {
+                destination_account_id: account_id,
+                amount,
+            }),
+        );
+    }
+
+    #[test]
+    fn test_rejects_transactions_out_of_sequence() {
+        let primary = processor();
+        let account_id = create_account(&primary);
+        deposit(&primary, account_id, 50);
+
+        let follower_processor = processor();
+        let mut follower = Follower::new(follower_processor.clone(), "");
+        let entries = primary.events.entries();
+        assert!(matches!(
+            follower.apply(entries[1].as_ref().clone()),
+            Err(ReplicationError::EventLog(EventLogError::SequenceGap {
+                expected: 1,
+                found: 2
+            }))
+        ));
+        for entry in &entries {
+            follower.apply(entry.as_ref().clone()).unwrap();
+        }
+        assert_eq!(
+            follower_processor
+                .ledger
+                .get_account(account_id)
+                .unwrap()
+                .balance,
+            50
+        );
+        assert_eq!(follower_processor.events.entries(), entries);
+    }
+
+    #[tokio::test]
+    async fn test_follower_replicates_primary_and_is_promoted() {
+        let primary = processor();
+        let account_id = create_account(&primary);
+        deposit(&primary, account_id, 100);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let service = QuasarGrpcServer::new(primary.clone(), Arc::new(SubmissionQueue::new(8)));
+        tokio::spawn(
+            Server::builder()
+                .add_service(GrpcServiceServer::new(service))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let replica = processor();
+        let state = Arc::new(ReplicationState::new(ReplicationRole::Follower));
+        let follower = Follower::new(replica.clone(), format!("http://{address}"));
+        let (shutdown_sender, shutdown_receiver) = broadcast::channel(1);
+        let replication = tokio::spawn(start_follower(
+            follower,
+            state.clone(),
+            50,
+            shutdown_receiver,
+        ));
+
+        // Commits made while the follower is connected are streamed live.
+        let other_id = create_account(&primary);
+        process(
+            &primary,
+            Instruction::Transfer(TransferInstruction {
+                source_account_id: account_id,
+                destination_account_id: other_id,
+                amount: 40,
+            }),
+        );
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while replica.events.last_sequence() < primary.events.last_sequence() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(replica.events.entries(), primary.events.entries());
+        for id in [account_id, other_id] {
+            assert_eq!(
+                replica.ledger.get_account(id).unwrap().balance,
+                primary.ledger.get_account(id).unwrap().balance
+            );
+        }
+
+        // The follower serves reads only, until promoted.
+        let replica_service =
+            QuasarGrpcServer::new(replica.clone(), Arc::new(SubmissionQueue::new(8)))
+                .with_replication(state.clone());
+        let deposit_request = || {
+            Request::new(DepositRequest {
+                transaction_id: Uuid::new_v4().to_string(),
+                destination_account_id: other_id.to_string(),
+                amount: 5,
+            })
+        };
+        let rejected = replica_service
+            .process_deposit(deposit_request())
+            .await
+            .unwrap_err();
+        assert_eq!(rejected.code(), Code::FailedPrecondition);
+        let transfer = replica_service
+            .process_transfer(Request::new(TransferRequest {
+                transaction_id: Uuid::new_v4().to_string(),
+                source_account_id: account_id.to_string(),
+                destination_account_id: other_id.to_string(),
+                amount: 1,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(transfer.code(), Code::FailedPrecondition);
+
+        let promotion = replica_service
+            .promote(Request::new(PromoteRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(promotion.promoted);
+        assert_eq!(promotion.last_sequence, primary.events.last_sequence());
+
+        replica_service
+            .process_deposit(deposit_request())
+            .await
+            .unwrap();
+        assert_eq!(replica.ledger.get_account(other_id).unwrap().balance, 45);
+
+        shutdown_sender.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), replication)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,17 @@
+use {thiserror::Error, uuid::Uuid};
+
+#[derive(Debug, Error)]
+pub enum RiskError {
+    #[error("Failed to load risk rules: {0}")]
+    InvalidRules(#[from] config::ConfigError),
+    #[error("No transaction {0} is held for review")]
+    UnknownReview(Uuid),
+    #[error("Transaction {0} was already reviewed")]
+    AlreadyReviewed(Uuid),
+    #[error("Transaction {0} was rejected on review")]
+    Rejected(Uuid),
+    #[error("Review store error: {0}")]
+    Store(#[from] rusqlite::Error),
+    #[error("Failed to encode held transaction: {0}")]
+    Encoding(#[from] serde_json::Error),
+}
@@ -0,0 +1,460 @@
+//! Risk rules evaluated by the transaction processor before a transfer is committed.
+//! Every rule looks at the transfer, both accounts and the payer's past transfers taken from
+//! the commit log, and may deny the transfer or hold it for review. Held transfers wait in the
+//! review queue until a review instruction approves them, committing them as submitted, or
+//! rejects them. Rules are configured in their own file, and custom ones can be added with
+//! [`RiskEngine::with_rule`].
+
+pub mod error;
+pub mod store;
+
+use {
+    crate::{
+        events::EventLog,
+        ledger::{error::LedgerError, interface::LedgerInterface},
+        models::{Account, Instruction, TransferInstruction},
+        risk::{error::RiskError, store::ReviewStore},
+    },
+    chrono::{DateTime, Duration, Utc},
+    config::{Config, File, FileFormat},
+    serde::{Deserialize, Serialize},
+    std::sync::Arc,
+    uuid::Uuid,
+};
+
+const DEFAULT_MIN_HISTORY: usize = 5;
+
+/// What happens to a transfer matched by a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskAction {
+    Deny,
+    Hold,
+}
+
+/// Outcome of evaluating every rule, naming the rule that decided it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RiskDecision {
+    Allow,
+    Deny(String),
+    Hold(String),
+}
+
+/// A transfer previously committed from the payer.
+#[derive(Debug, Clone)]
+pub struct PastTransfer {
+    pub destination_account_id: Uuid,
+    pub amount: u64,
+    /// Submission time of the transfer, which unlike its commit time is the same on every node.
+    pub submitted_at: DateTime<Utc>,
+}
+
+/// Everything a rule may look at.
+pub struct TransferContext<'a> {
+    pub transfer: &'a TransferInstruction,
+    /// Submission time of the transfer, so every node of a cluster decides the same way.
+    pub at: DateTime<Utc>,
+    pub payer: &'a Account,
+    pub payee: &'a Account,
+    /// Transfers committed from the payer, oldest first.
+    pub history: &'a [PastTransfer],
+}
+
+pub trait Rule: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// The action to take on the transfer, or `None` to let it through.
+    fn evaluate(&self, context: &TransferContext) -> Option<RiskAction>;
+}
+
+/// The conditions configured rules can check.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RiskCheck {
+    /// More than `max_transfers` transfers from the payer within `window_seconds`, this one
+    /// included.
+    Velocity {
+        max_transfers: usize,
+        window_seconds: u64,
+    },
+    /// An amount over `multiplier` times the payer's average transfer. Payers with fewer than
+    /// `min_history` past transfers are not checked.
+    UnusualAmount {
+        multiplier: f64,
+        #[serde(default = "default_min_history")]
+        min_history: usize,
+    },
+    /// A first transfer to a payee over `max_amount`.
+    NewPayee { max_amount: u64 },
+    /// Either account holds one of `keys` or is one of `accounts`.
+    Blocklist {
+        #[serde(default)]
+        keys: Vec<String>,
+        #[serde(default)]
+        accounts: Vec<Uuid>,
+    },
+}
+
+fn default_min_history() -> usize {
+    DEFAULT_MIN_HISTORY
+}
+
+/// A rule from the rules file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RiskRule {
+    pub name: String,
+    pub action: RiskAction,
+    pub check: RiskCheck,
+}
+
+impl RiskCheck {
+    fn matches(&self, context: &TransferContext) -> bool {
+        let amount = context.transfer.amount;
+        match self {
+            RiskCheck::Velocity {
+                max_transfers,
+                window_seconds,
+            } => {
+                let since = context.at - Duration::seconds(*window_seconds as i64);
+                let recent = context
+                    .history
+                    .iter()
+                    .filter(|past| past.submitted_at > since)
+                    .count();
+                recent + 1 > *max_transfers
+            }
+            RiskCheck::UnusualAmount {
+                multiplier,
+                min_history,
+            } => {
+                if context.history.is_empty() || context.history.len() < *min_history {
+                    return false;
+                }
+                let total: u128 = context.history.iter().map(|past| past.amount as u128).sum();
+                let average = total as f64 / context.history.len() as f64;
+                amount as f64 > average * multiplier
+            }
+            RiskCheck::NewPayee { max_amount } => {
+                amount > *max_amount
+                    && !context.history.iter().any(|past| {
+                        past.destination_account_id == context.transfer.destination_account_id
+                    })
+            }
+            RiskCheck::Blocklist { keys, accounts } => {
+                [context.payer, context.payee].iter().any(|account| {
+                    accounts.contains(&account.uuid)
+                        || account
+                            .keys
+                            .iter()
+                            .any(|key| keys.iter().any(|blocked| blocked == key.value()))
+                })
+            }
+        }
+    }
+}
+
+impl Rule for RiskRule {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn evaluate(&self, context: &TransferContext) -> Option<RiskAction> {
+        self.check.matches(context).then_some(self.action)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RulesFile {
+    #[serde(default)]
+    rules: Vec<RiskRule>,
+}
+
+/// Reads the `[[rules]]` of a TOML rules file.
+pub fn load_rules(path: &str) -> Result<Vec<RiskRule>, RiskError> {
+    let rules: RulesFile = Config::builder()
+        .add_source(File::new(path, FileFormat::Toml))
+        .build()?
+        .try_deserialize()?;
+    Ok(rules.rules)
+}
+
+pub struct RiskEngine {
+    rules: Vec<Box<dyn Rule>>,
+    pub reviews: Arc<ReviewStore>,
+}
+
+impl RiskEngine {
+    pub fn new(reviews: Arc<ReviewStore>) -> Self {
+        RiskEngine {
+            rules: Vec::new(),
+            reviews,
+        }
+    }
+
+    pub fn with_rules(self, rules: Vec<RiskRule>) -> Self {
+        rules.into_iter().fold(self, RiskEngine::with_rule)
+    }
+
+    /// Adds a rule, evaluated after the ones already added.
+    pub fn with_rule(mut self, rule: impl Rule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Evaluates every rule. A denial wins over a hold, and the first rule to decide is named.
+    pub fn evaluate(&self, context: &TransferContext) -> RiskDecision {
+        let mut decision = RiskDecision::Allow;
+        for rule in &self.rules {
+            match rule.evaluate(context) {
+                Some(RiskAction::Deny) => return RiskDecision::Deny(rule.name().to_string()),
+                Some(RiskAction::Hold) if decision == RiskDecision::Allow => {
+                    decision = RiskDecision::Hold(rule.name().to_string());
+                }
+                _ => {}
+            }
+        }
+        decision
+    }
+
+    /// Evaluates a transfer submitted at `at` against the current accounts and the payer's
+    /// logged transfers.
+    pub fn evaluate_transfer(
+        &self,
+        transfer: &TransferInstruction,
+        at: DateTime<Utc>,
+        ledger: &dyn LedgerInterface,
+        events: &EventLog,
+    ) -> Result<RiskDecision, LedgerError> {
+        if self.rules.is_empty() {
+            return Ok(RiskDecision::Allow);
+        }
+
+        let payer = ledger.get_account(transfer.source_account_id)?;
+        let payee = ledger.get_account(transfer.destination_account_id)?;
+        let history: Vec<PastTransfer> = events
+            .account_entries(payer.uuid)
+            .iter()
+            .filter_map(|entry| match &entry.instruction {
+                Instruction::Transfer(past) if past.source_account_id == payer.uuid => {
+                    Some(PastTransfer {
+                        destination_account_id: past.destination_account_id,
+                        amount: past.amount,
+                        submitted_at: entry.submitted_at,
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+
+        Ok(self.evaluate(&TransferContext {
+            transfer,
+            at,
+            payer: &payer,
+            payee: &payee,
+            history: &history,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            events::{CommittedTransaction, error::EventLogError},
+            ledger::Ledger,
+            models::{AccountType, Key, Posting},
+        },
+    };
+
+    fn account(keys: Vec<Key>) -> Account {
+        Account {
+            uuid: Uuid::new_v4(),
+            keys,
+            ..Default::default()
+        }
+    }
+
+    fn rule(action: RiskAction, check: RiskCheck) -> RiskRule {
+        RiskRule {
+            name: format!("{check:?}"),
+            action,
+            check,
+        }
+    }
+
+    #[test]
+    fn test_rules_match_transfers() {
+        let payer = account(vec![]);
+        let payee = account(vec![Key::Email("mule@example.com".to_string())]);
+        let known_payee = Uuid::new_v4();
+        let now = Utc::now();
+        let history: Vec<PastTransfer> = (0..5)
+            .map(|minutes| PastTransfer {
+                destination_account_id: known_payee,
+                amount: 100,
+                submitted_at: now - Duration::minutes(minutes * 10),
+            })
+            .collect();
+        let transfer = |destination_account_id, amount| TransferInstruction {
+            source_account_id: payer.uuid,
+            destination_account_id,
+            amount,
+        };
+        let matches = |check: RiskCheck, transfer: &TransferInstruction| {
+            check.matches(&TransferContext {
+                transfer,
+                at: now,
+                payer: &payer,
+                payee: &payee,
+                history: &history,
+            })
+        };
+
+        let velocity = || RiskCheck::Velocity {
+            max_transfers: 2,
+            window_seconds: 15 * 60,
+        };
+        // Two past transfers fall within the window.
+        assert!(matches(velocity(), &transfer(known_payee, 100)));
+        assert!(!matches(
+            RiskCheck::Velocity {
+                max_transfers: 3,
+                window_seconds: 15 * 60,
+            },
+            &transfer(known_payee, 100)
+        ));
+
+        let unusual = || RiskCheck::UnusualAmount {
+            multiplier: 3.0,
+            min_history: 5,
+        };
+        assert!(matches(unusual(), &transfer(known_payee, 301)));
+        assert!(!matches(unusual(), &transfer(known_payee, 300)));
+
+        let new_payee = || RiskCheck::NewPayee { max_amount: 50 };
+        assert!(matches(new_payee(), &transfer(payee.uuid, 51)));
+        assert!(!matches(new_payee(), &transfer(payee.uuid, 50)));
+        assert!(!matches(new_payee(), &transfer(known_payee, 500)));
+
+        let blocklist = RiskCheck::Blocklist {
+            keys: vec!["mule@example.com".to_string()],
+            accounts: vec![],
+        };
+        assert!(matches(blocklist, &transfer(payee.uuid, 1)));
+    }
+
+    #[test]
+    fn test_velocity_ignores_commit_times() {
+        let ledger = Ledger::default();
+        let [payer, payee] = [Uuid::new_v4(), Uuid::new_v4()].map(|transaction_id| {
+            ledger
+                .create_account(transaction_id, vec![], AccountType::Personal)
+                .unwrap();
+            Account::id_for(transaction_id)
+        });
+        let transfer = TransferInstruction {
+            source_account_id: payer,
+            destination_account_id: payee,
+            amount: 10,
+        };
+        let at = Utc::now();
+        let engine =
+            RiskEngine::new(Arc::new(ReviewStore::open(":memory:").unwrap())).with_rules(vec![
+                rule(
+                    RiskAction::Deny,
+                    RiskCheck::Velocity {
+                        max_transfers: 2,
+                        window_seconds: 15 * 60,
+                    },
+                ),
+            ]);
+
+        // The same log replicated by nodes whose clocks disagree by up to a day.
+        let decisions: Vec<RiskDecision> =
+            [Duration::zero(), Duration::days(-1), Duration::days(1)]
+                .into_iter()
+                .map(|skew| {
+                    let events = EventLog::default();
+                    for (sequence, minutes) in [(1, 10), (2, 5)] {
+                        let submitted_at = at - Duration::minutes(minutes);
+                        let event = CommittedTransaction {
+                            sequence,
+                            transaction_id: Uuid::new_v4(),
+                            instruction: Instruction::Transfer(transfer.clone()),
+                            postings: vec![Posting::debit(payer, 10), Posting::credit(payee, 10)],
+                            fee: None,
+                            created_account_id: None,
+                            submitted_at,
+                            committed_at: submitted_at + skew,
+                        };
+                        events
+                            .replicate(event, |_| Ok::<(), EventLogError>(()))
+                            .unwrap();
+                    }
+                    engine
+                        .evaluate_transfer(&transfer, at, &ledger, &events)
+                        .unwrap()
+                })
+                .collect();
+        assert!(matches!(decisions[0], RiskDecision::Deny(_)));
+        assert!(decisions.iter().all(|decision| *decision == decisions[0]));
+    }
+
+    #[test]
+    fn test_example_rules_load() {
+        let rules = load_rules(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/risk-rules.example.toml"
+        ))
+        .unwrap();
+        assert_eq!(rules.len(), 4);
+        assert!(matches!(
+            rules[3],
+            RiskRule {
+                action: RiskAction::Deny,
+                check: RiskCheck::Blocklist { .. },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_denials_win_over_holds() {
+        let reviews = Arc::new(ReviewStore::open(":memory:").unwrap());
+        let payer = account(vec![]);
+        let payee = account(vec![]);
+        let transfer = TransferInstruction {
+            source_account_id: payer.uuid,
+            destination_account_id: payee.uuid,
+            amount: 1000,
+        };
+        let context = TransferContext {
+            transfer: &transfer,
+            at: Utc::now(),
+            payer: &payer,
+            payee: &payee,
+            history: &[],
+        };
+
+        let hold = rule(RiskAction::Hold, RiskCheck::NewPayee { max_amount: 10 });
+        let deny = rule(
+            RiskAction::Deny,
+            RiskCheck::Blocklist {
+                keys: vec![],
+                accounts: vec![payee.uuid],
+            },
+        );
+        let engine = RiskEngine::new(reviews.clone()).with_rules(vec![hold.clone()]);
+        assert_eq!(
+            engine.evaluate(&context),
+            RiskDecision::Hold(hold.name.clone())
+        );
+        let engine = RiskEngine::new(reviews.clone()).with_rules(vec![hold, deny.clone()]);
+        assert_eq!(engine.evaluate(&context), RiskDecision::Deny(deny.name));
+        assert_eq!(
+            RiskEngine::new(reviews).evaluate(&context),
+            RiskDecision::Allow
+        );
+    }
+}
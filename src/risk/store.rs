@@ -0,0 +1,150 @@
+use {
+    crate::{models::Transaction, risk::error::RiskError},
+    chrono::{DateTime, SecondsFormat, Utc},
+    rusqlite::{Connection, OptionalExtension, Row, params},
+    serde::{Deserialize, Serialize},
+    std::sync::{Mutex, MutexGuard},
+    uuid::Uuid,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReviewStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl ReviewStatus {
+    fn name(self) -> &'static str {
+        match self {
+            ReviewStatus::Pending => "pending",
+            ReviewStatus::Approved => "approved",
+            ReviewStatus::Rejected => "rejected",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(ReviewStatus::Pending),
+            "approved" => Some(ReviewStatus::Approved),
+            "rejected" => Some(ReviewStatus::Rejected),
+            _ => None,
+        }
+    }
+}
+
+/// A transaction set aside by a risk rule until it is approved or rejected.
+#[derive(Debug, Clone)]
+pub struct HeldTransaction {
+    pub transaction: Transaction,
+    /// Name of the rule that held it.
+    pub rule: String,
+    pub status: ReviewStatus,
+    pub held_at: DateTime<Utc>,
+}
+
+/// SQLite-backed review queue. Uses its own connection, like the webhook store, so held
+/// transactions survive restarts whatever the persistence backend.
+pub struct ReviewStore {
+    conn: Mutex<Connection>,
+}
+
+impl ReviewStore {
+    pub fn open(db_path: &str) -> Result<Self, RiskError> {
+        let conn = Connection::open(db_path)?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS held_transactions (
+                transaction_id TEXT PRIMARY KEY,
+                transaction_json TEXT NOT NULL,
+                rule TEXT NOT NULL,
+                status TEXT NOT NULL,
+                held_at TEXT NOT NULL
+            )",
+            [],
+        )?;
+        Ok(ReviewStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds the transaction to the queue. Holding it again keeps the first entry.
+    pub fn hold(&self, transaction: &Transaction, rule: &str) -> Result<(), RiskError> {
+        self.conn().execute(
+            "INSERT OR IGNORE INTO held_transactions (transaction_id, transaction_json, rule, status, held_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                transaction.id.to_string(),
+                serde_json::to_string(transaction)?,
+                rule,
+                ReviewStatus::Pending.name(),
+                Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn get(&self, transaction_id: Uuid) -> Result<Option<HeldTransaction>, RiskError> {
+        self.conn()
+            .query_row(
+                "SELECT transaction_json, rule, status, held_at FROM held_transactions WHERE transaction_id = ?1",
+                params![transaction_id.to_string()],
+                held_row,
+            )
+            .optional()?
+            .map(decode)
+            .transpose()
+    }
+
+    /// Transactions awaiting a decision, oldest first.
+    pub fn pending(&self) -> Result<Vec<HeldTransaction>, RiskError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT transaction_json, rule, status, held_at FROM held_transactions
+             WHERE status = ?1 ORDER BY held_at",
+        )?;
+        stmt.query_map(params![ReviewStatus::Pending.name()], held_row)?
+            .map(|row| decode(row?))
+            .collect()
+    }
+
+    /// Records the decision on a pending transaction. Fails if it was already decided.
+    pub fn resolve(&self, transaction_id: Uuid, status: ReviewStatus) -> Result<(), RiskError> {
+        let updated = self.conn().execute(
+            "UPDATE held_transactions SET status = ?2 WHERE transaction_id = ?1 AND status = ?3",
+            params![
+                transaction_id.to_string(),
+                status.name(),
+                ReviewStatus::Pending.name()
+            ],
+        )?;
+        if updated == 0 {
+            return Err(RiskError::AlreadyReviewed(transaction_id));
+        }
+        Ok(())
+    }
+}
+
+type HeldRow = (String, String, String, String);
+
+fn held_row(row: &Row) -> rusqlite::Result<HeldRow> {
+    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+}
+
+fn decode(
+    (transaction_json, rule, status, held_at): HeldRow,
+) -> Result<HeldTransaction, RiskError> {
+    Ok(HeldTransaction {
+        transaction: serde_json::from_str(&transaction_json)?,
+        rule,
+        status: ReviewStatus::parse(&status).unwrap_or(ReviewStatus::Pending),
+        held_at: DateTime::parse_from_rfc3339(&held_at)
+            .map(|held_at| held_at.with_timezone(&Utc))
+            .unwrap_or_default(),
+    })
+}
@@ -0,0 +1,11 @@
+use {thiserror::Error, uuid::Uuid};
+
+#[derive(Debug, Error)]
+pub enum StatementError {
+    #[error("Statement period must end after it starts")]
+    InvalidRange,
+    #[error(
+        "Balance of account {account_id} went negative at sequence {sequence}; the log is incomplete"
+    )]
+    InconsistentLog { account_id: Uuid, sequence: u64 },
+}
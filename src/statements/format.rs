@@ -0,0 +1,163 @@
+use {
+    crate::statements::{Statement, StatementFormat},
+    chrono::{DateTime, Utc},
+    std::fmt::Write,
+};
+
+impl StatementFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            StatementFormat::Csv => "text/csv",
+            StatementFormat::Ofx => "application/x-ofx",
+        }
+    }
+}
+
+impl Statement {
+    pub fn render(&self, format: StatementFormat, currency: &str) -> String {
+        match format {
+            StatementFormat::Csv => self.to_csv(),
+            StatementFormat::Ofx => self.to_ofx(currency),
+        }
+    }
+
+    /// One row per movement, framed by opening and closing balance rows.
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            String::from("date,sequence,transaction_id,description,debit,credit,balance\n");
+
+        let _ = writeln!(
+            csv,
+            "{},,,Opening balance,,,{}",
+            self.from.to_rfc3339(),
+            self.opening_balance
+        );
+        for line in &self.lines {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{}",
+                line.posted_at.to_rfc3339(),
+                line.sequence,
+                line.transaction_id,
+                csv_field(&line.description),
+                line.debit,
+                line.credit,
+                line.balance
+            );
+        }
+        let _ = writeln!(
+            csv,
+            "{},,,Closing balance,,,{}",
+            self.to.to_rfc3339(),
+            self.closing_balance
+        );
+
+        csv
+    }
+
+    /// OFX 2.2 bank statement. Amounts are written in the ledger's integer units.
+    pub fn to_ofx(&self, currency: &str) -> String {
+        let mut transactions = String::new();
+        for line in &self.lines {
+            let amount = i128::from(line.credit) - i128::from(line.debit);
+            let _ = writeln!(
+                transactions,
+                "<STMTTRN><TRNTYPE>{}</TRNTYPE><DTPOSTED>{}</DTPOSTED><TRNAMT>{}</TRNAMT>\
+                 <FITID>{}</FITID><MEMO>{}</MEMO></STMTTRN>",
+                if amount < 0 { "DEBIT" } else { "CREDIT" },
+                ofx_date(line.posted_at),
+                amount,
+                line.transaction_id,
+                xml_escape(&line.description),
+            );
+        }
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <?OFX OFXHEADER=\"200\" VERSION=\"220\" SECURITY=\"NONE\" OLDFILEUID=\"NONE\" NEWFILEUID=\"NONE\"?>\n\
+             <OFX>\n\
+             <BANKMSGSRSV1><STMTTRNRS><TRNUID>0</TRNUID>\
+             <STATUS><CODE>0</CODE><SEVERITY>INFO</SEVERITY></STATUS>\n\
+             <STMTRS><CURDEF>{currency}</CURDEF>\n\
+             <BANKACCTFROM><BANKID>QUASAR</BANKID><ACCTID>{account_id}</ACCTID><ACCTTYPE>CHECKING</ACCTTYPE></BANKACCTFROM>\n\
+             <BANKTRANLIST><DTSTART>{start}</DTSTART><DTEND>{end}</DTEND>\n\
+             {transactions}\
+             </BANKTRANLIST>\n\
+             <LEDGERBAL><BALAMT>{closing}</BALAMT><DTASOF>{end}</DTASOF></LEDGERBAL>\n\
+             </STMTRS></STMTTRNRS></BANKMSGSRSV1>\n\
+             </OFX>\n",
+            currency = xml_escape(currency),
+            account_id = self.account_id,
+            start = ofx_date(self.from),
+            end = ofx_date(self.to),
+            closing = self.closing_balance,
+        )
+    }
+}
+
+fn ofx_date(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%d%H%M%S.%3f[0:UTC]").to_string()
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::statements::StatementLine, uuid::Uuid};
+
+    fn statement() -> Statement {
+        let from = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        Statement {
+            account_id: Uuid::nil(),
+            from,
+            to: from + chrono::Duration::days(31),
+            opening_balance: 100,
+            lines: vec![StatementLine {
+                posted_at: from + chrono::Duration::days(1),
+                sequence: 7,
+                transaction_id: Uuid::nil(),
+                description: "Transfer to x, with \"quotes\"".to_string(),
+                debit: 40,
+                credit: 0,
+                balance: 60,
+            }],
+            closing_balance: 60,
+        }
+    }
+
+    #[test]
+    fn test_csv_escapes_and_frames_balances() {
+        let csv = statement().to_csv();
+        let rows: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(rows.len(), 4);
+        assert!(rows[1].ends_with(",Opening balance,,,100"));
+        assert!(rows[2].contains(",\"Transfer to x, with \"\"quotes\"\"\",40,0,60"));
+        assert!(rows[3].ends_with(",Closing balance,,,60"));
+    }
+
+    #[test]
+    fn test_ofx_signs_debits() {
+        let ofx = statement().to_ofx("USD");
+
+        assert!(ofx.contains("<TRNTYPE>DEBIT</TRNTYPE>"));
+        assert!(ofx.contains("<TRNAMT>-40</TRNAMT>"));
+        assert!(ofx.contains("<DTPOSTED>20260102000000.000[0:UTC]</DTPOSTED>"));
+        assert!(ofx.contains("<BALAMT>60</BALAMT>"));
+    }
+}
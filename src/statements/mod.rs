@@ -0,0 +1,221 @@
+//! Account statements for reconciliation.
+//! A statement is rebuilt from the commit log: the opening balance is the sum of every movement
+//! before the period, followed by each movement in the period with its running balance.
+
+pub mod error;
+pub mod format;
+
+use {
+    crate::{
+        events::CommittedTransaction,
+        models::{DisputeAction, Instruction, PostingKind},
+        statements::error::StatementError,
+    },
+    chrono::{DateTime, Utc},
+    serde::Serialize,
+    uuid::Uuid,
+};
+
+/// Currency code written in OFX statements when none is given.
+pub const DEFAULT_STATEMENT_CURRENCY: &str = "USD";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementFormat {
+    Csv,
+    Ofx,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatementLine {
+    pub posted_at: DateTime<Utc>,
+    pub sequence: u64,
+    pub transaction_id: Uuid,
+    pub description: String,
+    pub debit: u64,
+    pub credit: u64,
+    pub balance: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Statement {
+    pub account_id: Uuid,
+    /// Inclusive start of the period.
+    pub from: DateTime<Utc>,
+    /// Exclusive end of the period.
+    pub to: DateTime<Utc>,
+    pub opening_balance: u64,
+    pub lines: Vec<StatementLine>,
+    pub closing_balance: u64,
+}
+
+impl Statement {
+    /// Builds the statement of `account_id` over `[from, to)` from committed transactions in
+    /// sequence order. Entries not touching the account are ignored.
+    pub fn generate<'a>(
+        account_id: Uuid,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        entries: impl IntoIterator<Item = &'a CommittedTransaction>,
+    ) -> Result<Self, StatementError> {
+        if to <= from {
+            return Err(StatementError::InvalidRange);
+        }
+
+        let mut statement = Statement {
+            account_id,
+            from,
+            to,
+            opening_balance: 0,
+            lines: Vec::new(),
+            closing_balance: 0,
+        };
+
+        let mut balance: u64 = 0;
+        for entry in entries {
+            if entry.committed_at >= to {
+                break;
+            }
+
+            let (debit, credit) = movement(account_id, entry);
+            if debit == 0 && credit == 0 {
+                continue;
+            }
+            balance = balance
+                .checked_add(credit)
+                .and_then(|balance| balance.checked_sub(debit))
+                .ok_or(StatementError::InconsistentLog {
+                    account_id,
+                    sequence: entry.sequence,
+                })?;
+
+            if entry.committed_at < from {
+                statement.opening_balance = balance;
+            } else {
+                statement.lines.push(StatementLine {
+                    posted_at: entry.committed_at,
+                    sequence: entry.sequence,
+                    transaction_id: entry.transaction_id,
+                    description: describe(account_id, entry),
+                    debit,
+                    credit,
+                    balance,
+                });
+            }
+        }
+
+        statement.closing_balance = balance;
+        Ok(statement)
+    }
+}
+
+/// Total debited from and credited to the account by a transaction, fees included.
+fn movement(account_id: Uuid, entry: &CommittedTransaction) -> (u64, u64) {
+    entry
+        .postings
+        .iter()
+        .filter(|posting| posting.account_id == account_id)
+        .fold((0, 0), |(debit, credit), posting| match posting.kind {
+            PostingKind::Debit => (debit + posting.amount, credit),
+            PostingKind::Credit => (debit, credit + posting.amount),
+        })
+}
+
+fn describe(account_id: Uuid, entry: &CommittedTransaction) -> String {
+    let fee = entry.fee.as_ref();
+    if fee.is_some_and(|fee| fee.revenue_account_id == account_id) {
+        return format!("Fee revenue ({})", fee.map_or("", |fee| fee.rule.as_str()));
+    }
+
+    let description = match &entry.instruction {
+        Instruction::Transfer(transfer) if transfer.source_account_id == account_id => {
+            format!("Transfer to {}", transfer.destination_account_id)
+        }
+        Instruction::Transfer(transfer) => {
+            format!("Transfer from {}", transfer.source_account_id)
+        }
+        Instruction::Deposit(_) => "Deposit".to_string(),
+        Instruction::CreateAccount(_) => "Account opened".to_string(),
+        Instruction::GetBalance(_) => "Balance inquiry".to_string(),
+        Instruction::Review(_) => "Review decision".to_string(),
+        Instruction::OpenDispute(_) => format!("Blocked by dispute {}", entry.transaction_id),
+        Instruction::UpdateDispute(update) => match update.action {
+            DisputeAction::StartAnalysis => format!("Dispute {} under analysis", update.dispute_id),
+            DisputeAction::Return => format!("Returned by dispute {}", update.dispute_id),
+            DisputeAction::Release | DisputeAction::Expire => {
+                format!("Released by dispute {}", update.dispute_id)
+            }
+        },
+    };
+
+    match fee {
+        Some(fee) => format!("{description} (fee {})", fee.amount),
+        None => description,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            events::EventLog,
+            models::{DepositInstruction, Posting, TransferInstruction},
+        },
+        chrono::Duration,
+    };
+
+    #[test]
+    fn test_running_balance_over_period() {
+        let log = EventLog::default();
+        let account = Uuid::new_v4();
+        let other = Uuid::new_v4();
+
+        log.append(
+            Uuid::new_v4(),
+            Instruction::Deposit(DepositInstruction {
+                destination_account_id: account,
+                amount: 100,
+            }),
+            vec![Posting::credit(account, 100)],
+            None,
+        )
+        .unwrap();
+        let from = log.get(1).unwrap().committed_at + Duration::nanoseconds(1);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        log.append(
+            Uuid::new_v4(),
+            Instruction::Transfer(TransferInstruction {
+                source_account_id: account,
+                destination_account_id: other,
+                amount: 30,
+            }),
+            vec![Posting::debit(account, 30), Posting::credit(other, 30)],
+            None,
+        )
+        .unwrap();
+
+        let entries = log.entries();
+        let statement = Statement::generate(
+            account,
+            from,
+            Utc::now() + Duration::seconds(1),
+            entries.iter().map(|e| e.as_ref()),
+        )
+        .unwrap();
+
+        assert_eq!(statement.opening_balance, 100);
+        assert_eq!(statement.lines.len(), 1);
+        assert_eq!(statement.lines[0].debit, 30);
+        assert_eq!(statement.lines[0].balance, 70);
+        assert_eq!(
+            statement.lines[0].description,
+            format!("Transfer to {other}")
+        );
+        assert_eq!(statement.closing_balance, 70);
+
+        assert!(matches!(
+            Statement::generate(account, from, from, entries.iter().map(|e| e.as_ref())),
+            Err(StatementError::InvalidRange)
+        ));
+    }
+}
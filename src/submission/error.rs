@@ -0,0 +1,9 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SubmissionError {
+    #[error("Submission queue is full")]
+    QueueFull,
+    #[error("Submission queue is closed")]
+    QueueClosed,
+}
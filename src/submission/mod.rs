@@ -0,0 +1,303 @@
+//! Asynchronous transaction submission.
+//! Transactions are accepted into a bounded queue and processed by background workers, while
+//! clients poll or watch their status by transaction ID. Statuses of processed transactions
+//! are kept for a while after they finish, then forgotten.
+
+pub mod error;
+
+use {
+    crate::{
+        metrics::{SUBMISSION_QUEUE_DEPTH, SUBMISSIONS_REJECTED_TOTAL},
+        models::Transaction,
+        submission::error::SubmissionError,
+        transaction_processor::{
+            TransactionProcessor,
+            interface::{TransactionProcessorInterface, TransactionResult},
+        },
+    },
+    dashmap::{DashMap, mapref::entry::Entry},
+    std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+    tokio::sync::{broadcast, mpsc, watch},
+    tracing::{error, info},
+    uuid::Uuid,
+};
+
+const DEFAULT_STATUS_TTL_SECONDS: u64 = 60 * 60;
+const DEFAULT_MAX_FINISHED_STATUSES: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubmissionStatus {
+    Queued,
+    Processing,
+    Completed(TransactionResult),
+    Failed(String),
+}
+
+impl SubmissionStatus {
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            SubmissionStatus::Completed(_) | SubmissionStatus::Failed(_)
+        )
+    }
+}
+
+pub struct SubmissionQueue {
+    sender: mpsc::Sender<Transaction>,
+    // Taken by the workers when they start.
+    receiver: Mutex<Option<mpsc::Receiver<Transaction>>>,
+    statuses: DashMap<Uuid, watch::Sender<SubmissionStatus>>,
+    // Transactions with a terminal status, in the order they finished.
+    finished: Mutex<VecDeque<(Uuid, Instant)>>,
+    status_ttl: Duration,
+    max_finished: usize,
+}
+
+impl SubmissionQueue {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+
+        SubmissionQueue {
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+            statuses: DashMap::new(),
+            finished: Mutex::new(VecDeque::new()),
+            status_ttl: Duration::from_secs(DEFAULT_STATUS_TTL_SECONDS),
+            max_finished: DEFAULT_MAX_FINISHED_STATUSES,
+        }
+    }
+
+    /// Forgets terminal statuses once they are older than `status_ttl`, or once more than
+    /// `max_finished` of them are tracked, oldest first.
+    pub fn with_retention(mut self, status_ttl: Duration, max_finished: usize) -> Self {
+        self.status_ttl = status_ttl;
+        self.max_finished = max_finished;
+        self
+    }
+
+    /// Enqueues a transaction and returns its ID without waiting for it to be processed.
+    /// Submitting an ID that is already tracked is a no-op, which keeps retries idempotent.
+    pub fn submit(&self, transaction: Transaction) -> Result<Uuid, SubmissionError> {
+        let id = transaction.id;
+
+        match self.statuses.entry(id) {
+            Entry::Occupied(_) => return Ok(id),
+            Entry::Vacant(entry) => {
+                entry.insert(watch::Sender::new(SubmissionStatus::Queued));
+            }
+        }
+
+        match self.sender.try_send(transaction) {
+            Ok(()) => {
+                SUBMISSION_QUEUE_DEPTH.inc();
+                Ok(id)
+            }
+            Err(e) => {
+                self.statuses.remove(&id);
+                match e {
+                    mpsc::error::TrySendError::Full(_) => {
+                        SUBMISSIONS_REJECTED_TOTAL.inc();
+                        Err(SubmissionError::QueueFull)
+                    }
+                    mpsc::error::TrySendError::Closed(_) => Err(SubmissionError::QueueClosed),
+                }
+            }
+        }
+    }
+
+    /// Returns the current status of a submitted transaction.
+    pub fn status(&self, id: Uuid) -> Option<SubmissionStatus> {
+        self.statuses.get(&id).map(|status| status.borrow().clone())
+    }
+
+    /// Returns a receiver notified on every status change of a submitted transaction.
+    pub fn watch(&self, id: Uuid) -> Option<watch::Receiver<SubmissionStatus>> {
+        self.statuses.get(&id).map(|status| status.subscribe())
+    }
+
+    fn set_status(&self, id: Uuid, status: SubmissionStatus) {
+        let terminal = status.is_terminal();
+        if let Some(sender) = self.statuses.get(&id) {
+            sender.send_replace(status);
+        }
+        if terminal {
+            self.finish(id, Instant::now());
+        }
+    }
+
+    /// Tracks a transaction that reached a terminal status, and evicts the ones kept too long.
+    fn finish(&self, id: Uuid, now: Instant) {
+        let mut finished = self.finished.lock().unwrap_or_else(|e| e.into_inner());
+        finished.push_back((id, now));
+        while let Some((oldest, finished_at)) = finished.front().copied() {
+            let expired = now.saturating_duration_since(finished_at) > self.status_ttl;
+            if !expired && finished.len() <= self.max_finished {
+                break;
+            }
+            finished.pop_front();
+            self.statuses.remove(&oldest);
+        }
+    }
+}
+
+/// Runs `workers` tasks draining the submission queue until shutdown.
+pub async fn start_submission_workers(
+    queue: Arc<SubmissionQueue>,
+    processor: Arc<TransactionProcessor>,
+    workers: usize,
+    mut shutdown_receiver: broadcast::Receiver<()>,
+) {
+    let Some(receiver) = queue.receiver.lock().ok().and_then(|mut r| r.take()) else {
+        error!("Submission workers were already started");
+        return;
+    };
+    let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+
+    info!("Starting {} submission workers", workers);
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for _ in 0..workers.max(1) {
+        let queue = queue.clone();
+        let processor = processor.clone();
+        let receiver = receiver.clone();
+        tasks.spawn(async move {
+            loop {
+                let Some(transaction) = receiver.lock().await.recv().await else {
+                    break;
+                };
+                SUBMISSION_QUEUE_DEPTH.dec();
+                process_submission(&queue, &processor, transaction).await;
+            }
+        });
+    }
+
+    shutdown_receiver.recv().await.ok();
+    info!("Shutting down submission workers...");
+    tasks.abort_all();
+}
+
+async fn process_submission(
+    queue: &SubmissionQueue,
+    processor: &Arc<TransactionProcessor>,
+    transaction: Transaction,
+) {
+    let id = transaction.id;
+    queue.set_status(id, SubmissionStatus::Processing);
+
+    let processor = processor.clone();
+    let result =
+        tokio::task::spawn_blocking(move || processor.process_transaction(transaction)).await;
+
+    let status = match result {
+        Ok(Ok(result)) => SubmissionStatus::Completed(result),
+        Ok(Err(e)) => SubmissionStatus::Failed(e.to_string()),
+        Err(e) => {
+            error!("Submission worker failed to process {}: {}", id, e);
+            SubmissionStatus::Failed("Internal error while processing transaction".to_string())
+        }
+    };
+    queue.set_status(id, status);
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            ledger::Ledger,
+            models::{CreateAccountInstruction, Instruction, TransactionStatus},
+        },
+        chrono::Utc,
+    };
+
+    fn create_account_transaction() -> Transaction {
+        Transaction {
+            id: Uuid::new_v4(),
+            instruction: Instruction::CreateAccount(CreateAccountInstruction::new(vec![])),
+            status: TransactionStatus::Pending,
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_submit_rejects_when_queue_is_full() {
+        let queue = SubmissionQueue::new(1);
+
+        let accepted = create_account_transaction();
+        assert_eq!(queue.submit(accepted.clone()).unwrap(), accepted.id);
+        // Resubmitting a tracked ID does not take another queue slot.
+        assert!(queue.submit(accepted.clone()).is_ok());
+
+        let rejected = create_account_transaction();
+        assert!(matches!(
+            queue.submit(rejected.clone()),
+            Err(SubmissionError::QueueFull)
+        ));
+        assert!(queue.status(rejected.id).is_none());
+        assert_eq!(queue.status(accepted.id), Some(SubmissionStatus::Queued));
+    }
+
+    #[tokio::test]
+    async fn test_submission_completes_in_background() {
+        let queue = Arc::new(SubmissionQueue::new(8));
+        let processor = Arc::new(TransactionProcessor::new(
+            Arc::new(Ledger::default()),
+            DashMap::new(),
+        ));
+        let (shutdown_sender, shutdown_receiver) = broadcast::channel(1);
+        let workers = tokio::spawn(start_submission_workers(
+            queue.clone(),
+            processor,
+            2,
+            shutdown_receiver,
+        ));
+
+        let id = queue.submit(create_account_transaction()).unwrap();
+        let mut status = queue.watch(id).unwrap();
+        let status = status.wait_for(|s| s.is_terminal()).await.unwrap().clone();
+
+        assert!(matches!(
+            status,
+            SubmissionStatus::Completed(TransactionResult::AccountCreated(_))
+        ));
+
+        shutdown_sender.send(()).unwrap();
+        workers.await.unwrap();
+    }
+
+    #[test]
+    fn test_finished_statuses_are_evicted() {
+        let queue = SubmissionQueue::new(8).with_retention(Duration::from_secs(60), 2);
+        let ids: Vec<Uuid> = (0..4)
+            .map(|_| queue.submit(create_account_transaction()).unwrap())
+            .collect();
+        let completed = SubmissionStatus::Completed(TransactionResult::Balance(0));
+        let start = Instant::now();
+        let finish = |id: &Uuid, at| {
+            queue
+                .statuses
+                .get(id)
+                .unwrap()
+                .send_replace(completed.clone());
+            queue.finish(*id, at);
+        };
+
+        // Beyond two finished statuses, the oldest is dropped. Pending ones are kept.
+        for id in &ids[..3] {
+            finish(id, start);
+        }
+        assert!(queue.status(ids[0]).is_none());
+        assert_eq!(queue.status(ids[2]), Some(completed.clone()));
+        assert_eq!(queue.status(ids[3]), Some(SubmissionStatus::Queued));
+
+        // Once expired, older statuses go when the next one finishes.
+        finish(&ids[3], start + Duration::from_secs(61));
+        assert!(queue.status(ids[1]).is_none());
+        assert!(queue.status(ids[2]).is_none());
+        assert!(queue.status(ids[3]).is_some());
+    }
+}
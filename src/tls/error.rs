@@ -0,0 +1,18 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Failed to read {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Invalid PEM in {path}: {reason}")]
+    Pem { path: String, reason: String },
+    #[error("Invalid TLS configuration: {0}")]
+    Config(String),
+    #[error("Invalid endpoint: {0}")]
+    Endpoint(#[from] tonic::transport::Error),
+    #[error("TLS error: {0}")]
+    Rustls(#[from] rustls::Error),
+}
@@ -0,0 +1,500 @@
+//! TLS for the gRPC and HTTP listeners and for gRPC clients.
+//! Certificates and keys are read from PEM files. When a client CA is configured, servers only
+//! accept clients presenting a certificate it issued, and both the gRPC and HTTP services can
+//! identify the client by the common name of that certificate.
+
+pub mod error;
+
+use {
+    crate::{
+        config::{ClientTlsConfig, TlsConfig},
+        tls::error::TlsError,
+    },
+    axum::{Extension, middleware::AddExtension},
+    axum_server::{
+        accept::Accept,
+        tls_rustls::{RustlsAcceptor, RustlsConfig},
+    },
+    rustls::{
+        RootCertStore, ServerConfig,
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+        server::WebPkiClientVerifier,
+    },
+    std::{future::Future, io, pin::Pin, sync::Arc},
+    tokio::io::{AsyncRead, AsyncWrite},
+    tonic::{
+        Request,
+        transport::{Certificate, Endpoint, Identity, ServerTlsConfig},
+    },
+    tower::Layer,
+};
+
+fn read(path: &str) -> Result<Vec<u8>, TlsError> {
+    std::fs::read(path).map_err(|source| TlsError::Read {
+        path: path.to_string(),
+        source,
+    })
+}
+
+fn parse_certificates(path: &str, pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certificates = CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TlsError::Pem {
+            path: path.to_string(),
+            reason: e.to_string(),
+        })?;
+    if certificates.is_empty() {
+        return Err(TlsError::Pem {
+            path: path.to_string(),
+            reason: "no certificate found".to_string(),
+        });
+    }
+    Ok(certificates)
+}
+
+fn parse_private_key(path: &str, pem: &[u8]) -> Result<PrivateKeyDer<'static>, TlsError> {
+    PrivateKeyDer::from_pem_slice(pem).map_err(|e| TlsError::Pem {
+        path: path.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+/// Reads a PEM certificate file, checking that it holds at least one certificate.
+fn read_certificate_pem(path: &str) -> Result<Vec<u8>, TlsError> {
+    let pem = read(path)?;
+    parse_certificates(path, &pem)?;
+    Ok(pem)
+}
+
+/// Reads a certificate chain and its private key, checking both.
+fn read_identity(cert_path: &str, key_path: &str) -> Result<Identity, TlsError> {
+    let key = read(key_path)?;
+    parse_private_key(key_path, &key)?;
+    Ok(Identity::from_pem(read_certificate_pem(cert_path)?, key))
+}
+
+/// TLS settings of the gRPC server.
+pub fn grpc_server_tls(config: &TlsConfig) -> Result<ServerTlsConfig, TlsError> {
+    let mut tls =
+        ServerTlsConfig::new().identity(read_identity(&config.cert_path, &config.key_path)?);
+    if !config.client_ca_path.is_empty() {
+        tls = tls.client_ca_root(Certificate::from_pem(read_certificate_pem(
+            &config.client_ca_path,
+        )?));
+    }
+    Ok(tls)
+}
+
+/// TLS settings of the HTTP server.
+pub fn http_server_tls(config: &TlsConfig) -> Result<ServerConfig, TlsError> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = if config.client_ca_path.is_empty() {
+        builder.with_no_client_auth()
+    } else {
+        let mut roots = RootCertStore::empty();
+        let path = &config.client_ca_path;
+        for certificate in parse_certificates(path, &read(path)?)? {
+            roots.add(certificate)?;
+        }
+        let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()
+            .map_err(|e| TlsError::Config(e.to_string()))?;
+        builder.with_client_cert_verifier(verifier)
+    };
+
+    let mut server_config = builder.with_single_cert(
+        parse_certificates(&config.cert_path, &read(&config.cert_path)?)?,
+        parse_private_key(&config.key_path, &read(&config.key_path)?)?,
+    )?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(server_config)
+}
+
+/// Endpoint of a gRPC server, over TLS when configured.
+pub fn endpoint(
+    url: impl Into<String>,
+    tls: Option<&ClientTlsConfig>,
+) -> Result<Endpoint, TlsError> {
+    let endpoint = Endpoint::from_shared(url.into())?;
+    let Some(config) = tls else {
+        return Ok(endpoint);
+    };
+
+    let mut tls = tonic::transport::ClientTlsConfig::new();
+    if !config.ca_path.is_empty() {
+        tls = tls.ca_certificate(Certificate::from_pem(read_certificate_pem(
+            &config.ca_path,
+        )?));
+    }
+    match (config.cert_path.is_empty(), config.key_path.is_empty()) {
+        (true, true) => {}
+        (false, false) => {
+            tls = tls.identity(read_identity(&config.cert_path, &config.key_path)?);
+        }
+        _ => {
+            return Err(TlsError::Config(
+                "a client certificate needs both cert_path and key_path".to_string(),
+            ));
+        }
+    }
+    if !config.domain_name.is_empty() {
+        tls = tls.domain_name(&config.domain_name);
+    }
+    Ok(endpoint.tls_config(tls)?)
+}
+
+/// Common name of the certificate the client presented, when the server verifies client
+/// certificates.
+pub fn peer_common_name<T>(request: &Request<T>) -> Option<String> {
+    common_name(request.peer_certs()?.first()?)
+}
+
+fn common_name(certificate: &[u8]) -> Option<String> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate).ok()?;
+    let common_name = certificate.subject().iter_common_name().next()?;
+    common_name.as_str().ok().map(str::to_string)
+}
+
+/// Common name of the verified certificate an HTTPS client presented, if any, added to each
+/// of its requests by [`PeerCertificateAcceptor`].
+#[derive(Clone, Debug)]
+pub struct PeerCommonName(pub Option<String>);
+
+/// Accepts HTTPS connections like [`RustlsAcceptor`], adding the [`PeerCommonName`] of the
+/// client to the requests of the connection.
+#[derive(Clone)]
+pub struct PeerCertificateAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl PeerCertificateAcceptor {
+    pub fn new(config: RustlsConfig) -> Self {
+        PeerCertificateAcceptor {
+            inner: RustlsAcceptor::new(config),
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for PeerCertificateAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = <RustlsAcceptor as Accept<I, S>>::Stream;
+    type Service = AddExtension<S, PeerCommonName>;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.inner.clone();
+        Box::pin(async move {
+            let (stream, service) = acceptor.accept(stream, service).await?;
+            let peer = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|certificates| common_name(certificates.first()?));
+            Ok((stream, Extension(PeerCommonName(peer)).layer(service)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            auth::{AuthInterceptor, Authenticator, Scope, store::OwnershipStore},
+            config::{AuthClient, AuthConfig},
+            grpc_server::{
+                QuasarGrpcServer,
+                server::{
+                    GetBalanceRequest, grpc_service_client::GrpcServiceClient,
+                    grpc_service_server::GrpcServiceServer,
+                },
+            },
+            http_server::router,
+            ledger::Ledger,
+            models::{CreateAccountInstruction, Instruction, Transaction, TransactionStatus},
+            submission::SubmissionQueue,
+            transaction_processor::{
+                TransactionProcessor,
+                interface::{TransactionProcessorInterface, TransactionResult},
+            },
+        },
+        chrono::Utc,
+        dashmap::DashMap,
+        rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, DnType, IsCa, KeyPair},
+        std::path::{Path, PathBuf},
+        tokio_stream::wrappers::TcpListenerStream,
+        tonic::{Code, transport::Server},
+        uuid::Uuid,
+    };
+
+    /// Writes a CA, a server certificate for localhost and client certificates with the
+    /// given common names, returning the directory holding them.
+    fn write_certificates(client_names: &[&str]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("quasar-tls-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "Quasar test CA");
+        let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+        std::fs::write(directory.join("ca.pem"), ca.pem()).unwrap();
+
+        for (name, common_name, subject_alt_names) in
+            std::iter::once(("server", "localhost", vec!["localhost".to_string()]))
+                .chain(client_names.iter().map(|name| (*name, *name, vec![])))
+        {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(subject_alt_names).unwrap();
+            params
+                .distinguished_name
+                .push(DnType::CommonName, common_name);
+            let certificate = params.signed_by(&key, &ca).unwrap();
+            std::fs::write(directory.join(format!("{name}.pem")), certificate.pem()).unwrap();
+            std::fs::write(directory.join(format!("{name}.key")), key.serialize_pem()).unwrap();
+        }
+        directory
+    }
+
+    fn path(directory: &Path, file: &str) -> String {
+        directory.join(file).display().to_string()
+    }
+
+    fn client_tls(directory: &Path, client: Option<&str>) -> ClientTlsConfig {
+        ClientTlsConfig {
+            ca_path: path(directory, "ca.pem"),
+            cert_path: client
+                .map(|name| path(directory, &format!("{name}.pem")))
+                .unwrap_or_default(),
+            key_path: client
+                .map(|name| path(directory, &format!("{name}.key")))
+                .unwrap_or_default(),
+            domain_name: "localhost".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grpc_clients_authenticate_with_certificates() {
+        let directory = write_certificates(&["auditor.example", "intruder.example"]);
+        let server_tls = TlsConfig {
+            cert_path: path(&directory, "server.pem"),
+            key_path: path(&directory, "server.key"),
+            client_ca_path: path(&directory, "ca.pem"),
+        };
+        let account_id = Uuid::new_v4();
+        let config = AuthConfig {
+            enabled: true,
+            jwt_secret: String::new(),
+            clients: vec![AuthClient {
+                id: "auditor".to_string(),
+                scopes: vec![Scope::Read],
+                accounts: vec![account_id],
+                api_keys: vec![],
+                certificate_subjects: vec!["auditor.example".to_string()],
+            }],
+        };
+        let owners = Arc::new(OwnershipStore::open(":memory:").unwrap());
+        let authenticator = Arc::new(Authenticator::new(&config, owners).unwrap());
+
+        let processor = Arc::new(TransactionProcessor::new(
+            Arc::new(Ledger::default()),
+            DashMap::new(),
+        ));
+        let service = QuasarGrpcServer::new(processor, Arc::new(SubmissionQueue::new(8)))
+            .with_auth(authenticator.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            Server::builder()
+                .tls_config(grpc_server_tls(&server_tls).unwrap())
+                .unwrap()
+                .add_service(GrpcServiceServer::with_interceptor(
+                    service,
+                    AuthInterceptor(Some(authenticator)),
+                ))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let get_balance = || GetBalanceRequest {
+            transaction_id: Uuid::new_v4().to_string(),
+            account_id: account_id.to_string(),
+            ..Default::default()
+        };
+
+        // The certificate alone identifies the client.
+        let channel = endpoint(&url, Some(&client_tls(&directory, Some("auditor.example"))))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let response = GrpcServiceClient::new(channel)
+            .get_balance(get_balance())
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.success, "the account does not exist");
+
+        let channel = endpoint(
+            &url,
+            Some(&client_tls(&directory, Some("intruder.example"))),
+        )
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+        let status = GrpcServiceClient::new(channel)
+            .get_balance(get_balance())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        // Without a certificate the handshake fails.
+        let failed = match endpoint(&url, Some(&client_tls(&directory, None)))
+            .unwrap()
+            .connect()
+            .await
+        {
+            Ok(channel) => GrpcServiceClient::new(channel)
+                .get_balance(get_balance())
+                .await
+                .is_err(),
+            Err(_) => true,
+        };
+        assert!(failed);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http_clients_authenticate_with_certificates() {
+        let directory = write_certificates(&["auditor.example", "intruder.example"]);
+        let server_tls = TlsConfig {
+            cert_path: path(&directory, "server.pem"),
+            key_path: path(&directory, "server.key"),
+            client_ca_path: path(&directory, "ca.pem"),
+        };
+        let processor = Arc::new(TransactionProcessor::new(
+            Arc::new(Ledger::default()),
+            DashMap::new(),
+        ));
+        let created = processor
+            .process_transaction(Transaction {
+                id: Uuid::new_v4(),
+                instruction: Instruction::CreateAccount(CreateAccountInstruction::new(vec![])),
+                status: TransactionStatus::Pending,
+                timestamp: Utc::now(),
+            })
+            .unwrap();
+        let TransactionResult::AccountCreated(account_id) = created else {
+            panic!("expected an account");
+        };
+        let config = AuthConfig {
+            enabled: true,
+            jwt_secret: String::new(),
+            clients: vec![AuthClient {
+                id: "auditor".to_string(),
+                scopes: vec![Scope::Read],
+                accounts: vec![account_id],
+                api_keys: vec![],
+                certificate_subjects: vec!["auditor.example".to_string()],
+            }],
+        };
+        let owners = Arc::new(OwnershipStore::open(":memory:").unwrap());
+        let authenticator = Arc::new(Authenticator::new(&config, owners).unwrap());
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = axum_server::from_tcp(listener).acceptor(PeerCertificateAcceptor::new(
+            RustlsConfig::from_config(Arc::new(http_server_tls(&server_tls).unwrap())),
+        ));
+        tokio::spawn(server.serve(router(processor, Some(authenticator)).into_make_service()));
+
+        let list = |client: &str| {
+            let read = |file: &str| std::fs::read(directory.join(file)).unwrap();
+            let identity = reqwest::Identity::from_pem(
+                &[
+                    read(&format!("{client}.key")),
+                    read(&format!("{client}.pem")),
+                ]
+                .concat(),
+            )
+            .unwrap();
+            let client = reqwest::Client::builder()
+                .use_rustls_tls()
+                .add_root_certificate(reqwest::Certificate::from_pem(&read("ca.pem")).unwrap())
+                .identity(identity)
+                .build()
+                .unwrap();
+            client
+                .get(format!(
+                    "https://localhost:{port}/accounts/{account_id}/transactions"
+                ))
+                .send()
+        };
+
+        // The certificate alone identifies the client.
+        let listed = list("auditor.example").await.unwrap();
+        assert_eq!(listed.status(), reqwest::StatusCode::OK);
+        let rejected = list("intruder.example").await.unwrap();
+        assert_eq!(rejected.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http_server_serves_tls() {
+        let directory = write_certificates(&[]);
+        let tls = TlsConfig {
+            cert_path: path(&directory, "server.pem"),
+            key_path: path(&directory, "server.key"),
+            client_ca_path: String::new(),
+        };
+        let missing = TlsConfig {
+            cert_path: path(&directory, "missing.pem"),
+            ..tls.clone()
+        };
+        assert!(matches!(
+            http_server_tls(&missing),
+            Err(TlsError::Read { .. })
+        ));
+
+        let processor = Arc::new(TransactionProcessor::new(
+            Arc::new(Ledger::default()),
+            DashMap::new(),
+        ));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = axum_server::from_tcp_rustls(
+            listener,
+            RustlsConfig::from_config(Arc::new(http_server_tls(&tls).unwrap())),
+        );
+        tokio::spawn(server.serve(router(processor, None).into_make_service()));
+
+        let client = reqwest::Client::builder()
+            .add_root_certificate(
+                reqwest::Certificate::from_pem(&std::fs::read(directory.join("ca.pem")).unwrap())
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!(
+                "https://localhost:{port}/accounts/{}/transactions",
+                Uuid::new_v4()
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,13 +1,35 @@
-use {crate::ledger::error::LedgerError, thiserror::Error};
+use {
+    crate::{
+        disputes::error::DisputeError, events::error::EventLogError, fees::error::FeeError,
+        ledger::error::LedgerError, risk::error::RiskError,
+    },
+    thiserror::Error,
+};
 
 #[derive(Debug, Error)]
 pub enum TransactionProcessorError {
     #[error("Ledger error: {0}")]
     LedgerError(#[from] LedgerError),
+    #[error("Event log error: {0}")]
+    EventLogError(#[from] EventLogError),
+    #[error("Fee error: {0}")]
+    FeeError(#[from] FeeError),
     #[error("Transaction has already been processed")]
     TransactionAlreadyProcessed,
     #[error("Insufficient funds for the transaction")]
     InsufficientFunds,
     #[error("Failed to acquire ledger lock")]
     FailedToAcquireLedgerLock,
+    #[error("Risk error: {0}")]
+    RiskError(#[from] RiskError),
+    #[error("Transaction denied by risk rule {0}")]
+    Denied(String),
+    #[error("Transaction held for review by risk rule {0}")]
+    HeldForReview(String),
+    #[error("Dispute error: {0}")]
+    DisputeError(#[from] DisputeError),
+    #[error("Transaction rejected by {middleware}: {reason}")]
+    Rejected { middleware: String, reason: String },
+    #[error("Unknown middleware {0}")]
+    UnknownMiddleware(String),
 }
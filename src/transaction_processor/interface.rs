@@ -1,8 +1,18 @@
-use {crate::transaction_processor::error::TransactionProcessorError, uuid::Uuid};
+use {
+    crate::{fees::FeeCharge, transaction_processor::error::TransactionProcessorError},
+    chrono::{DateTime, Utc},
+    serde::{Deserialize, Serialize},
+    uuid::Uuid,
+};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TransactionResult {
-    Success,
+    Success {
+        fee: Option<FeeCharge>,
+        // Position and time of the transaction in the commit log.
+        sequence: u64,
+        committed_at: DateTime<Utc>,
+    },
     AccountCreated(Uuid),
     Balance(u64),
 }
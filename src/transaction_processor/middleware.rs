@@ -0,0 +1,356 @@
+use {
+    crate::{
+        metrics::{
+            ACCOUNT_CREATION_TIME_SECONDS, DEPOSIT_TIME_SECONDS, GET_BALANCE_TIME_SECONDS,
+            TRANSACTION_PROCESSING_TIME_SECONDS, TRANSACTIONS_PROCESSED_TOTAL,
+            TRANSFER_TIME_SECONDS,
+        },
+        models::{InstructionKind, Transaction},
+        transaction_processor::{error::TransactionProcessorError, interface::TransactionResult},
+    },
+    prometheus::Histogram,
+    std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    },
+    tracing::debug,
+};
+
+/// Hooks run around the execution of every instruction by the transaction processor.
+pub trait Middleware: Send + Sync {
+    /// Name the middleware is listed under in `execution.middleware`.
+    fn name(&self) -> &str;
+
+    /// Runs before the instruction is executed. An error rejects the transaction: neither the
+    /// instruction nor the middlewares after this one run.
+    fn before(&self, _transaction: &Transaction) -> Result<(), TransactionProcessorError> {
+        Ok(())
+    }
+
+    /// Runs with the outcome of the transaction and the time since it entered the chain. Only
+    /// middlewares whose `before` hook succeeded are called.
+    fn after(
+        &self,
+        _transaction: &Transaction,
+        _result: &Result<TransactionResult, TransactionProcessorError>,
+        _elapsed: Duration,
+    ) {
+    }
+}
+
+/// Middlewares wrapping instruction execution. Before hooks run in order and after hooks in
+/// reverse order, so the first middleware sees the whole of the others' work.
+#[derive(Clone, Default)]
+pub struct MiddlewareChain {
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl MiddlewareChain {
+    /// Resolves the configured names, looking them up among `custom` before the built-in
+    /// middlewares. Every custom middleware must be listed.
+    pub fn from_config(
+        names: &[String],
+        custom: Vec<Arc<dyn Middleware>>,
+    ) -> Result<Self, TransactionProcessorError> {
+        if let Some(unlisted) = custom.iter().find(|m| !names.iter().any(|n| n == m.name())) {
+            return Err(TransactionProcessorError::UnknownMiddleware(
+                unlisted.name().to_string(),
+            ));
+        }
+
+        let mut chain = MiddlewareChain::default();
+        for name in names {
+            let middleware = custom
+                .iter()
+                .find(|m| m.name() == name)
+                .cloned()
+                .or_else(|| builtin(name))
+                .ok_or_else(|| TransactionProcessorError::UnknownMiddleware(name.clone()))?;
+            chain.push(middleware);
+        }
+        Ok(chain)
+    }
+
+    /// Adds a middleware inside the ones already added.
+    pub fn push(&mut self, middleware: Arc<dyn Middleware>) {
+        self.middlewares.push(middleware);
+    }
+
+    /// Adds the middlewares of `other` inside the ones already added, in their order.
+    pub fn append(&mut self, mut other: MiddlewareChain) {
+        self.middlewares.append(&mut other.middlewares);
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.middlewares.iter().map(|m| m.name()).collect()
+    }
+
+    pub fn run(
+        &self,
+        transaction: &Transaction,
+        execute: impl FnOnce(&Transaction) -> Result<TransactionResult, TransactionProcessorError>,
+    ) -> Result<TransactionResult, TransactionProcessorError> {
+        let started_at = Instant::now();
+        let mut entered = 0;
+        let mut rejection = None;
+        for middleware in &self.middlewares {
+            if let Err(e) = middleware.before(transaction) {
+                rejection = Some(e);
+                break;
+            }
+            entered += 1;
+        }
+
+        let result = match rejection {
+            Some(e) => Err(e),
+            None => execute(transaction),
+        };
+
+        let elapsed = started_at.elapsed();
+        for middleware in self.middlewares[..entered].iter().rev() {
+            middleware.after(transaction, &result, elapsed);
+        }
+        result
+    }
+}
+
+/// The configurable middleware shipped with the processor named `name`.
+pub fn builtin(name: &str) -> Option<Arc<dyn Middleware>> {
+    match name {
+        LoggingMiddleware::NAME => Some(Arc::new(LoggingMiddleware)),
+        _ => None,
+    }
+}
+
+/// Counts transactions and records their processing time, overall and per instruction. The
+/// processor always runs it outside the configured middlewares, so it cannot be listed.
+pub struct MetricsMiddleware;
+
+impl MetricsMiddleware {
+    pub const NAME: &'static str = "metrics";
+}
+
+fn instruction_time(kind: InstructionKind) -> Option<&'static Histogram> {
+    match kind {
+        InstructionKind::Transfer => Some(&TRANSFER_TIME_SECONDS),
+        InstructionKind::CreateAccount => Some(&ACCOUNT_CREATION_TIME_SECONDS),
+        InstructionKind::Deposit => Some(&DEPOSIT_TIME_SECONDS),
+        InstructionKind::GetBalance => Some(&GET_BALANCE_TIME_SECONDS),
+        InstructionKind::Review | InstructionKind::OpenDispute | InstructionKind::UpdateDispute => {
+            None
+        }
+    }
+}
+
+impl Middleware for MetricsMiddleware {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn before(&self, _transaction: &Transaction) -> Result<(), TransactionProcessorError> {
+        TRANSACTIONS_PROCESSED_TOTAL.inc();
+        Ok(())
+    }
+
+    fn after(
+        &self,
+        transaction: &Transaction,
+        _result: &Result<TransactionResult, TransactionProcessorError>,
+        elapsed: Duration,
+    ) {
+        let seconds = elapsed.as_secs_f64();
+        TRANSACTION_PROCESSING_TIME_SECONDS.observe(seconds);
+        if let Some(histogram) = instruction_time(transaction.instruction.kind()) {
+            histogram.observe(seconds);
+        }
+    }
+}
+
+/// Logs the outcome of every transaction at debug level.
+pub struct LoggingMiddleware;
+
+impl LoggingMiddleware {
+    pub const NAME: &'static str = "logging";
+}
+
+impl Middleware for LoggingMiddleware {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn after(
+        &self,
+        transaction: &Transaction,
+        result: &Result<TransactionResult, TransactionProcessorError>,
+        elapsed: Duration,
+    ) {
+        let kind = transaction.instruction.kind();
+        match result {
+            Ok(_) => debug!("{:?} {} succeeded in {:?}", kind, transaction.id, elapsed),
+            Err(e) => debug!(
+                "{:?} {} failed in {:?}: {}",
+                kind, transaction.id, elapsed, e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::models::{GetBalanceInstruction, Instruction, TransactionStatus},
+        chrono::Utc,
+        std::sync::Mutex,
+        uuid::Uuid,
+    };
+
+    struct Recorder {
+        name: String,
+        calls: Arc<Mutex<Vec<String>>>,
+        reject: bool,
+    }
+
+    impl Middleware for Recorder {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn before(&self, _transaction: &Transaction) -> Result<(), TransactionProcessorError> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("before {}", self.name));
+            if self.reject {
+                return Err(TransactionProcessorError::Rejected {
+                    middleware: self.name.clone(),
+                    reason: "test".to_string(),
+                });
+            }
+            Ok(())
+        }
+
+        fn after(
+            &self,
+            _transaction: &Transaction,
+            result: &Result<TransactionResult, TransactionProcessorError>,
+            _elapsed: Duration,
+        ) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("after {} ok={}", self.name, result.is_ok()));
+        }
+    }
+
+    fn transaction() -> Transaction {
+        Transaction {
+            id: Uuid::new_v4(),
+            instruction: Instruction::GetBalance(GetBalanceInstruction {
+                account_id: Uuid::new_v4(),
+            }),
+            status: TransactionStatus::Pending,
+            timestamp: Utc::now(),
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    /// A chain of recorders named after `spec`, where a `!` suffix marks one rejecting every
+    /// transaction, and the calls they record.
+    fn recorders(spec: &[&str]) -> (MiddlewareChain, Arc<Mutex<Vec<String>>>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = MiddlewareChain::default();
+        for entry in spec {
+            let name = entry.trim_end_matches('!');
+            chain.push(Arc::new(Recorder {
+                name: name.to_string(),
+                calls: calls.clone(),
+                reject: entry.ends_with('!'),
+            }));
+        }
+        (chain, calls)
+    }
+
+    #[test]
+    fn test_hooks_wrap_execution_in_configured_order() {
+        let (chain, calls) = recorders(&["outer", "inner"]);
+        let result = chain.run(&transaction(), |_| {
+            calls.lock().unwrap().push("execute".to_string());
+            Ok(TransactionResult::Balance(0))
+        });
+
+        assert!(result.is_ok());
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                "before outer",
+                "before inner",
+                "execute",
+                "after inner ok=true",
+                "after outer ok=true"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejection_skips_execution_and_inner_middlewares() {
+        let (chain, calls) = recorders(&["outer", "guard!", "inner"]);
+        let result = chain.run(&transaction(), |_| unreachable!());
+
+        assert!(matches!(
+            result,
+            Err(TransactionProcessorError::Rejected { middleware, .. }) if middleware == "guard"
+        ));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["before outer", "before guard", "after outer ok=false"]
+        );
+    }
+
+    #[test]
+    fn test_execution_errors_reach_every_after_hook() {
+        let (chain, calls) = recorders(&["outer", "inner"]);
+        let result = chain.run(&transaction(), |_| {
+            Err(TransactionProcessorError::InsufficientFunds)
+        });
+
+        assert!(matches!(
+            result,
+            Err(TransactionProcessorError::InsufficientFunds)
+        ));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                "before outer",
+                "before inner",
+                "after inner ok=false",
+                "after outer ok=false"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_config_resolves_custom_and_builtin_names() {
+        let (custom, _) = recorders(&["inner", "outer"]);
+        let chain = MiddlewareChain::from_config(
+            &names(&["outer", "logging", "inner"]),
+            custom.middlewares,
+        )
+        .unwrap();
+        assert_eq!(chain.names(), vec!["outer", "logging", "inner"]);
+
+        assert!(matches!(
+            MiddlewareChain::from_config(&names(&["metrics"]), vec![]),
+            Err(TransactionProcessorError::UnknownMiddleware(name)) if name == "metrics"
+        ));
+        // Custom middlewares must be listed.
+        let (unlisted, _) = recorders(&["outer"]);
+        assert!(matches!(
+            MiddlewareChain::from_config(&names(&[]), unlisted.middlewares),
+            Err(TransactionProcessorError::UnknownMiddleware(name)) if name == "outer"
+        ));
+    }
+}
@@ -3,32 +3,55 @@
 
 pub mod error;
 pub mod interface;
+pub mod middleware;
 
 use {
     crate::{
+        auth::store::OwnershipStore,
+        disputes::{DisputeManager, error::DisputeError},
+        events::{CommittedTransaction, EventLog},
+        fees::{FeeCharge, FeeEngine, error::FeeError},
         ledger::interface::LedgerInterface,
         metrics::{
-            ACCOUNT_CREATION_TIME_SECONDS, DEPOSIT_TIME_SECONDS, GET_BALANCE_TIME_SECONDS,
-            TRANSACTION_PROCESSING_TIME_SECONDS, TRANSACTIONS_PROCESSED_TOTAL,
-            TRANSFER_TIME_SECONDS,
+            DISPUTES_EXPIRED_TOTAL, DISPUTES_OPENED_TOTAL, TRANSFERS_DENIED_TOTAL,
+            TRANSFERS_HELD_TOTAL,
         },
         models::{
-            CreateAccountInstruction, DepositInstruction, Instruction, Transaction,
-            TransferInstruction,
+            Account, CreateAccountInstruction, DepositInstruction, DisputeAction, Instruction,
+            InstructionKind, OpenDisputeInstruction, Posting, ReviewDecision, ReviewInstruction,
+            Transaction, TransactionStatus, TransferInstruction, UpdateDisputeInstruction,
         },
+        risk::{RiskDecision, RiskEngine, error::RiskError, store::ReviewStatus},
         transaction_processor::{
             error::TransactionProcessorError,
             interface::{TransactionProcessorInterface, TransactionResult},
+            middleware::{MetricsMiddleware, Middleware, MiddlewareChain},
         },
     },
+    chrono::{DateTime, Utc},
     dashmap::DashMap,
     std::sync::Arc,
+    tracing::error,
     uuid::Uuid,
 };
 
 pub struct TransactionProcessor {
     pub ledger: Arc<dyn LedgerInterface + Send + Sync>,
     pub transactions: DashMap<Uuid, Transaction>,
+    pub events: Arc<EventLog>,
+    fee_engine: Option<FeeEngine>,
+    risk_engine: Option<RiskEngine>,
+    disputes: Option<DisputeManager>,
+    owners: Option<Arc<OwnershipStore>>,
+    middleware: MiddlewareChain,
+}
+
+/// The chain run by the processor: processing metrics, outside of the configured middlewares.
+fn processing_chain(configured: MiddlewareChain) -> MiddlewareChain {
+    let mut chain = MiddlewareChain::default();
+    chain.push(Arc::new(MetricsMiddleware));
+    chain.append(configured);
+    chain
 }
 
 impl TransactionProcessor {
@@ -39,41 +62,396 @@ impl TransactionProcessor {
         TransactionProcessor {
             ledger,
             transactions,
+            events: Arc::new(EventLog::default()),
+            fee_engine: None,
+            risk_engine: None,
+            disputes: None,
+            owners: None,
+            middleware: processing_chain(MiddlewareChain::default()),
+        }
+    }
+
+    pub fn with_event_log(mut self, events: Arc<EventLog>) -> Self {
+        self.events = events;
+        self
+    }
+
+    pub fn with_fee_engine(mut self, fee_engine: FeeEngine) -> Self {
+        self.fee_engine = Some(fee_engine);
+        self
+    }
+
+    /// Screens transfers with the risk rules before they are committed.
+    pub fn with_risk_engine(mut self, risk_engine: RiskEngine) -> Self {
+        self.risk_engine = Some(risk_engine);
+        self
+    }
+
+    pub fn risk_engine(&self) -> Option<&RiskEngine> {
+        self.risk_engine.as_ref()
+    }
+
+    /// Accepts dispute instructions against committed transfers.
+    pub fn with_disputes(mut self, disputes: DisputeManager) -> Self {
+        self.disputes = Some(disputes);
+        self
+    }
+
+    pub fn disputes(&self) -> Option<&DisputeManager> {
+        self.disputes.as_ref()
+    }
+
+    /// Records the owners of the accounts created through the processor, or replicated to it.
+    pub fn with_owners(mut self, owners: Arc<OwnershipStore>) -> Self {
+        self.owners = Some(owners);
+        self
+    }
+
+    /// Records the owner of a committed account creation. The creation is not undone if that
+    /// fails, only left without an owner.
+    pub fn record_owner(&self, event: &CommittedTransaction) {
+        if let Some(owners) = &self.owners
+            && let Err(e) = owners.record(event)
+        {
+            error!(
+                "Failed to record the owner of account {:?}: {}",
+                event.created_account_id, e
+            );
+        }
+    }
+
+    /// Replaces the middlewares run around every instruction, none by default. Processing
+    /// metrics are always recorded, outside of them.
+    pub fn with_middleware_chain(mut self, middleware: MiddlewareChain) -> Self {
+        self.middleware = processing_chain(middleware);
+        self
+    }
+
+    /// Adds a middleware inside the ones already added.
+    pub fn with_middleware(mut self, middleware: Arc<dyn Middleware>) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    fn execute(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<TransactionResult, TransactionProcessorError> {
+        let (id, timestamp) = (transaction.id, transaction.timestamp);
+        match transaction.instruction.clone() {
+            Instruction::Transfer(inst) => self
+                .screen_transfer(id, timestamp, &inst)
+                .and_then(|()| self.process_transfer(id, timestamp, inst)),
+            Instruction::CreateAccount(inst) => self.process_create_account(id, timestamp, inst),
+            Instruction::Deposit(inst) => self.process_deposit(id, timestamp, inst),
+            Instruction::GetBalance(inst) => self.get_balance(inst.account_id),
+            Instruction::Review(inst) => self.process_review(id, timestamp, inst),
+            Instruction::OpenDispute(inst) => self.process_open_dispute(id, timestamp, inst),
+            Instruction::UpdateDispute(inst) => self.process_update_dispute(id, timestamp, inst),
         }
     }
 
+    /// Evaluates the fee owed by `payer_id` for an instruction moving `amount`.
+    fn evaluate_fee(
+        &self,
+        kind: InstructionKind,
+        payer_id: Uuid,
+        amount: u64,
+    ) -> Result<Option<FeeCharge>, TransactionProcessorError> {
+        let Some(fee_engine) = &self.fee_engine else {
+            return Ok(None);
+        };
+
+        let payer = self.ledger.get_account(payer_id)?;
+
+        Ok(fee_engine.evaluate(kind, payer.account_type, amount)?)
+    }
+
     fn process_transfer(
         &self,
         transaction_id: Uuid,
+        timestamp: DateTime<Utc>,
         instruction: TransferInstruction,
     ) -> Result<TransactionResult, TransactionProcessorError> {
         if self.ledger.is_transaction_processed(transaction_id)? {
             return Err(TransactionProcessorError::TransactionAlreadyProcessed);
         }
 
-        self.ledger
-            .transfer(
-                transaction_id,
-                instruction.source_account_id,
-                instruction.destination_account_id,
-                instruction.amount,
-            )
-            .map_err(TransactionProcessorError::LedgerError)?;
+        let fee = self.evaluate_fee(
+            InstructionKind::Transfer,
+            instruction.source_account_id,
+            instruction.amount,
+        )?;
+
+        let mut postings = vec![
+            Posting::debit(instruction.source_account_id, instruction.amount),
+            Posting::credit(instruction.destination_account_id, instruction.amount),
+        ];
+        if let Some(fee) = &fee {
+            postings.push(Posting::debit(instruction.source_account_id, fee.amount));
+            postings.push(Posting::credit(fee.revenue_account_id, fee.amount));
+        }
 
-        Ok(TransactionResult::Success)
+        let event = self.events.commit(
+            transaction_id,
+            timestamp,
+            Instruction::Transfer(instruction),
+            postings.clone(),
+            fee.clone(),
+            || -> Result<(), TransactionProcessorError> {
+                Ok(self.ledger.commit_postings(transaction_id, &postings)?)
+            },
+        )?;
+
+        Ok(TransactionResult::Success {
+            fee,
+            sequence: event.sequence,
+            committed_at: event.committed_at,
+        })
+    }
+
+    /// Runs the risk rules on a transfer, putting it in the review queue if a rule holds it.
+    /// A transfer already in the queue is not evaluated again.
+    fn screen_transfer(
+        &self,
+        transaction_id: Uuid,
+        timestamp: DateTime<Utc>,
+        instruction: &TransferInstruction,
+    ) -> Result<(), TransactionProcessorError> {
+        let Some(risk_engine) = &self.risk_engine else {
+            return Ok(());
+        };
+        if self.ledger.is_transaction_processed(transaction_id)? {
+            return Err(TransactionProcessorError::TransactionAlreadyProcessed);
+        }
+
+        if let Some(held) = risk_engine.reviews.get(transaction_id)? {
+            return Err(match held.status {
+                ReviewStatus::Pending => TransactionProcessorError::HeldForReview(held.rule),
+                ReviewStatus::Approved => TransactionProcessorError::TransactionAlreadyProcessed,
+                ReviewStatus::Rejected => RiskError::Rejected(transaction_id).into(),
+            });
+        }
+
+        match risk_engine.evaluate_transfer(instruction, timestamp, &*self.ledger, &self.events)? {
+            RiskDecision::Allow => Ok(()),
+            RiskDecision::Deny(rule) => {
+                TRANSFERS_DENIED_TOTAL.inc();
+                Err(TransactionProcessorError::Denied(rule))
+            }
+            RiskDecision::Hold(rule) => {
+                let transaction = Transaction {
+                    id: transaction_id,
+                    instruction: Instruction::Transfer(instruction.clone()),
+                    status: TransactionStatus::Pending,
+                    timestamp,
+                };
+                risk_engine.reviews.hold(&transaction, &rule)?;
+                TRANSFERS_HELD_TOTAL.inc();
+                Err(TransactionProcessorError::HeldForReview(rule))
+            }
+        }
+    }
+
+    /// Approves or rejects a held transfer. Approval commits it as submitted, without
+    /// evaluating the rules again; if the commit fails the transfer stays held.
+    fn process_review(
+        &self,
+        transaction_id: Uuid,
+        timestamp: DateTime<Utc>,
+        instruction: ReviewInstruction,
+    ) -> Result<TransactionResult, TransactionProcessorError> {
+        if self.ledger.is_transaction_processed(transaction_id)? {
+            return Err(TransactionProcessorError::TransactionAlreadyProcessed);
+        }
+
+        let held_id = instruction.held_transaction_id;
+        let Some(risk_engine) = &self.risk_engine else {
+            return Err(RiskError::UnknownReview(held_id).into());
+        };
+        let held = risk_engine
+            .reviews
+            .get(held_id)?
+            .ok_or(RiskError::UnknownReview(held_id))?;
+        if held.status != ReviewStatus::Pending {
+            return Err(RiskError::AlreadyReviewed(held_id).into());
+        }
+        let Instruction::Transfer(transfer) = held.transaction.instruction else {
+            return Err(RiskError::UnknownReview(held_id).into());
+        };
+
+        let status = match instruction.decision {
+            ReviewDecision::Approve => {
+                self.process_transfer(held_id, held.transaction.timestamp, transfer)?;
+                ReviewStatus::Approved
+            }
+            ReviewDecision::Reject => ReviewStatus::Rejected,
+        };
+        risk_engine.reviews.resolve(held_id, status)?;
+
+        let event = self.events.commit(
+            transaction_id,
+            timestamp,
+            Instruction::Review(instruction),
+            vec![],
+            None,
+            || -> Result<(), TransactionProcessorError> {
+                Ok(self.ledger.mark_transaction_processed(transaction_id)?)
+            },
+        )?;
+
+        Ok(TransactionResult::Success {
+            fee: None,
+            sequence: event.sequence,
+            committed_at: event.committed_at,
+        })
+    }
+
+    /// Opens a dispute against a committed transfer, blocking the disputed funds the payee
+    /// still holds in escrow.
+    fn process_open_dispute(
+        &self,
+        transaction_id: Uuid,
+        timestamp: DateTime<Utc>,
+        instruction: OpenDisputeInstruction,
+    ) -> Result<TransactionResult, TransactionProcessorError> {
+        if self.ledger.is_transaction_processed(transaction_id)? {
+            return Err(TransactionProcessorError::TransactionAlreadyProcessed);
+        }
+        let Some(disputes) = &self.disputes else {
+            return Err(DisputeError::Disabled.into());
+        };
+
+        // Looked up in the log, which only holds committed transactions and cannot be
+        // overwritten by a later submission reusing the ID.
+        let disputed_id = instruction.disputed_transaction_id;
+        let transfer = match self.events.find(disputed_id).map(|e| e.instruction.clone()) {
+            Some(Instruction::Transfer(transfer)) => transfer,
+            _ => return Err(DisputeError::NotATransfer(disputed_id).into()),
+        };
+        let payee = self.ledger.get_account(transfer.destination_account_id)?;
+        let (dispute, postings) = disputes.open(
+            transaction_id,
+            timestamp,
+            &instruction,
+            &transfer,
+            payee.balance,
+        )?;
+
+        // Stored first so a concurrent dispute of the same transfer fails, and dropped again if
+        // the block cannot be committed.
+        disputes.store.insert(&dispute)?;
+        let committed = self.events.commit(
+            transaction_id,
+            timestamp,
+            Instruction::OpenDispute(instruction),
+            postings.clone(),
+            None,
+            || self.commit_or_mark(transaction_id, &postings),
+        );
+        let event = match committed {
+            Ok(event) => event,
+            Err(e) => {
+                disputes.store.remove(dispute.id)?;
+                return Err(e);
+            }
+        };
+        DISPUTES_OPENED_TOTAL.inc();
+
+        Ok(TransactionResult::Success {
+            fee: None,
+            sequence: event.sequence,
+            committed_at: event.committed_at,
+        })
+    }
+
+    /// Moves a dispute forward, returning or releasing its blocked funds when it concludes.
+    fn process_update_dispute(
+        &self,
+        transaction_id: Uuid,
+        timestamp: DateTime<Utc>,
+        instruction: UpdateDisputeInstruction,
+    ) -> Result<TransactionResult, TransactionProcessorError> {
+        if self.ledger.is_transaction_processed(transaction_id)? {
+            return Err(TransactionProcessorError::TransactionAlreadyProcessed);
+        }
+        let dispute_id = instruction.dispute_id;
+        let Some(disputes) = &self.disputes else {
+            return Err(DisputeError::UnknownDispute(dispute_id).into());
+        };
+        let dispute = disputes
+            .store
+            .get(dispute_id)?
+            .ok_or(DisputeError::UnknownDispute(dispute_id))?;
+        let (status, postings) = disputes.update(&dispute, instruction.action, timestamp)?;
+
+        if !disputes
+            .store
+            .transition(dispute_id, dispute.status, status)?
+        {
+            return Err(DisputeError::InvalidTransition {
+                status: disputes
+                    .store
+                    .get(dispute_id)?
+                    .map_or(dispute.status, |current| current.status),
+                action: instruction.action,
+            }
+            .into());
+        }
+        let action = instruction.action;
+        let committed = self.events.commit(
+            transaction_id,
+            timestamp,
+            Instruction::UpdateDispute(instruction),
+            postings.clone(),
+            None,
+            || self.commit_or_mark(transaction_id, &postings),
+        );
+        let event = match committed {
+            Ok(event) => event,
+            Err(e) => {
+                disputes
+                    .store
+                    .transition(dispute_id, status, dispute.status)?;
+                return Err(e);
+            }
+        };
+        if action == DisputeAction::Expire {
+            DISPUTES_EXPIRED_TOTAL.inc();
+        }
+
+        Ok(TransactionResult::Success {
+            fee: None,
+            sequence: event.sequence,
+            committed_at: event.committed_at,
+        })
     }
 
     fn process_create_account(
         &self,
         transaction_id: Uuid,
+        timestamp: DateTime<Utc>,
         instruction: CreateAccountInstruction,
     ) -> Result<TransactionResult, TransactionProcessorError> {
         if self.ledger.is_transaction_processed(transaction_id)? {
             return Err(TransactionProcessorError::TransactionAlreadyProcessed);
         }
 
-        let created_account_id = self.ledger.create_account(instruction.keys)?;
-        self.ledger.mark_transaction_processed(transaction_id)?;
+        let created_account_id = Account::id_for(transaction_id);
+        let (keys, account_type) = (instruction.keys.clone(), instruction.account_type);
+        let event = self.events.commit(
+            transaction_id,
+            timestamp,
+            Instruction::CreateAccount(instruction),
+            vec![],
+            None,
+            || -> Result<(), TransactionProcessorError> {
+                self.ledger
+                    .create_account(transaction_id, keys, account_type)?;
+                Ok(self.ledger.mark_transaction_processed(transaction_id)?)
+            },
+        )?;
+        self.record_owner(&event);
 
         Ok(TransactionResult::AccountCreated(created_account_id))
     }
@@ -81,17 +459,70 @@ impl TransactionProcessor {
     fn process_deposit(
         &self,
         transaction_id: Uuid,
+        timestamp: DateTime<Utc>,
         instruction: DepositInstruction,
     ) -> Result<TransactionResult, TransactionProcessorError> {
         if self.ledger.is_transaction_processed(transaction_id)? {
             return Err(TransactionProcessorError::TransactionAlreadyProcessed);
         }
 
-        self.ledger
-            .deposit_into_account(instruction.destination_account_id, instruction.amount)?;
-        self.ledger.mark_transaction_processed(transaction_id)?;
+        let fee = self.evaluate_fee(
+            InstructionKind::Deposit,
+            instruction.destination_account_id,
+            instruction.amount,
+        )?;
+
+        // Deposit fees are deducted from the deposited amount rather than charged on top.
+        let postings = match &fee {
+            Some(fee) => {
+                let credited = instruction.amount.checked_sub(fee.amount).ok_or(
+                    FeeError::FeeExceedsAmount {
+                        fee: fee.amount,
+                        amount: instruction.amount,
+                    },
+                )?;
+
+                vec![
+                    Posting::credit(instruction.destination_account_id, credited),
+                    Posting::credit(fee.revenue_account_id, fee.amount),
+                ]
+            }
+            None => vec![Posting::credit(
+                instruction.destination_account_id,
+                instruction.amount,
+            )],
+        };
 
-        Ok(TransactionResult::Success)
+        let event = self.events.commit(
+            transaction_id,
+            timestamp,
+            Instruction::Deposit(instruction),
+            postings.clone(),
+            fee.clone(),
+            || -> Result<(), TransactionProcessorError> {
+                Ok(self.ledger.commit_postings(transaction_id, &postings)?)
+            },
+        )?;
+
+        Ok(TransactionResult::Success {
+            fee,
+            sequence: event.sequence,
+            committed_at: event.committed_at,
+        })
+    }
+
+    /// Commits the postings of a dispute step, or only marks it processed when it moves nothing.
+    fn commit_or_mark(
+        &self,
+        transaction_id: Uuid,
+        postings: &[Posting],
+    ) -> Result<(), TransactionProcessorError> {
+        if postings.is_empty() {
+            self.ledger.mark_transaction_processed(transaction_id)?;
+        } else {
+            self.ledger.commit_postings(transaction_id, postings)?;
+        }
+        Ok(())
     }
 
     fn get_balance(
@@ -111,29 +542,8 @@ impl TransactionProcessorInterface for TransactionProcessor {
     ) -> Result<TransactionResult, TransactionProcessorError> {
         self.transactions
             .insert(transaction.id, transaction.clone());
-        TRANSACTIONS_PROCESSED_TOTAL.inc();
-        measure!(TRANSACTION_PROCESSING_TIME_SECONDS, {
-            match transaction.instruction {
-                Instruction::Transfer(inst) => measure!(TRANSFER_TIME_SECONDS, {
-                    self.process_transfer(transaction.id, inst)
-                }),
-                Instruction::CreateAccount(inst) => {
-                    measure!(ACCOUNT_CREATION_TIME_SECONDS, {
-                        self.process_create_account(transaction.id, inst)
-                    })
-                }
-                Instruction::Deposit(deposit_instruction) => {
-                    measure!(DEPOSIT_TIME_SECONDS, {
-                        self.process_deposit(transaction.id, deposit_instruction)
-                    })
-                }
-                Instruction::GetBalance(get_balance_instruction) => {
-                    measure!(GET_BALANCE_TIME_SECONDS, {
-                        self.get_balance(get_balance_instruction.account_id)
-                    })
-                }
-            }
-        })
+        self.middleware
+            .run(&transaction, |transaction| self.execute(transaction))
     }
 }
 
@@ -142,8 +552,14 @@ mod tests {
     use {
         super::*,
         crate::{
+            disputes::{DisputeStatus, store::DisputeStore},
+            fees::{FeeRule, FeeSchedule},
             ledger::{Ledger, error::LedgerError},
-            models::{CreateAccountInstruction, Key, TransactionStatus},
+            models::{
+                AccountType, CreateAccountInstruction, GetBalanceInstruction, Key,
+                TransactionStatus,
+            },
+            risk::{RiskAction, RiskCheck, RiskRule, store::ReviewStore},
         },
         chrono::Utc,
         dashmap::{DashMap, DashSet},
@@ -159,8 +575,12 @@ mod tests {
         let ledger = Arc::new(Ledger::new(DashMap::new(), DashSet::new()));
         let processor = TransactionProcessor::new(ledger.clone(), DashMap::new());
 
-        let source_id = ledger.create_account(vec![]).unwrap();
-        let dest_id = ledger.create_account(vec![]).unwrap();
+        let source_id = ledger
+            .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+            .unwrap();
+        let dest_id = ledger
+            .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+            .unwrap();
 
         let mut source_account = ledger.get_account(source_id).unwrap();
 
@@ -186,6 +606,8 @@ mod tests {
             id: Uuid::new_v4(),
             instruction: Instruction::CreateAccount(CreateAccountInstruction {
                 keys: vec![Key::Email("test@test.com".to_string())],
+                account_type: AccountType::Personal,
+                owner: None,
             }),
             timestamp: Utc::now(),
             status: TransactionStatus::Pending,
@@ -244,4 +666,266 @@ mod tests {
             TransactionProcessorError::LedgerError(LedgerError::InsufficientFunds)
         ));
     }
+
+    #[test]
+    fn test_process_transfer_with_fee() {
+        let ledger = Arc::new(Ledger::new(DashMap::new(), DashSet::new()));
+        let revenue_id = Uuid::new_v4();
+        ledger
+            .ensure_account(revenue_id, AccountType::Revenue)
+            .unwrap();
+
+        let fee_engine = FeeEngine::new(
+            revenue_id,
+            vec![FeeRule {
+                name: "transfer".to_string(),
+                instruction: Some(InstructionKind::Transfer),
+                account_type: None,
+                schedule: FeeSchedule::Flat { amount: 10 },
+            }],
+        );
+        let processor =
+            TransactionProcessor::new(ledger.clone(), DashMap::new()).with_fee_engine(fee_engine);
+
+        let source_id = ledger
+            .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+            .unwrap();
+        let dest_id = ledger
+            .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+            .unwrap();
+        ledger.accounts.get_mut(&source_id).unwrap().balance = 105;
+
+        let transfer = |amount| Transaction {
+            id: Uuid::new_v4(),
+            instruction: Instruction::Transfer(TransferInstruction {
+                source_account_id: source_id,
+                destination_account_id: dest_id,
+                amount,
+            }),
+            timestamp: Utc::now(),
+            status: TransactionStatus::Pending,
+        };
+
+        // Enough for the amount but not for the fee: nothing must be moved.
+        let result = processor.process_transaction(transfer(100));
+        assert!(matches!(
+            result.err().unwrap(),
+            TransactionProcessorError::LedgerError(LedgerError::InsufficientFunds)
+        ));
+        assert_eq!(ledger.get_account(source_id).unwrap().balance, 105);
+
+        let Ok(TransactionResult::Success { fee, sequence, .. }) =
+            processor.process_transaction(transfer(90))
+        else {
+            panic!("transfer failed");
+        };
+        assert_eq!(
+            fee,
+            Some(FeeCharge {
+                amount: 10,
+                revenue_account_id: revenue_id,
+                rule: "transfer".to_string(),
+            })
+        );
+        assert_eq!(sequence, 1);
+        assert_eq!(ledger.get_account(source_id).unwrap().balance, 5);
+        assert_eq!(ledger.get_account(dest_id).unwrap().balance, 90);
+        assert_eq!(ledger.get_account(revenue_id).unwrap().balance, 10);
+    }
+
+    #[test]
+    fn test_held_transfers_wait_for_review() {
+        let ledger = Arc::new(Ledger::new(DashMap::new(), DashSet::new()));
+        let reviews = Arc::new(ReviewStore::open(":memory:").unwrap());
+        let risk_engine = RiskEngine::new(reviews.clone()).with_rules(vec![RiskRule {
+            name: "new-payee".to_string(),
+            action: RiskAction::Hold,
+            check: RiskCheck::NewPayee { max_amount: 50 },
+        }]);
+        let processor =
+            TransactionProcessor::new(ledger.clone(), DashMap::new()).with_risk_engine(risk_engine);
+
+        let source_id = ledger
+            .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+            .unwrap();
+        let dest_id = ledger
+            .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+            .unwrap();
+        ledger.accounts.get_mut(&source_id).unwrap().balance = 1000;
+
+        let transaction = |instruction| Transaction {
+            id: Uuid::new_v4(),
+            instruction,
+            timestamp: Utc::now(),
+            status: TransactionStatus::Pending,
+        };
+        let transfer = |amount| {
+            transaction(Instruction::Transfer(TransferInstruction {
+                source_account_id: source_id,
+                destination_account_id: dest_id,
+                amount,
+            }))
+        };
+        let review = |held_transaction_id, decision| {
+            transaction(Instruction::Review(ReviewInstruction {
+                held_transaction_id,
+                decision,
+            }))
+        };
+
+        // Held, and still held when submitted again.
+        let held = transfer(100);
+        for _ in 0..2 {
+            assert!(matches!(
+                processor.process_transaction(held.clone()),
+                Err(TransactionProcessorError::HeldForReview(rule)) if rule == "new-payee"
+            ));
+        }
+        let rejected = transfer(200);
+        assert!(processor.process_transaction(rejected.clone()).is_err());
+        assert_eq!(ledger.get_account(source_id).unwrap().balance, 1000);
+        assert_eq!(reviews.pending().unwrap().len(), 2);
+
+        processor
+            .process_transaction(review(held.id, ReviewDecision::Approve))
+            .unwrap();
+        assert_eq!(ledger.get_account(dest_id).unwrap().balance, 100);
+        assert_eq!(reviews.pending().unwrap().len(), 1);
+        assert!(matches!(
+            processor.process_transaction(review(held.id, ReviewDecision::Reject)),
+            Err(TransactionProcessorError::RiskError(
+                RiskError::AlreadyReviewed(_)
+            ))
+        ));
+
+        // The payee is known now, but a rejected transfer stays rejected.
+        assert!(processor.process_transaction(transfer(200)).is_ok());
+        processor
+            .process_transaction(review(rejected.id, ReviewDecision::Reject))
+            .unwrap();
+        assert!(matches!(
+            processor.process_transaction(rejected),
+            Err(TransactionProcessorError::RiskError(RiskError::Rejected(_)))
+        ));
+        assert_eq!(ledger.get_account(dest_id).unwrap().balance, 300);
+    }
+
+    #[test]
+    fn test_disputes_block_and_return_funds() {
+        let ledger = Arc::new(Ledger::new(DashMap::new(), DashSet::new()));
+        let escrow_id = Uuid::new_v4();
+        ledger
+            .ensure_account(escrow_id, AccountType::Escrow)
+            .unwrap();
+        let store = Arc::new(DisputeStore::open(":memory:").unwrap());
+        let processor = TransactionProcessor::new(ledger.clone(), DashMap::new()).with_disputes(
+            DisputeManager::new(escrow_id, chrono::Duration::hours(1), store.clone()),
+        );
+
+        let source_id = ledger
+            .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+            .unwrap();
+        let dest_id = ledger
+            .create_account(Uuid::new_v4(), vec![], AccountType::Personal)
+            .unwrap();
+        ledger.accounts.get_mut(&source_id).unwrap().balance = 1000;
+        let balance = |id| ledger.get_account(id).unwrap().balance;
+
+        let transaction = |instruction| Transaction {
+            id: Uuid::new_v4(),
+            instruction,
+            timestamp: Utc::now(),
+            status: TransactionStatus::Pending,
+        };
+        let transfer = |source_account_id, destination_account_id, amount| {
+            transaction(Instruction::Transfer(TransferInstruction {
+                source_account_id,
+                destination_account_id,
+                amount,
+            }))
+        };
+        let open = |disputed_transaction_id, amount| {
+            transaction(Instruction::OpenDispute(OpenDisputeInstruction {
+                disputed_transaction_id,
+                amount,
+            }))
+        };
+        let update = |dispute_id, action| {
+            transaction(Instruction::UpdateDispute(UpdateDisputeInstruction {
+                dispute_id,
+                action,
+            }))
+        };
+
+        let disputed = transfer(source_id, dest_id, 500);
+        processor.process_transaction(disputed.clone()).unwrap();
+        // Reusing the transfer's ID for another instruction does not hide the transfer.
+        let _ = processor.process_transaction(Transaction {
+            id: disputed.id,
+            ..transaction(Instruction::GetBalance(GetBalanceInstruction {
+                account_id: dest_id,
+            }))
+        });
+        // The payee spends part of the money before the dispute is opened.
+        processor
+            .process_transaction(transfer(dest_id, source_id, 200))
+            .unwrap();
+
+        assert!(matches!(
+            processor.process_transaction(open(disputed.id, 501)),
+            Err(TransactionProcessorError::DisputeError(
+                DisputeError::InvalidAmount { .. }
+            ))
+        ));
+        let dispute = open(disputed.id, 400);
+        processor.process_transaction(dispute.clone()).unwrap();
+        assert_eq!(balance(dest_id), 0);
+        assert_eq!(balance(escrow_id), 300);
+        assert!(matches!(
+            processor.process_transaction(open(disputed.id, 100)),
+            Err(TransactionProcessorError::DisputeError(
+                DisputeError::AlreadyDisputed(_)
+            ))
+        ));
+
+        processor
+            .process_transaction(update(dispute.id, DisputeAction::StartAnalysis))
+            .unwrap();
+        assert!(matches!(
+            processor.process_transaction(update(dispute.id, DisputeAction::Expire)),
+            Err(TransactionProcessorError::DisputeError(
+                DisputeError::DeadlineNotReached(_)
+            ))
+        ));
+        processor
+            .process_transaction(update(dispute.id, DisputeAction::Return))
+            .unwrap();
+        assert_eq!(balance(escrow_id), 0);
+        assert_eq!(balance(source_id), 1000);
+        assert_eq!(
+            store.get(dispute.id).unwrap().unwrap().status,
+            DisputeStatus::Returned
+        );
+        assert!(matches!(
+            processor.process_transaction(update(dispute.id, DisputeAction::Release)),
+            Err(TransactionProcessorError::DisputeError(
+                DisputeError::InvalidTransition { .. }
+            ))
+        ));
+        assert!(store.active().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_configured_middlewares_run_inside_metrics() {
+        let (processor, _, _, _) = setup_for_transfer();
+        assert_eq!(processor.middleware.names(), vec![MetricsMiddleware::NAME]);
+
+        let mut chain = MiddlewareChain::default();
+        chain.push(Arc::new(middleware::LoggingMiddleware));
+        let processor = processor.with_middleware_chain(chain);
+        assert_eq!(
+            processor.middleware.names(),
+            vec![MetricsMiddleware::NAME, middleware::LoggingMiddleware::NAME]
+        );
+    }
 }
@@ -0,0 +1,383 @@
+use {
+    crate::{
+        config::WebhooksConfig,
+        events::{CommittedTransaction, EventLog},
+        metrics::{
+            WEBHOOK_DELIVERIES_DEAD_TOTAL, WEBHOOK_DELIVERIES_FAILED_TOTAL,
+            WEBHOOK_DELIVERIES_SUCCEEDED_TOTAL, WEBHOOK_DELIVERY_TIME_SECONDS,
+            WEBHOOK_OUTBOX_DEPTH,
+        },
+        models::PostingKind,
+        webhooks::{
+            SIGNATURE_HEADER, WebhookEventType, WebhookPayload,
+            error::WebhookError,
+            sign,
+            store::{NewDelivery, OutboxEntry, WebhookStore},
+        },
+    },
+    chrono::Utc,
+    reqwest::Client,
+    std::{collections::BTreeMap, sync::Arc, time::Duration},
+    tokio::{sync::broadcast, time::interval},
+    tracing::{debug, error, info, warn},
+    uuid::Uuid,
+};
+
+const DELIVERY_BATCH_SIZE: usize = 100;
+
+pub struct WebhookDispatcher {
+    config: WebhooksConfig,
+    store: Arc<WebhookStore>,
+    client: Client,
+}
+
+impl WebhookDispatcher {
+    pub fn new(config: WebhooksConfig, store: Arc<WebhookStore>) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_millis(config.request_timeout_ms))
+            .build()
+            .unwrap_or_default();
+
+        WebhookDispatcher {
+            config,
+            store,
+            client,
+        }
+    }
+
+    /// Turns every committed transaction after the stored cursor into outbox entries.
+    pub fn enqueue_new_events(&self, events: &EventLog) -> Result<(), WebhookError> {
+        let from = match self.store.cursor()? {
+            None => 1,
+            Some((sequence, transaction_id)) => match events.get(sequence) {
+                Some(event) if event.transaction_id == transaction_id => sequence + 1,
+                // The log the cursor points into was not kept, as with the in-memory backend:
+                // every entry of the current one is new.
+                _ => {
+                    warn!(
+                        "Webhook cursor at sequence {} does not match the event log, starting over",
+                        sequence
+                    );
+                    1
+                }
+            },
+        };
+        for event in events.since(from) {
+            let deliveries = self.deliveries_for(&event)?;
+            self.store
+                .enqueue(&deliveries, event.sequence, event.transaction_id)?;
+        }
+        Ok(())
+    }
+
+    fn deliveries_for(
+        &self,
+        event: &CommittedTransaction,
+    ) -> Result<Vec<NewDelivery>, WebhookError> {
+        // Several postings on the same account and direction (e.g. amount and fee) are
+        // notified as a single movement.
+        let mut movements: BTreeMap<(Uuid, bool), u64> = BTreeMap::new();
+        for posting in &event.postings {
+            let received = posting.kind == PostingKind::Credit;
+            *movements.entry((posting.account_id, received)).or_default() += posting.amount;
+        }
+
+        let mut deliveries = Vec::new();
+        for ((account_id, received), amount) in movements {
+            let event_type = if received {
+                WebhookEventType::MoneyReceived
+            } else {
+                WebhookEventType::MoneySent
+            };
+
+            for webhook in self.store.list(Some(account_id))? {
+                if !webhook.event_types.contains(&event_type) {
+                    continue;
+                }
+
+                let payload = WebhookPayload {
+                    delivery_id: Uuid::new_v4(),
+                    webhook_id: webhook.id,
+                    event_type,
+                    account_id,
+                    transaction_id: event.transaction_id,
+                    sequence: event.sequence,
+                    amount,
+                    committed_at: event.committed_at,
+                };
+                deliveries.push(NewDelivery {
+                    id: payload.delivery_id,
+                    webhook_id: webhook.id,
+                    payload: serde_json::to_string(&payload)?,
+                });
+            }
+        }
+        Ok(deliveries)
+    }
+
+    /// Attempts every delivery that is due.
+    pub async fn deliver_due(&self) -> Result<(), WebhookError> {
+        for entry in self.store.due(Utc::now(), DELIVERY_BATCH_SIZE)? {
+            let attempts = entry.attempts + 1;
+            let result = measure!(WEBHOOK_DELIVERY_TIME_SECONDS, {
+                self.deliver(&entry).await
+            });
+
+            match result {
+                Ok(()) => {
+                    WEBHOOK_DELIVERIES_SUCCEEDED_TOTAL.inc();
+                    debug!("Delivered webhook {} to {}", entry.id, entry.url);
+                    self.store.mark_delivered(entry.id)?;
+                }
+                Err(e) if attempts >= self.config.max_attempts => {
+                    WEBHOOK_DELIVERIES_DEAD_TOTAL.inc();
+                    error!(
+                        "Giving up on webhook {} to {} after {} attempts: {}",
+                        entry.id, entry.url, attempts, e
+                    );
+                    self.store
+                        .mark_failed(entry.id, attempts, None, &e.to_string())?;
+                }
+                Err(e) => {
+                    WEBHOOK_DELIVERIES_FAILED_TOTAL.inc();
+                    let backoff = self.backoff(attempts);
+                    warn!(
+                        "Webhook {} to {} failed (attempt {}), retrying in {:?}: {}",
+                        entry.id, entry.url, attempts, backoff, e
+                    );
+                    let next_attempt_at = Utc::now()
+                        + chrono::Duration::from_std(backoff).unwrap_or(chrono::Duration::MAX);
+                    self.store.mark_failed(
+                        entry.id,
+                        attempts,
+                        Some(next_attempt_at),
+                        &e.to_string(),
+                    )?;
+                }
+            }
+        }
+
+        WEBHOOK_OUTBOX_DEPTH.set(self.store.pending_count()? as f64);
+        Ok(())
+    }
+
+    async fn deliver(&self, entry: &OutboxEntry) -> Result<(), WebhookError> {
+        let response = self
+            .client
+            .post(&entry.url)
+            .header("Content-Type", "application/json")
+            .header(
+                SIGNATURE_HEADER,
+                sign(&entry.secret, entry.payload.as_bytes()),
+            )
+            .body(entry.payload.clone())
+            .send()
+            .await
+            .map_err(|e| WebhookError::Delivery(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(WebhookError::Delivery(format!(
+                "receiver answered {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Exponential backoff after the given number of failed attempts.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+        Duration::from_millis(
+            self.config
+                .initial_backoff_ms
+                .saturating_mul(factor)
+                .min(self.config.max_backoff_ms),
+        )
+    }
+}
+
+pub async fn start_webhook_dispatcher(
+    dispatcher: WebhookDispatcher,
+    events: Arc<EventLog>,
+    mut shutdown_receiver: broadcast::Receiver<()>,
+) {
+    // The receiver is only used as a wake-up signal: events are always read from the log
+    // starting at the stored cursor, so a lagging receiver loses nothing.
+    let mut receiver = events.subscribe(None).receiver;
+    let mut ticker = interval(Duration::from_millis(dispatcher.config.poll_interval_ms));
+
+    info!("Webhook dispatcher initialized");
+
+    loop {
+        tokio::select! {
+            result = receiver.recv() => {
+                if let Err(broadcast::error::RecvError::Closed) = result {
+                    break;
+                }
+                if let Err(e) = dispatcher.enqueue_new_events(&events) {
+                    error!("Failed to enqueue webhook deliveries: {}", e);
+                }
+            }
+            _ = ticker.tick() => {
+                if let Err(e) = dispatcher.enqueue_new_events(&events) {
+                    error!("Failed to enqueue webhook deliveries: {}", e);
+                }
+                if let Err(e) = dispatcher.deliver_due().await {
+                    error!("Failed to deliver webhooks: {}", e);
+                }
+            }
+            _ = shutdown_receiver.recv() => {
+                info!("Shutting down webhook dispatcher...");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            models::{DepositInstruction, Instruction, Posting},
+            webhooks::verify_signature,
+        },
+        axum::{Router, body::Bytes, http::HeaderMap, routing::post},
+        tokio::sync::mpsc,
+    };
+
+    fn config() -> WebhooksConfig {
+        WebhooksConfig {
+            enabled: true,
+            max_attempts: 3,
+            initial_backoff_ms: 60_000,
+            max_backoff_ms: 600_000,
+            poll_interval_ms: 100,
+            request_timeout_ms: 1_000,
+        }
+    }
+
+    fn deposit(events: &EventLog, account_id: Uuid, amount: u64) {
+        events
+            .append(
+                Uuid::new_v4(),
+                Instruction::Deposit(DepositInstruction {
+                    destination_account_id: account_id,
+                    amount,
+                }),
+                vec![Posting::credit(account_id, amount)],
+                None,
+            )
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delivers_signed_payload() {
+        let (sender, mut received) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: Bytes| async move {
+                let signature = headers[SIGNATURE_HEADER].to_str().unwrap().to_string();
+                sender.send((signature, body)).unwrap();
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let store = Arc::new(WebhookStore::open(":memory:").unwrap());
+        let account_id = Uuid::new_v4();
+        store
+            .register(
+                account_id,
+                format!("http://{address}/hook"),
+                "secret".to_string(),
+                vec![WebhookEventType::MoneyReceived],
+            )
+            .unwrap();
+
+        let events = EventLog::default();
+        deposit(&events, account_id, 250);
+        deposit(&events, Uuid::new_v4(), 100);
+
+        let dispatcher = WebhookDispatcher::new(config(), store.clone());
+        dispatcher.enqueue_new_events(&events).unwrap();
+        dispatcher.deliver_due().await.unwrap();
+
+        let (signature, body) = received.recv().await.unwrap();
+        assert!(verify_signature("secret", &body, &signature));
+        assert!(!verify_signature("other", &body, &signature));
+
+        let payload: WebhookPayload = serde_json::from_slice(&body).unwrap();
+        assert_eq!(payload.account_id, account_id);
+        assert_eq!(payload.amount, 250);
+        assert_eq!(payload.event_type, WebhookEventType::MoneyReceived);
+        assert!(received.try_recv().is_err());
+        assert_eq!(store.pending_count().unwrap(), 0);
+        assert_eq!(
+            store.cursor().unwrap(),
+            Some((2, events.get(2).unwrap().transaction_id))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery_is_rescheduled() {
+        // Grab a free port and close it so the delivery is refused.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let store = Arc::new(WebhookStore::open(":memory:").unwrap());
+        let account_id = Uuid::new_v4();
+        store
+            .register(
+                account_id,
+                format!("http://{address}/hook"),
+                "secret".to_string(),
+                vec![WebhookEventType::MoneyReceived],
+            )
+            .unwrap();
+
+        let events = EventLog::default();
+        deposit(&events, account_id, 10);
+
+        let dispatcher = WebhookDispatcher::new(config(), store.clone());
+        dispatcher.enqueue_new_events(&events).unwrap();
+        dispatcher.deliver_due().await.unwrap();
+
+        // Still pending, but not due again until the backoff elapses.
+        assert_eq!(store.pending_count().unwrap(), 1);
+        assert!(store.due(Utc::now(), 10).unwrap().is_empty());
+        assert_eq!(dispatcher.backoff(1), Duration::from_secs(60));
+        assert_eq!(dispatcher.backoff(2), Duration::from_secs(120));
+        assert_eq!(dispatcher.backoff(10), Duration::from_secs(600));
+    }
+
+    #[test]
+    fn test_events_of_a_new_log_are_not_skipped() {
+        let store = Arc::new(WebhookStore::open(":memory:").unwrap());
+        let account_id = Uuid::new_v4();
+        store
+            .register(
+                account_id,
+                "http://127.0.0.1:1/hook".to_string(),
+                "secret".to_string(),
+                vec![WebhookEventType::MoneyReceived],
+            )
+            .unwrap();
+        let dispatcher = WebhookDispatcher::new(config(), store.clone());
+
+        let events = EventLog::default();
+        deposit(&events, account_id, 10);
+        deposit(&events, account_id, 20);
+        dispatcher.enqueue_new_events(&events).unwrap();
+        dispatcher.enqueue_new_events(&events).unwrap();
+        assert_eq!(store.pending_count().unwrap(), 2);
+
+        // A log that was not kept restarts at sequence 1 under the stored cursor.
+        let restarted = EventLog::default();
+        deposit(&restarted, account_id, 30);
+        dispatcher.enqueue_new_events(&restarted).unwrap();
+        assert_eq!(store.pending_count().unwrap(), 3);
+    }
+}
@@ -0,0 +1,15 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum WebhookError {
+    #[error("Webhook store error: {0}")]
+    Store(#[from] rusqlite::Error),
+    #[error("Failed to encode webhook data: {0}")]
+    Encoding(#[from] serde_json::Error),
+    #[error("Webhook not found")]
+    NotFound,
+    #[error("Invalid webhook URL: {0}")]
+    InvalidUrl(String),
+    #[error("Delivery failed: {0}")]
+    Delivery(String),
+}
@@ -0,0 +1,70 @@
+//! Webhook notifications for account activity.
+//! Registrations and pending deliveries live in SQLite so notifications survive restarts.
+//! The dispatcher turns committed transactions into outbox entries and delivers them as
+//! HMAC-signed JSON POST requests, retrying with exponential backoff.
+
+pub mod dispatcher;
+pub mod error;
+pub mod store;
+
+use {
+    chrono::{DateTime, Utc},
+    hmac::{Hmac, Mac},
+    serde::{Deserialize, Serialize},
+    sha2::Sha256,
+    uuid::Uuid,
+};
+
+/// Header carrying the hex encoded HMAC-SHA256 of the request body.
+pub const SIGNATURE_HEADER: &str = "X-Quasar-Signature";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventType {
+    /// Money was credited to the account.
+    MoneyReceived,
+    /// Money was debited from the account.
+    MoneySent,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub account_id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub event_types: Vec<WebhookEventType>,
+}
+
+/// JSON body POSTed to the webhook URL.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub delivery_id: Uuid,
+    pub webhook_id: Uuid,
+    pub event_type: WebhookEventType,
+    pub account_id: Uuid,
+    pub transaction_id: Uuid,
+    pub sequence: u64,
+    pub amount: u64,
+    pub committed_at: DateTime<Utc>,
+}
+
+/// Signs a payload with the webhook secret, producing the value of [`SIGNATURE_HEADER`].
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Checks a signature produced by [`sign`] in constant time. Used by receivers.
+pub fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Some(Ok(expected)) = signature.strip_prefix("sha256=").map(hex::decode) else {
+        return false;
+    };
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
@@ -0,0 +1,333 @@
+use {
+    crate::webhooks::{Webhook, WebhookEventType, error::WebhookError},
+    chrono::{DateTime, Utc},
+    rusqlite::{Connection, OptionalExtension, params},
+    std::sync::{Mutex, MutexGuard},
+    uuid::Uuid,
+};
+
+/// A pending delivery, joined with the registration it targets.
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub payload: String,
+    pub attempts: u32,
+}
+
+/// A delivery to be added to the outbox.
+#[derive(Debug, Clone)]
+pub struct NewDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub payload: String,
+}
+
+/// SQLite-backed webhook registrations and delivery outbox. Uses its own connection so
+/// outbox writes are durable immediately, independently of the ledger state checkpoints.
+pub struct WebhookStore {
+    conn: Mutex<Connection>,
+}
+
+impl WebhookStore {
+    pub fn open(db_path: &str) -> Result<Self, WebhookError> {
+        let conn = Connection::open(db_path)?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        let store = WebhookStore {
+            conn: Mutex::new(conn),
+        };
+        store.init_db()?;
+        Ok(store)
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn init_db(&self) -> Result<(), WebhookError> {
+        let conn = self.conn();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS webhooks (
+                id TEXT PRIMARY KEY,
+                account_id TEXT NOT NULL,
+                url TEXT NOT NULL,
+                secret TEXT NOT NULL,
+                event_types TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS webhook_outbox (
+                id TEXT PRIMARY KEY,
+                webhook_id TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                attempts INTEGER NOT NULL,
+                next_attempt_at INTEGER NOT NULL,
+                dead INTEGER NOT NULL,
+                last_error TEXT
+            )",
+            [],
+        )?;
+        // Sequence number and ID of the last committed transaction turned into outbox entries.
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS webhook_cursor (
+                id INTEGER PRIMARY KEY CHECK (id = 0),
+                sequence INTEGER NOT NULL,
+                transaction_id TEXT NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    pub fn register(
+        &self,
+        account_id: Uuid,
+        url: String,
+        secret: String,
+        event_types: Vec<WebhookEventType>,
+    ) -> Result<Webhook, WebhookError> {
+        let parsed =
+            reqwest::Url::parse(&url).map_err(|e| WebhookError::InvalidUrl(e.to_string()))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(WebhookError::InvalidUrl(format!(
+                "unsupported scheme {}",
+                parsed.scheme()
+            )));
+        }
+
+        let webhook = Webhook {
+            id: Uuid::new_v4(),
+            account_id,
+            url,
+            secret,
+            event_types,
+        };
+
+        self.conn().execute(
+            "INSERT INTO webhooks (id, account_id, url, secret, event_types) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                webhook.id.to_string(),
+                webhook.account_id.to_string(),
+                webhook.url,
+                webhook.secret,
+                serde_json::to_string(&webhook.event_types)?,
+            ],
+        )?;
+
+        Ok(webhook)
+    }
+
+    /// Removes a registration together with its pending deliveries.
+    pub fn delete(&self, id: Uuid) -> Result<(), WebhookError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let deleted = tx.execute("DELETE FROM webhooks WHERE id = ?1", [id.to_string()])?;
+        tx.execute(
+            "DELETE FROM webhook_outbox WHERE webhook_id = ?1",
+            [id.to_string()],
+        )?;
+        tx.commit()?;
+
+        if deleted == 0 {
+            return Err(WebhookError::NotFound);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, id: Uuid) -> Result<Webhook, WebhookError> {
+        let row = self
+            .conn()
+            .query_row(
+                "SELECT account_id, url, secret, event_types FROM webhooks WHERE id = ?1",
+                [id.to_string()],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        let (account_id, url, secret, event_types) = row.ok_or(WebhookError::NotFound)?;
+        Ok(Webhook {
+            id,
+            account_id: parse_uuid(&account_id)?,
+            url,
+            secret,
+            event_types: serde_json::from_str(&event_types)?,
+        })
+    }
+
+    /// Lists registrations, optionally restricted to one account.
+    pub fn list(&self, account_id: Option<Uuid>) -> Result<Vec<Webhook>, WebhookError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT id, account_id, url, secret, event_types FROM webhooks
+             WHERE ?1 IS NULL OR account_id = ?1",
+        )?;
+        let rows = stmt.query_map([account_id.map(|id| id.to_string())], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        })?;
+
+        let mut webhooks = Vec::new();
+        for row in rows {
+            let (id, account_id, url, secret, event_types) = row?;
+            webhooks.push(Webhook {
+                id: parse_uuid(&id)?,
+                account_id: parse_uuid(&account_id)?,
+                url,
+                secret,
+                event_types: serde_json::from_str(&event_types)?,
+            });
+        }
+        Ok(webhooks)
+    }
+
+    /// Sequence number and ID of the last committed transaction already turned into
+    /// deliveries, or `None` before the first one.
+    pub fn cursor(&self) -> Result<Option<(u64, Uuid)>, WebhookError> {
+        let cursor: Option<(u64, String)> = self
+            .conn()
+            .query_row(
+                "SELECT sequence, transaction_id FROM webhook_cursor WHERE id = 0",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        cursor
+            .map(|(sequence, transaction_id)| Ok((sequence, parse_uuid(&transaction_id)?)))
+            .transpose()
+    }
+
+    /// Adds deliveries to the outbox and advances the cursor to the given transaction in a
+    /// single transaction, so an event is never enqueued twice nor skipped across restarts.
+    pub fn enqueue(
+        &self,
+        deliveries: &[NewDelivery],
+        sequence: u64,
+        transaction_id: Uuid,
+    ) -> Result<(), WebhookError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let now = Utc::now().timestamp_millis();
+
+        for delivery in deliveries {
+            tx.execute(
+                "INSERT INTO webhook_outbox (id, webhook_id, payload, attempts, next_attempt_at, dead)
+                 VALUES (?1, ?2, ?3, 0, ?4, 0)",
+                params![
+                    delivery.id.to_string(),
+                    delivery.webhook_id.to_string(),
+                    delivery.payload,
+                    now,
+                ],
+            )?;
+        }
+        tx.execute(
+            "INSERT INTO webhook_cursor (id, sequence, transaction_id) VALUES (0, ?1, ?2)
+             ON CONFLICT(id) DO UPDATE
+             SET sequence = excluded.sequence, transaction_id = excluded.transaction_id",
+            params![sequence, transaction_id.to_string()],
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns up to `limit` live deliveries whose next attempt is due.
+    pub fn due(&self, now: DateTime<Utc>, limit: usize) -> Result<Vec<OutboxEntry>, WebhookError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT o.id, o.webhook_id, w.url, w.secret, o.payload, o.attempts
+             FROM webhook_outbox o JOIN webhooks w ON w.id = o.webhook_id
+             WHERE o.dead = 0 AND o.next_attempt_at <= ?1
+             ORDER BY o.next_attempt_at
+             LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![now.timestamp_millis(), limit as i64], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, u32>(5)?,
+            ))
+        })?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let (id, webhook_id, url, secret, payload, attempts) = row?;
+            entries.push(OutboxEntry {
+                id: parse_uuid(&id)?,
+                webhook_id: parse_uuid(&webhook_id)?,
+                url,
+                secret,
+                payload,
+                attempts,
+            });
+        }
+        Ok(entries)
+    }
+
+    pub fn mark_delivered(&self, id: Uuid) -> Result<(), WebhookError> {
+        self.conn()
+            .execute("DELETE FROM webhook_outbox WHERE id = ?1", [id.to_string()])?;
+        Ok(())
+    }
+
+    /// Records a failed attempt. `next_attempt_at` of `None` gives up on the delivery, which
+    /// is kept in the outbox as dead for inspection.
+    pub fn mark_failed(
+        &self,
+        id: Uuid,
+        attempts: u32,
+        next_attempt_at: Option<DateTime<Utc>>,
+        error: &str,
+    ) -> Result<(), WebhookError> {
+        self.conn().execute(
+            "UPDATE webhook_outbox
+             SET attempts = ?2, next_attempt_at = ?3, dead = ?4, last_error = ?5
+             WHERE id = ?1",
+            params![
+                id.to_string(),
+                attempts,
+                next_attempt_at.map_or(0, |at| at.timestamp_millis()),
+                next_attempt_at.is_none(),
+                error,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Number of deliveries still to be attempted.
+    pub fn pending_count(&self) -> Result<u64, WebhookError> {
+        Ok(self.conn().query_row(
+            "SELECT COUNT(*) FROM webhook_outbox WHERE dead = 0",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+}
+
+fn parse_uuid(value: &str) -> Result<Uuid, WebhookError> {
+    Uuid::parse_str(value).map_err(|e| {
+        WebhookError::Store(rusqlite::Error::FromSqlConversionFailure(
+            0,
+            rusqlite::types::Type::Text,
+            Box::new(e),
+        ))
+    })
+}